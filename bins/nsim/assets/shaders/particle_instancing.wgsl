@@ -0,0 +1,35 @@
+// Unlit instanced particles, see src/instancing.rs
+//
+// Instance positions are already relative to the floating origin so the
+// mesh transform is ignored and vertices go straight through the view.
+
+#import bevy_pbr::mesh_view_bindings::view
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+
+    @location(3) i_pos_scale: vec4<f32>,
+    @location(4) i_color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let position = vertex.position * vertex.i_pos_scale.w + vertex.i_pos_scale.xyz;
+
+    var out: VertexOutput;
+    out.clip_position = view.view_proj * vec4<f32>(position, 1.0);
+    out.color = vertex.i_color;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
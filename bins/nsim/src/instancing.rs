@@ -0,0 +1,300 @@
+//! Draws all particles with a single instanced draw call, they share one mesh
+//! and their color and scale are read from [ParticleInstance]
+//!
+//! Based on bevy's `shader_instancing` example
+
+use bevy::{
+    core_pipeline::core_3d::Transparent3d,
+    ecs::{entity::EntityHashMap, query::QueryItem, system::{lifetimeless::*, SystemParamItem}},
+    pbr::{MeshPipeline, MeshPipelineKey, RenderMeshInstances, SetMeshBindGroup, SetMeshViewBindGroup},
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        mesh::{GpuBufferInfo, MeshVertexBufferLayout},
+        render_asset::RenderAssets,
+        render_phase::{AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline, TrackedRenderPass},
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        view::{ExtractedView, NoFrustumCulling},
+        Render, RenderApp, RenderSet,
+    },
+};
+use bytemuck::{Pod, Zeroable};
+
+const SHADER_PATH: &str = "shaders/particle_instancing.wgsl";
+
+pub struct ParticleInstancingPlugin;
+
+impl Plugin for ParticleInstancingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugins(ExtractComponentPlugin::<ParticleInstances>::default())
+            .add_systems(PostUpdate,
+                collect_instances_system.after(doprec::TransformSystems)
+            );
+
+        app.sub_app_mut(RenderApp)
+            .add_render_command::<Transparent3d, DrawParticles>()
+            .init_resource::<SpecializedMeshPipelines<ParticlePipeline>>()
+            .add_systems(Render, (
+                queue_particles_system.in_set(RenderSet::QueueMeshes),
+                prepare_instance_buffers_system.in_set(RenderSet::PrepareResources),
+            ));
+    }
+
+    fn finish(&self, app: &mut App) {
+        app.sub_app_mut(RenderApp).init_resource::<ParticlePipeline>();
+    }
+}
+
+/// Per particle rendering data, the particle is drawn at the translation of
+/// its [GlobalTransform]
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct ParticleInstance {
+    pub color: Color,
+    pub scale: f32,
+}
+
+/// Spawns the entity holding the instances, all particles will use the given
+/// mesh
+pub fn spawn_instances_holder(commands: &mut Commands, mesh: Handle<Mesh>) {
+    commands.spawn((
+        mesh,
+        SpatialBundle::INHERITED_IDENTITY,
+        ParticleInstances::default(),
+        // instances are positioned by the shader, the holder's aabb is
+        // meaningless
+        NoFrustumCulling,
+    ));
+}
+
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct InstanceData {
+    position: Vec3,
+    scale: f32,
+    color: [f32; 4],
+}
+
+#[derive(Component, Debug, Default, Clone)]
+struct ParticleInstances(Vec<InstanceData>);
+
+impl ExtractComponent for ParticleInstances {
+    type QueryData = &'static ParticleInstances;
+    type QueryFilter = ();
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(item.clone())
+    }
+}
+
+fn collect_instances_system(
+    particles_query: Query<(&GlobalTransform, &ParticleInstance), Without<nbody::Disabled>>,
+    mut holders_query: Query<&mut ParticleInstances>,
+) {
+    for mut instances in &mut holders_query {
+        instances.0.clear();
+        instances.0.extend(particles_query.iter().map(|(transform, instance)| {
+            InstanceData {
+                position: transform.translation(),
+                scale: instance.scale,
+                color: instance.color.as_linear_rgba_f32(),
+            }
+        }));
+    }
+}
+
+#[derive(Component)]
+struct InstanceBuffer {
+    buffer: Buffer,
+    length: usize,
+}
+
+/// Instance buffers are kept across frames and only reallocated when the
+/// instances no longer fit, the render world entities being cleared each frame
+fn prepare_instance_buffers_system(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut buffers: Local<EntityHashMap<Buffer>>,
+
+    query: Query<(Entity, &ParticleInstances)>,
+) {
+    buffers.retain(|entity, _| query.contains(*entity));
+
+    for (entity, instances) in &query {
+        let contents: &[u8] = bytemuck::cast_slice(instances.0.as_slice());
+        let buffer = buffers.entry(entity)
+            .and_modify(|buffer| if buffer.size() < contents.len() as u64 {
+                *buffer = create_instance_buffer(&render_device, contents.len());
+            })
+            .or_insert_with(|| create_instance_buffer(&render_device, contents.len()));
+        render_queue.write_buffer(buffer, 0, contents);
+
+        commands.entity(entity).insert(InstanceBuffer {
+            buffer: buffer.clone(),
+            length: instances.0.len(),
+        });
+    }
+}
+
+/// Room for at least `size` bytes, rounded up to the next power of two so
+/// growing particle counts do not reallocate every frame
+fn create_instance_buffer(render_device: &RenderDevice, size: usize) -> Buffer {
+    render_device.create_buffer(&BufferDescriptor {
+        label: Some("particle instances buffer"),
+        size: size.max(1).next_power_of_two() as u64,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_particles_system(
+    transparent_3d_draw_functions: Res<DrawFunctions<Transparent3d>>,
+    particle_pipeline: Res<ParticlePipeline>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<ParticlePipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+
+    holders_query: Query<Entity, With<ParticleInstances>>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
+) {
+    let draw_particles = transparent_3d_draw_functions.read().id::<DrawParticles>();
+    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+
+    for (view, mut transparent_phase) in &mut views {
+        let view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
+        let rangefinder = view.rangefinder3d();
+        for entity in &holders_query {
+            let Some(mesh_instance) = render_mesh_instances.get(&entity)
+            else { continue; };
+            let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id)
+            else { continue; };
+            let key = view_key |
+                MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            let pipeline = match pipelines.specialize(
+                &pipeline_cache, &particle_pipeline, key, &mesh.layout
+            ) {
+                Ok(pipeline) => pipeline,
+                Err(error) => {
+                    log::error!("Could not specialize particle pipeline: {error}");
+                    continue;
+                },
+            };
+            transparent_phase.add(Transparent3d {
+                entity,
+                pipeline,
+                draw_function: draw_particles,
+                distance: rangefinder.distance_translation(
+                    &mesh_instance.transforms.transform.translation
+                ),
+                batch_range: 0..1,
+                dynamic_offset: None,
+            });
+        }
+    }
+}
+
+#[derive(Resource)]
+struct ParticlePipeline {
+    shader: Handle<Shader>,
+    mesh_pipeline: MeshPipeline,
+}
+
+impl FromWorld for ParticlePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let mesh_pipeline = world.resource::<MeshPipeline>().clone();
+
+        Self {
+            shader: world.load_asset(SHADER_PATH),
+            mesh_pipeline,
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for ParticlePipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.vertex.buffers.push(VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceData>() as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![
+                // position and scale
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: 3,
+                },
+                // color
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: VertexFormat::Float32x4.size(),
+                    shader_location: 4,
+                },
+            ],
+        });
+        descriptor.fragment.as_mut()
+            .expect("mesh pipeline has a fragment state")
+            .shader = self.shader.clone();
+
+        Ok(descriptor)
+    }
+}
+
+type DrawParticles = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    DrawMeshInstanced,
+);
+
+struct DrawMeshInstanced;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<RenderMeshInstances>);
+    type ViewQuery = ();
+    type ItemQuery = Read<InstanceBuffer>;
+
+    #[inline]
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        instance_buffer: Option<&'w InstanceBuffer>,
+        (meshes, render_mesh_instances): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(mesh_instance) = render_mesh_instances.get(&item.entity())
+        else { return RenderCommandResult::Failure; };
+        let Some(gpu_mesh) = meshes.into_inner().get(mesh_instance.mesh_asset_id)
+        else { return RenderCommandResult::Failure; };
+        let Some(instance_buffer) = instance_buffer
+        else { return RenderCommandResult::Failure; };
+
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
+
+        let instances = 0..instance_buffer.length as u32;
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed { buffer, index_format, count } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*count, 0, instances);
+            },
+            GpuBufferInfo::NonIndexed => {
+                pass.draw(0..gpu_mesh.vertex_count, instances);
+            },
+        }
+        RenderCommandResult::Success
+    }
+}
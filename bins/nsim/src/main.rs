@@ -1,6 +1,7 @@
 #![feature(duration_millis_float)]
 
 mod orbit_camera;
+mod instancing;
 
 use std::{ops::Range, time::Instant};
 
@@ -18,10 +19,21 @@ use rand::prelude::*;
 use utils::{IsZeroApprox, Vec3Ext};
 
 const COLLISION_DIAG: DiagnosticPath = DiagnosticPath::const_new("collision_compute");
-const INTEGRATION_DIAG: DiagnosticPath = DiagnosticPath::const_new("velocity_compute");
+/// Number of closest particles checked for a collision with each particle
+const MERGE_CANDIDATE_COUNT: usize = 4;
+/// Min affect distance of particles relative to their radius, which is also
+/// how their radius is recovered from snapshots
+const MIN_AFFECT_DISTANCE_PER_RADIUS: f64 = 0.5;
+
+const SNAPSHOT_PATH: &str = "nsim_snapshot.ron";
+const LOG_PATH: &str = "nsim.log";
 
 fn main() {
-    utils::logging::setup_basic_logging().unwrap();
+    // Runs can last for hours so logs are kept in a file as well
+    utils::logging::LoggingConfig::new()
+        .env_filters("NSIM_LOG").unwrap()
+        .file(utils::logging::FileOutput::new(LOG_PATH).rolling(16 << 20, 3))
+        .apply().unwrap();
 
     App::new()
         .add_plugins(bevy::diagnostic::FrameTimeDiagnosticsPlugin)
@@ -45,6 +57,7 @@ fn main() {
             doprec::DoprecPlugin::default(),
             nbody::NBodyPlugin,
             orbit_camera::OrbitCameraPlugin,
+            instancing::ParticleInstancingPlugin,
         ))
 
         .register_diagnostic(
@@ -54,23 +67,24 @@ fn main() {
 
         .add_systems(Startup, setup_system)
         .add_systems(Update, (
-            update_particles_colors.run_if(|| false),
+            update_particles_colors,
             update_debug_text_system,
             input_update_system,
+            snapshot_system,
         ))
+        // Chained so that particles released by the merges are not released
+        // again
         .add_systems(FixedUpdate, (
             particle_merge_system,
             particle_destroy_system,
-            position_integration_system,
             timestep_compute_system,
-        ).after(nbody::GravitySystems))
+        ).chain().after(nbody::GravitySystems))
 
         .insert_resource(Time::<Fixed>::from_hz(60.0))
-        .insert_resource(nbody::GravityConfig {
-            enabled_svo: true,
-            gravity_field_sample_backlog_count: 2,
-            ..default()
-        })
+        .insert_resource(nbody::GravityConfig::default()
+            .with_svo(true)
+            .with_gravity_field_sample_backlog_count(2)
+        )
         
         .run();
 }
@@ -83,14 +97,25 @@ pub struct Particle {
     pub radius: f64,
 }
 
-#[derive(Component, Default, Debug, Clone, Copy, PartialEq)]
-pub struct ParticleVelocity {
-    pub velocity: DVec3,
+/// Rng used to spawn particles, its seed is saved in snapshots
+#[derive(Resource, Debug, Clone)]
+pub struct ParticleRng {
+    pub seed: u64,
+    pub rng: SmallRng,
+}
+
+impl ParticleRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
 }
 
 #[derive(Resource, Debug, Clone)]
 pub struct ParticleConfig {
-    pub material: Handle<StandardMaterial>,
+    pub color: Color,
     pub mesh: Handle<Mesh>,
     pub density: f64,
 
@@ -104,28 +129,30 @@ pub struct ParticleConfig {
     pub enable_collision_detection: bool,
 
     pub enable_dynamic_timesteps: bool,
+
+    /// Colors particles from yellow to red depending on their depth in the
+    /// gravity svo
+    pub color_by_svo_depth: bool,
 }
 
 #[derive(Bundle, Debug)]
 pub struct ParticleBundle {
     transform: Transform64Bundle,
-    visibility: VisibilityBundle,
-    mesh: Handle<Mesh>,
-    material: Handle<StandardMaterial>,
+    instance: instancing::ParticleInstance,
     particle: Particle,
-    velocity: ParticleVelocity,
+    velocity: nbody::Velocity,
     gravity_field_sample: nbody::GravityFieldSample,
     massive: nbody::Massive,
     attracted: nbody::Attracted,
     attractor: nbody::Attractor,
     timestep: nbody::TimeStep,
+    sleep: nbody::GravitySleep,
+    integrated_motion: nbody::IntegratedMotion,
 }
 
 impl ParticleBundle {
     pub fn new(
         cfg: &ParticleConfig,
-        _meshes: &mut Assets<Mesh>,
-        _materials: &mut Assets<StandardMaterial>,
         mass: f64,
         pos: DVec3,
         custom_radius: Option<f64>,
@@ -133,29 +160,30 @@ impl ParticleBundle {
         let radius = custom_radius.unwrap_or_else(||
             (3. * (mass / cfg.density)) / (4. * std::f64::consts::PI)
         );
-        // let material = materials.add(materials.get(&cfg.material).unwrap().clone());
-        let material = cfg.material.clone();
 
         Self {
             transform: Transform64Bundle {
                 local: Transform64 {
                     translation: pos,
                     rotation: default(),
-                    scale: DVec3::splat(radius),
+                    scale: DVec3::ONE,
                 },
                 ..default()
             },
-            visibility: default(),
-            mesh: cfg.mesh.clone(),
-            material,
+            instance: instancing::ParticleInstance {
+                color: cfg.color,
+                scale: radius as f32,
+            },
             particle: Particle { radius },
             velocity: default(),
             gravity_field_sample: nbody::GravityFieldSample::default()
-                .with_min_affect_distance(radius / 2.),
+                .with_min_affect_distance(radius * MIN_AFFECT_DISTANCE_PER_RADIUS),
             massive: nbody::Massive { mass },
             attracted: default(),
             attractor: default(),
             timestep: default(),
+            sleep: default(),
+            integrated_motion: default(),
         }
     }
 }
@@ -163,14 +191,12 @@ impl ParticleBundle {
 fn spawn_particles(
     cfg: &ParticleConfig,
     gravity_cfg: &nbody::GravityConfig,
+    rng: &mut ParticleRng,
     mut commands: Commands,
 
-    materials: &mut Assets<StandardMaterial>,
-    meshes: &mut Assets<Mesh>,
-
     count: usize,
 ) {
-    let mut rng = SmallRng::from_entropy();
+    let rng = &mut rng.rng;
 
     let mass_distributions = rand_distr::Normal::new(
         (cfg.mass_range.start + cfg.mass_range.end) / 2.,
@@ -197,11 +223,9 @@ fn spawn_particles(
 
         commands
             .spawn(ParticleBundle {
-                velocity: ParticleVelocity { velocity },
+                velocity: nbody::Velocity { velocity },
                 ..ParticleBundle::new(
-                    cfg, meshes, materials,
-                    mass, pos,
-                    None,
+                    cfg, mass, pos, None,
                 )
             });
     }
@@ -209,16 +233,12 @@ fn spawn_particles(
 
 fn setup_system(
     mut commands: Commands,
-    mut materials: ResMut<Assets<StandardMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
     gravity_cfg: Res<nbody::GravityConfig>,
 ) {
+    let mut rng = ParticleRng::new(random());
     let cfg = ParticleConfig {
-        material: materials.add(StandardMaterial {
-            base_color: Color::RED,
-            unlit: true,
-            ..default()
-        }),
+        color: Color::RED,
         mesh: meshes.add(SphereMeshBuilder::new(1., SphereKind::Ico {
             subdivisions: 4,
         }).build()),
@@ -233,26 +253,28 @@ fn setup_system(
 
         enable_collision_detection: false,
         enable_dynamic_timesteps: true,
+
+        color_by_svo_depth: false,
     };
     commands.insert_resource(cfg.clone());
+    instancing::spawn_instances_holder(&mut commands, cfg.mesh.clone());
     
     commands
         .spawn(ParticleBundle::new(
-            &cfg, &mut meshes, &mut materials,
-            cfg.sun_mass, DVec3::ZERO,
+            &cfg, cfg.sun_mass, DVec3::ZERO,
             Some(1_000f64),
         ));
 
     spawn_particles(
-        &cfg, &gravity_cfg, commands.reborrow(),
-        &mut materials, &mut meshes, 1_000,
+        &cfg, &gravity_cfg, &mut rng, commands.reborrow(), 1_000,
     );
+    commands.insert_resource(rng);
 
     // let mass = 1_000f64;
-    // commands.spawn(ParticleBundle::new(&cfg, &mut *meshes, &mut *materials, mass, DVec3::new(
+    // commands.spawn(ParticleBundle::new(&cfg, mass, DVec3::new(
     //     -10., 0., 0.,
     // )));
-    // commands.spawn(ParticleBundle::new(&cfg, &mut *meshes, &mut *materials, mass, DVec3::new(
+    // commands.spawn(ParticleBundle::new(&cfg, mass, DVec3::new(
     //     10., 0., 0.,
     // )));
 
@@ -311,16 +333,15 @@ fn setup_system(
     }).set_parent(root_uinode);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn input_update_system(
     mut commands: Commands,
 
     mut cfg: ResMut<ParticleConfig>,
     mut gravity_cfg: ResMut<nbody::GravityConfig>,
+    mut rng: ResMut<ParticleRng>,
 
     kb_input: Res<ButtonInput<KeyCode>>,
-
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut meshes: ResMut<Assets<Mesh>>,
 ) {
     if kb_input.just_pressed(KeyCode::KeyS) {
         gravity_cfg.enabled_svo = !gravity_cfg.enabled_svo;
@@ -339,8 +360,7 @@ fn input_update_system(
 
     if kb_input.just_pressed(KeyCode::KeyP) {
         spawn_particles(
-            &cfg, &gravity_cfg, commands.reborrow(),
-            &mut materials, &mut meshes, 500,
+            &cfg, &gravity_cfg, &mut rng, commands.reborrow(), 500,
         );
     }
 
@@ -351,6 +371,81 @@ fn input_update_system(
     if kb_input.just_pressed(KeyCode::KeyT) {
         cfg.enable_dynamic_timesteps = !cfg.enable_dynamic_timesteps;
     }
+
+    if kb_input.just_pressed(KeyCode::KeyA) {
+        let monitor = &mut gravity_cfg.svo_error_monitor;
+        monitor.auto_adjust = !monitor.auto_adjust;
+    }
+
+    if kb_input.just_pressed(KeyCode::KeyD) {
+        cfg.color_by_svo_depth = !cfg.color_by_svo_depth;
+    }
+}
+
+/// Saves the simulation on F5 and restores the last save on F9
+#[allow(clippy::type_complexity)]
+fn snapshot_system(
+    mut commands: Commands,
+
+    cfg: Res<ParticleConfig>,
+    mut rng: ResMut<ParticleRng>,
+    mut pool: ResMut<nbody::BodyPool>,
+
+    kb_input: Res<ButtonInput<KeyCode>>,
+
+    particles_query: Query<(
+        Entity, &Transform64, &nbody::Velocity, &nbody::Massive,
+        &nbody::GravityFieldSample, Option<&nbody::TimeStep>,
+        Option<&nbody::IntegratedMotion>,
+    ), (With<Particle>, Without<nbody::Disabled>)>,
+) {
+    if kb_input.just_pressed(KeyCode::F5) {
+        let snapshot = nbody::GravitySnapshot::capture(
+            rng.seed,
+            particles_query.iter().map(|(_, transform, velocity, massive, sample, timestep, motion)|
+                (transform, velocity, massive, sample, timestep, motion)
+            ),
+        );
+        match snapshot.save(SNAPSHOT_PATH) {
+            Ok(()) => log::info!(
+                "Saved {} particles to {SNAPSHOT_PATH}", snapshot.bodies.len()
+            ),
+            Err(error) => log::error!("Could not save snapshot: {error}"),
+        }
+    }
+
+    if kb_input.just_pressed(KeyCode::F9) {
+        let snapshot = match nbody::GravitySnapshot::load(SNAPSHOT_PATH) {
+            Ok(snapshot) => snapshot,
+            Err(error) => {
+                log::error!("Could not load snapshot: {error}");
+                return;
+            },
+        };
+
+        for (entity, ..) in &particles_query {
+            commands.entity(entity).despawn();
+        }
+        pool.clear(&mut commands);
+
+        *rng = ParticleRng::new(snapshot.seed);
+
+        for body in &snapshot.bodies {
+            let mut bundle = ParticleBundle {
+                velocity: nbody::Velocity { velocity: body.velocity() },
+                integrated_motion: body.integrated_motion(),
+                ..ParticleBundle::new(
+                    &cfg, body.mass, body.position(),
+                    Some(body.min_affect_distance / MIN_AFFECT_DISTANCE_PER_RADIUS),
+                )
+            };
+            bundle.timestep.multiplier = body.timestep_multiplier;
+            commands.spawn(bundle);
+        }
+        log::info!(
+            "Loaded {} particles from {SNAPSHOT_PATH}", snapshot.bodies.len()
+        );
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -361,8 +456,9 @@ fn update_debug_text_system(
     gravity_svo_ctx: Res<nbody::GravitySvoContext>,
 
     cam_query: Query<(&Transform64, &orbit_camera::OrbitCameraComp)>,
-    particles_query: Query<(&nbody::Massive, &ParticleVelocity), With<Particle>>,
-    timestep_query: Query<&nbody::TimeStep, With<Particle>>,
+    particles_query: Query<(&nbody::Massive, &nbody::Velocity), (With<Particle>, Without<nbody::Disabled>)>,
+    timestep_query: Query<&nbody::TimeStep, (With<Particle>, Without<nbody::Disabled>)>,
+    sleep_query: Query<&nbody::GravitySleep, (With<Particle>, Without<nbody::Disabled>)>,
 
     mut debug_text: Query<&mut Text, With<DebugTextComp>>,
 ) {
@@ -385,6 +481,9 @@ fn update_debug_text_system(
     let svo_update_duration = diagnostics.get(&nbody::GRAVITY_SVO_UPDATE_SYSTEM_DURATION)
         .and_then(|diag| diag.smoothed())
         .unwrap_or(f64::NAN);
+    let integration_duration = diagnostics.get(&nbody::GRAVITY_INTEGRATION_SYSTEM_DURATION)
+        .and_then(|diag| diag.smoothed())
+        .unwrap_or(f64::NAN);
     let collision_compute_duration = diagnostics.get(&COLLISION_DIAG)
         .and_then(|diag| diag.smoothed())
         .unwrap_or(f64::NAN);
@@ -415,6 +514,14 @@ fn update_debug_text_system(
     let svo_depth = gravity_svo_ctx.depth();
     let svo_max_depth = gravity_svo_ctx.max_depth();
     let svo_theta = gravity_cfg.svo_skip_config.opening_angle;
+    let svo_error = diagnostics.get(&nbody::GRAVITY_SVO_RELATIVE_ERROR)
+        .and_then(|diag| diag.value())
+        .unwrap_or(f64::NAN) * 100.;
+    let svo_auto_theta_state = if gravity_cfg.svo_error_monitor.auto_adjust {
+        "enabled"
+    } else {
+        "disabled"
+    };
 
     let energy = particles_query.iter().map(|(m, v)| m.mass * v.velocity.length()).sum::<f64>();
     let average_multiplier = {
@@ -425,59 +532,79 @@ fn update_debug_text_system(
         sum / count
     };
 
+    let asleep_count = sleep_query.iter().filter(|sleep| sleep.asleep()).count();
+
+    let color_by_depth_state = if cfg.color_by_svo_depth {
+        "enabled"
+    } else {
+        "disabled"
+    };
+
     let mut debug_text = debug_text.single_mut();
     debug_text.sections[0].value = format!("\
     {fps:.1} fps - {frame_time:.3} ms/frame\n\
     - Transform propagation: {transform_propagation_duration:.3} ms\n\
     - Svo update: {svo_update_duration:.3} ms\n\
     - Gravity compute: {grav_compute_duration:.3} ms\n\
+    - Integration: {integration_duration:.3} ms\n\
     - Collision detection: {collision_info} (use 'c' to toggle)\n\
     Camera: speed {cam_speed:.3}, position {cam_pos:.3?}\n\
     Particles: count {particle_count} (press 'p' to spawn more),\n\
     - total energy: {energy:.2}\n\
     - average timestep mutliplier: {average_multiplier:.2}\n\
+    - asleep: {asleep_count}\n\
     - dynamic timesteps: {dynamic_timesteps_state} (press 't' to toggle)\n\
     Svo: {svo_state} (press 's' to toggle), depth: {svo_depth}/{svo_max_depth}, theta: {svo_theta:.2} (+/- 0.05)\n\
+    - relative error: {svo_error:.3}%, auto theta: {svo_auto_theta_state} (press 'a' to toggle)\n\
+    Color by svo depth: {color_by_depth_state} (press 'd' to toggle)\n\
+    Snapshot: F5 to save, F9 to load\n\
     ");
 }
 
 fn update_particles_colors(
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    cfg: Res<ParticleConfig>,
 
-    mut particle_query: Query<(&nbody::Attractor, &mut Handle<StandardMaterial>), With<Particle>>,
+    mut particle_query: Query<(&nbody::Attractor, &mut instancing::ParticleInstance), (With<Particle>, Without<nbody::Disabled>)>,
 ) {
+    if !cfg.color_by_svo_depth {
+        for (_, mut instance) in &mut particle_query {
+            if instance.color != cfg.color {
+                instance.color = cfg.color;
+            }
+        }
+        return;
+    }
+
     let min_color = Color::YELLOW.rgba_linear_to_vec4();
     let max_color = Color::RED.rgba_linear_to_vec4();
 
     let max_depth = particle_query.iter()
         .filter_map(|par| par.0.last_svo_position.as_ref().map(|p| p.depth()))
-        .max().unwrap_or_default();
+        .max().unwrap_or_default()
+        .max(1);
 
-    for (attractor, mut material_handle) in &mut particle_query {
+    for (attractor, mut instance) in &mut particle_query {
         let depth = attractor.last_svo_position.as_ref()
             .map(|p| p.depth()).unwrap_or(0);
         let prop = depth as f32 / max_depth as f32;
 
-        let color = Color::rgba_linear_from_array(
+        instance.color = Color::rgba_linear_from_array(
             min_color * (1. - prop) + max_color * prop
         );
-
-        material_handle.set_changed();
-        let material = materials.get_mut(&*material_handle).unwrap();
-        material.base_color = color;
     }
 }
 
 fn particle_merge_system(
     mut diagnostics: Diagnostics,
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
     time: Res<Time<Fixed>>,
 
     cfg: Res<ParticleConfig>,
+    gravity_cfg: Res<nbody::GravityConfig>,
+    gravity_svo_ctx: Res<nbody::GravitySvoContext>,
+    mut pool: ResMut<nbody::BodyPool>,
 
-    particle_query: Query<(Entity, &Transform64, &ParticleVelocity, &nbody::GravityFieldSample, &nbody::Massive, &Particle)>,
+    particle_query: Query<(Entity, &Transform64, &nbody::Velocity, &nbody::GravityFieldSample, &nbody::Massive, &Particle), Without<nbody::Disabled>>,
 ) {
     if !cfg.enable_collision_detection {
         return;
@@ -489,68 +616,85 @@ fn particle_merge_system(
     for (
         entity, transform, velocity_comp, sample_comp, massive_comp, particle_comp,
     ) in &particle_query {
-        let &Some(nbody::AttractorInfo {
-            entity: closest_entity, ..
-        }) = sample_comp.closest_attractor()
-        else { continue; };
-
-        if destroyed.contains(&entity) || destroyed.contains(&closest_entity) {
+        if destroyed.contains(&entity) {
             continue;
         }
 
-        let Ok((
-            _other_entity, other_transform, other_velocity_comp, _other_attracted_comp, other_massive_comp, other_particle_comp,
-        )) = particle_query.get(closest_entity)
-        else { continue; };
-
         let p1 = transform.translation;
         let v1 = velocity_comp.velocity;
         let r1 = particle_comp.radius;
         let m1 = massive_comp.mass;
 
-        let p2 = other_transform.translation;
-        let v2 = other_velocity_comp.velocity;
-        let r2 = other_particle_comp.radius;
-        let m2 = other_massive_comp.mass;
-
-        let dp = p1 - p2;
-        let dv = v1 - v2;
-
-        if dv.is_zero_approx() {
-            continue;
+        // The closest attractor alone misses collisions with particles of
+        // comparable mass that are a bit further
+        let candidates = if gravity_cfg.enabled_svo {
+            gravity_svo_ctx.knn(p1, MERGE_CANDIDATE_COUNT + 1).into_iter()
+                .map(|neighbor| neighbor.entity)
+                .filter(|&other| other != entity)
+                .collect::<Vec<_>>()
         }
-
-        let t =
-            -(dp * dv).array().into_iter().sum::<f64>() /
-            dv.array().into_iter().map(|x| x*x).sum::<f64>();
-
-        let t = t.clamp(
-            -time.delta_seconds_f64(),
-            time.delta_seconds_f64(),
-        );
-
-        let closest_distance_squared = (dp + t * dv).length_squared();
-
-        let contact_distance = (r1 + r2) / 10.;
-
-        if contact_distance.powi(2) < closest_distance_squared {
-            // no collision
-            continue;
+        else {
+            sample_comp.closest_attractor().iter()
+                .map(|info| info.entity)
+                .collect()
+        };
+
+        for closest_entity in candidates {
+            if destroyed.contains(&closest_entity) {
+                continue;
+            }
+
+            let Ok((
+                _other_entity, other_transform, other_velocity_comp, _other_attracted_comp, other_massive_comp, other_particle_comp,
+            )) = particle_query.get(closest_entity)
+            else { continue; };
+
+            let p2 = other_transform.translation;
+            let v2 = other_velocity_comp.velocity;
+            let r2 = other_particle_comp.radius;
+            let m2 = other_massive_comp.mass;
+
+            let dp = p1 - p2;
+            let dv = v1 - v2;
+
+            if dv.is_zero_approx() {
+                continue;
+            }
+
+            let t =
+                -(dp * dv).array().into_iter().sum::<f64>() /
+                dv.array().into_iter().map(|x| x*x).sum::<f64>();
+
+            let t = t.clamp(
+                -time.delta_seconds_f64(),
+                time.delta_seconds_f64(),
+            );
+
+            let closest_distance_squared = (dp + t * dv).length_squared();
+
+            let contact_distance = (r1 + r2) / 10.;
+
+            if contact_distance.powi(2) < closest_distance_squared {
+                // no collision
+                continue;
+            }
+
+            // Entities are recycled as merges happen all the time
+            destroyed.insert(entity);
+            pool.release(&mut commands, entity);
+            destroyed.insert(closest_entity);
+            pool.release(&mut commands, closest_entity);
+
+            let m3 = m1 + m2;
+            let v3 = ((m1 * v1) + (m2 * v2)) / m3;
+            let p3 = ((p1 + v1 * t) * m1 + (p2 + v2 * t) * m2) / m3;
+
+            pool.spawn(&mut commands, ParticleBundle {
+                velocity: nbody::Velocity { velocity: v3 },
+                ..ParticleBundle::new(&cfg, m3, p3, None)
+            });
+            break;
         }
-
-        destroyed.insert(entity);
-        commands.entity(entity).despawn();
-        destroyed.insert(closest_entity);
-        commands.entity(closest_entity).despawn();
-
-        let m3 = m1 + m2;
-        let v3 = ((m1 * v1) + (m2 * v2)) / m3;
-        let p3 = ((p1 + v1 * t) * m1 + (p2 + v2 * t) * m2) / m3;
-
-        commands.spawn(ParticleBundle {
-            velocity: ParticleVelocity { velocity: v3 },
-            ..ParticleBundle::new(&cfg, &mut meshes, &mut materials, m3, p3, None)
-        });
     }
 
     diagnostics.add_measurement(&COLLISION_DIAG, || start.elapsed().as_millis_f64())
@@ -561,12 +705,13 @@ fn particle_destroy_system(
     mut commands: Commands,
 
     cfg: Res<ParticleConfig>,
+    mut pool: ResMut<nbody::BodyPool>,
 
-    particle_query: Query<(Entity, &Transform64), With<Particle>>,
+    particle_query: Query<(Entity, &Transform64), (With<Particle>, Without<nbody::Disabled>)>,
 ) {
     for (entity, transform) in &particle_query {
         if transform.translation.length() > cfg.max_distance {
-            commands.entity(entity).despawn();
+            pool.release(&mut commands, entity);
         }
     }
 }
@@ -575,8 +720,8 @@ fn timestep_compute_system(
     cfg: Res<ParticleConfig>,
 
     mut particle_query: Query<(
-        &mut nbody::TimeStep, &ParticleVelocity
-    ), With<Particle>>,
+        &mut nbody::TimeStep, &nbody::Velocity
+    ), (With<Particle>, Without<nbody::Disabled>)>,
 ) {
     if !cfg.enable_dynamic_timesteps {
         particle_query.par_iter_mut().for_each(|(mut timestep, ..)| {
@@ -600,43 +745,3 @@ fn timestep_compute_system(
         timestep.multiplier = (val.floor() as u32).clamp(1, 10);
     });
 }
-
-fn position_integration_system(
-    mut diagnostics: Diagnostics,
-    time: Res<Time<Fixed>>,
-
-    mut particle_query: Query<(
-        &nbody::GravityFieldSample, &nbody::TimeStep,
-        &mut ParticleVelocity, &mut Transform64,
-    ), With<Particle>>,
-) {
-    let start = Instant::now();
-    particle_query.par_iter_mut().for_each(|(
-        sample, timestep, mut velocity_comp, mut transform,
-    )| {
-        // if !timestep.last_updated() {
-        //     return;
-        // }
-        if sample.field_forces().len() < 2 {
-            return;
-        }
-
-        // leapfrog integration (i hope?)
-        let dt = time.delta_seconds_f64();
-        let v = velocity_comp.velocity;
-        let p = transform.translation;
-        let a = sample.field_force(1).expect("checked");
-        let na = sample.field_force(0).expect("checked");
-
-        // let half_dt = dt / 2.;
-        // let half_nv = v + a * half_dt;
-        // let np      = p + half_nv * dt;
-        // let nv      = half_nv + na * half_dt;
-        let np = p + v * dt + 0.5 * a * dt.powi(2);
-        let nv = v + 0.5 * ( a + na ) * dt;
-
-        transform.translation = np;
-        velocity_comp.velocity = nv;
-    });
-    diagnostics.add_measurement(&INTEGRATION_DIAG, || start.elapsed().as_millis_f64())
-}
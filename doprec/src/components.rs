@@ -2,7 +2,7 @@ use std::ops::Mul;
 
 use bevy::prelude::*;
 use bevy::math::{Affine3A, DAffine3, DQuat, DVec3};
-use utils::DQuatExt;
+use utils::{DAabb, DQuatExt, FixedVec3};
 
 // TODO: Gather info about why trans / rot / scale is separated for Transform and not
 // for GlobalTransform
@@ -62,6 +62,16 @@ impl Transform64 {
         }
     }
 
+    /// Translation is rounded to the closest float
+    pub fn from_fixed_translation(translation: FixedVec3) -> Self {
+        Self::from_translation(translation.to_dvec3())
+    }
+
+    /// Does not implement Into<FixedVec3> as rotation and scale are lost
+    pub fn fixed_translation(&self) -> FixedVec3 {
+        FixedVec3::from_dvec3(self.translation)
+    }
+
     /// Does not implement From<Transform> to prevent implicit precision loss
     pub fn from_32(transform: Transform) -> Self {
         Self {
@@ -157,6 +167,12 @@ impl Transform64 {
             scale: 1. / self.scale,
         }
     }
+
+    /// Smallest aabb containing the given one once transformed, see
+    /// [DAabb::transformed]
+    pub fn transform_aabb(&self, aabb: DAabb) -> DAabb {
+        aabb.transformed(&GlobalTransform64::from(*self).affine())
+    }
 }
 
 impl Mul<DVec3> for Transform64 {
@@ -214,6 +230,16 @@ impl GlobalTransform64 {
         self.0.translation
     }
 
+    pub fn affine(&self) -> DAffine3 {
+        self.0
+    }
+
+    /// Smallest aabb containing the given one once transformed, see
+    /// [DAabb::transformed]
+    pub fn transform_aabb(&self, aabb: DAabb) -> DAabb {
+        aabb.transformed(&self.0)
+    }
+
     pub fn rotation(&self) -> DQuat {
         self.0.to_scale_rotation_translation().1
     }
@@ -6,6 +6,8 @@ pub use plugin::*;
 pub(crate) mod systems;
 pub(crate) mod components;
 pub use components::*;
+pub(crate) mod visibility;
+pub use visibility::*;
 
 pub use systems::TransformSystems;
 
@@ -1,6 +1,6 @@
 use crate::systems;
 
-use bevy::{diagnostic::{Diagnostic, RegisterDiagnostic}, prelude::*};
+use bevy::{diagnostic::{Diagnostic, RegisterDiagnostic}, prelude::*, render::view::VisibilitySystems};
 
 #[derive(Default)]
 pub struct DoprecPlugin {
@@ -36,6 +36,10 @@ impl Plugin for DoprecPlugin {
                     systems::update_on_floating_origin_system,
                     systems::propagate_start_end_system,
                 ).chain(),
-            ).in_set(systems::TransformSystems));
+            ).in_set(systems::TransformSystems))
+            .add_systems(PostUpdate, crate::visibility::frustum_culling64_system
+                .after(systems::TransformSystems)
+                .after(VisibilitySystems::CheckVisibility)
+            );
     }
 }
@@ -0,0 +1,123 @@
+use bevy::{
+    math::{DAffine3, DMat4, DVec3, DVec4},
+    prelude::*,
+    render::{camera::CameraProjection, view::{NoFrustumCulling, VisibleEntities}},
+    utils::HashSet,
+};
+use utils::DAabb;
+
+use crate::components::GlobalTransform64;
+
+/// Local space bounding box used to frustum cull the entity in f64 instead of
+/// with bevy's f32 [Aabb](bevy::render::primitives::Aabb)
+///
+/// Bevy's culling must be disabled for the entity with [NoFrustumCulling],
+/// see [Culling64Bundle]
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Aabb64(pub DAabb);
+
+#[derive(Bundle, Default)]
+pub struct Culling64Bundle {
+    pub aabb: Aabb64,
+    pub no_frustum_culling: NoFrustumCulling,
+}
+
+impl Culling64Bundle {
+    pub fn new(aabb: DAabb) -> Self {
+        Self {
+            aabb: Aabb64(aabb),
+            no_frustum_culling: NoFrustumCulling,
+        }
+    }
+}
+
+/// f64 version of bevy's [Frustum](bevy::render::primitives::Frustum)
+///
+/// Like bevy's visibility check there is no far plane, half spaces are
+/// (normal, d) with normals pointing inside the frustum
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DFrustum {
+    pub half_spaces: [DVec4; 5],
+}
+
+impl DFrustum {
+    /// Planes extracted from a reversed-z (like bevy's) view projection matrix
+    pub fn from_view_projection(view_projection: &DMat4) -> Self {
+        let row3 = view_projection.row(3);
+        let half_spaces = [
+            row3 + view_projection.row(0),
+            row3 - view_projection.row(0),
+            row3 + view_projection.row(1),
+            row3 - view_projection.row(1),
+            // near
+            row3 - view_projection.row(2),
+        ].map(|half_space| half_space / half_space.truncate().length());
+
+        Self { half_spaces }
+    }
+
+    pub fn from_camera(
+        projection: &impl CameraProjection, transform: &GlobalTransform64,
+    ) -> Self {
+        let view_projection =
+            projection.get_projection_matrix().as_dmat4() *
+            DMat4::from(transform.affine().inverse());
+        Self::from_view_projection(&view_projection)
+    }
+
+    /// Checks if the given aabb, transformed by `model_to_world`, is at least
+    /// partially inside the frustum
+    pub fn intersects_obb(&self, aabb: DAabb, model_to_world: &DAffine3) -> bool {
+        let half_extents = aabb.size / 2.;
+        let center = model_to_world.transform_point3(aabb.position + half_extents)
+            .extend(1.);
+        let axes = model_to_world.matrix3;
+
+        self.half_spaces.iter().all(|half_space| {
+            let normal = half_space.truncate();
+            let relative_radius = DVec3::new(
+                normal.dot(axes.x_axis),
+                normal.dot(axes.y_axis),
+                normal.dot(axes.z_axis),
+            ).abs().dot(half_extents);
+            half_space.dot(center) + relative_radius > 0.
+        })
+    }
+}
+
+/// Removes entities with an [Aabb64] outside of the camera's frustum from its
+/// [VisibleEntities], and hides the ones no camera sees
+pub fn frustum_culling64_system(
+    mut cameras: Query<(
+        &Camera, &Projection, &GlobalTransform64, &mut VisibleEntities
+    )>,
+    mut culled: Query<(
+        Entity, (&Aabb64, &GlobalTransform64, &mut ViewVisibility)
+    )>,
+) {
+    let mut seen = HashSet::<Entity>::new();
+
+    for (camera, projection, transform, mut visible_entities) in &mut cameras {
+        if !camera.is_active {
+            continue;
+        }
+        let frustum = DFrustum::from_camera(projection, transform);
+
+        visible_entities.entities.retain(|&entity| {
+            let Ok((_, (aabb, transform, _))) = culled.get(entity)
+            else { return true; };
+
+            let visible = frustum.intersects_obb(aabb.0, &transform.affine());
+            if visible {
+                seen.insert(entity);
+            }
+            visible
+        });
+    }
+
+    for (entity, (.., mut view_visibility)) in culled.iter_mut() {
+        if view_visibility.get() && !seen.contains(&entity) {
+            *view_visibility = ViewVisibility::HIDDEN;
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::{math::DVec3, pbr::NotShadowCaster, prelude::*, time::common_conditions::on_timer, utils::HashMap};
+use doprec::{Culling64Bundle, Transform64, Transform64Bundle};
+use svo::{mesh_generation::{fluid_surface, marching_cubes}, CellPath, ChunkView};
+use utils::DAabb;
+
+use crate::preset::FluidPreset;
+use crate::svo_provider::SvoProviderComponent;
+use crate::task_runner::{self, OptionTaskExt, Task};
+
+/// Simulates and renders the water of the planets with a [FluidComponent]
+#[derive(Default)]
+pub struct FluidPlugin;
+
+impl Plugin for FluidPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedUpdate, fluid_relax_system);
+        app.add_systems(Update,
+            fluid_mesh_system
+                .run_if(on_timer(Duration::from_millis(250))),
+        );
+    }
+}
+
+/// Water of a planet, on the same entity as its
+/// [SvoRendererComponent](crate::svo_renderer::SvoRendererComponent)
+///
+/// The water is stored in its own svo with the renderer's root aabb, filled
+/// up to the sea level once the terrain is generated and then relaxed one
+/// step at a time in the background. Its surface is meshed in chunks that
+/// are children of the renderer.
+#[derive(Component)]
+pub struct FluidComponent {
+    preset: FluidPreset,
+    root_aabb: DAabb,
+    /// Cells closer than this to the center of the root aabb start filled
+    sea_radius: f64,
+    material: Handle<StandardMaterial>,
+
+    /// Terrain at the depth of the fluid, requested once from the provider
+    terrain_task: Option<Task<Arc<svo::TerrainCell>>>,
+    terrain: Option<Arc<svo::TerrainCell>>,
+
+    relax_task: Option<Task<Arc<svo::FluidCell>>>,
+    fluid: Option<Arc<svo::FluidCell>>,
+
+    should_update_meshes: bool,
+    chunks: HashMap<CellPath, FluidChunk>,
+}
+
+#[derive(Default)]
+struct FluidChunk {
+    entity: Option<Entity>,
+    mesh_task: Option<Task<marching_cubes::Out>>,
+    /// Must be in sync with the `Handle<Mesh>` component on the chunk's entity
+    mesh: Option<Handle<Mesh>>,
+}
+
+impl FluidComponent {
+    pub fn new(
+        preset: FluidPreset,
+        root_aabb: DAabb,
+        planet_radius: f64,
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Self {
+        let [r, g, b, a] = preset.color;
+        let material = materials.add(StandardMaterial {
+            base_color: Color::rgba(r, g, b, a),
+            alpha_mode: AlphaMode::Blend,
+            perceptual_roughness: preset.perceptual_roughness,
+            ..default()
+        });
+
+        Self {
+            sea_radius: preset.sea_level * planet_radius,
+            preset,
+            root_aabb,
+            material,
+
+            terrain_task: None,
+            terrain: None,
+            relax_task: None,
+            fluid: None,
+
+            should_update_meshes: false,
+            chunks: HashMap::new(),
+        }
+    }
+
+    fn gravity_center(&self) -> DVec3 {
+        self.root_aabb.position + self.root_aabb.size / 2.
+    }
+
+    /// Water up to the sea level where the terrain is empty
+    fn initial_fluid(&self, terrain: &svo::TerrainCell) -> svo::FluidCell {
+        let center = self.gravity_center();
+        svo::FluidCell::par_build_with(self.preset.depth, |path| {
+            let aabb = path.get_aabb(self.root_aabb);
+            let below_sea = (aabb.position + aabb.size / 2.).distance(center) < self.sea_radius;
+            let empty = terrain.get_path(path).into_inner().kind.empty();
+            svo::FluidCellData::new(if below_sea && empty { 1. } else { 0. })
+        })
+    }
+}
+
+fn fluid_relax_system(
+    mut fluids: Query<(&mut FluidComponent, &mut SvoProviderComponent)>,
+) {
+    for (mut fluid, mut provider) in &mut fluids {
+        if fluid.terrain.is_none() && fluid.terrain_task.is_none() {
+            fluid.terrain_task = Some(provider.request_chunk(
+                &CellPath::new(), fluid.preset.depth
+            ));
+        }
+        if let Some(terrain) = fluid.terrain_task.take_if_finished() {
+            if fluid.fluid.is_none() {
+                fluid.fluid = Some(Arc::new(fluid.initial_fluid(&terrain)));
+                fluid.should_update_meshes = true;
+            }
+            fluid.terrain = Some(terrain);
+        }
+
+        if let Some(relaxed) = fluid.relax_task.take_if_finished() {
+            fluid.fluid = Some(relaxed);
+            fluid.should_update_meshes = true;
+        }
+
+        // Only one step at a time, the next one starts from its result
+        if fluid.relax_task.is_some() {
+            continue;
+        }
+        let (Some(terrain), Some(current)) = (&fluid.terrain, &fluid.fluid)
+        else { continue; };
+        let terrain = Arc::clone(terrain);
+        let current = Arc::clone(current);
+        let relaxation = fluid.preset.relaxation;
+        let root_aabb = fluid.root_aabb;
+        let gravity_center = fluid.gravity_center();
+        let depth = fluid.preset.depth;
+        fluid.relax_task = Some(task_runner::spawn(move || Arc::new(relaxation.relax(
+            &current, &terrain, root_aabb, gravity_center, depth
+        ))));
+    }
+}
+
+fn fluid_mesh_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+
+    mut fluids: Query<(Entity, &mut FluidComponent)>,
+) {
+    for (renderer_entity, mut fluid) in &mut fluids {
+        let fluid = &mut *fluid;
+
+        for (path, chunk) in &mut fluid.chunks {
+            let Some(mut out) = chunk.mesh_task.take_if_finished()
+            else { continue; };
+            let entity = *chunk.entity.get_or_insert_with(|| {
+                let chunk_aabb = path.get_aabb(fluid.root_aabb);
+                let chunk_origin = marching_cubes::mesh_origin(path, fluid.root_aabb);
+                commands.spawn((
+                    Transform64Bundle {
+                        local: Transform64::from_translation(chunk_origin),
+                        ..default()
+                    },
+                    VisibilityBundle::default(),
+                    fluid.material.clone(),
+                    NotShadowCaster,
+                    Culling64Bundle::new(chunk_aabb.translated(-chunk_origin)),
+                )).set_parent(renderer_entity).id()
+            });
+
+            let current_mesh = chunk.mesh.take()
+                .filter(|handle| meshes.contains(handle));
+            chunk.mesh = if out.vertices.is_empty() {
+                None
+            }
+            else if let Some(handle) = current_mesh {
+                out.write_to_mesh(meshes.get_mut(&handle).expect("Checked above"));
+                Some(handle)
+            }
+            else {
+                Some(meshes.add(out.into_mesh()))
+            };
+
+            if let Some(handle) = &chunk.mesh {
+                commands.entity(entity).try_insert(handle.clone());
+            }
+            else {
+                commands.entity(entity).remove::<Handle<Mesh>>();
+            }
+        }
+
+        // Waits for all chunks so the surface is never made of meshes of
+        // different steps for long
+        let meshing = fluid.chunks.values().any(|chunk| chunk.mesh_task.is_some());
+        if !fluid.should_update_meshes || meshing {
+            continue;
+        }
+        let Some(current) = &fluid.fluid
+        else { continue; };
+        fluid.should_update_meshes = false;
+
+        let subdivs = fluid.preset.depth.saturating_sub(fluid.preset.chunk_depth);
+        for path in CellPath::all_iter(fluid.preset.chunk_depth.min(fluid.preset.depth)) {
+            // Meshes are relative to the chunk's origin
+            let root_aabb = fluid.root_aabb;
+            let current = Arc::clone(current);
+            let chunk_path = path.clone();
+            fluid.chunks.entry(path).or_default().mesh_task = Some(task_runner::spawn(move || {
+                let mut out = marching_cubes::Out::new(true, true);
+                let view = ChunkView::from_root(&current, chunk_path);
+                // The material gives the color and transparency
+                fluid_surface::run(
+                    &mut out, &view, root_aabb, subdivs, Color::WHITE,
+                );
+                out
+            }));
+        }
+    }
+}
@@ -25,7 +25,7 @@ impl Generator for SphereGenerator {
         // let rs = svo.simplify();
         // log::trace!("Simplified svo: {rs}");
         svo::svo_from_sdf(move |_| false, move |sp| {
-            let dist = sp.length() - radius;
+            let dist = svo::primitives::sphere(*sp, radius);
             let material = if dist < 0. {
                 global_material
             } else {
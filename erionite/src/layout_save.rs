@@ -0,0 +1,114 @@
+use std::path::{Path, PathBuf};
+
+use bevy::{app::AppExit, math::DVec3, prelude::*};
+use doprec::Transform64;
+use serde::{Deserialize, Serialize};
+use utils::{ApproxEq, RonFileError, Tolerance};
+
+use crate::preset::PendingPreset;
+use crate::svo_renderer::{ChunkComponent, ChunkLayout, SvoRendererComponent};
+
+/// Folder (relative to the working directory) where layouts are saved
+pub const LAYOUT_SAVES_FOLDER: &str = "saves";
+
+/// Saves the chunk layouts of all renderers and the camera position when the
+/// app exits, so that the next run with the same preset can restore them
+/// (see [SvoRendererComponentOptions::initial_layout](crate::svo_renderer::SvoRendererComponentOptions::initial_layout))
+#[derive(Default)]
+pub struct LayoutSavePlugin;
+
+impl Plugin for LayoutSavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Last,
+            save_layout_system
+                .run_if(on_event::<AppExit>())
+                .run_if(resource_exists::<LayoutSaveTarget>),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SavedLayout {
+    pub camera_position: Option<[f64; 3]>,
+    /// Layouts of the renderers along with their position, which identifies
+    /// them as planets don't move
+    pub renderers: Vec<([f64; 3], ChunkLayout)>,
+}
+
+impl SavedLayout {
+    pub fn load(path: &Path) -> Result<Self, RonFileError> {
+        utils::load_ron(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RonFileError> {
+        if let Some(folder) = path.parent() {
+            std::fs::create_dir_all(folder)?;
+        }
+        utils::save_ron(path, self, true)
+    }
+
+    pub fn camera_position(&self) -> Option<DVec3> {
+        self.camera_position.map(DVec3::from_array)
+    }
+
+    /// Layout of the renderer at the given position
+    pub fn renderer_layout(&self, position: DVec3) -> Option<&ChunkLayout> {
+        self.renderers.iter()
+            .find(|(pos, _)| DVec3::from_array(*pos).approx_eq(&position, Tolerance::Absolute(1e-3)))
+            .map(|(_, layout)| layout)
+    }
+}
+
+/// File the layout is saved to on exit, inserted once the world is spawned
+#[derive(Resource, Debug, Clone)]
+pub struct LayoutSaveTarget {
+    pub path: PathBuf,
+}
+
+impl LayoutSaveTarget {
+    /// Target of the given preset, None if its name is unknown
+    pub fn for_preset(preset: &PendingPreset) -> Option<Self> {
+        Some(Self {
+            path: Path::new(LAYOUT_SAVES_FOLDER)
+                .join(format!("{}.layout.ron", preset.name()?)),
+        })
+    }
+
+    /// Loads the saved layout, None if there is none or it could not be read
+    pub fn load(&self) -> Option<SavedLayout> {
+        match SavedLayout::load(&self.path) {
+            Ok(layout) => Some(layout),
+            Err(e) if e.is_not_found() => None,
+            Err(e) => {
+                log::warn!("Ignoring layout save {:?}: {e}", self.path);
+                None
+            },
+        }
+    }
+}
+
+fn save_layout_system(
+    target: Res<LayoutSaveTarget>,
+    camera: Res<crate::Cam>,
+
+    transforms: Query<&Transform64>,
+    renderers: Query<(&SvoRendererComponent, &Transform64)>,
+    chunks: Query<&ChunkComponent>,
+) {
+    let saved = SavedLayout {
+        camera_position: camera.entity
+            .and_then(|entity| transforms.get(entity).ok())
+            .map(|transform| transform.translation.to_array()),
+        renderers: renderers.iter()
+            .filter_map(|(renderer, transform)| Some((
+                transform.translation.to_array(),
+                renderer.chunk_layout(&chunks)?,
+            )))
+            .collect(),
+    };
+
+    match saved.save(&target.path) {
+        Ok(()) => log::info!("Saved chunk layout to {:?}", target.path),
+        Err(e) => log::warn!("Could not save chunk layout: {e}"),
+    }
+}
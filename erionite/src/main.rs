@@ -5,16 +5,25 @@ mod generator;
 mod svo_renderer;
 use svo_renderer::{ChunkComponent, SvoRendererBundle, SvoRendererComponent, SvoRendererComponentOptions};
 mod svo_provider;
-use svo_provider::generator_svo_provider;
+use svo_provider::{edit_overlay_provider::{EditOperation, TerrainEdit}, SvoProviderComponent};
+mod player;
+mod preset;
+mod layout_save;
+mod fluid;
+mod settings;
+mod replay;
 pub mod task_runner;
 
-use bevy::{core_pipeline::{bloom::{BloomCompositeMode, BloomSettings}, Skybox}, diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin}, ecs::system::EntityCommands, input::mouse::{MouseMotion, MouseWheel}, math::DVec3, pbr::{CascadeShadowConfigBuilder, DirectionalLightShadowMap, NotShadowCaster, NotShadowReceiver}, prelude::*, render::mesh::{SphereKind, SphereMeshBuilder}, window::{CursorGrabMode, PrimaryWindow}};
+use bevy::{core_pipeline::{bloom::{BloomCompositeMode, BloomSettings}, Skybox}, diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin}, ecs::system::EntityCommands, input::mouse::{MouseMotion, MouseWheel}, math::{DQuat, DVec3}, pbr::{CascadeShadowConfigBuilder, NotShadowCaster, NotShadowReceiver}, prelude::*, render::{mesh::{SphereKind, SphereMeshBuilder}, view::RenderLayers}, window::{CursorGrabMode, PrimaryWindow}};
 use utils::DAabb;
 use doprec::*;
-use rapier_overlay::{rapier::geometry::ColliderBuilder, *};
+use serde::{Deserialize, Serialize};
+use rapier_overlay::{rapier::{dynamics::CoefficientCombineRule, geometry::ColliderBuilder}, *};
 
 fn main() {
-    utils::logging::setup_basic_logging().unwrap();
+    utils::logging::LoggingConfig::new()
+        .env_filters("ERIONITE_LOG").unwrap()
+        .apply().unwrap();
 
     App::new()
         .add_plugins(bevy::diagnostic::FrameTimeDiagnosticsPlugin)
@@ -28,16 +37,31 @@ fn main() {
             nbody::NBodyPlugin,
             DoprecPlugin::default(),
             RapierPlugin::default(),
+            player::PlayerPlugin,
+            preset::PresetPlugin,
+            layout_save::LayoutSavePlugin,
+            fluid::FluidPlugin,
+            settings::SettingsPlugin,
+            replay::ReplayPlugin,
         ))
 
-        .add_systems(Startup, setup_system)
-        .add_systems(Update, (camera_system, update_debug_text_system))
+        .add_systems(Update, (
+            setup_system.run_if(resource_exists::<preset::PendingPreset>),
+            (
+                camera_system.run_if(not(replay::is_replaying)),
+                camera_action_system.after(replay::ReplaySystems::Replay),
+            ).chain().before(player::PlayerSystems).before(replay::ReplaySystems::Record),
+            surface_teleport_system.after(camera_system),
+            update_debug_text_system,
+            planet_spin_system,
+        ))
 
-        .insert_resource(DirectionalLightShadowMap { size: 2048 })
         .insert_resource(RapierConfig {
             gravity: DVec3::ZERO,
+            ..default()
         })
         .init_resource::<Cam>()
+        .add_event::<CameraAction>()
         
         .run();
 }
@@ -50,6 +74,8 @@ pub struct Cam {
     /// Changed by the cam controller
     /// Changing it manually have no effect
     pub gravity_redirect_enabled: bool,
+    /// Player entity when in player mode, managed by the [player] module
+    pub player: Option<Entity>,
 }
 
 impl Cam {
@@ -62,6 +88,7 @@ impl FromWorld for Cam {
             speed: 50.,
             forced_gravity_toggle: false,
             gravity_redirect_enabled: false,
+            player: None,
         }
     }
 }
@@ -69,8 +96,30 @@ impl FromWorld for Cam {
 #[derive(Component)]
 struct DebugTextComponent;
 
+/// Rotates the planet's renderer, see [preset::PlanetPreset::spin]
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+struct PlanetSpin {
+    /// In radians per second
+    angular_velocity: DVec3,
+}
+
+fn planet_spin_system(
+    time: Res<Time>,
+    mut planets: Query<(&PlanetSpin, &mut Transform64)>,
+) {
+    let dt = time.delta_seconds_f64();
+    for (spin, mut transform) in &mut planets {
+        let rotation = DQuat::from_scaled_axis(spin.angular_velocity * dt);
+        transform.rotation = (rotation * transform.rotation).normalize();
+    }
+}
+
+/// Spawns the world once the picked [preset::PlanetPreset] is loaded
+#[allow(clippy::too_many_arguments)]
 fn setup_system(
     gravity_cfg: Res<nbody::GravityConfig>,
+    pending_preset: Res<preset::PendingPreset>,
+    presets: Res<Assets<preset::PlanetPreset>>,
 
     mut commands: Commands,
     mut materials: ResMut<Assets<StandardMaterial>>,
@@ -79,18 +128,21 @@ fn setup_system(
 
     assets: Res<AssetServer>,
 ) {
-    let subdivs = 17u32;
-    let aabb_size = 2f64.powi((subdivs-2) as i32);
-    let radius = aabb_size / 4.;
-    let aabb: DAabb = DAabb::new_center_size(DVec3::ZERO, DVec3::splat(aabb_size));
-    // let volume = (radius.powi(3) * std::f64::consts::PI * 4.) / 3.;
-    // let mass = volume / 1_000_000.;
-    let target_surface_gravity = 9.8;
-    let mass = (target_surface_gravity / gravity_cfg.gravity_constant) * radius.powi(2);
+    let Some(preset) = pending_preset.get(&presets, &assets)
+    else { return; };
+    commands.remove_resource::<preset::PendingPreset>();
 
-    log::info!("AABB Size    : {aabb_size}");
-    log::info!("Planet radius: {radius}");
-    log::info!("Planet's mass: {radius}");
+    let save_target = layout_save::LayoutSaveTarget::for_preset(&pending_preset);
+    let saved_layout = save_target.as_ref().and_then(|target| target.load());
+    if let Some(save_target) = save_target {
+        commands.insert_resource(save_target);
+    }
+
+    let planet_pos = DVec3::from_array(preset.position);
+    spawn_planet(
+        &mut commands, &mut materials, &gravity_cfg, &preset, planet_pos,
+        saved_layout.as_ref(),
+    );
 
     commands.spawn((
         PbrBundle {
@@ -124,89 +176,57 @@ fn setup_system(
             ..default()
         },
         ..default()
-    }).insert(Transform64Bundle::default());
-
-    let mat = materials.add(StandardMaterial {
-        perceptual_roughness: 0.8,
-        metallic: 0.,
-        ..default()
-    });
-
-    commands.spawn(SvoRendererBundle {
-        transform: Transform64Bundle::default(),
-        svo_render: SvoRendererComponent::new(SvoRendererComponentOptions {
-            max_subdivs: subdivs,
-            min_subdivs: 4,
-            chunk_falloff_multiplier: 30.,
-            
-            chunk_split_subdivs: 6,
-            chunk_merge_subdivs: 5,
-
-            root_aabb: aabb,
-            on_new_chunk: Some(Box::new({
-                let mat = mat.clone();
-                move |mut commands: EntityCommands<'_>| {
-                    commands.insert(mat.clone());
-                }
-            }) as Box<_>),
-
-            ..default()
-        }),
-        svo_provider: generator_svo_provider::GeneratorSvoProvider::new(
-            generator::PlanetGenerator {
-                radius,
-                seed: 1,
-            },
-            // generator::SphereGenerator {
-            //     radius,
-            //     material: svo::TerrainCellKind::Pink,
-            // },
-            aabb
-        ).into(),
     }).insert((
-        nbody::Massive {
-            mass,
-        },
-        nbody::Attractor::default(),
+        Transform64Bundle::default(),
+        RenderLayers::default().with(svo_renderer::SHADOW_PROXY_RENDER_LAYER),
     ));
 
-    let cam_pos = DVec3::new(
-        0.,
-        0.,
-        radius+200.
-    );
+    let cam_pos = saved_layout.as_ref()
+        .and_then(|saved| saved.camera_position())
+        .unwrap_or(planet_pos + DVec3::new(
+            0.,
+            0.,
+            preset.radius()+200.
+        ));
     // let cam_pos = DVec3::new(0., radius * 5., 0.);
     
     // camera
-    camera.entity = Some(commands
+    let mut camera_commands = commands
         .spawn(Camera3dBundle {
             camera: Camera {
                 hdr: true,
                 ..default()
             },
             ..default()
-        })
+        });
+    camera_commands
         .insert(Transform64Bundle {
             local: Transform64::from_translation(cam_pos)
-                .looking_at(DVec3::NEG_X + cam_pos, cam_pos.normalize()),
+                .looking_at(DVec3::NEG_X + cam_pos, (cam_pos - planet_pos).normalize()),
             ..default()
         })
         .insert((
             FloatingOrigin,
             nbody::GravityFieldSample::default(),
+            // Only used to orient the camera, doesn't need to be accurate
+            nbody::SampleQuality {
+                opening_angle: Some(1.),
+                ..default()
+            },
             BloomSettings {
                 intensity: 0.02,
                 composite_mode: BloomCompositeMode::EnergyConserving,
 
                 ..default()
             },
-            Skybox {
-                image: assets.load("images/skybox/skybox.ktx2"),
-                brightness: 1000.0,
-            },
-        ))
-        .id()
-    );
+        ));
+    if let Some(skybox) = &preset.skybox {
+        camera_commands.insert(Skybox {
+            image: assets.load(skybox.image.clone()),
+            brightness: skybox.brightness,
+        });
+    }
+    camera.entity = Some(camera_commands.id());
 
     let root_uinode = commands
         .spawn(NodeBundle {
@@ -242,6 +262,104 @@ fn setup_system(
     }).set_parent(root_uinode);
 }
 
+/// Spawns the renderer of the planet (and of its moons) at the given position,
+/// restoring its chunks from the saved layout if there is one
+fn spawn_planet(
+    commands: &mut Commands,
+    materials: &mut Assets<StandardMaterial>,
+    gravity_cfg: &nbody::GravityConfig,
+    preset: &preset::PlanetPreset,
+    position: DVec3,
+    saved_layout: Option<&layout_save::SavedLayout>,
+) {
+    let subdivs = preset.subdivs;
+    let aabb_size = preset.aabb_size();
+    let radius = preset.radius();
+    let aabb: DAabb = DAabb::new_center_size(DVec3::ZERO, DVec3::splat(aabb_size));
+    let mass = preset.mass(aabb, gravity_cfg.gravity_constant);
+
+    log::info!("Planet at {position:?}");
+    log::info!("AABB Size    : {aabb_size}");
+    log::info!("Planet radius: {radius}");
+    log::info!("Planet's mass: {mass}");
+
+    let mat = materials.add(preset.material.to_material());
+
+    let mut planet = commands.spawn(SvoRendererBundle {
+        transform: Transform64Bundle {
+            local: Transform64::from_translation(position),
+            ..default()
+        },
+        svo_render: SvoRendererComponent::new(SvoRendererComponentOptions {
+            max_subdivs: subdivs,
+            min_subdivs: preset.renderer.min_subdivs,
+            chunk_falloff_multiplier: preset.renderer.chunk_falloff_multiplier,
+            
+            chunk_split_subdivs: preset.renderer.chunk_split_subdivs,
+            chunk_merge_subdivs: preset.renderer.chunk_merge_subdivs,
+            subdivs_hysteresis: preset.renderer.subdivs_hysteresis,
+            min_state_duration: preset.renderer.min_state_duration,
+            collider_simplification: preset.renderer.collider_simplification,
+            collider_activation_radius: preset.renderer.collider_activation_radius,
+            shadow_subdivs_reduction: preset.renderer.shadow_subdivs_reduction,
+            max_simplification_error: preset.renderer.max_simplification_error,
+            uv_scale: preset.renderer.uv_scale,
+            prefetch: (preset.renderer.prefetch_lookahead > 0.)
+                .then(|| svo_renderer::PrefetchOptions {
+                    lookahead: preset.renderer.prefetch_lookahead,
+                    ..default()
+                }),
+            far_view: Some(default()),
+
+            root_aabb: aabb,
+            on_new_chunk: Some(Box::new({
+                let mat = mat.clone();
+                move |mut commands: EntityCommands<'_>| {
+                    commands.insert(mat.clone());
+                }
+            }) as Box<_>),
+            on_new_shadow_proxy: Some(Box::new({
+                let mat = mat.clone();
+                move |mut commands: EntityCommands<'_>| {
+                    commands.insert(mat.clone());
+                }
+            }) as Box<_>),
+            initial_layout: saved_layout
+                .and_then(|saved| saved.renderer_layout(position))
+                .cloned(),
+
+            ..default()
+        }),
+        svo_provider: preset.svo_provider(aabb),
+    }).insert((
+        nbody::Massive {
+            mass,
+        },
+        nbody::Attractor::default(),
+    ));
+    let angular_velocity = DVec3::from_array(preset.spin);
+    if angular_velocity != DVec3::ZERO {
+        planet.insert(PlanetSpin { angular_velocity });
+    }
+    planet.insert(settings::RendererLodBase {
+        max_subdivs: subdivs,
+        chunk_falloff_multiplier: preset.renderer.chunk_falloff_multiplier,
+    });
+    if let Some(fluid_preset) = &preset.fluid {
+        planet.insert(fluid::FluidComponent::new(
+            fluid_preset.clone(), aabb, radius, materials,
+        ));
+    }
+
+    for moon in &preset.moons {
+        spawn_planet(
+            commands, materials, gravity_cfg, moon,
+            position + DVec3::from_array(moon.position),
+            saved_layout,
+        );
+    }
+}
+
 fn update_debug_text_system(
     time: Res<Time>,
     diagnostics: Res<DiagnosticsStore>,
@@ -289,6 +407,27 @@ fn update_debug_text_system(
         }
     }
 
+    let svo_memory = diagnostics.get(&svo_renderer::SVO_MEMORY_USAGE_DIAG)
+        .and_then(|diag| diag.value())
+        .unwrap_or_default();
+    let svo_cells = diagnostics.get(&svo_renderer::SVO_CELL_COUNT_DIAG)
+        .and_then(|diag| diag.value())
+        .unwrap_or_default();
+    let svo_value = |path| diagnostics.get(path)
+        .and_then(|diag| diag.value())
+        .unwrap_or_default();
+    let svo_depth = svo_value(&svo_renderer::SVO_MAX_DEPTH_DIAG);
+    let svo_packed = svo_value(&svo_renderer::SVO_PACKED_RATIO_DIAG);
+    let svo_branching = svo_value(&svo_renderer::SVO_AVERAGE_BRANCHING_DIAG);
+    let diag_value = |path| diagnostics.get(path)
+        .and_then(|diag| diag.smoothed())
+        .unwrap_or_default();
+    let physics_step = diag_value(&PHYSICS_STEP_DURATION);
+    let physics_bodies = diag_value(&PHYSICS_ACTIVE_BODIES);
+    let physics_contacts = diag_value(&PHYSICS_CONTACT_PAIRS);
+    let solver_utilization = diag_value(&PHYSICS_SOLVER_UTILIZATION);
+    let solver_threads = diag_value(&PHYSICS_SOLVER_THREADS);
+
     let cam_pos = cam_transform.translation;
     let cam_speed = camera.speed;
 
@@ -297,24 +436,176 @@ fn update_debug_text_system(
     grav_info += ", grav_redirect: ";
     grav_info += if camera.gravity_redirect_enabled { "enabled" } else { "disabled" };
     grav_info += "\n";
+    grav_info += "Player mode (toggle with p): ";
+    grav_info += if camera.player.is_some() { "enabled" } else { "disabled" };
+    grav_info += "\n";
 
     let mut debug_text = debug_text.single_mut();
     debug_text.sections[0].value = format!("\
 {fps:.1} fps - {frame_time:.3} ms/frame \n\
 Chunks: {chunk_count}, gen {chunk_gen_count}, mesh {chunk_mesh_gen_count}, col {chunk_col_gen_count} \n\
+Svos: {svo_memory:.1} MiB, {svo_cells} cells \n\
+Svo trees: depth {svo_depth}, {svo_packed:.1}% packed, branching {svo_branching:.2} \n\
+Physics: {physics_step:.2} ms/step, {physics_bodies} active bodies, {physics_contacts} contacts \n\
+Solver: {solver_utilization:.0}% of {solver_threads} threads \n\
 Camera: speed {cam_speed:.3}, position {cam_pos:.3?} \n\
 {grav_info}
     ");
 }
 
+/// Height above the terrain the camera is put at by [surface_teleport_system]
+const SURFACE_TELEPORT_HEIGHT: f64 = 2.;
+
+/// Moves the free camera to the terrain surface under it, standing upright,
+/// when T is pressed
+fn surface_teleport_system(
+    kb_input: Res<ButtonInput<KeyCode>>,
+    camera: Res<Cam>,
+
+    surface: svo_renderer::TerrainSurface,
+    renderers: Query<(Entity, &GlobalTransform64), With<SvoRendererComponent>>,
+    mut transforms: Query<&mut Transform64>,
+) {
+    if !kb_input.just_pressed(KeyCode::KeyT) || camera.player.is_some() {
+        return;
+    }
+    let Some(mut camera_trans) = camera.entity
+        .and_then(|entity| transforms.get_mut(entity).ok())
+    else { return; };
+
+    let camera_pos = camera_trans.translation;
+    let closest_renderer = renderers.iter()
+        .min_by(|(_, a), (_, b)| a.translation().distance_squared(camera_pos)
+            .total_cmp(&b.translation().distance_squared(camera_pos)))
+        .map(|(entity, _)| entity);
+    let Some(point) = closest_renderer
+        .and_then(|renderer| surface.surface_under(renderer, camera_pos))
+    else {
+        log::warn!("No loaded terrain under the camera");
+        return;
+    };
+
+    *camera_trans = point.transform();
+    camera_trans.translation += point.up * SURFACE_TELEPORT_HEIGHT;
+}
+
+/// Discrete actions of the free camera, sent by [camera_system] from the
+/// inputs (or by the [replay] module) and applied by [camera_action_system]
+#[derive(Event, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CameraAction {
+    /// Toggles [SvoRendererComponentOptions::enable_subdivs_update] of every
+    /// renderer
+    ToggleSubdivsUpdate,
+    /// Toggles [Cam::forced_gravity_toggle]
+    ToggleForcedGravity,
+    SpawnBall {
+        position: [f64; 3],
+    },
+    /// Digs or fills the terrain where the ray hits it, see
+    /// [svo_provider::SvoProvider::edit]
+    EditTerrain {
+        origin: [f64; 3],
+        direction: [f64; 3],
+        dig: bool,
+    },
+}
+
+/// Radius of the spheres of terrain changed by [CameraAction::EditTerrain]
+const TERRAIN_EDIT_RADIUS: f64 = 1.5;
+/// Max distance of the terrain edited by [CameraAction::EditTerrain]
+const TERRAIN_EDIT_MAX_DISTANCE: f64 = 50.;
+
 #[allow(clippy::too_many_arguments)]
-fn camera_system(
+fn camera_action_system(
     mut commands: Commands,
+    mut actions: EventReader<CameraAction>,
 
-    mut camera_query: Query<(&mut Transform64, &nbody::GravityFieldSample)>,
+    mut camera: ResMut<Cam>,
     mut renderers: Query<&mut SvoRendererComponent>,
+    mut providers: Query<(&mut SvoProviderComponent, &GlobalTransform64), With<SvoRendererComponent>>,
+    rapier_context: Res<RapierContext>,
+
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for action in actions.read() {
+        match *action {
+            CameraAction::ToggleSubdivsUpdate => {
+                for mut r in &mut renderers {
+                    r.options.enable_subdivs_update = !r.options.enable_subdivs_update;
+                }
+            },
+            CameraAction::ToggleForcedGravity => {
+                camera.forced_gravity_toggle = !camera.forced_gravity_toggle;
+            },
+            CameraAction::SpawnBall { position } => {
+                log::info!("Spawning ball !");
+                let radius = 0.25;
+                let mass = 5.;
+                commands.spawn((
+                    Transform64Bundle {
+                        local: Transform64::from_translation(DVec3::from_array(position)),
+                        ..default()
+                    },
+                    VisibilityBundle::default(),
+                    meshes.add(SphereMeshBuilder::new(radius, SphereKind::Ico {
+                        subdivisions: 5,
+                    }).build()),
+                    materials.add(StandardMaterial {
+                        perceptual_roughness: 0.2,
+                        metallic: 0.8,
+                        base_color: Color::GOLD,
+                        ..default()
+                    }),
+                    ColliderBundle {
+                        mass: ColliderMassComp { mass },
+                        ..ColliderBundle::from(
+                            ColliderBuilder::ball(radius as f64)
+                                .restitution(0.6)
+                                .restitution_combine_rule(CoefficientCombineRule::Max)
+                        )
+                    },
+                    RigidBodyBundle::dynamic(),
+                    nbody::GravityFieldSample::default(),
+                    nbody::Massive::default(),
+                    nbody::Attracted,
+                ));
+            },
+            CameraAction::EditTerrain { origin, direction, dig } => {
+                let origin = DVec3::from_array(origin);
+                let direction = DVec3::from_array(direction);
+                let Some((_, toi)) = rapier_context.cast_ray(
+                    origin, direction, TERRAIN_EDIT_MAX_DISTANCE, true,
+                    QueryFilter::new().exclude_dyanmic(),
+                )
+                else { continue; };
+                let hit = origin + direction * toi;
+
+                for (mut provider, renderer_trans) in &mut providers {
+                    let edit = TerrainEdit {
+                        operation: if dig {
+                            EditOperation::Dig
+                        }
+                        else {
+                            EditOperation::Fill(svo::TerrainCellKind::Stone)
+                        },
+                        center: renderer_trans.affine().inverse().transform_point3(hit),
+                        radius: TERRAIN_EDIT_RADIUS,
+                    };
+                    provider.edit(&edit);
+                }
+            },
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn camera_system(
+    mut camera_query: Query<(&mut Transform64, &nbody::GravityFieldSample)>,
+    mut actions: EventWriter<CameraAction>,
 
     mut camera: ResMut<Cam>,
+    settings_menu: Res<settings::SettingsMenu>,
 
     mut mouse_move_events: EventReader<MouseMotion>,
     mut mouse_wheel_events: EventReader<MouseWheel>,
@@ -323,8 +614,6 @@ fn camera_system(
     mouse_input: Res<ButtonInput<MouseButton>>,
 
     time: Res<Time>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
 
     mut q_windows: Query<&mut Window, With<PrimaryWindow>>,
 ) {
@@ -338,7 +627,8 @@ fn camera_system(
         camera_gravity,
     ) = camera_query.get_mut(entity).unwrap();
 
-    if mouse_input.just_pressed(MouseButton::Left) {
+    // The cursor is needed to use the settings menu
+    if mouse_input.just_pressed(MouseButton::Left) && !settings_menu.open {
         window.cursor.grab_mode = CursorGrabMode::Confined;
         window.cursor.visible = false;
     }
@@ -348,43 +638,27 @@ fn camera_system(
     }
 
     if kb_input.just_pressed(KeyCode::KeyR) {
-        for mut r in &mut renderers {
-            r.options.enable_subdivs_update = !r.options.enable_subdivs_update;
-        }
+        actions.send(CameraAction::ToggleSubdivsUpdate);
     }
 
     if kb_input.just_pressed(KeyCode::KeyG) {
-        camera.forced_gravity_toggle = !camera.forced_gravity_toggle;
+        actions.send(CameraAction::ToggleForcedGravity);
     }
 
     if kb_input.just_pressed(KeyCode::KeyB) {
-        log::info!("Spawning ball !");
-        let radius = 0.25;
-        let mass = 5.;
-        commands.spawn((
-            Transform64Bundle {
-                local: Transform64::from_translation(camera_trans.translation),
-                ..default()
-            },
-            VisibilityBundle::default(),
-            meshes.add(SphereMeshBuilder::new(radius, SphereKind::Ico {
-                subdivisions: 5,
-            }).build()),
-            materials.add(StandardMaterial {
-                perceptual_roughness: 0.2,
-                metallic: 0.8,
-                base_color: Color::GOLD,
-                ..default()
-            }),
-            ColliderBundle {
-                mass: ColliderMassComp { mass },
-                ..ColliderBundle::from(ColliderBuilder::ball(radius as f64))
-            },
-            RigidBodyBundle::dynamic(),
-            nbody::GravityFieldSample::default(),
-            nbody::Massive::default(),
-            nbody::Attracted,
-        ));
+        actions.send(CameraAction::SpawnBall {
+            position: camera_trans.translation.to_array(),
+        });
+    }
+
+    for (key, dig) in [(KeyCode::KeyX, true), (KeyCode::KeyC, false)] {
+        if kb_input.just_pressed(key) {
+            actions.send(CameraAction::EditTerrain {
+                origin: camera_trans.translation.to_array(),
+                direction: camera_trans.forward().to_array(),
+                dig,
+            });
+        }
     }
 
     for mwe in mouse_wheel_events.read() {
@@ -396,7 +670,7 @@ fn camera_system(
         }
     }
 
-    if mouse_input.pressed(MouseButton::Left) {
+    if mouse_input.pressed(MouseButton::Left) && !settings_menu.open {
         for me in mouse_move_events.read() {
             let mov = me.delta.as_dvec2() / -300.;
 
@@ -429,6 +703,11 @@ fn camera_system(
         camera_trans.rotate_local_z((rot_speed * dir).clamp(-angle.abs(), angle.abs()));
     }
 
+    // The player module handles movements in player mode
+    if camera.player.is_some() {
+        return;
+    }
+
     let mut movement = DVec3::ZERO;
     if kb_input.pressed(KeyCode::KeyW) {
         movement += forward;
@@ -0,0 +1,220 @@
+use bevy::{math::{DQuat, DVec3}, prelude::*};
+use doprec::{Transform64, Transform64Bundle};
+use rapier_overlay::{rapier::{dynamics::RigidBodyType, geometry::ColliderBuilder}, *};
+use utils::IsZeroApprox;
+
+use crate::Cam;
+
+/// Height of the camera above the center of the player's capsule
+const PLAYER_EYE_HEIGHT: f64 = 0.7;
+
+#[derive(SystemSet, Debug, PartialEq, Eq, Default, Hash, Clone, Copy)]
+pub struct PlayerSystems;
+
+/// Adds a 'planet walk' mode toggled with P, in which the camera is attached to
+/// a capsule character walking on the chunks colliders with the gravity given
+/// by nbody
+#[derive(Default)]
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (
+            player_toggle_system,
+            player_input_system,
+            player_camera_follow_system,
+        ).chain().in_set(PlayerSystems));
+
+        app.add_systems(FixedUpdate, (
+            player_physics_system
+                .after(nbody::GravitySystems)
+                .before(PhysicsStepSystems),
+            player_after_physics_system
+                .after(PhysicsStepSystems),
+        ));
+    }
+}
+
+#[derive(Component, Debug, Clone, derivative::Derivative)]
+#[derivative(Default)]
+pub struct PlayerComponent {
+    #[derivative(Default(value = "10."))]
+    pub speed: f64,
+    #[derivative(Default(value = "6."))]
+    pub jump_speed: f64,
+
+    /// Wanted velocity (in world space) given by the inputs
+    input_velocity: DVec3,
+    jump_requested: bool,
+    velocity: DVec3,
+    /// Opposite of the last known gravity direction
+    #[derivative(Default(value = "DVec3::Y"))]
+    up: DVec3,
+}
+
+impl PlayerComponent {
+    pub fn velocity(&self) -> DVec3 {
+        self.velocity
+    }
+
+    pub fn up(&self) -> DVec3 {
+        self.up
+    }
+}
+
+fn player_toggle_system(
+    mut commands: Commands,
+
+    mut camera: ResMut<Cam>,
+    camera_query: Query<&Transform64, Without<PlayerComponent>>,
+
+    kb_input: Res<ButtonInput<KeyCode>>,
+) {
+    if !kb_input.just_pressed(KeyCode::KeyP) {
+        return;
+    }
+
+    if let Some(player) = camera.player.take() {
+        log::info!("Leaving player mode");
+        commands.entity(player).despawn_recursive();
+        return;
+    }
+
+    let Some(camera_transform) = camera.entity
+        .and_then(|entity| camera_query.get(entity).ok())
+    else { return; };
+
+    log::info!("Entering player mode");
+    let up = camera_transform.up();
+    camera.player = Some(commands.spawn((
+        Transform64Bundle {
+            local: Transform64 {
+                translation: camera_transform.translation - up * PLAYER_EYE_HEIGHT,
+                rotation: DQuat::from_rotation_arc(DVec3::Y, up),
+                ..default()
+            },
+            ..default()
+        },
+        ColliderBundle::from(ColliderBuilder::capsule_y(0.5, 0.4).mass(80.)),
+        RigidBodyBundle::new(RigidBodyType::KinematicPositionBased),
+        CharacterControllerBundle {
+            comp: CharacterControllerComp {
+                up,
+                ..default()
+            },
+            ..default()
+        },
+        nbody::GravityFieldSample::default(),
+        PlayerComponent {
+            up,
+            ..default()
+        },
+    )).id());
+}
+
+fn player_input_system(
+    camera: Res<Cam>,
+    camera_query: Query<&Transform64, Without<PlayerComponent>>,
+    mut player_query: Query<&mut PlayerComponent>,
+
+    kb_input: Res<ButtonInput<KeyCode>>,
+) {
+    let Some(mut player) = camera.player
+        .and_then(|entity| player_query.get_mut(entity).ok())
+    else { return; };
+    let Some(camera_transform) = camera.entity
+        .and_then(|entity| camera_query.get(entity).ok())
+    else { return; };
+
+    if kb_input.just_pressed(KeyCode::Space) {
+        player.jump_requested = true;
+    }
+
+    // Project the camera's directions on the ground plane
+    let up = player.up;
+    let forward = camera_transform.forward().reject_from_normalized(up).normalize_or_zero();
+    let left = camera_transform.left().reject_from_normalized(up).normalize_or_zero();
+
+    let mut movement = DVec3::ZERO;
+    if kb_input.pressed(KeyCode::KeyW) {
+        movement += forward;
+    }
+    if kb_input.pressed(KeyCode::KeyS) {
+        movement -= forward;
+    }
+    if kb_input.pressed(KeyCode::KeyA) {
+        movement += left;
+    }
+    if kb_input.pressed(KeyCode::KeyD) {
+        movement -= left;
+    }
+    player.input_velocity = movement.normalize_or_zero() * player.speed;
+}
+
+fn player_camera_follow_system(
+    camera: Res<Cam>,
+    mut camera_query: Query<&mut Transform64, Without<PlayerComponent>>,
+    player_query: Query<(&Transform64, &PlayerComponent)>,
+) {
+    let Some((player_transform, player)) = camera.player
+        .and_then(|entity| player_query.get(entity).ok())
+    else { return; };
+    let Some(mut camera_transform) = camera.entity
+        .and_then(|entity| camera_query.get_mut(entity).ok())
+    else { return; };
+
+    camera_transform.translation =
+        player_transform.translation + player.up * PLAYER_EYE_HEIGHT;
+}
+
+fn player_physics_system(
+    time: Res<Time<Fixed>>,
+
+    mut player_query: Query<(
+        &mut PlayerComponent, &mut Transform64,
+        &mut CharacterControllerComp, &mut CharacterNextTranslationComp,
+        &CharacterResultsComp, &nbody::GravityFieldSample,
+    )>,
+) {
+    let dt = time.delta_seconds_f64();
+
+    for (
+        mut player, mut transform,
+        mut controller, mut next_translation,
+        results, gravity_sample,
+    ) in &mut player_query {
+        let gravity = gravity_sample.field_force(0).unwrap_or_default();
+        if !gravity.is_zero_approx() {
+            player.up = -gravity.normalize();
+        }
+        let up = player.up;
+
+        // Keep the capsule standing along the gravity
+        let rotation = DQuat::from_rotation_arc(transform.up(), up);
+        transform.rotation = rotation * transform.rotation;
+        controller.up = up;
+
+        let vertical_vel = up * player.velocity.dot(up);
+        let sideway_vel = player.velocity - vertical_vel;
+        player.velocity = vertical_vel + sideway_vel.lerp(player.input_velocity, 0.2);
+
+        if player.jump_requested && results.on_ground() {
+            player.velocity += up * player.jump_speed;
+        }
+        player.jump_requested = false;
+
+        player.velocity += gravity * dt;
+
+        next_translation.next_translation = player.velocity * dt;
+    }
+}
+
+fn player_after_physics_system(
+    time: Res<Time<Fixed>>,
+
+    mut player_query: Query<(&mut PlayerComponent, &CharacterResultsComp)>,
+) {
+    for (mut player, results) in &mut player_query {
+        player.velocity = results.translation() / time.delta_seconds_f64();
+    }
+}
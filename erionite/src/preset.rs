@@ -0,0 +1,310 @@
+use bevy::{asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext, LoadState}, prelude::*, reflect::TypePath, utils::BoxedFuture};
+use serde::Deserialize;
+
+use crate::generator::{self, Generator};
+
+/// Folder (in the assets) where presets are looked for
+pub const PRESETS_FOLDER: &str = "presets";
+/// Preset used when none is given on the command line
+pub const DEFAULT_PRESET: &str = "default";
+
+/// Loads the preset whose name is given as first command line argument
+/// (`presets/<name>.planet.ron`) and inserts it as [PendingPreset]
+#[derive(Default)]
+pub struct PresetPlugin;
+
+impl Plugin for PresetPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_asset::<PlanetPreset>()
+            .init_asset_loader::<PlanetPresetLoader>()
+            .add_systems(PreStartup, pick_preset_system);
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum GeneratorPreset {
+    Planet {
+        seed: i64,
+    },
+    Sphere {
+        material: svo::TerrainCellKind,
+    },
+}
+
+impl Default for GeneratorPreset {
+    fn default() -> Self {
+        Self::Planet { seed: 1 }
+    }
+}
+
+/// How the mass of the planet is obtained
+#[derive(Debug, Clone, Deserialize)]
+pub enum PlanetMass {
+    /// Mass giving this gravity at the surface of the planet
+    SurfaceGravity(f64),
+    /// Mass integrated from the terrain generated at the given depth (see
+    /// [svo::Cell::integrate]), multiplied by `density_scale` as densities
+    /// are relative to water
+    Terrain {
+        depth: u32,
+        density_scale: f64,
+    },
+}
+
+impl Default for PlanetMass {
+    fn default() -> Self {
+        Self::SurfaceGravity(9.8)
+    }
+}
+
+/// See [SvoRendererComponentOptions](crate::svo_renderer::SvoRendererComponentOptions)
+#[derive(Debug, Clone, Deserialize, derivative::Derivative)]
+#[derivative(Default)]
+#[serde(default)]
+pub struct RendererPreset {
+    #[derivative(Default(value = "4"))]
+    pub min_subdivs: u32,
+    #[derivative(Default(value = "6"))]
+    pub chunk_split_subdivs: u32,
+    #[derivative(Default(value = "5"))]
+    pub chunk_merge_subdivs: u32,
+    /// See [SvoRendererComponentOptions::subdivs_hysteresis](crate::svo_renderer::SvoRendererComponentOptions::subdivs_hysteresis)
+    #[derivative(Default(value = "0.1"))]
+    pub subdivs_hysteresis: f64,
+    /// See [SvoRendererComponentOptions::min_state_duration](crate::svo_renderer::SvoRendererComponentOptions::min_state_duration)
+    #[derivative(Default(value = "0.5"))]
+    pub min_state_duration: f64,
+    #[derivative(Default(value = "30."))]
+    pub chunk_falloff_multiplier: f64,
+    #[derivative(Default(value = "1"))]
+    pub collider_simplification: u32,
+    /// See [SvoRendererComponentOptions::collider_activation_radius](crate::svo_renderer::SvoRendererComponentOptions::collider_activation_radius)
+    #[derivative(Default(value = "Some(100.)"))]
+    pub collider_activation_radius: Option<f64>,
+    /// See [PrefetchOptions::lookahead](crate::svo_renderer::PrefetchOptions::lookahead),
+    /// 0 disables prefetching
+    #[derivative(Default(value = "3."))]
+    pub prefetch_lookahead: f64,
+    /// See [SvoRendererComponentOptions::shadow_subdivs_reduction](crate::svo_renderer::SvoRendererComponentOptions::shadow_subdivs_reduction)
+    pub shadow_subdivs_reduction: Option<u32>,
+    /// See [SvoRendererComponentOptions::max_simplification_error](crate::svo_renderer::SvoRendererComponentOptions::max_simplification_error)
+    pub max_simplification_error: Option<f64>,
+    /// See [SvoRendererComponentOptions::uv_scale](crate::svo_renderer::SvoRendererComponentOptions::uv_scale)
+    pub uv_scale: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, derivative::Derivative)]
+#[derivative(Default)]
+#[serde(default)]
+pub struct MaterialPreset {
+    #[derivative(Default(value = "[1., 1., 1.]"))]
+    pub base_color: [f32; 3],
+    #[derivative(Default(value = "0.8"))]
+    pub perceptual_roughness: f32,
+    pub metallic: f32,
+}
+
+impl MaterialPreset {
+    pub fn to_material(&self) -> StandardMaterial {
+        let [r, g, b] = self.base_color;
+        StandardMaterial {
+            base_color: Color::rgb(r, g, b),
+            perceptual_roughness: self.perceptual_roughness,
+            metallic: self.metallic,
+            ..default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, derivative::Derivative)]
+#[derivative(Default)]
+#[serde(default)]
+pub struct SkyboxPreset {
+    /// Path of the cubemap in the assets
+    #[derivative(Default(value = "\"images/skybox/skybox.ktx2\".into()"))]
+    pub image: String,
+    #[derivative(Default(value = "1000."))]
+    pub brightness: f32,
+}
+
+/// See [FluidComponent](crate::fluid::FluidComponent)
+#[derive(Debug, Clone, Deserialize, derivative::Derivative)]
+#[derivative(Default)]
+#[serde(default)]
+pub struct FluidPreset {
+    /// Depth of the fluid svo, the terrain is sampled at the same depth
+    #[derivative(Default(value = "6"))]
+    pub depth: u32,
+    /// Depth of the chunks the water surface is meshed in
+    #[derivative(Default(value = "2"))]
+    pub chunk_depth: u32,
+    /// Empty cells closer to the center than this ratio of the planet's
+    /// radius start filled with water
+    #[derivative(Default(value = "1."))]
+    pub sea_level: f64,
+    pub relaxation: svo::FluidRelaxation,
+    #[derivative(Default(value = "[0.1, 0.3, 0.6, 0.6]"))]
+    pub color: [f32; 4],
+    #[derivative(Default(value = "0.1"))]
+    pub perceptual_roughness: f32,
+}
+
+/// Everything needed to spawn a planet, the default value is the planet that
+/// used to be hardcoded
+#[derive(Asset, TypePath, Debug, Clone, Deserialize, derivative::Derivative)]
+#[derivative(Default)]
+#[serde(default)]
+pub struct PlanetPreset {
+    /// Subdivisions of the whole svo, the planet's size is derived from it
+    #[derivative(Default(value = "17"))]
+    pub subdivs: u32,
+    pub generator: GeneratorPreset,
+    pub renderer: RendererPreset,
+    pub mass: PlanetMass,
+    pub material: MaterialPreset,
+    /// Merges smooth parts of the terrain to save memory, no merging if None
+    pub merge_policy: Option<svo::TerrainMergePolicy>,
+    /// No water if None
+    pub fluid: Option<FluidPreset>,
+    /// No skybox if None
+    #[derivative(Default(value = "Some(default())"))]
+    pub skybox: Option<SkyboxPreset>,
+    /// Position of the planet's center, relative to its parent for moons
+    pub position: [f64; 3],
+    /// Angular velocity of the planet around its center, in radians per
+    /// second
+    pub spin: [f64; 3],
+    /// Planets spawned along this one with their own renderer and gravity,
+    /// their skybox is ignored
+    pub moons: Vec<PlanetPreset>,
+}
+
+impl PlanetPreset {
+    pub fn aabb_size(&self) -> f64 {
+        2f64.powi(self.subdivs as i32 - 2)
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.aabb_size() / 4.
+    }
+
+    pub fn mass(&self, aabb: utils::DAabb, gravity_constant: f64) -> f64 {
+        match self.mass {
+            PlanetMass::SurfaceGravity(gravity) =>
+                (gravity / gravity_constant) * self.radius().powi(2),
+            PlanetMass::Terrain { depth, density_scale } => {
+                let terrain = self.generator().generate_chunk(
+                    aabb, &svo::CellPath::new(), depth
+                );
+                terrain.integrate(aabb, aabb).mass * density_scale
+            },
+        }
+    }
+
+    pub fn generator(&self) -> Box<dyn Generator> {
+        let radius = self.radius();
+        match self.generator {
+            GeneratorPreset::Planet { seed } =>
+                Box::new(generator::PlanetGenerator { radius, seed }),
+            GeneratorPreset::Sphere { material } =>
+                Box::new(generator::SphereGenerator { radius, material }),
+        }
+    }
+
+    /// The generated terrain, with the user edits on top of it
+    pub fn svo_provider(&self, aabb: utils::DAabb) -> crate::svo_provider::SvoProviderComponent {
+        use crate::svo_provider::generator_svo_provider::GeneratorSvoProvider;
+        use crate::svo_provider::edit_overlay_provider::EditOverlayProvider;
+
+        fn with_policy<G: Generator + 'static>(
+            provider: GeneratorSvoProvider<G>,
+            policy: Option<svo::TerrainMergePolicy>,
+        ) -> crate::svo_provider::SvoProviderComponent {
+            let aabb = provider.aabb();
+            let provider = match policy {
+                Some(policy) => provider.with_merge_policy(policy),
+                None => provider,
+            };
+            EditOverlayProvider::new(provider, aabb).into()
+        }
+
+        let radius = self.radius();
+        match self.generator {
+            GeneratorPreset::Planet { seed } => with_policy(GeneratorSvoProvider::new(
+                generator::PlanetGenerator { radius, seed }, aabb
+            ), self.merge_policy),
+            GeneratorPreset::Sphere { material } => with_policy(GeneratorSvoProvider::new(
+                generator::SphereGenerator { radius, material }, aabb
+            ), self.merge_policy),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PlanetPresetLoader;
+
+impl AssetLoader for PlanetPresetLoader {
+    type Asset = PlanetPreset;
+    type Settings = ();
+    type Error = utils::RonFileError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<PlanetPreset, utils::RonFileError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["planet.ron"]
+    }
+}
+
+/// The preset picked at startup, removed once the planet is spawned
+#[derive(Resource)]
+pub struct PendingPreset(pub Handle<PlanetPreset>);
+
+impl PendingPreset {
+    /// Name of the preset as given on the command line
+    pub fn name(&self) -> Option<&str> {
+        self.0.path()?
+            .path().file_name()?
+            .to_str()?
+            .strip_suffix(".planet.ron")
+    }
+
+    /// Returns None while the preset is loading, and falls back to the
+    /// default preset if it fails to load
+    pub fn get(
+        &self, presets: &Assets<PlanetPreset>, assets: &AssetServer,
+    ) -> Option<PlanetPreset> {
+        if let Some(preset) = presets.get(&self.0) {
+            return Some(preset.clone());
+        }
+        if assets.load_state(&self.0) == LoadState::Failed {
+            log::error!("Could not load the planet preset, using the default one");
+            return Some(default());
+        }
+        None
+    }
+}
+
+fn pick_preset_system(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+) {
+    let name = std::env::args().nth(1)
+        .unwrap_or_else(|| DEFAULT_PRESET.to_string());
+    log::info!("Planet preset: {name}");
+
+    commands.insert_resource(PendingPreset(
+        assets.load(format!("{PRESETS_FOLDER}/{name}.planet.ron"))
+    ));
+}
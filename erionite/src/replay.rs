@@ -0,0 +1,280 @@
+use std::path::{Path, PathBuf};
+
+use bevy::{math::{DQuat, DVec3}, prelude::*};
+use doprec::Transform64;
+use serde::{Deserialize, Serialize};
+use utils::RonFileError;
+
+use crate::{Cam, CameraAction};
+
+/// File (relative to the working directory) recordings are saved to and
+/// replayed from with the keyboard
+pub const REPLAY_PATH: &str = "replay.ron";
+/// Environment variable with the path of a recording to replay as soon as
+/// the camera is spawned, to reproduce it from a fresh start
+pub const REPLAY_ENV_VAR: &str = "ERIONITE_REPLAY";
+
+/// Records the camera movements and the [CameraAction]s (F5 to start and
+/// stop, saved to [REPLAY_PATH]) and replays them (F6) at the same fixed
+/// update they happened in, to reproduce bugs and performance problems
+/// across machines
+///
+/// The player mode is not recorded, only the camera that follows it.
+#[derive(Default)]
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ReplayState>()
+            .init_resource::<FixedUpdateCount>()
+            .add_systems(FixedFirst, count_fixed_updates_system)
+            .add_systems(Update, (
+                (
+                    replay_control_system,
+                    start_pending_replay_system
+                        .run_if(resource_exists::<PendingReplay>),
+                    replay_system.run_if(is_replaying),
+                ).chain().in_set(ReplaySystems::Replay),
+                record_system
+                    .run_if(is_recording)
+                    .in_set(ReplaySystems::Record),
+            ));
+
+        if let Some(path) = std::env::var_os(REPLAY_ENV_VAR) {
+            app.insert_resource(PendingReplay {
+                path: path.into(),
+            });
+        }
+    }
+}
+
+#[derive(SystemSet, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ReplaySystems {
+    /// Events are replayed, before the [CameraAction]s are applied
+    Replay,
+    /// Events are recorded, after the camera moved
+    Record,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ReplayEvent {
+    /// Only recorded when the camera moved, at most once per fixed update
+    Camera {
+        translation: [f64; 3],
+        rotation: [f64; 4],
+    },
+    Action(CameraAction),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    /// The events in order, with the fixed update (counted from the start of
+    /// the recording) they happened in
+    pub events: Vec<(u64, ReplayEvent)>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, RonFileError> {
+        utils::load_ron(path)
+    }
+
+    /// Not pretty printed as recordings get long
+    pub fn save(&self, path: &Path) -> Result<(), RonFileError> {
+        utils::save_ron(path, self, false)
+    }
+
+    /// Number of fixed updates the recording lasts
+    pub fn duration(&self) -> u64 {
+        self.events.last().map(|(update, _)| update + 1).unwrap_or(0)
+    }
+}
+
+/// Number of fixed updates since the start of the app
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct FixedUpdateCount(pub u64);
+
+#[derive(Resource, Debug, Default)]
+pub enum ReplayState {
+    #[default]
+    Idle,
+    Recording {
+        start: u64,
+        recording: Recording,
+    },
+    Replaying {
+        start: u64,
+        recording: Recording,
+        /// Index of the next event to replay
+        next: usize,
+    },
+}
+
+/// Recording to replay once the camera is spawned, see [REPLAY_ENV_VAR]
+#[derive(Resource, Debug, Clone)]
+pub struct PendingReplay {
+    pub path: PathBuf,
+}
+
+pub fn is_recording(state: Res<ReplayState>) -> bool {
+    matches!(*state, ReplayState::Recording { .. })
+}
+
+pub fn is_replaying(state: Res<ReplayState>) -> bool {
+    matches!(*state, ReplayState::Replaying { .. })
+}
+
+fn count_fixed_updates_system(
+    mut count: ResMut<FixedUpdateCount>,
+) {
+    count.0 += 1;
+}
+
+/// Starts replaying the recording at the given path, logs why if it can't
+fn start_replay(state: &mut ReplayState, count: FixedUpdateCount, path: &Path) {
+    match Recording::load(path) {
+        Ok(recording) => {
+            log::info!(
+                "Replaying {path:?} ({} events over {} fixed updates)",
+                recording.events.len(), recording.duration(),
+            );
+            *state = ReplayState::Replaying {
+                start: count.0,
+                recording,
+                next: 0,
+            };
+        },
+        Err(e) => log::warn!("Could not replay {path:?}: {e}"),
+    }
+}
+
+fn replay_control_system(
+    kb_input: Res<ButtonInput<KeyCode>>,
+    count: Res<FixedUpdateCount>,
+    mut state: ResMut<ReplayState>,
+) {
+    if kb_input.just_pressed(KeyCode::F5) {
+        match std::mem::take(&mut *state) {
+            ReplayState::Recording { recording, .. } => {
+                match recording.save(Path::new(REPLAY_PATH)) {
+                    Ok(()) => log::info!(
+                        "Saved recording of {} fixed updates to {REPLAY_PATH:?}",
+                        recording.duration(),
+                    ),
+                    Err(e) => log::warn!("Could not save recording: {e}"),
+                }
+            },
+            ReplayState::Idle => {
+                log::info!("Recording...");
+                *state = ReplayState::Recording {
+                    start: count.0,
+                    recording: default(),
+                };
+            },
+            replaying @ ReplayState::Replaying { .. } => {
+                log::warn!("Cannot record while replaying");
+                *state = replaying;
+            },
+        }
+    }
+
+    if kb_input.just_pressed(KeyCode::F6) {
+        if matches!(*state, ReplayState::Replaying { .. }) {
+            log::info!("Replay stopped");
+            *state = ReplayState::Idle;
+        }
+        else if matches!(*state, ReplayState::Recording { .. }) {
+            log::warn!("Cannot replay while recording");
+        }
+        else {
+            start_replay(&mut state, *count, Path::new(REPLAY_PATH));
+        }
+    }
+}
+
+fn start_pending_replay_system(
+    mut commands: Commands,
+    pending: Res<PendingReplay>,
+    camera: Res<Cam>,
+    count: Res<FixedUpdateCount>,
+    mut state: ResMut<ReplayState>,
+) {
+    if camera.entity.is_none() {
+        return;
+    }
+    commands.remove_resource::<PendingReplay>();
+    start_replay(&mut state, *count, &pending.path);
+}
+
+fn replay_system(
+    count: Res<FixedUpdateCount>,
+    camera: Res<Cam>,
+    mut state: ResMut<ReplayState>,
+    mut actions: EventWriter<CameraAction>,
+
+    mut transforms: Query<&mut Transform64>,
+) {
+    let ReplayState::Replaying { start, recording, next } = &mut *state
+    else { return; };
+    let current = count.0 - *start;
+
+    while let Some((update, event)) = recording.events.get(*next) {
+        if *update > current {
+            break;
+        }
+        *next += 1;
+
+        match *event {
+            ReplayEvent::Camera { translation, rotation } => {
+                let Some(mut transform) = camera.entity
+                    .and_then(|entity| transforms.get_mut(entity).ok())
+                else { continue; };
+                transform.translation = DVec3::from_array(translation);
+                transform.rotation = DQuat::from_array(rotation);
+            },
+            ReplayEvent::Action(action) => {
+                actions.send(action);
+            },
+        }
+    }
+
+    if *next >= recording.events.len() {
+        log::info!("Replay finished");
+        *state = ReplayState::Idle;
+    }
+}
+
+fn record_system(
+    count: Res<FixedUpdateCount>,
+    camera: Res<Cam>,
+    mut state: ResMut<ReplayState>,
+    mut actions: EventReader<CameraAction>,
+
+    transforms: Query<&Transform64>,
+) {
+    let ReplayState::Recording { start, recording } = &mut *state
+    else { return; };
+    let current = count.0 - *start;
+
+    for action in actions.read() {
+        recording.events.push((current, ReplayEvent::Action(*action)));
+    }
+
+    let Some(transform) = camera.entity
+        .and_then(|entity| transforms.get(entity).ok())
+    else { return; };
+    let camera_event = ReplayEvent::Camera {
+        translation: transform.translation.to_array(),
+        rotation: transform.rotation.to_array(),
+    };
+    let last_camera = recording.events.iter()
+        .rposition(|(_, event)| matches!(event, ReplayEvent::Camera { .. }));
+    match last_camera {
+        Some(i) if recording.events[i].1 == camera_event => (),
+        // Only the last position of each fixed update is kept
+        Some(i) if recording.events[i].0 == current && i == recording.events.len() - 1 => {
+            recording.events[i].1 = camera_event;
+        },
+        _ => recording.events.push((current, camera_event)),
+    }
+}
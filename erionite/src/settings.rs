@@ -0,0 +1,338 @@
+use std::path::Path;
+
+use bevy::{core_pipeline::bloom::BloomSettings, pbr::DirectionalLightShadowMap, prelude::*};
+use serde::{Deserialize, Serialize};
+use utils::RonFileError;
+
+use crate::svo_renderer::SvoRendererComponent;
+
+/// File (relative to the working directory) the settings are saved to
+pub const SETTINGS_PATH: &str = "settings.ron";
+
+/// Loads the [Settings] from [SETTINGS_PATH], applies them to the renderers,
+/// the camera and the shadow map, and shows a menu to change them (toggled
+/// with F1) which saves them on every change
+#[derive(Default)]
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(Settings::load_or_default(Path::new(SETTINGS_PATH)))
+            .init_resource::<SettingsMenu>()
+            .add_systems(Startup, spawn_settings_menu_system)
+            .add_systems(Update, (
+                (
+                    toggle_settings_menu_system,
+                    settings_button_system,
+                ).chain(),
+                (
+                    save_settings_system
+                        .run_if(resource_changed::<Settings>),
+                    apply_settings_system,
+                    update_settings_text_system
+                        .run_if(resource_changed::<Settings>),
+                ),
+            ).chain());
+    }
+}
+
+/// Graphics and level of detail settings, persisted across runs
+///
+/// Level of detail settings are relative to the preset of each renderer,
+/// see [RendererLodBase].
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize, derivative::Derivative)]
+#[derivative(Default)]
+#[serde(default)]
+pub struct Settings {
+    /// Added to the max subdivs of every renderer
+    pub max_subdivs_offset: i32,
+    /// Multiplies the chunk falloff multiplier of every renderer, higher
+    /// gives more subdivs
+    #[derivative(Default(value = "1."))]
+    pub falloff_scale: f64,
+    /// See [DirectionalLightShadowMap::size]
+    #[derivative(Default(value = "2048"))]
+    pub shadow_map_size: usize,
+    /// See [BloomSettings::intensity]
+    #[derivative(Default(value = "0.02"))]
+    pub bloom_intensity: f32,
+    /// Vertical field of view of the camera, in degrees
+    #[derivative(Default(value = "45."))]
+    pub fov: f32,
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Result<Self, RonFileError> {
+        utils::load_ron(path)
+    }
+
+    /// Loads the settings, the default ones if there are none or they could
+    /// not be read
+    pub fn load_or_default(path: &Path) -> Self {
+        match Self::load(path) {
+            Ok(settings) => settings,
+            Err(e) if e.is_not_found() => default(),
+            Err(e) => {
+                log::warn!("Ignoring settings {path:?}: {e}");
+                default()
+            },
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RonFileError> {
+        utils::save_ron(path, self, true)
+    }
+}
+
+/// Options of a renderer as given by its preset, which the [Settings] are
+/// applied on top of
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct RendererLodBase {
+    pub max_subdivs: u32,
+    pub chunk_falloff_multiplier: f64,
+}
+
+/// State of the settings menu
+#[derive(Resource, Debug, Default)]
+pub struct SettingsMenu {
+    pub open: bool,
+    root: Option<Entity>,
+}
+
+/// A setting that can be changed from the menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SettingKind {
+    MaxSubdivs,
+    Falloff,
+    ShadowMapSize,
+    Bloom,
+    Fov,
+}
+
+impl SettingKind {
+    const ALL: [Self; 5] = [
+        Self::MaxSubdivs, Self::Falloff, Self::ShadowMapSize, Self::Bloom, Self::Fov,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::MaxSubdivs => "Max subdivs offset",
+            Self::Falloff => "LOD falloff scale",
+            Self::ShadowMapSize => "Shadow map size",
+            Self::Bloom => "Bloom intensity",
+            Self::Fov => "Field of view",
+        }
+    }
+
+    fn value(self, settings: &Settings) -> String {
+        match self {
+            Self::MaxSubdivs => format!("{:+}", settings.max_subdivs_offset),
+            Self::Falloff => format!("{:.2}", settings.falloff_scale),
+            Self::ShadowMapSize => format!("{}", settings.shadow_map_size),
+            Self::Bloom => format!("{:.2}", settings.bloom_intensity),
+            Self::Fov => format!("{:.0}°", settings.fov),
+        }
+    }
+
+    /// Increases the setting by one step if `up`, decreases it otherwise
+    fn step(self, settings: &mut Settings, up: bool) {
+        let sign = if up { 1. } else { -1. };
+        match self {
+            Self::MaxSubdivs => {
+                settings.max_subdivs_offset = (settings.max_subdivs_offset + sign as i32)
+                    .clamp(-8, 8);
+            },
+            Self::Falloff => {
+                settings.falloff_scale = (settings.falloff_scale * 1.25f64.powf(sign))
+                    .clamp(0.1, 10.);
+            },
+            Self::ShadowMapSize => {
+                settings.shadow_map_size = if up {
+                    settings.shadow_map_size * 2
+                } else {
+                    settings.shadow_map_size / 2
+                }.clamp(256, 8192);
+            },
+            Self::Bloom => {
+                settings.bloom_intensity = (settings.bloom_intensity + sign as f32 * 0.01)
+                    .clamp(0., 1.);
+            },
+            Self::Fov => {
+                settings.fov = (settings.fov + sign as f32 * 5.).clamp(30., 120.);
+            },
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy)]
+struct SettingButton {
+    setting: SettingKind,
+    up: bool,
+}
+
+#[derive(Component, Debug, Clone, Copy)]
+struct SettingValueText(SettingKind);
+
+const BUTTON_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+const HOVERED_BUTTON_COLOR: Color = Color::rgb(0.3, 0.3, 0.3);
+
+fn spawn_settings_menu_system(
+    settings: Res<Settings>,
+    mut menu: ResMut<SettingsMenu>,
+    mut commands: Commands,
+) {
+    let text_style = TextStyle {
+        font_size: 15.0,
+        ..default()
+    };
+
+    let root = commands.spawn(NodeBundle {
+        style: Style {
+            display: Display::None,
+            position_type: PositionType::Absolute,
+            right: Val::Px(5.),
+            top: Val::Px(5.),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(4.),
+            padding: UiRect::all(Val::Px(8.)),
+            ..default()
+        },
+        background_color: Color::rgba(0., 0., 0., 0.7).into(),
+        z_index: ZIndex::Global(10),
+        ..default()
+    }).with_children(|builder| {
+        builder.spawn(TextBundle::from_section("Settings (F1)", text_style.clone()));
+
+        for setting in SettingKind::ALL {
+            builder.spawn(NodeBundle {
+                style: Style {
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(6.),
+                    ..default()
+                },
+                ..default()
+            }).with_children(|row| {
+                row.spawn(TextBundle::from_section(setting.label(), text_style.clone())
+                    .with_style(Style {
+                        width: Val::Px(140.),
+                        ..default()
+                    }));
+                for up in [false, true] {
+                    row.spawn((
+                        ButtonBundle {
+                            style: Style {
+                                width: Val::Px(20.),
+                                justify_content: JustifyContent::Center,
+                                ..default()
+                            },
+                            background_color: BUTTON_COLOR.into(),
+                            ..default()
+                        },
+                        SettingButton { setting, up },
+                    )).with_children(|button| {
+                        button.spawn(TextBundle::from_section(
+                            if up { "+" } else { "-" }, text_style.clone(),
+                        ));
+                    });
+                }
+                row.spawn((
+                    TextBundle::from_section(setting.value(&settings), text_style.clone()),
+                    SettingValueText(setting),
+                ));
+            });
+        }
+    }).id();
+    menu.root = Some(root);
+}
+
+fn toggle_settings_menu_system(
+    kb_input: Res<ButtonInput<KeyCode>>,
+    mut menu: ResMut<SettingsMenu>,
+    mut styles: Query<&mut Style>,
+) {
+    if !kb_input.just_pressed(KeyCode::F1) {
+        return;
+    }
+    menu.open = !menu.open;
+    if let Some(mut style) = menu.root.and_then(|root| styles.get_mut(root).ok()) {
+        style.display = if menu.open { Display::Flex } else { Display::None };
+    }
+}
+
+fn settings_button_system(
+    mut settings: ResMut<Settings>,
+    mut buttons: Query<
+        (&Interaction, &SettingButton, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+) {
+    for (interaction, button, mut color) in &mut buttons {
+        *color = match interaction {
+            Interaction::Pressed => {
+                button.setting.step(&mut settings, button.up);
+                HOVERED_BUTTON_COLOR
+            },
+            Interaction::Hovered => HOVERED_BUTTON_COLOR,
+            Interaction::None => BUTTON_COLOR,
+        }.into();
+    }
+}
+
+fn save_settings_system(
+    settings: Res<Settings>,
+) {
+    // Nothing changed yet, don't create the file for nothing
+    if settings.is_added() {
+        return;
+    }
+    if let Err(e) = settings.save(Path::new(SETTINGS_PATH)) {
+        log::warn!("Could not save settings: {e}");
+    }
+}
+
+/// Applies the settings when they change, and to the renderers and cameras
+/// spawned since the last update
+fn apply_settings_system(
+    settings: Res<Settings>,
+    mut shadow_map: ResMut<DirectionalLightShadowMap>,
+
+    mut renderers: Query<(Ref<RendererLodBase>, &mut SvoRendererComponent)>,
+    mut cameras: Query<(Ref<Camera>, &mut Projection, Option<&mut BloomSettings>)>,
+) {
+    if settings.is_changed() && shadow_map.size != settings.shadow_map_size {
+        shadow_map.size = settings.shadow_map_size;
+    }
+
+    for (base, mut renderer) in &mut renderers {
+        if !settings.is_changed() && !base.is_added() {
+            continue;
+        }
+        let options = &mut renderer.options;
+        options.max_subdivs = base.max_subdivs
+            .saturating_add_signed(settings.max_subdivs_offset)
+            .max(options.min_subdivs);
+        options.chunk_falloff_multiplier = base.chunk_falloff_multiplier * settings.falloff_scale;
+    }
+
+    for (camera, mut projection, bloom) in &mut cameras {
+        if !settings.is_changed() && !camera.is_added() {
+            continue;
+        }
+        if let Projection::Perspective(perspective) = &mut *projection {
+            perspective.fov = settings.fov.to_radians();
+        }
+        if let Some(mut bloom) = bloom {
+            bloom.intensity = settings.bloom_intensity;
+        }
+    }
+}
+
+fn update_settings_text_system(
+    settings: Res<Settings>,
+    mut texts: Query<(&mut Text, &SettingValueText)>,
+) {
+    for (mut text, SettingValueText(setting)) in &mut texts {
+        text.sections[0].value = setting.value(&settings);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod generator_svo_provider;
+pub mod edit_overlay_provider;
 
 use crate::task_runner;
 
@@ -6,19 +7,49 @@ use std::sync::Arc;
 
 use bevy::ecs::component::Component;
 
+/// See [SvoProvider::request_chunk_progressive]
+pub type ProgressiveChunkTask =
+    task_runner::ProgressiveTask<Arc<svo::TerrainCell>, (u32, Arc<svo::TerrainCell>)>;
+
 pub trait SvoProvider {
     /// Called mutliple times a second (may be bevy's Update or FixedUpdate schedule)
     fn update(&mut self) {}
 
+    /// The returned svo is the root one, which must contain the data of the
+    /// chunk and of its neighbors
     fn request_chunk(
         &mut self,
         path: &svo::CellPath,
         subdivs: u32,
     ) -> task_runner::Task<Arc<svo::TerrainCell>>;
 
+    /// Like [Self::request_chunk] but the task also reports the root svo
+    /// along with the subdivs of the chunk in it when the chunk is generated
+    /// in several steps of increasing subdivs, so that a coarse mesh can be
+    /// shown quickly and refined later
+    ///
+    /// By default no intermediate result is ever reported.
+    fn request_chunk_progressive(
+        &mut self,
+        path: &svo::CellPath,
+        subdivs: u32,
+    ) -> ProgressiveChunkTask {
+        task_runner::ProgressiveTask::from_task(self.request_chunk(path, subdivs))
+    }
+
     /// Gets and resets a accumulated list of chunks that changed since last
     /// call to this function
     fn drain_dirty_chunks(&mut self) -> Box<[svo::CellPath]>;
+
+    /// Applies an edit of the user to the terrain, changed chunks are then
+    /// given by [Self::drain_dirty_chunks]
+    ///
+    /// Returns false if the provider doesn't support edits (the default, see
+    /// [EditOverlayProvider](edit_overlay_provider::EditOverlayProvider)) or
+    /// the edit is outside of its svo.
+    fn edit(&mut self, _edit: &edit_overlay_provider::TerrainEdit) -> bool {
+        false
+    }
 }
 
 #[derive(Component)]
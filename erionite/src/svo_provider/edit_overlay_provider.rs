@@ -0,0 +1,375 @@
+use std::sync::Arc;
+
+use bevy::math::DVec3;
+use bevy::prelude::default;
+use bevy::utils::HashSet;
+use half::f16;
+use utils::DAabb;
+
+use crate::svo_provider::{ProgressiveChunkTask, SvoProvider};
+use crate::task_runner::{self, ProgressHandle, ProgressiveTask, Task};
+
+/// Edits are stored with cells of about this fraction of their radius
+const EDIT_CELLS_PER_RADIUS: f64 = 4.;
+const MAX_EDIT_DEPTH: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditOperation {
+    /// Adds terrain of the given kind
+    Fill(svo::TerrainCellKind),
+    /// Replaces terrain by air
+    Dig,
+}
+
+/// A sphere of terrain changed by the user, see [SvoProvider::edit]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainEdit {
+    pub operation: EditOperation,
+    /// Relative to the root aabb of the provider's svo
+    pub center: DVec3,
+    pub radius: f64,
+}
+
+impl TerrainEdit {
+    fn aabb(&self) -> DAabb {
+        DAabb::from_sphere(self.center, self.radius)
+    }
+
+    fn distance(&self, point: DVec3) -> f32 {
+        (point.distance(self.center) - self.radius) as f32
+    }
+}
+
+/// Combination of all edits at a cell, as signed distances (negative
+/// inside) to the filled and dug volumes, infinite where there are none
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct EditCellData {
+    filled: f32,
+    filled_kind: svo::TerrainCellKind,
+    dug: f32,
+}
+
+impl Default for EditCellData {
+    fn default() -> Self {
+        Self {
+            filled: f32::INFINITY,
+            filled_kind: svo::TerrainCellKind::Air,
+            dug: f32::INFINITY,
+        }
+    }
+}
+
+impl EditCellData {
+    fn is_edited(&self) -> bool {
+        self.filled.is_finite() || self.dug.is_finite()
+    }
+
+    /// Adds an edit whose volume is at the given distance, later edits
+    /// taking precedence over earlier ones where they overlap
+    fn add(&mut self, operation: EditOperation, distance: f32) {
+        match operation {
+            EditOperation::Fill(kind) => {
+                if distance < self.filled {
+                    self.filled = distance;
+                    self.filled_kind = kind;
+                }
+                self.dug = self.dug.max(-distance);
+            },
+            EditOperation::Dig => {
+                self.dug = self.dug.min(distance);
+                self.filled = self.filled.max(-distance);
+            },
+        }
+    }
+
+    /// Union with the filled volume then difference with the dug one
+    fn apply(&self, data: &mut svo::TerrainCellData) {
+        let mut distance = data.distance.to_f32();
+        let mut kind = data.kind;
+        if self.filled < distance {
+            distance = self.filled;
+            if distance < 0. {
+                kind = self.filled_kind;
+            }
+        }
+        if -self.dug > distance {
+            distance = -self.dug;
+            kind = svo::TerrainCellKind::Air;
+        }
+
+        data.distance = f16::from_f32(distance);
+        data.kind = kind;
+        data.empty = kind.empty();
+    }
+}
+
+impl svo::Data for EditCellData {
+    type Internal = EditCellData;
+}
+
+impl svo::InternalData for EditCellData {}
+
+impl svo::SplittableData for EditCellData {
+    fn split(self) -> (Self::Internal, [Self; 8]) {
+        (self, [self; 8])
+    }
+}
+
+impl svo::AggregateData for EditCellData {
+    /// Closest edit of any child, so cells coarser than the edits get them
+    fn aggregate<'a>(
+        children: [svo::EitherDataRef<Self>; 8]
+    ) -> Self::Internal {
+        let children = children.map(|c| *c.into_inner());
+        let closest_fill = children.iter()
+            .min_by(|a, b| a.filled.total_cmp(&b.filled))
+            .expect("8");
+        Self {
+            filled: closest_fill.filled,
+            filled_kind: closest_fill.filled_kind,
+            dug: children.iter().map(|c| c.dug).fold(f32::INFINITY, f32::min),
+        }
+    }
+}
+
+type EditCell = svo::Cell<EditCellData>;
+
+fn overlaps(a: &DAabb, b: &DAabb) -> bool {
+    a.min().cmple(b.max()).all() && b.min().cmple(a.max()).all()
+}
+
+/// Adds the edit to the cells of the overlay overlapping the region, split
+/// down to the given depth, and collects the paths of the coarser ones
+fn add_edit(
+    cell: &mut EditCell,
+    path: svo::CellPath,
+    root_aabb: DAabb,
+    region: &DAabb,
+    depth: u32,
+    edit: &TerrainEdit,
+    dirty: &mut HashSet<svo::CellPath>,
+) {
+    let aabb = path.get_aabb(root_aabb);
+    if !overlaps(&aabb, region) {
+        return;
+    }
+
+    if path.len() >= depth && !cell.has_children() {
+        cell.data_mut().into_inner().add(edit.operation, edit.distance(aabb.center()));
+        return;
+    }
+
+    if path.len() < depth {
+        dirty.insert(path.clone());
+    }
+    let internal = cell.to_internal();
+    for (child, comp) in internal.iter_children_mut().zip(svo::CellPath::components()) {
+        add_edit(child, path.clone().with_push(comp), root_aabb, region, depth, edit, dirty);
+    }
+    internal.shallow_update();
+}
+
+fn apply_to_all(cell: &mut svo::TerrainCell, edit: &EditCellData) {
+    match cell {
+        svo::Cell::Internal(internal) => {
+            for child in internal.iter_children_mut() {
+                apply_to_all(child, edit);
+            }
+            internal.shallow_update();
+        },
+        svo::Cell::Leaf(leaf) => edit.apply(&mut leaf.data),
+        svo::Cell::Packed(packed) => {
+            packed.leaf_level_mut().map_in_place(|mut data| {
+                edit.apply(&mut data);
+                data
+            });
+            packed.update_all();
+        },
+    }
+}
+
+/// Applies the edits of the overlay to the terrain, terrain cells coarser
+/// than the edits are split down to `max_depth` if they are on the path of a
+/// focused cell (a requested chunk and its neighbors), and get the combined
+/// edits of their region otherwise
+fn apply_edits(
+    cell: &mut svo::TerrainCell,
+    overlay: &EditCell,
+    path: svo::CellPath,
+    focus: &[svo::CellPath],
+    max_depth: u32,
+) {
+    let edit = *overlay.data().into_inner();
+    if !edit.is_edited() {
+        return;
+    }
+
+    let focused = path.len() < max_depth && focus.iter()
+        .any(|focused| path.is_prefix_of(focused) || focused.is_prefix_of(&path));
+    if !overlay.has_children() || !(cell.has_children() || focused) {
+        apply_to_all(cell, &edit);
+        return;
+    }
+
+    let internal = cell.to_internal();
+    for ((child, overlay_child), comp) in internal.iter_children_mut()
+        .zip(overlay.iter_children())
+        .zip(svo::CellPath::components())
+    {
+        apply_edits(child, &**overlay_child, path.clone().with_push(comp), focus, max_depth);
+    }
+    internal.shallow_update();
+}
+
+fn merge_overlay(
+    root: &Arc<svo::TerrainCell>,
+    overlay: &EditCell,
+    path: &svo::CellPath,
+    subdivs: u32,
+) -> Arc<svo::TerrainCell> {
+    if !overlay.data().into_inner().is_edited() {
+        return Arc::clone(root);
+    }
+
+    let focus = std::iter::once(path.clone())
+        .chain(path.clone().neighbors().map(|(_, neighbor)| neighbor))
+        .collect::<Vec<_>>();
+    let mut merged = (**root).clone();
+    apply_edits(
+        &mut merged, overlay, svo::CellPath::new(), &focus, path.len() + subdivs,
+    );
+    Arc::new(merged)
+}
+
+/// Intermediate results of a base request forwarded with the edits applied
+struct ForwardedPartials {
+    base: ProgressiveChunkTask,
+    handle: ProgressHandle<Arc<svo::TerrainCell>, (u32, Arc<svo::TerrainCell>)>,
+    path: svo::CellPath,
+    merge_task: Option<Task<()>>,
+}
+
+/// Wraps another provider and applies the edits made by the user on top of
+/// the svos it gives
+///
+/// The edits are kept in a sparse overlay svo, separate from the (e.g.
+/// procedural) terrain of the base provider, where cells store the distances
+/// to the filled and dug volumes which are combined with the terrain with
+/// a union and a difference.
+pub struct EditOverlayProvider<P: SvoProvider> {
+    base: P,
+    aabb: DAabb,
+
+    /// Replaced on each edit, requests keep the one they started with
+    overlay: Arc<EditCell>,
+    dirty_chunks: HashSet<svo::CellPath>,
+    forwarded: Vec<ForwardedPartials>,
+}
+
+impl<P: SvoProvider> EditOverlayProvider<P> {
+    pub fn new(base: P, aabb: DAabb) -> Self {
+        Self {
+            base,
+            aabb,
+
+            overlay: Arc::new(svo::LeafCell::new(EditCellData::default()).into()),
+            dirty_chunks: default(),
+            forwarded: Vec::new(),
+        }
+    }
+}
+
+impl<P: SvoProvider> SvoProvider for EditOverlayProvider<P> {
+    fn update(&mut self) {
+        self.base.update();
+
+        let overlay = &self.overlay;
+        self.forwarded.retain_mut(|forwarded| {
+            if forwarded.handle.canceled() || forwarded.handle.finished() {
+                return false;
+            }
+
+            if let Some((subdivs, root)) = forwarded.base.take_partial() {
+                let overlay = Arc::clone(overlay);
+                let handle = forwarded.handle.clone();
+                let path = forwarded.path.clone();
+                forwarded.merge_task = Some(task_runner::spawn(move || {
+                    let merged = merge_overlay(&root, &overlay, &path, subdivs);
+                    if !handle.finished() {
+                        handle.report((subdivs, merged));
+                    }
+                }));
+            }
+            true
+        });
+    }
+
+    fn request_chunk(
+        &mut self,
+        path: &svo::CellPath,
+        subdivs: u32,
+    ) -> Task<Arc<svo::TerrainCell>> {
+        let overlay = Arc::clone(&self.overlay);
+        let path = path.clone();
+        self.base.request_chunk(&path, subdivs).then_task(move |root| {
+            merge_overlay(root, &overlay, &path, subdivs)
+        })
+    }
+
+    fn request_chunk_progressive(
+        &mut self,
+        path: &svo::CellPath,
+        subdivs: u32,
+    ) -> ProgressiveChunkTask {
+        let base = self.base.request_chunk_progressive(path, subdivs);
+
+        let overlay = Arc::clone(&self.overlay);
+        let final_path = path.clone();
+        let merged = base.then_task(move |root| {
+            merge_overlay(root, &overlay, &final_path, subdivs)
+        });
+
+        let task = ProgressiveTask::new();
+        let handle = task.handle();
+        merged.then({
+            let handle = handle.clone();
+            move |root| handle.finish(Arc::clone(root))
+        });
+        handle.add_parent(merged);
+
+        self.forwarded.push(ForwardedPartials {
+            base,
+            handle,
+            path: path.clone(),
+            merge_task: None,
+        });
+        task
+    }
+
+    fn drain_dirty_chunks(&mut self) -> Box<[svo::CellPath]> {
+        let mut dirty = std::mem::take(&mut self.dirty_chunks);
+        dirty.extend(self.base.drain_dirty_chunks().into_vec());
+        dirty.into_iter().collect()
+    }
+
+    fn edit(&mut self, edit: &TerrainEdit) -> bool {
+        if !overlaps(&edit.aabb(), &self.aabb) || edit.radius <= 0. {
+            return false;
+        }
+
+        let cell_size = edit.radius / EDIT_CELLS_PER_RADIUS;
+        let depth = (self.aabb.size.max_element() / cell_size).log2().ceil()
+            .clamp(0., MAX_EDIT_DEPTH as f64) as u32;
+        // Includes the cells around whose meshes use the edited ones
+        let margin = self.aabb.size.max_element() / 2f64.powi(depth as i32) * 2.;
+        let region = DAabb::from_minmax(
+            edit.aabb().min() - margin, edit.aabb().max() + margin,
+        );
+
+        add_edit(
+            Arc::make_mut(&mut self.overlay), svo::CellPath::new(),
+            self.aabb, &region, depth, edit, &mut self.dirty_chunks,
+        );
+        true
+    }
+}
@@ -5,7 +5,8 @@ use bevy::utils::HashSet;
 use utils::DAabb;
 use itertools::Itertools;
 
-use crate::task_runner::{self, Task, TaskHandle};
+use crate::svo_provider::ProgressiveChunkTask;
+use crate::task_runner::{self, ProgressHandle, ProgressiveTask, Task};
 use crate::generator::Generator;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -57,10 +58,20 @@ impl svo::MergeableData for GeneratedDepthData {
     }
 }
 
+/// Chunks are first generated with this many subdivs, then with each
+/// multiple of it until the requested subdivs, see
+/// [SvoProvider::request_chunk_progressive](super::SvoProvider::request_chunk_progressive)
+const PROGRESSIVE_SUBDIVS_STEP: u32 = 2;
+
+type GenHandle = ProgressHandle<Arc<svo::TerrainCell>, (u32, Arc<svo::TerrainCell>)>;
+
 #[derive(Debug, Clone)]
 struct GenPromise {
     started: bool,
-    handle: TaskHandle<Arc<svo::TerrainCell>>,
+    /// Whether coarser versions are generated first, see
+    /// [PROGRESSIVE_SUBDIVS_STEP]
+    progressive: bool,
+    handle: GenHandle,
     depth: i64,
 }
 
@@ -79,6 +90,7 @@ impl GenTaskData {
         Self {
             promise: Some(GenPromise {
                 started: false,
+                progressive: task.progressive,
                 handle: task.handle,
                 depth: task.depth - 1,
             })
@@ -130,6 +142,8 @@ pub struct GeneratorSvoProvider<G: Generator> {
     aabb: DAabb,
 
     generator: Arc<G>,
+    /// Applied to every generated chunk, see [svo::Cell::merge_terrain]
+    merge_policy: Option<svo::TerrainMergePolicy>,
 
     svo_data: Arc<Mutex<SharedData>>,
     dirty_chunks: Arc<Mutex<HashSet<svo::CellPath>>>,
@@ -148,6 +162,7 @@ impl<G: Generator + 'static> GeneratorSvoProvider<G> {
         Self {
             aabb,
             generator,
+            merge_policy: None,
 
             svo_data: Arc::new(Mutex::new(SharedData {
                 root_svo,
@@ -161,14 +176,27 @@ impl<G: Generator + 'static> GeneratorSvoProvider<G> {
         }
     }
 
+    pub fn aabb(&self) -> DAabb {
+        self.aabb
+    }
+
+    /// Merges the smooth parts of the generated terrain
+    pub fn with_merge_policy(mut self, policy: svo::TerrainMergePolicy) -> Self {
+        self.svo_data.lock().unwrap().root_svo.merge_terrain(self.aabb, &policy);
+        self.merge_policy = Some(policy);
+        self
+    }
+
     pub fn start_promise(
         &self,
         path: &svo::CellPath,
         subdivs: u32,
-        handle: TaskHandle<Arc<svo::TerrainCell>>,
+        progressive: bool,
+        handle: GenHandle,
     ) {
         let generator = Arc::clone(&self.generator);
         let aabb = self.aabb;
+        let merge_policy = self.merge_policy;
 
         let data = self.svo_data.clone();
         let dirties = self.dirty_chunks.clone();
@@ -177,16 +205,49 @@ impl<G: Generator + 'static> GeneratorSvoProvider<G> {
         let path = path.clone();
 
         let task = task_runner::spawn::<(), _>(move || {
-            let must_regen = {
+            // Subdivs the chunk is already generated with
+            let generated_subdivs = {
                 let lock = data.lock().unwrap();
                 let (found_path, found) = lock.generated.follow_path(&path);
-                (found.data().into_inner().0 + i64::from(found_path.len()))
-                    <
-                From::from(subdivs + path.len())
+                found.data().into_inner().0 + i64::from(found_path.len())
+                    - i64::from(path.len())
             };
+            let must_regen = generated_subdivs < i64::from(subdivs);
+
+            // Coarser versions first, each one is only generated if it is
+            // better than what is already there
+            let steps = (PROGRESSIVE_SUBDIVS_STEP..subdivs)
+                .step_by(PROGRESSIVE_SUBDIVS_STEP as usize)
+                .filter(|_| progressive)
+                .filter(|&step| i64::from(step) > generated_subdivs);
+            for step in steps {
+                let mut result = generator.generate_chunk(aabb, &path, step);
+                if let Some(policy) = &merge_policy {
+                    result.merge_terrain(path.get_aabb(aabb), policy);
+                }
+
+                if handle.canceled() {
+                    return;
+                }
+
+                let mut lock = data.lock().unwrap();
+                *lock.root_svo.follow_internal_path(&path) = result;
+                lock.root_svo.update_on_path(&path);
+
+                *lock.generated.follow_internal_path(&path) =
+                    svo::LeafCell::new(GeneratedDepthData(step.into())).into();
+                lock.generated.update_on_path(&path);
+
+                handle.report((step, Arc::new(lock.root_svo.clone())));
+            }
+
             let mut lock;
             if must_regen {
-                let result = generator.generate_chunk(aabb, &path, subdivs);
+                let mut result = generator.generate_chunk(aabb, &path, subdivs);
+                if let Some(policy) = &merge_policy {
+                    let merged = result.merge_terrain(path.get_aabb(aabb), policy);
+                    log::trace!("Merged {merged} cells of chunk {path:?}");
+                }
 
                 if handle.canceled() {
                     return;
@@ -220,6 +281,41 @@ impl<G: Generator + 'static> GeneratorSvoProvider<G> {
 
         handle2.add_parent(task);
     }
+
+    fn request(
+        &mut self,
+        path: &svo::CellPath,
+        subdivs: u32,
+        progressive: bool,
+    ) -> ProgressiveChunkTask {
+        let isubdivs = i64::from(subdivs);
+        let cell = self.gen_target.follow_internal_path(path);
+        
+        if let Some(promise) = &mut cell.data_mut().into_inner().promise {
+            if promise.depth >= isubdivs {
+                // Only matters if it was not started yet
+                promise.progressive |= progressive;
+                if let Some(task) = promise.handle.upgrade() {
+                    return task;
+                }
+            }
+        }
+
+        let task = ProgressiveTask::new();
+
+        *cell = svo::LeafCell {
+            data: GenTaskData {
+                promise: Some(GenPromise {
+                    handle: task.handle(),
+                    depth: isubdivs,
+                    started: false,
+                    progressive,
+                }),
+            },
+        }.into();
+
+        task
+    }
 }
 
 impl<G: Generator + 'static> super::SvoProvider for GeneratorSvoProvider<G> {
@@ -251,7 +347,8 @@ impl<G: Generator + 'static> super::SvoProvider for GeneratorSvoProvider<G> {
                     promise.started = true;
 
                     self.start_promise(
-                        &path, promise.depth as u32, promise.handle.clone()
+                        &path, promise.depth as u32, promise.progressive,
+                        promise.handle.clone(),
                     );
 
                     true
@@ -264,7 +361,10 @@ impl<G: Generator + 'static> super::SvoProvider for GeneratorSvoProvider<G> {
             }
 
             if cell.has_children() {
-                todo.extend(path.children());
+                match path.try_children() {
+                    Ok(children) => todo.extend(children),
+                    Err(error) => log::warn!("Invalid generation target {path:?}: {error}"),
+                }
             }
         }
 
@@ -276,30 +376,15 @@ impl<G: Generator + 'static> super::SvoProvider for GeneratorSvoProvider<G> {
         path: &svo::CellPath,
         subdivs: u32,
     ) -> Task<Arc<svo::TerrainCell>> {
-        let isubdivs = i64::from(subdivs);
-        let cell = self.gen_target.follow_internal_path(path);
-        
-        if let Some(promise) = &cell.data().into_inner().promise {
-            if promise.depth >= isubdivs {
-                if let Some(task) = promise.handle.upgrade() {
-                    return task;
-                }
-            }
-        }
-
-        let task = Task::new();
-
-        *cell = svo::LeafCell {
-            data: GenTaskData {
-                promise: Some(GenPromise {
-                    handle: task.handle(),
-                    depth: isubdivs,
-                    started: false,
-                }),
-            },
-        }.into();
+        self.request(path, subdivs, false).into_task()
+    }
 
-        task
+    fn request_chunk_progressive(
+        &mut self,
+        path: &svo::CellPath,
+        subdivs: u32,
+    ) -> ProgressiveChunkTask {
+        self.request(path, subdivs, true)
     }
 
     fn drain_dirty_chunks(&mut self) -> Box<[svo::CellPath]> {
@@ -1,17 +1,47 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic};
 use bevy::time::common_conditions::on_timer;
-use doprec::{GlobalTransform64, Transform64, Transform64Bundle};
+use doprec::{Culling64Bundle, GlobalTransform64, Transform64, Transform64Bundle};
 use ordered_float::OrderedFloat;
-use bevy::{ecs::system::EntityCommands, prelude::*};
-use rapier_overlay::rapier::geometry::{ColliderBuilder, SharedShape};
-use rapier_overlay::{BevyMeshExt, ColliderBundle, ColliderHandleComp};
-use svo::{mesh_generation::marching_cubes, CellPath};
-use utils::{AabbExt, DAabb};
+use bevy::{ecs::system::EntityCommands, math::DVec3, pbr::NotShadowCaster, prelude::*, render::view::{NoFrustumCulling, RenderLayers}, utils::{HashMap, HashSet}};
+use rapier_overlay::rapier::{dynamics::RigidBodyType, geometry::{ColliderBuilder, SharedShape, TriMesh}, na::Point3};
+use rapier_overlay::{ColliderBundle, ColliderHandleComp, RigidBodyComp, RigidBodyHandleComp};
+use serde::{Deserialize, Serialize};
+use svo::{mesh_generation::marching_cubes, AbsoluteSubdivs, CellPath, ChunkView, RelativeSubdivs};
+use utils::DAabb;
 
 use crate::task_runner::{self, OptionTaskExt, Task};
-use crate::svo_provider::SvoProviderComponent;
+use crate::svo_provider::{ProgressiveChunkTask, SvoProviderComponent};
+
+mod surface;
+pub use surface::*;
+mod far_view;
+pub use far_view::*;
+
+/// Memory used by the svos of all chunks, cells shared between chunks
+/// counted once
+pub const SVO_MEMORY_USAGE_DIAG: DiagnosticPath =
+    DiagnosticPath::const_new("svo_memory_usage");
+/// Number of cells in the svos of all chunks
+pub const SVO_CELL_COUNT_DIAG: DiagnosticPath =
+    DiagnosticPath::const_new("svo_cell_count");
+/// Depth of the deepest chunk svo, see [svo::TreeStats::depth]
+pub const SVO_MAX_DEPTH_DIAG: DiagnosticPath =
+    DiagnosticPath::const_new("svo_max_depth");
+/// Percentage of the cells of all chunks that are inside packed cells, see
+/// [svo::TreeStats::packed_ratio]
+pub const SVO_PACKED_RATIO_DIAG: DiagnosticPath =
+    DiagnosticPath::const_new("svo_packed_ratio");
+/// Mean of the [svo::TreeStats::average_branching] of the chunk svos
+pub const SVO_AVERAGE_BRANCHING_DIAG: DiagnosticPath =
+    DiagnosticPath::const_new("svo_average_branching");
+
+/// Render layer of the shadow proxies of chunks (see
+/// [SvoRendererComponentOptions::shadow_subdivs_reduction]), shadow casting
+/// lights must have it in their [RenderLayers] and cameras must not
+pub const SHADOW_PROXY_RENDER_LAYER: u8 = 1;
 
 #[derive(Default)]
 pub struct SvoRendererPlugin {
@@ -26,12 +56,42 @@ impl Plugin for SvoRendererPlugin {
             dirty_chunks_drainer_system,
             chunks_subdivs_system,
             chunk_split_merge_system,
+            collider_activation_system,
             chunk_system,
+            prefetch_system,
             provider_updates_system,
         )
             .chain()
             .run_if(on_timer(Duration::from_millis(125))),
         );
+        app.add_systems(Update, far_view_system.after(chunk_system));
+        app.add_systems(Update,
+            memory_usage_diagnostic_system
+                .run_if(on_timer(Duration::from_secs(1))),
+        );
+
+        app.register_diagnostic(
+            Diagnostic::new(SVO_MEMORY_USAGE_DIAG)
+                .with_suffix(" MiB")
+                .with_max_history_length(1)
+        );
+        app.register_diagnostic(
+            Diagnostic::new(SVO_CELL_COUNT_DIAG)
+                .with_max_history_length(1)
+        );
+        app.register_diagnostic(
+            Diagnostic::new(SVO_MAX_DEPTH_DIAG)
+                .with_max_history_length(1)
+        );
+        app.register_diagnostic(
+            Diagnostic::new(SVO_PACKED_RATIO_DIAG)
+                .with_suffix("%")
+                .with_max_history_length(1)
+        );
+        app.register_diagnostic(
+            Diagnostic::new(SVO_AVERAGE_BRANCHING_DIAG)
+                .with_max_history_length(1)
+        );
     }
 }
 
@@ -43,6 +103,23 @@ pub struct SvoRendererBundle {
 }
 
 type NewChunkCallback = Box<dyn FnMut(EntityCommands) + Send + Sync>;
+type ChunkCallback = Box<dyn FnMut(EntityCommands, ChunkCallbackInfo) + Send + Sync>;
+
+/// Given to the chunk lifecycle callbacks of [SvoRendererComponentOptions]
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkCallbackInfo<'a> {
+    pub path: &'a CellPath,
+    /// Subdivisions of the chunk's mesh, relative to the chunk
+    pub subdivs: u32,
+}
+
+impl<'a> ChunkCallbackInfo<'a> {
+    /// Subdivisions relative to the root of the renderer, a higher
+    /// lod means more details
+    pub fn lod(&self) -> AbsoluteSubdivs {
+        RelativeSubdivs(self.subdivs).absolute_from(self.path)
+    }
+}
 
 #[derive(derivative::Derivative)]
 #[derivative(Default)]
@@ -54,16 +131,118 @@ pub struct SvoRendererComponentOptions {
     pub chunk_split_subdivs: u32,
     /// Chunks with less subdivs are merged
     pub chunk_merge_subdivs: u32,
+    /// The subdivs of a chunk are only lowered once the closest camera is
+    /// this ratio farther than the distance they changed at, so chunks don't
+    /// keep remeshing (or splitting and merging) when a camera hovers
+    /// around it
+    #[derivative(Default(value="0.1"))]
+    pub subdivs_hysteresis: f64,
+    /// Minimum time in seconds a chunk stays split or merged before it can
+    /// switch again
+    #[derivative(Default(value="0.5"))]
+    pub min_state_duration: f64,
 
     /// higher = more subdivs?
     pub chunk_falloff_multiplier: f64,
+    /// If set chunks are meshed with the least subdivs (at most the ones of
+    /// their data) whose simplification error (see
+    /// [svo::Cell::simplification_errors]) is below this ratio of the
+    /// distance to the closest camera, so flat terrain gets less subdivs
+    /// than rough terrain at the same distance
+    ///
+    /// Colliders and [ChunkCallbackInfo] still use the subdivs of the data.
+    pub max_simplification_error: Option<f64>,
+    /// If set chunk meshes get uvs and tangents (see
+    /// [marching_cubes::UvOptions]) so textured and normal mapped materials
+    /// can be used, the texture repeating every this many units
+    pub uv_scale: Option<f64>,
+    /// Colliders are generated with this many less subdivs than the chunk's
+    /// visual mesh (see [marching_cubes::run_collision])
+    #[derivative(Default(value="1"))]
+    pub collider_simplification: u32,
+    /// If set only chunks closer than this distance to a non fixed rigid
+    /// body (dynamic or kinematic) get a collider, the ones of chunks that
+    /// get too far from all of them are removed
+    pub collider_activation_radius: Option<f64>,
+    /// If set chunks don't cast shadows themselves, a child entity (their
+    /// shadow proxy) with a mesh with this many less subdivs does instead,
+    /// making shadow maps cheaper to render
+    pub shadow_subdivs_reduction: Option<u32>,
 
     pub root_aabb: DAabb,
 
     pub on_new_chunk: Option<NewChunkCallback>,
+    /// Called on the shadow proxy of every new chunk (see
+    /// [Self::shadow_subdivs_reduction]), which needs a material to be
+    /// rendered
+    pub on_new_shadow_proxy: Option<NewChunkCallback>,
+    /// Called every time a new mesh is attached to a chunk
+    pub on_mesh_ready: Option<ChunkCallback>,
+    /// Called after [Self::on_mesh_ready] when the new mesh has different
+    /// subdivs than the previous one
+    pub on_lod_changed: Option<ChunkCallback>,
+    /// Called just before a chunk is despawned (when its parent merges)
+    pub on_despawn: Option<ChunkCallback>,
 
     #[derivative(Default(value="true"))]
     pub enable_subdivs_update: bool,
+
+    /// Chunks are spawned following this layout when the renderer is
+    /// created instead of starting from the root chunk alone, see
+    /// [SvoRendererComponent::chunk_layout]
+    pub initial_layout: Option<ChunkLayout>,
+
+    /// Requests the data of the chunks cameras are about to need, disabled
+    /// if None
+    pub prefetch: Option<PrefetchOptions>,
+    /// Draws the whole renderer with a single coarse mesh when cameras are
+    /// far away, disabled if None. The mesh gets [Self::on_new_chunk] called
+    /// on its entity too.
+    pub far_view: Option<FarViewOptions>,
+}
+
+/// Options of the data prefetching of a renderer, which extrapolates the
+/// position of the cameras from their velocity and requests the data of the
+/// chunks that would be needed there before they are spawned, so fast
+/// movements don't show holes while they are generated
+#[derive(Debug, Clone, derivative::Derivative)]
+#[derivative(Default)]
+pub struct PrefetchOptions {
+    /// How far in the future (in seconds) camera positions are predicted
+    #[derivative(Default(value="3."))]
+    pub lookahead: f64,
+    /// Number of predicted positions between now and [Self::lookahead]
+    #[derivative(Default(value="4"))]
+    pub steps: u32,
+    /// Prefetched data has this many less subdivs than the predicted chunks,
+    /// as it is only needed until they are close enough to request theirs
+    #[derivative(Default(value="1"))]
+    pub subdivs_reduction: u32,
+    /// Maximum number of prefetch requests running at once
+    #[derivative(Default(value="16"))]
+    pub max_requests: usize,
+}
+
+/// Serializable snapshot of the chunk tree of a renderer, used to restore it
+/// quickly when reloading the world
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkLayout {
+    pub target_subdivs: u32,
+    /// Subdivs the chunk's data was requested from the provider with, None
+    /// if it had no data yet, so that cached svos can be looked up
+    pub data_subdivs: Option<u32>,
+    /// Layouts of the children if the chunk is split
+    pub children: Option<Box<[ChunkLayout; 8]>>,
+}
+
+impl ChunkLayout {
+    /// Number of chunks in the layout, including this one
+    pub fn chunk_count(&self) -> usize {
+        1 + self.children.iter()
+            .flat_map(|children| children.iter())
+            .map(Self::chunk_count)
+            .sum::<usize>()
+    }
 }
 
 #[derive(Component)]
@@ -71,6 +250,9 @@ pub struct SvoRendererComponent {
     pub options: SvoRendererComponentOptions,
 
     root_chunk: Entity,
+    /// Kept so the requests are not canceled, see [PrefetchOptions]
+    prefetch_tasks: HashMap<CellPath, Task<Arc<svo::TerrainCell>>>,
+    far_view: FarViewState,
 }
 
 impl SvoRendererComponent {
@@ -80,8 +262,31 @@ impl SvoRendererComponent {
             options,
 
             root_chunk: Entity::PLACEHOLDER,
+            prefetch_tasks: default(),
+            far_view: default(),
         }
     }
+
+    /// Current layout of the renderer's chunks, None if the renderer has
+    /// not spawned its root chunk yet
+    pub fn chunk_layout(&self, chunks: &Query<&ChunkComponent>) -> Option<ChunkLayout> {
+        (self.root_chunk != Entity::PLACEHOLDER)
+            .then(|| chunk_layout(chunks, self.root_chunk))
+    }
+}
+
+fn chunk_layout(chunks: &Query<&ChunkComponent>, entity: Entity) -> ChunkLayout {
+    let Ok(chunk) = chunks.get(entity)
+    else { return default(); };
+
+    ChunkLayout {
+        target_subdivs: chunk.target_subdivs,
+        data_subdivs: chunk.data.as_ref().map(|data| data.for_subdivs),
+        // Children of merging chunks are about to be despawned
+        children: chunk.chunk_children
+            .filter(|_| chunk.target_state.is_split())
+            .map(|children| Box::new(children.map(|child| chunk_layout(chunks, child)))),
+    }
 }
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
@@ -109,6 +314,21 @@ impl ChunkMergeState {
     }
 }
 
+/// Chunks meshed down to at most this depth of the svo give their tasks a
+/// copy of the svo only that deep instead of the whole tree, which the tasks
+/// would keep alive, see [task_data]
+const SHALLOW_TASK_DATA_MAX_DEPTH: u32 = 5;
+
+/// The svo given to a mesh, shadow mesh or collider task meshing down to the
+/// given subdivs, cut at that depth for low detail chunks (see
+/// [svo::Cell::clone_to_depth])
+fn task_data(data: Arc<svo::TerrainCell>, subdivs: AbsoluteSubdivs) -> Arc<svo::TerrainCell> {
+    if subdivs.0 > SHALLOW_TASK_DATA_MAX_DEPTH {
+        return data;
+    }
+    Arc::new(data.clone_to_depth(subdivs.0))
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 struct GeneratedData<T> {
     for_subdivs: u32,
@@ -141,6 +361,9 @@ pub struct ChunkComponent {
     path: svo::CellPath,
     target_subdivs: u32,
     target_state: ChunkMergeState,
+    /// Elapsed time at which the subdivs system last split or merged the
+    /// chunk, see [SvoRendererComponentOptions::min_state_duration]
+    target_state_since: f64,
 
     #[derivative(Default(value="Entity::PLACEHOLDER"))]
     renderer: Entity,
@@ -161,15 +384,45 @@ pub struct ChunkComponent {
     children_have_meshes: bool,
     /// Like [Self::children_have_meshes] but for colliders
     children_have_colliders: bool,
+    /// Whether the chunk should have a collider, see
+    /// [SvoRendererComponentOptions::collider_activation_radius]
+    #[derivative(Default(value="true"))]
+    collider_active: bool,
 
+    // Tasks are canceled when dropped, so despawning the chunk cancels them
     should_update_data: bool,
-    data_task: Option<Task<GeneratedData<Arc<svo::TerrainCell>>>>,
+    /// Also gives whether the new data is equal to the one the current mesh
+    /// was generated from, in which case there is no need to remesh, and
+    /// its [Self::simplification_errors]
+    data_task: Option<Task<(GeneratedData<Arc<svo::TerrainCell>>, bool, Option<Vec<f32>>)>>,
+    /// Request [Self::data_task] comes from, polled for coarser data to
+    /// mesh while it runs (see
+    /// [SvoProvider::request_chunk_progressive](crate::svo_provider::SvoProvider::request_chunk_progressive))
+    data_request: Option<ProgressiveChunkTask>,
     data: Option<GeneratedData<Arc<svo::TerrainCell>>>,
+    /// Errors of meshing [Self::data] with each number of subdivs, only
+    /// computed if [SvoRendererComponentOptions::max_simplification_error]
+    /// is set
+    simplification_errors: Option<Vec<f32>>,
+    /// Subdivs the mesh is generated with if less than the data's ones, see
+    /// [SvoRendererComponentOptions::max_simplification_error]
+    simplified_subdivs: Option<u32>,
 
     should_update_mesh: bool,
-    mesh_task: Option<Task<GeneratedData<Option<Mesh>>>>,
+    mesh_task: Option<Task<GeneratedData<marching_cubes::Out>>>,
     /// Must be in sync with the `Handle<Mesh>` component on the chunk's entity
     mesh: Option<GeneratedData<Option<Handle<Mesh>>>>,
+    /// Buffers given back by the last remesh so the next one of this chunk
+    /// doesn't have to allocate new ones
+    mesh_buffers: Option<marching_cubes::Out>,
+
+    /// Child entity casting the shadows of the chunk, see
+    /// [SvoRendererComponentOptions::shadow_subdivs_reduction]
+    shadow_proxy: Option<Entity>,
+    should_update_shadow_mesh: bool,
+    shadow_mesh_task: Option<Task<marching_cubes::Out>>,
+    /// Must be in sync with the `Handle<Mesh>` component on the shadow proxy
+    shadow_mesh: Option<Handle<Mesh>>,
 
     should_update_collider: bool,
     collider_task: Option<Task<GeneratedData<Option<ColliderBundle>>>>,
@@ -178,10 +431,11 @@ pub struct ChunkComponent {
 }
 
 impl ChunkComponent {
-    fn new(renderer: Entity, path: svo::CellPath) -> Self {
+    fn new(renderer: Entity, path: svo::CellPath, target_state_since: f64) -> Self {
         Self {
             path,
             renderer,
+            target_state_since,
 
             waiting_for_subdivs: true,
 
@@ -217,6 +471,21 @@ impl ChunkComponent {
         self.collider_task.is_some()
     }
 
+    pub fn is_generating_shadow_mesh(&self) -> bool {
+        self.shadow_mesh_task.is_some()
+    }
+
+    /// Removes the mesh of the shadow proxy, to be done along with the
+    /// removal of the chunk's mesh
+    fn remove_shadow_mesh(&mut self, commands: &mut Commands) {
+        self.should_update_shadow_mesh = false;
+        self.shadow_mesh_task = None;
+        self.shadow_mesh = None;
+        if let Some(proxy) = self.shadow_proxy {
+            commands.entity(proxy).remove::<Handle<Mesh>>();
+        }
+    }
+
     pub fn is_busy(&self) -> bool {
         if self.waiting_for_subdivs {
             return true;
@@ -226,7 +495,8 @@ impl ChunkComponent {
             ChunkMergeState::Merge => {
                 self.should_update_data || self.is_generating() ||
                 self.should_update_mesh || self.is_generating_mesh() ||
-                self.should_update_collider || self.is_generating_collider()
+                self.should_update_collider || self.is_generating_collider() ||
+                self.should_update_shadow_mesh || self.is_generating_shadow_mesh()
             },
             ChunkMergeState::Split | ChunkMergeState::ParentMerging => {
                 false
@@ -235,22 +505,142 @@ impl ChunkComponent {
     }
 }
 
+fn memory_usage_diagnostic_system(
+    mut diagnostics: Diagnostics,
+
+    chunks: Query<&ChunkComponent>,
+) {
+    let mut counter = svo::MemoryUsageCounter::new();
+    let mut max_depth = 0;
+    let mut cell_count = 0;
+    let mut packed_cell_count = 0;
+    let mut branching_sum = 0.;
+    let mut chunk_count = 0;
+    for data in chunks.iter().filter_map(|chunk| chunk.data.as_ref()) {
+        counter.add(&*data.data);
+
+        let stats = data.data.tree_stats();
+        max_depth = max_depth.max(stats.depth());
+        cell_count += stats.cell_count();
+        packed_cell_count += stats.packed_cell_count;
+        branching_sum += stats.average_branching();
+        chunk_count += 1;
+    }
+    let usage = counter.usage();
+
+    diagnostics.add_measurement(
+        &SVO_MEMORY_USAGE_DIAG,
+        || usage.total() as f64 / (1024. * 1024.),
+    );
+    diagnostics.add_measurement(
+        &SVO_CELL_COUNT_DIAG,
+        || usage.cell_count() as f64,
+    );
+    diagnostics.add_measurement(
+        &SVO_MAX_DEPTH_DIAG,
+        || max_depth as f64,
+    );
+    diagnostics.add_measurement(
+        &SVO_PACKED_RATIO_DIAG,
+        || if cell_count == 0 { 0. } else { packed_cell_count as f64 * 100. / cell_count as f64 },
+    );
+    diagnostics.add_measurement(
+        &SVO_AVERAGE_BRANCHING_DIAG,
+        || if chunk_count == 0 { 0. } else { branching_sum / chunk_count as f64 },
+    );
+}
+
 fn new_renderer_system(
     mut commands: Commands,
+    time: Res<Time>,
     mut svo_renders: Query<(Entity, &mut SvoRendererComponent), Added<SvoRendererComponent>>,
 ) {
     for (renderer_entity, mut renderer) in &mut svo_renders {
         commands.entity(renderer_entity).insert(VisibilityBundle::default());
-        let root_chunk_entitiy = commands.spawn((
-            ChunkComponent::new(renderer_entity, CellPath::new()),
+        let layout = renderer.options.initial_layout.take();
+        let root_origin = marching_cubes::mesh_origin(&CellPath::new(), renderer.options.root_aabb);
+        let root_chunk_entitiy = spawn_chunk(
+            &mut commands, &mut renderer.options,
+            renderer_entity, renderer_entity,
+            CellPath::new(), Transform64::from_translation(root_origin), layout.as_ref(),
+            time.elapsed_seconds_f64(),
+        );
+        renderer.root_chunk = root_chunk_entitiy;
+        if let Some(layout) = &layout {
+            log::info!("Restored {} chunks", layout.chunk_count());
+        }
+    }
+}
+
+/// Spawns a new chunk, and its descendants if a layout is given
+///
+/// Chunks are translated to the [mesh_origin](marching_cubes::mesh_origin)
+/// of their meshes, so `local` is the difference with the one of the parent.
+/// `now` is the elapsed time their target state starts at.
+#[allow(clippy::too_many_arguments)]
+fn spawn_chunk(
+    commands: &mut Commands,
+    options: &mut SvoRendererComponentOptions,
+    renderer: Entity,
+    parent: Entity,
+    path: CellPath,
+    local: Transform64,
+    layout: Option<&ChunkLayout>,
+    now: f64,
+) -> Entity {
+    let chunk_entity = commands.spawn((
+        Transform64Bundle { local, ..default() },
+        VisibilityBundle::default(),
+    )).set_parent(parent).id();
+    if let Some(on_new_chunk) = &mut options.on_new_chunk {
+        on_new_chunk(commands.entity(chunk_entity));
+    }
+
+    let mut chunk = ChunkComponent::new(renderer, path, now);
+    if options.shadow_subdivs_reduction.is_some() {
+        commands.entity(chunk_entity).insert(NotShadowCaster);
+        // Only lights have the proxy's layer, and as no camera sees it it
+        // cannot be culled with an Aabb64
+        let proxy = commands.spawn((
             Transform64Bundle::default(),
             VisibilityBundle::default(),
-        )).set_parent(renderer_entity).id();
-        renderer.root_chunk = root_chunk_entitiy;
-        if let Some(on_new_chunk) = &mut renderer.options.on_new_chunk {
-            on_new_chunk(commands.entity(root_chunk_entitiy));
+            RenderLayers::layer(SHADOW_PROXY_RENDER_LAYER),
+            NoFrustumCulling,
+        )).set_parent(chunk_entity).id();
+        if let Some(on_new_shadow_proxy) = &mut options.on_new_shadow_proxy {
+            on_new_shadow_proxy(commands.entity(proxy));
+        }
+        chunk.shadow_proxy = Some(proxy);
+    }
+    if let Some(layout) = layout {
+        // Restored chunks start generating without waiting for the subdivs
+        // system
+        chunk.waiting_for_subdivs = false;
+        chunk.should_update_data = true;
+        chunk.target_subdivs = layout.target_subdivs;
+
+        // Paths can only be too deep if the layout doesn't come from this
+        // renderer, in which case the chunk is left merged
+        let children = layout.children.as_ref()
+            .zip(chunk.path.try_children().ok());
+        if let Some((children_layouts, children_paths)) = children {
+            let chunk_origin = marching_cubes::mesh_origin(&chunk.path, options.root_aabb);
+            let mut children_paths = children_paths.into_iter();
+            chunk.chunk_children = Some(std::array::from_fn(|i| {
+                let child_path = children_paths.next().expect("Eight children");
+                let child_origin = marching_cubes::mesh_origin(&child_path, options.root_aabb);
+                spawn_chunk(
+                    commands, options, renderer, chunk_entity, child_path,
+                    Transform64::from_translation(child_origin - chunk_origin),
+                    Some(&children_layouts[i]), now,
+                )
+            }));
+            chunk.set_target_state(ChunkMergeState::Split);
         }
     }
+    commands.entity(chunk_entity).insert(chunk);
+
+    chunk_entity
 }
 
 fn dirty_chunks_drainer_system(
@@ -281,8 +671,84 @@ fn provider_updates_system(
     }
 }
 
+/// Updates [ChunkComponent::collider_active] from the positions of the rigid
+/// bodies, see [SvoRendererComponentOptions::collider_activation_radius]
+fn collider_activation_system(
+    mut commands: Commands,
+
+    bodies: Query<(&RigidBodyComp, &GlobalTransform64), With<RigidBodyHandleComp>>,
+    mut chunks: Query<(Entity, &mut ChunkComponent)>,
+    svo_renders: Query<(&SvoRendererComponent, &GlobalTransform64)>,
+) {
+    let bodies_poses = bodies.iter()
+        .filter(|(body, _)| body.enabled && body.kind != RigidBodyType::Fixed)
+        .map(|(_, transform)| transform.translation())
+        .collect::<Vec<_>>();
+    let mut renderer_bodies_poses = HashMap::<Entity, Vec<DVec3>>::new();
+
+    for (chunk_entity, mut chunk) in &mut chunks {
+        let Ok((SvoRendererComponent { options, .. }, renderer_trans)) =
+            svo_renders.get(chunk.renderer)
+        else { continue; };
+
+        let active = match options.collider_activation_radius {
+            None => true,
+            Some(radius) => {
+                let chunk_aabb = chunk.path.get_aabb(options.root_aabb);
+                // Bodies in the renderer's space, as root_aabb is
+                let local_bodies_poses = renderer_bodies_poses.entry(chunk.renderer)
+                    .or_insert_with(|| {
+                        let world_to_renderer = renderer_trans.affine().inverse();
+                        bodies_poses.iter()
+                            .map(|&pos| world_to_renderer.transform_point3(pos))
+                            .collect()
+                    });
+                local_bodies_poses.iter().any(|&pos|
+                    chunk_aabb.distance_squared_to(pos) <= radius * radius
+                )
+            },
+        };
+        if active == chunk.collider_active {
+            continue;
+        }
+
+        chunk.collider_active = active;
+        if active {
+            chunk.should_update_collider = chunk.data.is_some();
+        }
+        else {
+            chunk.should_update_collider = false;
+            chunk.collider_task = None;
+            if chunk.collider.take().is_some_and(|collider| collider.data.is_some()) {
+                commands.entity(chunk_entity).remove::<ColliderBundle>();
+            }
+        }
+    }
+}
+
+/// Subdivs of the chunk with the given path (and aabb) when the closest
+/// camera is at the given distance from it
+fn target_subdivs(
+    options: &SvoRendererComponentOptions,
+    path: &CellPath,
+    chunk_aabb: DAabb,
+    closest_camera_dist: f64,
+) -> RelativeSubdivs {
+    let mut total_subdivs = AbsoluteSubdivs(options.max_subdivs);
+    while total_subdivs > AbsoluteSubdivs(options.min_subdivs) &&
+        closest_camera_dist >
+            total_subdivs.relative_to(path).cell_size(chunk_aabb.size).length()
+                * options.chunk_falloff_multiplier
+    {
+        total_subdivs.0 -= 1;
+    }
+
+    total_subdivs.relative_to(path)
+}
+
 /// Updates chunks target_subdivs
 fn chunks_subdivs_system(
+    time: Res<Time>,
     cameras: Query<(&Camera, &GlobalTransform64)>,
     mut chunks: Query<&mut ChunkComponent>,
     svo_renders: Query<(&SvoRendererComponent, &GlobalTransform64)>,
@@ -291,6 +757,7 @@ fn chunks_subdivs_system(
         .filter(|(c, _)| c.is_active)
         .map(|(_, t)| t.translation())
         .collect::<Vec<_>>();
+    let mut renderer_cameras_poses = HashMap::<Entity, Vec<DVec3>>::new();
 
     for mut chunk in &mut chunks {
         let Ok((SvoRendererComponent { options, .. }, &renderer_trans)) =
@@ -305,46 +772,68 @@ fn chunks_subdivs_system(
         }
 
         let chunk_aabb = chunk.path.get_aabb(options.root_aabb);
-        let renderer_translation = renderer_trans.translation();
+        // Cameras in the renderer's space, as root_aabb is
+        let local_cameras_poses = renderer_cameras_poses.entry(chunk.renderer)
+            .or_insert_with(|| {
+                let world_to_renderer = renderer_trans.affine().inverse();
+                cameras_poses.iter()
+                    .map(|&cp| world_to_renderer.transform_point3(cp))
+                    .collect()
+            });
 
-        let Some(closest_camera_dist_2) = cameras_poses.iter()
-            .map(|&cp| cp - renderer_translation)
-            .map(|campos| chunk_aabb.closest_point(campos).distance_squared(campos))
+        let Some(closest_camera_dist_2) = local_cameras_poses.iter()
+            .map(|&campos| chunk_aabb.distance_squared_to(campos))
             .min_by_key(|&d| OrderedFloat(d))
         else { continue };
         let closest_camera_dist = closest_camera_dist_2.sqrt();
 
-        let mut total_subdivs = options.max_subdivs;
-        while total_subdivs > options.min_subdivs &&
-            closest_camera_dist >
-                (chunk_aabb.size /
-                    2f64.powi(total_subdivs.saturating_sub(chunk.path.depth()) as i32)
-                ).length() * options.chunk_falloff_multiplier
-        {
-            total_subdivs -= 1;
+        let RelativeSubdivs(mut subdivs) = target_subdivs(
+            options, &chunk.path, chunk_aabb, closest_camera_dist,
+        );
+        if !chunk.waiting_for_subdivs && subdivs < chunk.target_subdivs {
+            let RelativeSubdivs(far_subdivs) = target_subdivs(
+                options, &chunk.path, chunk_aabb,
+                closest_camera_dist / (1. + options.subdivs_hysteresis),
+            );
+            subdivs = far_subdivs.min(chunk.target_subdivs);
         }
-
-        let subdivs = total_subdivs.saturating_sub(chunk.path.depth());
         if chunk.waiting_for_subdivs || chunk.target_subdivs != subdivs {
             chunk.waiting_for_subdivs = false;
             chunk.should_update_data = true;
             chunk.target_subdivs = subdivs;
         }
 
+        let simplified_subdivs = options.max_simplification_error
+            .zip(chunk.simplification_errors.as_ref())
+            .and_then(|(max_error, errors)| errors.iter()
+                .position(|&error| f64::from(error) <= max_error * closest_camera_dist))
+            .map(|subdivs| subdivs as u32);
+        if chunk.simplified_subdivs != simplified_subdivs {
+            chunk.simplified_subdivs = simplified_subdivs;
+            chunk.should_update_mesh |= chunk.data.is_some();
+        }
+
+        let now = time.elapsed_seconds_f64();
+        if now - chunk.target_state_since < options.min_state_duration {
+            continue;
+        }
         let old_state = chunk.target_state;
         if old_state == ChunkMergeState::Split &&
             chunk.target_subdivs < options.chunk_merge_subdivs {
             chunk.set_target_state(ChunkMergeState::Merge);
+            chunk.target_state_since = now;
         }
         if old_state == ChunkMergeState::Merge &&
             chunk.target_subdivs > options.chunk_split_subdivs {
             chunk.set_target_state(ChunkMergeState::Split);
+            chunk.target_state_since = now;
         }
     }
 }
 
 fn chunk_split_merge_system(
     mut commands: Commands,
+    time: Res<Time>,
     mut chunk_entities: Query<Entity, (With<ChunkComponent>, With<Visibility>)>,
     mut chunks: Query<&mut ChunkComponent>,
     chunk_complementaries: Query<(Option<&Handle<Mesh>>, Option<&ColliderHandleComp>)>,
@@ -363,29 +852,25 @@ fn chunk_split_merge_system(
         };
         let options = &mut renderer.options;
 
-        let chunk_aabb = chunk.path.get_aabb(options.root_aabb);
-
         // must split
         if chunk.chunk_children.is_none() && chunk.target_state.is_split() {
-            let n_children = CellPath::components().map(|child| {
-                let child_path = chunk.path.clone().with_push(child);
-                let child_aabb = child_path.get_aabb(options.root_aabb);
-
-                let child_chunk_entitiy = commands.spawn((
-                    ChunkComponent::new(chunk.renderer, child_path.clone()),
-                    Transform64Bundle {
-                        local: Transform64::from_translation(chunk_aabb.min() - child_aabb.min()),
-                        ..default()
-                    },
-                    VisibilityBundle::default(),
-                    // Into::<Aabb>::into(child_path.get_aabb(options.root_aabb)),
-                )).set_parent(chunk_entity).id();
-
-                if let Some(on_new_chunk) = &mut options.on_new_chunk {
-                    on_new_chunk(commands.entity(child_chunk_entitiy));
-                }
-
-                child_chunk_entitiy
+            let children_paths = match chunk.path.try_children() {
+                Ok(paths) => paths,
+                Err(error) => {
+                    log::warn!("Cannot split chunk {:?}: {error}", chunk.path);
+                    chunk.set_target_state(ChunkMergeState::Merge);
+                    continue 'chunks_iter;
+                },
+            };
+            let chunk_origin = marching_cubes::mesh_origin(&chunk.path, options.root_aabb);
+            let n_children = children_paths.map(|child_path| {
+                let child_origin = marching_cubes::mesh_origin(&child_path, options.root_aabb);
+
+                spawn_chunk(
+                    &mut commands, options, chunk.renderer, chunk_entity, child_path,
+                    Transform64::from_translation(child_origin - chunk_origin),
+                    None, time.elapsed_seconds_f64(),
+                )
             });
 
             chunk.chunk_children = Some(n_children);
@@ -426,10 +911,14 @@ fn chunk_split_merge_system(
             if chunk_mesh.is_some() && chunk.children_have_meshes {
                 chunk.mesh = None;
                 commands.entity(chunk_entity).remove::<Handle<Mesh>>();
+                chunk.remove_shadow_mesh(&mut commands);
             }
 
+            // Inactive children won't get a collider but the parent's one
+            // must still be removed to not overlap the ones of the others
             chunk.children_have_colliders = children.iter()
-                .all(|chunk| chunk.collider.is_some() || chunk.children_have_colliders);
+                .all(|chunk| chunk.collider.is_some() || chunk.children_have_colliders ||
+                    !chunk.collider_active);
 
             if chunk_collider.is_some() && chunk.children_have_colliders {
                 chunk.collider = None;
@@ -441,11 +930,16 @@ fn chunk_split_merge_system(
             let can_destroy_children = !chunk.is_busy();
 
             if can_destroy_children {
+                chunk.chunk_children = None;
+
+                if let Some(on_despawn) = &mut options.on_despawn {
+                    notify_chunks_despawn(
+                        &mut commands, &chunks, on_despawn, children_entities
+                    );
+                }
                 for childe in children_entities {
                     commands.entity(childe).despawn_recursive();
                 }
-
-                chunk.chunk_children = None;
             }
             else {
                 for child in &mut children {
@@ -456,6 +950,145 @@ fn chunk_split_merge_system(
     }
 }
 
+/// Calls the callback for the given chunks and all their chunk descendants
+fn notify_chunks_despawn(
+    commands: &mut Commands,
+    chunks: &Query<&mut ChunkComponent>,
+    on_despawn: &mut ChunkCallback,
+    roots: [Entity; 8],
+) {
+    let mut todo = Vec::from(roots);
+    while let Some(entity) = todo.pop() {
+        let Ok(chunk) = chunks.get(entity)
+        else { continue; };
+
+        let subdivs = chunk.mesh.as_ref()
+            .map_or(chunk.target_subdivs, |mesh| mesh.for_subdivs);
+        on_despawn(commands.entity(entity), ChunkCallbackInfo {
+            path: &chunk.path,
+            subdivs,
+        });
+
+        todo.extend(chunk.chunk_children.into_iter().flatten());
+    }
+}
+
+/// Path and data subdivs of the merged chunk that would contain the given
+/// camera position (in the renderer's space) following the same rules as
+/// [chunks_subdivs_system]
+fn chunk_for_camera(
+    options: &SvoRendererComponentOptions,
+    camera_pos: DVec3,
+) -> (CellPath, RelativeSubdivs) {
+    let split_subdivs = RelativeSubdivs(options.chunk_split_subdivs);
+    let mut path = CellPath::new();
+    loop {
+        let aabb = path.get_aabb(options.root_aabb);
+        let dist = aabb.distance_squared_to(camera_pos).sqrt();
+        let subdivs = target_subdivs(options, &path, aabb, dist);
+        let children = match path.try_children() {
+            Ok(children) if subdivs > split_subdivs => children,
+            _ => return (path, split_subdivs.min(subdivs)),
+        };
+
+        path = children.into_iter()
+            .min_by_key(|child| {
+                let child_aabb = child.get_aabb(options.root_aabb);
+                OrderedFloat(child_aabb.distance_squared_to(camera_pos))
+            })
+            .expect("Eight children");
+    }
+}
+
+/// Requests the data of the chunks at the predicted camera positions, see
+/// [PrefetchOptions]
+#[allow(clippy::type_complexity)]
+fn prefetch_system(
+    time: Res<Time>,
+    // Last position of each camera in the space of each renderer along with
+    // the elapsed time it was taken at
+    mut last_cameras_poses: Local<HashMap<(Entity, Entity), (DVec3, f64)>>,
+
+    cameras: Query<(Entity, &Camera, &GlobalTransform64)>,
+    chunks: Query<&ChunkComponent>,
+    mut svo_renders: Query<(
+        Entity, &mut SvoRendererComponent, &mut SvoProviderComponent, &GlobalTransform64,
+    )>,
+) {
+    let now = time.elapsed_seconds_f64();
+    let cameras = cameras.iter()
+        .filter(|(_, c, _)| c.is_active)
+        .map(|(entity, _, transform)| (entity, transform.translation()))
+        .collect::<Vec<_>>();
+    last_cameras_poses.retain(|(_, camera), _|
+        cameras.iter().any(|(entity, _)| entity == camera)
+    );
+
+    // Paths of all chunks and of their parents, which must not be
+    // prefetched as requests override the ones of their descendants
+    let mut chunk_paths = HashMap::<Entity, HashSet<CellPath>>::new();
+    for chunk in &chunks {
+        chunk_paths.entry(chunk.renderer).or_default()
+            .extend(std::iter::once(chunk.path.clone()).chain(chunk.path.parents()));
+    }
+
+    for (entity, mut renderer, mut provider, renderer_trans) in &mut svo_renders {
+        let renderer = &mut *renderer;
+        let Some(prefetch) = &renderer.options.prefetch
+        else {
+            renderer.prefetch_tasks.clear();
+            continue;
+        };
+        let world_to_renderer = renderer_trans.affine().inverse();
+        let existing = chunk_paths.get(&entity);
+
+        // Cameras with their velocity in the renderer's space, so that the
+        // rotation of a spinning planet under a still camera is predicted
+        let local_cameras = cameras.iter()
+            .map(|&(camera, pos)| {
+                let pos = world_to_renderer.transform_point3(pos);
+                let last = last_cameras_poses.insert((entity, camera), (pos, now));
+                let velocity = match last {
+                    Some((last_pos, last_time)) if now > last_time =>
+                        (pos - last_pos) / (now - last_time),
+                    _ => DVec3::ZERO,
+                };
+                (pos, velocity)
+            })
+            .collect::<Vec<_>>();
+
+        let mut predicted = Vec::<(CellPath, RelativeSubdivs)>::new();
+        for step in 1..=prefetch.steps {
+            let dt = prefetch.lookahead * f64::from(step) / f64::from(prefetch.steps);
+            for &(pos, velocity) in &local_cameras {
+                if velocity == DVec3::ZERO {
+                    continue;
+                }
+                let (path, subdivs) = chunk_for_camera(&renderer.options, pos + velocity * dt);
+                if existing.is_some_and(|e| e.contains(&path)) ||
+                    predicted.iter().any(|(p, _)| p.is_prefix_of(&path) || path.is_prefix_of(p))
+                {
+                    continue;
+                }
+                predicted.push((path, subdivs.saturating_sub(prefetch.subdivs_reduction)));
+            }
+        }
+        predicted.truncate(prefetch.max_requests);
+
+        // Outdated requests are canceled, finished ones are kept while still
+        // predicted so they are not requested again
+        renderer.prefetch_tasks.retain(|path, _|
+            predicted.iter().any(|(p, _)| p == path)
+        );
+        for (path, subdivs) in predicted {
+            if !renderer.prefetch_tasks.contains_key(&path) {
+                let task = provider.request_chunk(&path, subdivs.0);
+                renderer.prefetch_tasks.insert(path, task);
+            }
+        }
+    }
+}
+
 /// Updates chunk datas, meshes etc.
 fn chunk_system(
     mut commands: Commands,
@@ -465,7 +1098,7 @@ fn chunk_system(
     mut svo_renders: Query<(&mut SvoRendererComponent, &mut SvoProviderComponent)>,
 ) {
     for (chunk_entitiy, mut chunk) in chunks.iter_mut() {
-        let Ok((renderer, mut provider)) = svo_renders.get_mut(chunk.renderer)
+        let Ok((mut renderer, mut provider)) = svo_renders.get_mut(chunk.renderer)
         else { continue; };
 
         let actual_subdivs = renderer.options.chunk_split_subdivs
@@ -474,20 +1107,59 @@ fn chunk_system(
         if chunk.target_state.is_merge() && chunk.should_update_data {
             chunk.should_update_data = false;
 
-            chunk.data_task = Some(provider.request_chunk(
+            // Data of the current mesh, if it has the right subdivs
+            let meshed_data = chunk.data.as_ref()
+                .filter(|data| data.for_subdivs == actual_subdivs)
+                .filter(|_| chunk.mesh.as_ref()
+                    .is_some_and(|mesh| mesh.for_subdivs == actual_subdivs))
+                .map(|data| Arc::clone(&data.data));
+            let request = provider.request_chunk_progressive(
                 &chunk.path,
                 actual_subdivs
-            ).then_task(move |c| {
+            );
+            let chunkpath = chunk.path.clone();
+            let compute_errors = renderer.options.max_simplification_error.is_some();
+            chunk.data_task = Some(request.then_task(move |c| {
                 let t = Arc::clone(c);
-                GeneratedData { for_subdivs: actual_subdivs, data: t }
+                // Compared in the task as it can take a while if the
+                // provider didn't share unchanged subtrees
+                let unchanged = meshed_data
+                    .is_some_and(|old| Arc::ptr_eq(&old, &t) || *old == *t);
+                let errors = compute_errors
+                    .then(|| t.simplification_errors(&chunkpath, actual_subdivs));
+                (GeneratedData { for_subdivs: actual_subdivs, data: t }, unchanged, errors)
             }));
+            chunk.data_request = Some(request);
         }
 
-        if let Some(data) = chunk.data_task.take_if_finished() {
-            chunk.data = Some(data);
+        // Coarser data is only meshed if it is better than the current mesh
+        // and no other mesh is generating, so that the final one comes first
+        let partial = chunk.data_request.as_ref()
+            .filter(|_| !chunk.is_generating_mesh())
+            .and_then(|request| request.take_partial_if(|&(subdivs, _)| chunk.mesh.as_ref()
+                .map_or(true, |mesh| mesh.for_subdivs < subdivs)));
+        if let Some((for_subdivs, data)) = partial {
+            chunk.data = Some(GeneratedData { for_subdivs, data });
+            chunk.simplification_errors = None;
+            chunk.simplified_subdivs = None;
             chunk.should_update_mesh = true;
         }
 
+        if let Some((data, unchanged, errors)) = chunk.data_task.take_if_finished() {
+            chunk.data_request = None;
+            chunk.data = Some(data);
+            chunk.simplification_errors = errors;
+            // A running mesh task may be for older data
+            if !unchanged || chunk.is_generating_mesh() {
+                chunk.should_update_mesh = true;
+            }
+        }
+
+        // Meshes are relative to the chunk's origin, which its entity is
+        // translated to
+        let root_aabb = renderer.options.root_aabb;
+        let chunk_origin = marching_cubes::mesh_origin(&chunk.path, root_aabb);
+
         if let Some(GeneratedData {
             for_subdivs: subdivs, data
         }) = (chunk.target_state.is_merge() && chunk.should_update_mesh)
@@ -496,70 +1168,167 @@ fn chunk_system(
             chunk.should_update_mesh = false;
 
             let chunkpath = chunk.path.clone();
-            let root_aabb = renderer.options.root_aabb
-                .translated(
-                    chunkpath.get_aabb(renderer.options.root_aabb).min() -
-                        renderer.options.root_aabb.min()
-                );
+            let mesh_subdivs = chunk.simplified_subdivs.map_or(subdivs, |s| s.min(subdivs));
+            let data = task_data(data, RelativeSubdivs(mesh_subdivs).absolute_from(&chunkpath));
+            let mut out = chunk.mesh_buffers.take()
+                .unwrap_or_else(|| marching_cubes::Out::new(true, false));
+            out.uv_options = renderer.options.uv_scale.map(|scale| marching_cubes::UvOptions {
+                scale,
+                // Uvs are computed before the chunk's origin is removed so
+                // they already match across chunks
+                origin: DVec3::ZERO,
+            });
             chunk.mesh_task = Some(task_runner::spawn(move || {
-                let mut out = marching_cubes::Out::new(true, false);
+                out.clear();
+                // The provider gives the root svo so cells across the chunk
+                // borders can be sampled
+                let view = ChunkView::from_root(&data, chunkpath);
                 marching_cubes::run(
-                    &mut out, chunkpath, &data, root_aabb, subdivs
+                    &mut out, &view, root_aabb, mesh_subdivs
                 );
 
-                if out.vertices.is_empty() {
-                    return GeneratedData {
-                        for_subdivs: subdivs,
-                        data: None,
-                    };
-                }
-                
-                let m = out.into_mesh();
-
                 GeneratedData {
                     for_subdivs: subdivs,
-                    data: Some(m),
+                    data: out,
                 }
             }));
         }
 
-        if let Some(maybe_new_mesh) = chunk.mesh_task.take_if_finished() {
-            let maybe_new_mesh = maybe_new_mesh.map(|m| m.map(|mesh| meshes.add(mesh)));
+        if let Some(GeneratedData {
+            for_subdivs, data: mut out
+        }) = chunk.mesh_task.take_if_finished() {
+            // Update the current mesh asset in place if there is one to avoid
+            // creating a new asset on every remesh
+            let current_mesh = chunk.mesh.as_ref()
+                .and_then(|m| m.data.clone())
+                .filter(|handle| meshes.contains(handle));
+
+            let new_mesh = if out.vertices.is_empty() {
+                None
+            }
+            else if let Some(handle) = current_mesh {
+                out.write_to_mesh(meshes.get_mut(&handle).expect("Checked above"));
+                Some(handle)
+            }
+            else {
+                Some(meshes.add(out.into_mesh()))
+            };
+            chunk.mesh_buffers = Some(out);
+
+            let maybe_new_mesh = GeneratedData { for_subdivs, data: new_mesh };
             if let Some(new_mesh) = &maybe_new_mesh.data {
-                commands.entity(chunk_entitiy).insert(new_mesh.clone());
+                // Culled in f64 as f32 aabbs of far away huge chunks are
+                // too imprecise
+                commands.entity(chunk_entitiy).try_insert((
+                    new_mesh.clone(),
+                    Culling64Bundle::new(chunk.path.get_aabb(root_aabb).translated(-chunk_origin)),
+                ));
                 
-                chunk.should_update_collider = true;
+                chunk.should_update_collider = chunk.collider_active;
+                chunk.should_update_shadow_mesh = chunk.shadow_proxy.is_some();
+
+                let info = ChunkCallbackInfo {
+                    path: &chunk.path,
+                    subdivs: for_subdivs,
+                };
+                let options = &mut renderer.options;
+                if let Some(on_mesh_ready) = &mut options.on_mesh_ready {
+                    on_mesh_ready(commands.entity(chunk_entitiy), info);
+                }
+                let lod_changed = chunk.mesh.as_ref()
+                    .is_some_and(|old| old.for_subdivs != for_subdivs);
+                if lod_changed {
+                    if let Some(on_lod_changed) = &mut options.on_lod_changed {
+                        on_lod_changed(commands.entity(chunk_entitiy), info);
+                    }
+                }
             }
             else {
                 commands.entity(chunk_entitiy).remove::<Handle<Mesh>>();
+                chunk.remove_shadow_mesh(&mut commands);
             }
             chunk.mesh = Some(maybe_new_mesh);
         }
 
-        if let Some(mesh_for_collider) = (
-            chunk.target_state.is_merge() && chunk.should_update_collider
-        )
-            .then_some(chunk.mesh.clone())
-            .flatten()
-            .and_then(|g| g.map(|handle| handle.map(|handle| {
-                meshes.get(handle).cloned()
-            })).transpose())
+        // Shadow meshes are generated from the same data as the visual one
+        // with less subdivs
+        if let Some(GeneratedData {
+            for_subdivs, data
+        }) = (chunk.target_state.is_merge() && chunk.should_update_shadow_mesh)
+            .then_some(&chunk.data).cloned().flatten()
+        {
+            chunk.should_update_shadow_mesh = false;
+
+            let chunkpath = chunk.path.clone();
+            let subdivs = chunk.simplified_subdivs.map_or(for_subdivs, |s| s.min(for_subdivs))
+                .saturating_sub(renderer.options.shadow_subdivs_reduction.unwrap_or(0));
+            let data = task_data(data, RelativeSubdivs(subdivs).absolute_from(&chunkpath));
+            chunk.shadow_mesh_task = Some(task_runner::spawn(move || {
+                let mut out = marching_cubes::Out::new(true, false);
+                let view = ChunkView::from_root(&data, chunkpath);
+                marching_cubes::run(
+                    &mut out, &view, root_aabb, subdivs
+                );
+                out
+            }));
+        }
+
+        if let Some(mut out) = chunk.shadow_mesh_task.take_if_finished() {
+            let proxy = chunk.shadow_proxy
+                .expect("Only chunks with a shadow proxy generate shadow meshes");
+            let current_mesh = chunk.shadow_mesh.take()
+                .filter(|handle| meshes.contains(handle));
+
+            chunk.shadow_mesh = if out.vertices.is_empty() {
+                None
+            }
+            else if let Some(handle) = current_mesh {
+                out.write_to_mesh(meshes.get_mut(&handle).expect("Checked above"));
+                Some(handle)
+            }
+            else {
+                Some(meshes.add(out.into_mesh()))
+            };
+
+            if let Some(handle) = &chunk.shadow_mesh {
+                commands.entity(proxy).try_insert(handle.clone());
+            }
+            else {
+                commands.entity(proxy).remove::<Handle<Mesh>>();
+            }
+        }
+
+        // Colliders are generated from the chunk's data with a simplified
+        // mesher instead of from the visual mesh
+        if let Some(GeneratedData {
+            for_subdivs: subdivs, data
+        }) = (chunk.target_state.is_merge() && chunk.should_update_collider)
+            .then_some(&chunk.data).cloned().flatten()
         {
             chunk.should_update_collider = false;
 
+            let chunkpath = chunk.path.clone();
+            let simplification = renderer.options.collider_simplification;
+            let data = task_data(data, RelativeSubdivs(subdivs).absolute_from(&chunkpath));
             chunk.collider_task = Some(task_runner::spawn(move || {
-                let data = 'data: {
-                    let Some(mesh) = mesh_for_collider.data
-                    else { break 'data None };
-                    let Some(trimesh) = mesh.to_trimesh()
-                    else { break 'data None };
-
-                    Some(ColliderBundle::from(ColliderBuilder::new(SharedShape::new(
-                        trimesh
-                    ))))
-                };
+                let mut out = marching_cubes::CollisionOut::new();
+                let view = ChunkView::from_root(&data, chunkpath);
+                marching_cubes::run_collision(
+                    &mut out, &view, root_aabb, subdivs, simplification
+                );
+
+                let data = (!out.triangles.is_empty()).then(|| {
+                    ColliderBundle::from(ColliderBuilder::new(SharedShape::new(
+                        TriMesh::new(
+                            out.vertices.iter()
+                                .map(|v| Point3::from(v.as_dvec3().to_array()))
+                                .collect(),
+                            out.triangles,
+                        )
+                    )))
+                });
                 GeneratedData {
-                    for_subdivs: mesh_for_collider.for_subdivs,
+                    for_subdivs: subdivs,
                     data,
                 }
             }));
@@ -568,7 +1337,7 @@ fn chunk_system(
         if let Some(maybe_collider) = chunk.collider_task.take_if_finished() {
             chunk.collider = Some(maybe_collider.clone());
             if let Some(collider) = maybe_collider.data {
-                commands.entity(chunk_entitiy).insert(collider);
+                commands.entity(chunk_entitiy).try_insert(collider);
             }
             else {
                 commands.entity(chunk_entitiy).remove::<ColliderBundle>();
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use bevy::{math::{DAffine3, DVec3}, pbr::NotShadowCaster, prelude::*, render::view::NoFrustumCulling};
+use doprec::{GlobalTransform64, Transform64, Transform64Bundle};
+use ordered_float::OrderedFloat;
+use svo::{mesh_generation::marching_cubes, CellPath, ChunkView};
+
+use crate::task_runner::{self, OptionTaskExt, Task};
+use super::{ChunkComponent, SvoRendererComponent};
+
+/// Options of the far view of a renderer: a coarse mesh of its whole root
+/// chunk, shown instead of its chunks once cameras are so far away that the
+/// chunks would end up past their far plane, scaled down towards the camera
+/// so it fits in front of it while keeping the same apparent size
+#[derive(Debug, Clone, derivative::Derivative)]
+#[derivative(Default)]
+pub struct FarViewOptions {
+    /// Subdivs of the mesh relative to the root chunk, at most the ones of
+    /// the root chunk's data
+    #[derivative(Default(value="5"))]
+    pub subdivs: u32,
+    /// The far view is shown when the renderer is farther than this ratio
+    /// of the far plane of the closest camera
+    #[derivative(Default(value="0.5"))]
+    pub far_plane_ratio: f64,
+}
+
+/// State of the far view of a renderer, see [FarViewOptions]
+#[derive(Default)]
+pub(super) struct FarViewState {
+    entity: Option<Entity>,
+    /// Data of the root chunk the mesh (or the running task) comes from
+    meshed_data: Option<Arc<svo::TerrainCell>>,
+    mesh_task: Option<Task<marching_cubes::Out>>,
+    mesh: Option<Handle<Mesh>>,
+    shown: bool,
+}
+
+/// Remeshes the far views whose root chunk got new data, and shows them
+/// (placed for the closest perspective camera) instead of the chunks when
+/// far enough
+pub(super) fn far_view_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+
+    cameras: Query<(&Camera, &Projection, &GlobalTransform64)>,
+    mut renderers: Query<(Entity, &mut SvoRendererComponent, &GlobalTransform64)>,
+    chunks: Query<&ChunkComponent>,
+    mut transforms: Query<&mut Transform64, Without<SvoRendererComponent>>,
+) {
+    for (renderer_entity, mut renderer, renderer_trans) in &mut renderers {
+        let renderer = &mut *renderer;
+        let Some(far_options) = renderer.options.far_view.clone()
+        else { continue; };
+        if renderer.root_chunk == Entity::PLACEHOLDER {
+            continue;
+        }
+        let root_aabb = renderer.options.root_aabb;
+        let uv_options = renderer.options.uv_scale.map(|scale| marching_cubes::UvOptions {
+            scale,
+            origin: DVec3::ZERO,
+        });
+        let state = &mut renderer.far_view;
+
+        // A child of the renderer so it is despawned along with it
+        let entity = *state.entity.get_or_insert_with(|| {
+            let entity = commands.spawn((
+                Transform64Bundle::default(),
+                VisibilityBundle {
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                NotShadowCaster,
+                NoFrustumCulling,
+            )).set_parent(renderer_entity).id();
+            if let Some(on_new_chunk) = &mut renderer.options.on_new_chunk {
+                on_new_chunk(commands.entity(entity));
+            }
+            entity
+        });
+
+        let new_data = chunks.get(renderer.root_chunk).ok()
+            .and_then(|chunk| chunk.data.clone())
+            .filter(|data| state.meshed_data.as_ref()
+                .map_or(true, |meshed| !Arc::ptr_eq(meshed, &data.data)));
+        if let Some(data) = new_data {
+            state.meshed_data = Some(Arc::clone(&data.data));
+            let subdivs = far_options.subdivs.min(data.for_subdivs);
+            state.mesh_task = Some(task_runner::spawn(move || {
+                let mut out = marching_cubes::Out::new(true, false);
+                out.uv_options = uv_options;
+                let view = ChunkView::from_root(&data.data, CellPath::new());
+                marching_cubes::run(&mut out, &view, root_aabb, subdivs);
+                out
+            }));
+        }
+
+        if let Some(mut out) = state.mesh_task.take_if_finished() {
+            let current_mesh = state.mesh.take()
+                .filter(|handle| meshes.contains(handle));
+            state.mesh = if out.vertices.is_empty() {
+                None
+            }
+            else if let Some(handle) = current_mesh {
+                out.write_to_mesh(meshes.get_mut(&handle).expect("Checked above"));
+                Some(handle)
+            }
+            else {
+                Some(meshes.add(out.into_mesh()))
+            };
+
+            if let Some(handle) = &state.mesh {
+                commands.entity(entity).try_insert(handle.clone());
+            }
+            else {
+                commands.entity(entity).remove::<Handle<Mesh>>();
+            }
+        }
+
+        let (center, radius) = renderer_trans.transform_aabb(root_aabb).bounding_sphere();
+        // Position of the closest camera and the distance it is shown from
+        let closest_camera = cameras.iter()
+            .filter(|(camera, ..)| camera.is_active)
+            .filter_map(|(_, projection, transform)| match projection {
+                Projection::Perspective(perspective) => Some((
+                    transform.translation(),
+                    f64::from(perspective.far) * far_options.far_plane_ratio,
+                )),
+                Projection::Orthographic(_) => None,
+            })
+            .min_by_key(|(position, _)| OrderedFloat(position.distance_squared(center)));
+
+        let show = state.mesh.is_some() && closest_camera
+            .is_some_and(|(position, max_distance)| {
+                position.distance(center) - radius > max_distance
+            });
+        if show != state.shown {
+            state.shown = show;
+            let (far_view, chunks_visibility) = if show {
+                (Visibility::Inherited, Visibility::Hidden)
+            }
+            else {
+                (Visibility::Hidden, Visibility::Inherited)
+            };
+            commands.entity(entity).try_insert(far_view);
+            // Hides all chunks as they are its descendants
+            commands.entity(renderer.root_chunk).try_insert(chunks_visibility);
+        }
+
+        let (Some((camera_pos, max_distance)), true) = (closest_camera, show)
+        else { continue; };
+        let Ok(mut transform) = transforms.get_mut(entity)
+        else { continue; };
+        // Scaled around the camera so it looks the same from there, with its
+        // farthest point at the max distance
+        let scale = (max_distance / (camera_pos.distance(center) + radius)).min(1.);
+        let global = DAffine3::from_translation(camera_pos * (1. - scale))
+            * DAffine3::from_scale(DVec3::splat(scale))
+            * renderer_trans.affine()
+            * DAffine3::from_translation(marching_cubes::mesh_origin(&CellPath::new(), root_aabb));
+        *transform = Transform64::from(GlobalTransform64::from(
+            renderer_trans.affine().inverse() * global
+        ));
+    }
+}
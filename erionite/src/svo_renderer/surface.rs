@@ -0,0 +1,140 @@
+use bevy::{ecs::system::SystemParam, math::{DQuat, DVec3}, prelude::*};
+use doprec::{GlobalTransform64, Transform64};
+use svo::TrilinearSample;
+use utils::DAabb;
+
+use super::{ChunkComponent, SvoRendererComponent};
+
+/// Distances of the terrain are not exact so the ray marching steps only
+/// by this fraction of them
+const STEP_FACTOR: f64 = 0.5;
+/// Minimum step, relative to the size of the renderer's root aabb
+const MIN_RELATIVE_STEP: f64 = 1e-6;
+/// Bisection iterations done once the surface has been crossed
+const REFINE_ITERATIONS: u32 = 24;
+
+/// A point on the surface of the terrain of a renderer, see [TerrainSurface]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfacePoint {
+    /// World position of the point
+    pub position: DVec3,
+    /// Direction away from the renderer's center, where gravity points away
+    /// from on planets
+    pub up: DVec3,
+}
+
+impl SurfacePoint {
+    /// Transform on the point with its Y axis aligned with [Self::up], so
+    /// objects spawned with it stand upright on the terrain
+    pub fn transform(&self) -> Transform64 {
+        Transform64 {
+            translation: self.position,
+            rotation: DQuat::from_rotation_arc(DVec3::Y, self.up),
+            ..default()
+        }
+    }
+}
+
+/// Finds points on the terrain of [SvoRendererComponent]s using the data of
+/// their chunks, so only the terrain of loaded chunks can be found, with the
+/// precision of the chunk it is in
+#[derive(SystemParam)]
+pub struct TerrainSurface<'w, 's> {
+    renderers: Query<'w, 's, (&'static SvoRendererComponent, &'static GlobalTransform64)>,
+    chunks: Query<'w, 's, &'static ChunkComponent>,
+}
+
+impl<'w, 's> TerrainSurface<'w, 's> {
+    /// Samples the terrain of the most detailed loaded chunk containing the
+    /// given position (in the renderer's space)
+    fn sample(
+        &self, renderer: &SvoRendererComponent, pos: DVec3,
+    ) -> Option<TrilinearSample> {
+        let root_aabb = renderer.options.root_aabb;
+        let mut entity = renderer.root_chunk;
+        let mut sample = None;
+        while let Ok(chunk) = self.chunks.get(entity) {
+            if let Some(data) = &chunk.data {
+                sample = svo::sample_trilinear(&data.data, root_aabb, pos).or(sample);
+            }
+            let Some(children) = chunk.chunk_children
+            else { break; };
+            let child = children.into_iter().find(|&child| self.chunks.get(child)
+                .is_ok_and(|child| contains(child.path.get_aabb(root_aabb), pos)));
+            let Some(child) = child
+            else { break; };
+            entity = child;
+        }
+        sample
+    }
+
+    /// Finds the outermost surface point of the renderer's terrain in the
+    /// given direction (in world space) from the center of its root aabb,
+    /// which can be given as a latitude and longitude with
+    /// [utils::direction_from_lat_lon].
+    ///
+    /// Returns None if the ray goes through a region without loaded chunks
+    /// before the surface, or if there is no surface in this direction.
+    pub fn surface_in_direction(
+        &self, renderer_entity: Entity, direction: DVec3,
+    ) -> Option<SurfacePoint> {
+        let (renderer, renderer_trans) = self.renderers.get(renderer_entity).ok()?;
+        let root_aabb = renderer.options.root_aabb;
+        let center = root_aabb.position + root_aabb.size / 2.;
+        let affine = renderer_trans.affine();
+        let direction = affine.inverse().transform_vector3(direction)
+            .try_normalize()?;
+
+        // Marches from where the ray leaves the root aabb towards the center
+        let half_size = root_aabb.size / 2.;
+        let exit = (half_size / direction.abs()).min_element() * 0.999;
+        let min_step = root_aabb.size.max_element() * MIN_RELATIVE_STEP;
+
+        let at = |t: f64| center + direction * t;
+        let mut outside = exit;
+        let mut t = exit;
+        loop {
+            let sample = self.sample(renderer, at(t))?;
+            if sample.distance <= 0. {
+                break;
+            }
+            outside = t;
+            if t <= 0. {
+                return None;
+            }
+            t = (t - (sample.distance * STEP_FACTOR).max(min_step)).max(0.);
+        }
+
+        let mut inside = t;
+        for _ in 0..REFINE_ITERATIONS {
+            let middle = (inside + outside) / 2.;
+            if self.sample(renderer, at(middle))?.distance <= 0. {
+                inside = middle;
+            }
+            else {
+                outside = middle;
+            }
+        }
+
+        Some(SurfacePoint {
+            position: affine.transform_point3(at(outside)),
+            up: affine.transform_vector3(direction).normalize(),
+        })
+    }
+
+    /// Like [Self::surface_in_direction] with the direction to the given
+    /// world position, finding the surface point 'under' it
+    pub fn surface_under(
+        &self, renderer_entity: Entity, position: DVec3,
+    ) -> Option<SurfacePoint> {
+        let (renderer, renderer_trans) = self.renderers.get(renderer_entity).ok()?;
+        let root_aabb = renderer.options.root_aabb;
+        let center = renderer_trans.affine()
+            .transform_point3(root_aabb.position + root_aabb.size / 2.);
+        self.surface_in_direction(renderer_entity, position - center)
+    }
+}
+
+fn contains(aabb: DAabb, pos: DVec3) -> bool {
+    pos.cmpge(aabb.min()).all() && pos.cmple(aabb.max()).all()
+}
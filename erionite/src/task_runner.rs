@@ -1,6 +1,15 @@
 mod task;
 pub use task::*;
+mod progressive;
+pub use progressive::*;
 
+use bevy::tasks::{AsyncComputeTaskPool, TaskPool};
+
+/// Runs the function on bevy's [AsyncComputeTaskPool]
+///
+/// The function is not run if the returned task is dropped (and so
+/// canceled) before it starts, tasks stored in a component are thus canceled
+/// when its entity is despawned.
 pub fn spawn<T, F>(f: F) -> Task<T>
     where T: Send + Sync + 'static,
           F: FnOnce() -> T + Send + Sync + 'static,
@@ -8,13 +17,13 @@ pub fn spawn<T, F>(f: F) -> Task<T>
     let task = Task::new();
     let handle = task.handle();
 
-    rayon::spawn(move || {
+    AsyncComputeTaskPool::get_or_init(TaskPool::default).spawn(async move {
         if handle.canceled() {
             return;
         }
         let out = f();
         handle.finish(out);
-    });
+    }).detach();
 
     task
 }
@@ -0,0 +1,104 @@
+use std::{ops::Deref, sync::{Arc, Mutex}};
+
+use super::{Task, TaskHandle};
+
+/// A [Task] that can also give intermediate results of type `P` before its
+/// final one, e.g. coarser versions of it
+///
+/// Like the task it derefs to, all clones dropped -> task cancelled.
+#[derive(Debug)]
+pub struct ProgressiveTask<T, P> {
+    task: Task<T>,
+    partial: Arc<Mutex<Option<P>>>,
+}
+
+impl<T, P> Default for ProgressiveTask<T, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, P> ProgressiveTask<T, P> {
+    pub fn new() -> Self {
+        Self::from_task(Task::new())
+    }
+
+    /// Progressive task that never gives any intermediate result
+    pub fn from_task(task: Task<T>) -> Self {
+        Self {
+            task,
+            partial: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn handle(&self) -> ProgressHandle<T, P> {
+        ProgressHandle {
+            handle: self.task.handle(),
+            partial: Arc::clone(&self.partial),
+        }
+    }
+
+    /// Takes the latest intermediate result, None if none was reported since
+    /// the last call
+    pub fn take_partial(&self) -> Option<P> {
+        self.partial.lock().unwrap().take()
+    }
+
+    /// Like [ProgressiveTask::take_partial] but leaves the intermediate
+    /// result in place when the predicate is false
+    pub fn take_partial_if(&self, predicate: impl FnOnce(&P) -> bool) -> Option<P> {
+        self.partial.lock().unwrap().take_if(|partial| predicate(partial))
+    }
+
+    /// The task of the final result only
+    pub fn into_task(self) -> Task<T> {
+        self.task
+    }
+}
+
+impl<T, P> Clone for ProgressiveTask<T, P> {
+    fn clone(&self) -> Self {
+        Self {
+            task: self.task.clone(),
+            partial: Arc::clone(&self.partial),
+        }
+    }
+}
+
+impl<T, P> Deref for ProgressiveTask<T, P> {
+    type Target = Task<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.task
+    }
+}
+
+/// [TaskHandle] of a [ProgressiveTask]
+#[derive(Debug, Clone)]
+pub struct ProgressHandle<T, P> {
+    handle: TaskHandle<T>,
+    partial: Arc<Mutex<Option<P>>>,
+}
+
+impl<T, P> ProgressHandle<T, P> {
+    /// Replaces the latest intermediate result, the previous one is lost if
+    /// it was not taken yet
+    pub fn report(&self, partial: P) {
+        *self.partial.lock().unwrap() = Some(partial);
+    }
+
+    pub fn upgrade(&self) -> Option<ProgressiveTask<T, P>> {
+        Some(ProgressiveTask {
+            task: self.handle.upgrade()?,
+            partial: Arc::clone(&self.partial),
+        })
+    }
+}
+
+impl<T, P> Deref for ProgressHandle<T, P> {
+    type Target = TaskHandle<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
@@ -0,0 +1,208 @@
+//! Compares the forces of the svo approximation with direct summation on a
+//! plummer sphere, for several opening angles and minimum affect distances,
+//! and prints the results as csv
+//!
+//! `cargo run --release -p nbody --features examples --example svo_accuracy -- [bodies] [steps] [seed]`
+//!
+//! Every configuration runs the same bodies for the same number of steps.
+//! At each step the svo forces of all bodies are compared with the exact
+//! ones at the same positions (so errors don't accumulate in the
+//! comparison), and the drift of the total energy is measured over the
+//! whole run. The `direct` rows run without the svo and are the reference
+//! for the energy drift and the step duration.
+
+use std::time::{Duration, Instant};
+
+use bevy::{math::DVec3, prelude::*};
+use doprec::{DoprecPlugin, GlobalTransform64, Transform64, Transform64Bundle};
+use nbody::initial_conditions::{InitialBody, PlummerSphere};
+use rand::SeedableRng;
+
+const GRAVITY_CONSTANT: f64 = nbody::GravityUnits::Natural.gravity_constant();
+/// In the time unit of the plummer sphere, whose crossing time is about 1
+const TIME_STEP: f64 = 1. / 256.;
+const OPENING_ANGLES: [f64; 5] = [0.3, 0.5, 0.7, 1., 1.5];
+/// The softening of this crate: closer attractors are ignored, see
+/// [nbody::GravityFieldSample::min_affect_distance]
+const MIN_AFFECT_DISTANCES: [f64; 3] = [0., 0.01, 0.05];
+
+#[derive(Debug, Clone, Copy)]
+struct RunConfig {
+    /// Direct summation if None
+    opening_angle: Option<f64>,
+    min_affect_distance: f64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct RunResult {
+    /// Root mean square of the relative force errors of all bodies at all
+    /// steps
+    rms_force_error: f64,
+    max_force_error: f64,
+    /// Relative change of the total energy between the first and last step
+    energy_drift: f64,
+    mean_step_ms: f64,
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1)
+        .map(|arg| arg.parse::<u64>().expect("arguments are [bodies] [steps] [seed]"));
+    let body_count = args.next().unwrap_or(1000) as usize;
+    let steps = args.next().unwrap_or(200) as usize;
+    let seed = args.next().unwrap_or(0);
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let bodies = PlummerSphere { count: body_count, ..default() }
+        .generate(&mut rng, GRAVITY_CONSTANT);
+
+    let configs = MIN_AFFECT_DISTANCES.into_iter().flat_map(|min_affect_distance| {
+        std::iter::once(None).chain(OPENING_ANGLES.map(Some))
+            .map(move |opening_angle| RunConfig { opening_angle, min_affect_distance })
+    });
+
+    println!("opening_angle,min_affect_distance,rms_force_error,max_force_error,energy_drift,mean_step_ms");
+    for config in configs {
+        let result = run(&bodies, steps, config);
+        let opening_angle = config.opening_angle
+            .map_or_else(|| "direct".to_string(), |angle| angle.to_string());
+        println!(
+            "{opening_angle},{},{:e},{:e},{:e},{:.3}",
+            config.min_affect_distance, result.rms_force_error,
+            result.max_force_error, result.energy_drift, result.mean_step_ms,
+        );
+    }
+}
+
+fn run(bodies: &[InitialBody], steps: usize, config: RunConfig) -> RunResult {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        DoprecPlugin::default(),
+        nbody::NBodyPlugin,
+    ));
+    app.insert_resource(nbody::GravityConfig {
+        gravity_constant: GRAVITY_CONSTANT,
+        enabled_svo: config.opening_angle.is_some(),
+        managed_varying_timesteps: false,
+        svo_skip_config: nbody::SvoSkipConfig {
+            opening_angle: config.opening_angle.unwrap_or_default(),
+        },
+        // Measured here for all bodies
+        svo_error_monitor: nbody::SvoErrorMonitorConfig {
+            interval: 0,
+            ..default()
+        },
+        gravity_field_sample_backlog_count: 2,
+        ..default()
+    });
+    app.update();
+
+    for body in bodies {
+        app.world.spawn((
+            Transform64Bundle {
+                local: Transform64::from_translation(body.position),
+                ..default()
+            },
+            nbody::Velocity { velocity: body.velocity },
+            nbody::GravityFieldSample::default()
+                .with_min_affect_distance(config.min_affect_distance),
+            nbody::Massive { mass: body.mass },
+            nbody::Attracted,
+            nbody::Attractor::default(),
+            nbody::IntegratedMotion::default(),
+        ));
+    }
+    app.world.run_schedule(PostUpdate);
+
+    let initial_energy = total_energy(&mut app.world, config.min_affect_distance);
+    let mut error_sum = 0.;
+    let mut error_count = 0usize;
+    let mut max_force_error = 0f64;
+    let mut step_duration = Duration::ZERO;
+    for _ in 0..steps {
+        app.world.resource_mut::<Time<Fixed>>()
+            .advance_by(Duration::from_secs_f64(TIME_STEP));
+        let start = Instant::now();
+        app.world.run_schedule(FixedUpdate);
+        step_duration += start.elapsed();
+
+        // Global transforms are only propagated after the step, so they are
+        // still the positions the forces were computed at
+        let state = app.world
+            .query::<(Entity, &GlobalTransform64, &nbody::Massive, &nbody::GravityFieldSample)>()
+            .iter(&app.world)
+            .map(|(entity, transform, massive, sample)| {
+                (entity, transform.translation(), massive.mass, sample.field_force(0))
+            })
+            .collect::<Vec<_>>();
+        for &(entity, position, _, force) in &state {
+            let Some(force) = force
+            else { continue; };
+            let attractors = state.iter()
+                .map(|&(entity, position, mass, _)| (entity, position, mass));
+            let exact = exact_field_force(entity, position, attractors, config.min_affect_distance);
+            if exact == DVec3::ZERO {
+                continue;
+            }
+            let error = (force - exact).length() / exact.length();
+            error_sum += error * error;
+            error_count += 1;
+            max_force_error = max_force_error.max(error);
+        }
+
+        app.world.run_schedule(PostUpdate);
+    }
+    let final_energy = total_energy(&mut app.world, config.min_affect_distance);
+
+    RunResult {
+        rms_force_error: (error_sum / error_count.max(1) as f64).sqrt(),
+        max_force_error,
+        energy_drift: ((final_energy - initial_energy) / initial_energy).abs(),
+        mean_step_ms: step_duration.as_secs_f64() * 1000. / steps.max(1) as f64,
+    }
+}
+
+/// Force the same way [nbody] computes it without the svo
+fn exact_field_force(
+    victim: Entity,
+    victim_pos: DVec3,
+    attractors: impl Iterator<Item = (Entity, DVec3, f64)>,
+    min_affect_distance: f64,
+) -> DVec3 {
+    attractors
+        .filter(|(entity, ..)| *entity != victim)
+        .map(|(_, position, mass)| {
+            let diff = position - victim_pos;
+            let distance = diff.length();
+            if distance <= min_affect_distance || distance == 0. {
+                return DVec3::ZERO;
+            }
+            diff / distance * GRAVITY_CONSTANT * mass / (distance * distance)
+        })
+        .sum()
+}
+
+/// Kinetic plus potential energy, pairs closer than the minimum affect
+/// distance don't attract each other so they have no potential energy
+fn total_energy(world: &mut World, min_affect_distance: f64) -> f64 {
+    let bodies = world.query::<(&GlobalTransform64, &nbody::Velocity, &nbody::Massive)>()
+        .iter(world)
+        .map(|(transform, velocity, massive)| {
+            (transform.translation(), velocity.velocity, massive.mass)
+        })
+        .collect::<Vec<_>>();
+
+    let kinetic = bodies.iter()
+        .map(|(_, velocity, mass)| 0.5 * mass * velocity.length_squared())
+        .sum::<f64>();
+    let mut potential = 0.;
+    for (i, (a_pos, _, a_mass)) in bodies.iter().enumerate() {
+        for (b_pos, _, b_mass) in &bodies[i + 1..] {
+            let distance = a_pos.distance(*b_pos);
+            if distance > min_affect_distance && distance > 0. {
+                potential -= GRAVITY_CONSTANT * a_mass * b_mass / distance;
+            }
+        }
+    }
+    kinetic + potential
+}
@@ -4,8 +4,14 @@ mod systems;
 pub use systems::*;
 mod tree_acceleration;
 use tree_acceleration::*;
+mod force_accumulator;
+use force_accumulator::*;
 mod resources;
 pub use resources::*;
+mod neighbors;
+pub use neighbors::*;
+mod sphere_of_influence;
+pub use sphere_of_influence::*;
 
 use bevy::diagnostic::DiagnosticPath;
 
@@ -13,6 +19,13 @@ pub const GRAVITY_COMPUTE_SYSTEM_DURATION: DiagnosticPath =
     DiagnosticPath::const_new("gravity_compute");
 pub const GRAVITY_SVO_UPDATE_SYSTEM_DURATION: DiagnosticPath =
     DiagnosticPath::const_new("svo_update_compute");
+/// Duration of the integration of [IntegratedMotion] bodies
+pub const GRAVITY_INTEGRATION_SYSTEM_DURATION: DiagnosticPath =
+    DiagnosticPath::const_new("gravity_integration");
+/// Mean relative error of the svo forces measured by
+/// [monitor_svo_error_system], see [SvoErrorMonitorConfig]
+pub const GRAVITY_SVO_RELATIVE_ERROR: DiagnosticPath =
+    DiagnosticPath::const_new("svo_relative_error");
 
 /// If set to true, when visiting the svo, cells that contains the current particle
 /// will always be visited
@@ -21,6 +34,10 @@ const SHOULD_CORRECT_STATS_ON_OWN_CELL: bool = false;
 
 const DEFAULT_THETA: f64 = 0.5;
 
+/// Fraction of an [Attractor::influence_radius] over which the force of the
+/// attractor smoothly decreases to zero, see [influence_factor]
+pub const INFLUENCE_CUTOFF_WIDTH: f64 = 0.2;
+
 /// If an svo has more than this amount of particles it is splitted if the
 /// max depth has not been reached
 const SVO_LEAF_MAX_PARTICLE_COUNT: usize = 50;
@@ -1,11 +1,78 @@
+use std::collections::VecDeque;
+
 use bevy::{math::DVec3, prelude::*};
-use utils::SmallVec;
+use super::INFLUENCE_CUTOFF_WIDTH;
 
+/// Mass of a body, can be negative to make an [Attractor] repulsive
 #[derive(Component, Default, Debug, Clone, Copy, PartialEq)]
 pub struct Massive {
     pub mass: f64,
 }
 
+impl Massive {
+    /// Checks that the mass can be used by an [Attractor]
+    pub fn validate(&self) -> Result<(), MassError> {
+        if !self.mass.is_finite() {
+            return Err(MassError::NotFinite(self.mass));
+        }
+        if self.mass == 0. {
+            return Err(MassError::Zero);
+        }
+        Ok(())
+    }
+}
+
+/// Why a [Massive] is invalid, see [Massive::validate]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MassError {
+    /// Massless attractors have no effect and are rejected
+    Zero,
+    NotFinite(f64),
+}
+
+impl std::fmt::Display for MassError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Zero => write!(f, "attractors cannot have a zero mass"),
+            Self::NotFinite(mass) => write!(f, "mass {mass} is not finite"),
+        }
+    }
+}
+
+impl std::error::Error for MassError {}
+
+/// Added along with [Disabled](crate::Disabled) to bodies whose position or
+/// velocity stopped being finite, which would otherwise corrupt the svo and
+/// so the force of every other body, see [BodyQuarantined]
+///
+/// Removing both components (once the body is fixed) puts it back in the
+/// simulation.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Quarantined(pub QuarantineReason);
+
+/// Why a body was [Quarantined]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuarantineReason {
+    Position(DVec3),
+    Velocity(DVec3),
+}
+
+impl std::fmt::Display for QuarantineReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Position(position) => write!(f, "position {position} is not finite"),
+            Self::Velocity(velocity) => write!(f, "velocity {velocity} is not finite"),
+        }
+    }
+}
+
+/// Sent when a body gets [Quarantined]
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct BodyQuarantined {
+    pub entity: Entity,
+    pub reason: QuarantineReason,
+}
+
 /// Spatial entities with this component will have it updated with the
 /// total gravital force of all Attractors on its position.
 ///
@@ -13,10 +80,10 @@ pub struct Massive {
 #[derive(getset::Getters, Component, Debug, Default, PartialEq, Clone)]
 #[getset(get = "pub")]
 pub struct GravityFieldSample {
-    /// List of samples of the field force were the last one is the latest one
-    /// the second-to-last one is the previous one etc... up to the limit set
-    /// in the [GravityConfig]
-    field_forces: SmallVec<[DVec3; 1]>,
+    /// Ring buffer of the samples of the field force were the last one is the
+    /// latest one the second-to-last one is the previous one etc... up to the
+    /// limit set in [GravityConfig::gravity_field_sample_backlog_count]
+    samples: VecDeque<FieldForceSample>,
     pub(crate) closest_attractor: Option<AttractorInfo>,
     /// Any attractor closer than this distance do not count for the field_force
     /// (still for closest_attractor)
@@ -33,30 +100,189 @@ impl GravityFieldSample {
         }
     }
 
-    pub(crate) fn new_field_force(&mut self, force: DVec3, count_limit: usize) {
-        // Most of the time only one force will be removed so no perf problem
-        // can arise from this not 'bulk' removing them
-        while self.field_forces.len()+1 > count_limit {
-            self.field_forces.remove(0);
+    pub(crate) fn new_field_force(&mut self, force: DVec3, time: f64, count_limit: usize) {
+        // Most of the time only one force will be removed
+        while self.samples.len() >= count_limit.max(1) {
+            self.samples.pop_front();
         }
-        self.field_forces.push(force);
+        self.samples.push_back(FieldForceSample { force, time });
+    }
+
+    /// Adds the given force to the latest field force, if any
+    pub(crate) fn correct_latest_field_force(&mut self, correction: DVec3) {
+        if let Some(latest) = self.samples.back_mut() {
+            latest.force += correction;
+        }
+    }
+
+    /// Scales the latest field force down to the given length if it is
+    /// longer
+    pub(crate) fn clamp_latest_field_force(&mut self, max_length: f64) {
+        if let Some(latest) = self.samples.back_mut() {
+            latest.force = latest.force.clamp_length_max(max_length);
+        }
+    }
+
+    /// All kept field forces, from the oldest to the latest
+    pub fn field_forces(&self) -> impl ExactSizeIterator<Item = DVec3> + DoubleEndedIterator + '_ {
+        self.samples.iter().map(|sample| sample.force)
+    }
+
+    /// Returns the nth latest computed sample
+    /// So 0 is the latest and 1 the previous one
+    pub fn sample(&self, go_back: usize) -> Option<FieldForceSample> {
+        self.samples.iter().rev().nth(go_back).copied()
     }
 
     /// Returns the nth latest computed force
     /// So 0 is the latest and 1 the previous one
     pub fn field_force(&self, go_back: usize) -> Option<DVec3> {
-        if go_back >= self.field_forces.len() {
-            None
+        self.sample(go_back).map(|sample| sample.force)
+    }
+
+    /// Field force at the given time, linearly interpolated between the two
+    /// samples around it, or extrapolated from the closest two
+    ///
+    /// Useful for bodies with a [TimeStep] whose samples are not computed on
+    /// every step.
+    pub fn interpolated_field_force(&self, time: f64) -> Option<DVec3> {
+        let (a, b) = match self.samples.len() {
+            0 => return None,
+            1 => return self.field_force(0),
+            len => {
+                let after = self.samples.iter()
+                    .position(|sample| sample.time >= time)
+                    .unwrap_or(len - 1)
+                    .max(1);
+                (self.samples[after - 1], self.samples[after])
+            },
+        };
+        let dt = b.time - a.time;
+        if dt <= 0. {
+            return Some(b.force);
         }
-        else {
-            Some(self.field_forces[self.field_forces.len() - go_back - 1])
+        Some(a.force.lerp(b.force, (time - a.time) / dt))
+    }
+}
+
+/// A field force of a [GravityFieldSample] along with when it was computed
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FieldForceSample {
+    pub force: DVec3,
+    /// Elapsed time of the [FixedUpdate] schedule, in seconds, when the
+    /// force was computed
+    pub time: f64,
+}
+
+/// Linear velocity of a body.
+/// Only integrated by this crate for [IntegratedMotion] bodies but always
+/// part of the simulation state saved by [GravitySnapshot](crate::GravitySnapshot)
+#[derive(Component, Default, Debug, Clone, Copy, PartialEq)]
+pub struct Velocity {
+    pub velocity: DVec3,
+}
+
+/// Makes [GravityStage::Integrate](crate::GravityStage::Integrate) move the
+/// body (its [Transform64](doprec::Transform64), so it should not have a
+/// parent) and update its [Velocity] with its [GravityFieldSample] and
+/// [ExternalAcceleration].
+///
+/// Integrated with velocity verlet, whose second half of the velocity update
+/// needs the acceleration at the new position: it is only done on the next
+/// step once its field force is known, so [Velocity] is the velocity at the
+/// position of the latest field force, one step behind the
+/// [Transform64](doprec::Transform64).
+#[derive(getset::CopyGetters, Component, Default, Debug, Clone, Copy, PartialEq)]
+#[getset(get_copy = "pub")]
+pub struct IntegratedMotion {
+    /// Total acceleration (gravity and [ExternalAcceleration]) of the last
+    /// step, used to finish its velocity update
+    previous_acceleration: Option<DVec3>,
+}
+
+impl IntegratedMotion {
+    /// Resumes the integration of a body whose last step is known, e.g. when
+    /// loading a [GravitySnapshot](crate::GravitySnapshot)
+    pub fn with_previous_acceleration(self, previous_acceleration: Option<DVec3>) -> Self {
+        Self {
+            previous_acceleration,
+            ..self
         }
     }
 }
 
+/// Non-gravity accelerations (thrust, drag...) and impulses accumulated
+/// during a step and applied by the integration of [IntegratedMotion]
+/// bodies along with gravity, after which they are cleared.
+#[derive(Component, Default, Debug, Clone, Copy, PartialEq)]
+pub struct ExternalAcceleration {
+    /// Constant over the step
+    pub acceleration: DVec3,
+    /// Instantly added to the velocity at the start of the step
+    pub velocity_change: DVec3,
+}
+
+impl ExternalAcceleration {
+    pub fn add_acceleration(&mut self, acceleration: DVec3) {
+        self.acceleration += acceleration;
+    }
+
+    /// Adds the acceleration the given force gives to a body of the given mass
+    pub fn add_force(&mut self, force: DVec3, mass: f64) {
+        self.acceleration += force / mass;
+    }
+
+    /// Adds the velocity change the given impulse gives to a body of the
+    /// given mass
+    pub fn add_impulse(&mut self, impulse: DVec3, mass: f64) {
+        self.velocity_change += impulse / mass;
+    }
+
+    pub fn clear(&mut self) {
+        *self = default();
+    }
+}
+
 #[derive(Component, Debug, Default, Clone)]
 pub struct Attractor {
     pub last_svo_position: Option<svo::CellPath>,
+    /// If set, bodies further than this distance are not affected at all by
+    /// this attractor, which lets the svo skip it entirely for them.
+    /// See [influence_factor] for how the force goes to zero.
+    ///
+    /// The cutoff is not applied when the attractor is approximated together
+    /// with attractors of unlimited influence (in far away svo cells).
+    pub influence_radius: Option<f64>,
+}
+
+impl Attractor {
+    /// Sets [Self::influence_radius]
+    pub fn with_influence_radius(self, radius: f64) -> Self {
+        Self {
+            influence_radius: Some(radius),
+            ..self
+        }
+    }
+}
+
+/// Factor applied to the force of an attractor with the given
+/// [Attractor::influence_radius] at the given distance.
+///
+/// It is 1 up to the last [INFLUENCE_CUTOFF_WIDTH] fraction of the radius
+/// and then smoothly decreases to 0 at the radius, so that the force stays
+/// continuous.
+pub fn influence_factor(influence_radius: Option<f64>, distance: f64) -> f64 {
+    let Some(radius) = influence_radius
+    else { return 1.; };
+    let start = radius * (1. - INFLUENCE_CUTOFF_WIDTH);
+    if distance <= start {
+        return 1.;
+    }
+    if distance >= radius {
+        return 0.;
+    }
+    let t = (distance - start) / (radius - start);
+    1. - t * t * (3. - 2. * t)
 }
 
 #[derive(Debug, Clone, Copy,PartialEq)]
@@ -70,6 +296,66 @@ pub struct AttractorInfo {
 #[getset(get_copy = "pub")]
 pub struct Attracted;
 
+/// Optional component for entities whose motion is dominated by a single
+/// attractor (e.g. a moon around its planet).
+///
+/// The pull of the given attractor is computed analytically from its exact
+/// position and mass and is excluded from the svo approximation, which then
+/// only provides the perturbations from every other attractor.
+/// Has no effect when the svo is disabled as all forces are then exact.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DominantAttractor(pub Entity);
+
+/// Optional component overriding how accurately the svo is traversed for the
+/// [GravityFieldSample] of the entity, e.g. to make cosmetic samples like the
+/// camera's cheaper than the ones of physics bodies
+///
+/// Has no effect when the svo is disabled as all forces are then exact.
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
+pub struct SampleQuality {
+    /// Cells at this depth are never opened, whatever the opening angle
+    pub max_depth: Option<u32>,
+    /// Replaces [SvoSkipConfig::opening_angle](crate::SvoSkipConfig::opening_angle),
+    /// higher is coarser
+    pub opening_angle: Option<f64>,
+}
+
+/// Aggregates the [Attractor]s with an [AttractorGroupMember] pointing to
+/// this entity into a single virtual body, e.g. thousands of asteroid
+/// fragments orbiting together.
+///
+/// Samples at least [Self::far_distance] away from the group's center of mass
+/// only see its total mass and skip the members entirely, whatever the svo
+/// opening angle. Members are not in the svo and closer samples compute each
+/// of them exactly.
+/// The [Attractor::influence_radius] of members is ignored when far away.
+#[derive(getset::CopyGetters, Component, Debug, Clone, Copy, PartialEq)]
+#[getset(get_copy = "pub")]
+pub struct VirtualAttractorGroup {
+    #[getset(skip)]
+    pub far_distance: f64,
+    /// Total mass of the members, updated every gravity step
+    pub(crate) mass: f64,
+    pub(crate) center_of_mass: DVec3,
+    pub(crate) member_count: usize,
+}
+
+impl VirtualAttractorGroup {
+    pub fn new(far_distance: f64) -> Self {
+        Self {
+            far_distance,
+            mass: 0.,
+            center_of_mass: DVec3::ZERO,
+            member_count: 0,
+        }
+    }
+}
+
+/// Makes the [Attractor] part of the given [VirtualAttractorGroup] entity,
+/// if it exists
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttractorGroupMember(pub Entity);
+
 /// Optional component that if added will make the current entity skip timesteps
 #[derive(getset::CopyGetters, Component, Debug, Clone, Copy, derivative::Derivative)]
 #[derivative(Default)]
@@ -84,3 +370,174 @@ pub struct TimeStep {
     /// Wether or not the last update didn't skip this entity
     pub(crate) last_updated: bool,
 }
+
+/// Optional component that makes the field force of the entity be computed
+/// less often while it is weak and steady, e.g. for particles far from every
+/// attractor.
+///
+/// After [Self::calm_steps_before_sleep] computed forces under
+/// [Self::max_force] that changed by less than [Self::max_force_change] per
+/// step, the entity falls asleep and its force is only computed every
+/// [Self::sample_interval] steps, being extrapolated from the last two
+/// computed ones in between, whatever the length of the
+/// [GravityFieldSample] history.
+/// It wakes up as soon as a computed force is over the thresholds, its
+/// closest attractor changes, or gets [Self::wake_distance_ratio] closer.
+#[derive(getset::CopyGetters, Component, Debug, Clone, Copy, derivative::Derivative)]
+#[derivative(Default)]
+#[getset(get_copy = "pub")]
+pub struct GravitySleep {
+    #[getset(skip)]
+    #[derivative(Default(value = "0.01"))]
+    pub max_force: f64,
+    #[getset(skip)]
+    #[derivative(Default(value = "0.0001"))]
+    pub max_force_change: f64,
+    #[getset(skip)]
+    #[derivative(Default(value = "60"))]
+    pub calm_steps_before_sleep: u32,
+    #[getset(skip)]
+    #[derivative(Default(value = "8"))]
+    pub sample_interval: u32,
+    /// Fraction of the distance to the closest attractor when falling asleep
+    #[getset(skip)]
+    #[derivative(Default(value = "0.25"))]
+    pub wake_distance_ratio: f64,
+
+    /// Number of consecutive calm forces
+    #[getset(skip)]
+    pub(crate) calm_steps: u32,
+    pub(crate) asleep: bool,
+    #[getset(skip)]
+    pub(crate) steps_since_sample: u32,
+    /// Latest computed (not extrapolated) force
+    #[getset(skip)]
+    pub(crate) last_force: Option<FieldForceSample>,
+    /// Computed force before [Self::last_force]
+    #[getset(skip)]
+    pub(crate) previous_force: Option<FieldForceSample>,
+    /// Closest attractor when falling asleep
+    #[getset(skip)]
+    pub(crate) sleep_attractor: Option<AttractorInfo>,
+    /// Wether the last update computed the force instead of extrapolating it
+    #[derivative(Default(value = "true"))]
+    pub(crate) last_sampled: bool,
+}
+
+impl GravitySleep {
+    pub(crate) fn wake_up(&mut self) {
+        self.asleep = false;
+        self.calm_steps = 0;
+        self.sleep_attractor = None;
+    }
+
+    /// Whether the force must be computed this update, otherwise the caller
+    /// should call [Self::extrapolate] instead
+    ///
+    /// `attractor_distance_squared` gives the current squared distance to an
+    /// attractor, if known.
+    pub(crate) fn should_sample(
+        &mut self,
+        attractor_distance_squared: impl FnOnce(Entity) -> Option<f64>,
+    ) -> bool {
+        if !self.asleep {
+            return true;
+        }
+        self.steps_since_sample += 1;
+
+        if let Some(info) = self.sleep_attractor {
+            let max_squared_ratio = (1. - self.wake_distance_ratio).max(0.).powi(2);
+            let came_closer = attractor_distance_squared(info.entity)
+                .is_some_and(|d| d < info.squared_distance * max_squared_ratio);
+            if came_closer {
+                self.wake_up();
+                return true;
+            }
+        }
+
+        self.steps_since_sample >= self.sample_interval
+    }
+
+    /// Pushes the extrapolated force of the current time to the sample
+    pub(crate) fn extrapolate(
+        &mut self, sample: &mut GravityFieldSample, now: f64, count_limit: usize,
+    ) {
+        self.last_sampled = false;
+        let force = match (self.previous_force, self.last_force) {
+            (Some(previous), Some(last)) if last.time > previous.time => {
+                let slope = (last.force - previous.force) / (last.time - previous.time);
+                last.force + slope * (now - last.time)
+            },
+            (_, Some(last)) => last.force,
+            _ => return,
+        };
+        sample.new_field_force(force, now, count_limit);
+    }
+
+    /// Updates the sleep state with the force that was just computed
+    pub(crate) fn sampled(&mut self, sample: &GravityFieldSample) {
+        self.last_sampled = true;
+        let Some(latest) = sample.sample(0)
+        else { return; };
+        let force = latest.force;
+        let steps = std::mem::take(&mut self.steps_since_sample).max(1);
+        let change = self.last_force
+            .map_or(f64::INFINITY, |last| (force - last.force).length() / steps as f64);
+        self.previous_force = std::mem::replace(&mut self.last_force, Some(latest));
+
+        let is_calm = force.length() <= self.max_force && change <= self.max_force_change;
+        if !is_calm {
+            self.wake_up();
+            return;
+        }
+
+        if self.asleep {
+            let attractor_changed = self.sleep_attractor.map(|info| info.entity) !=
+                sample.closest_attractor.map(|info| info.entity);
+            if attractor_changed {
+                self.wake_up();
+            }
+            return;
+        }
+
+        self.calm_steps += 1;
+        if self.calm_steps >= self.calm_steps_before_sleep {
+            self.asleep = true;
+            self.sleep_attractor = sample.closest_attractor;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_sleep_extrapolation() {
+        let mut sleep = GravitySleep::default();
+        let mut sample = GravityFieldSample::default();
+
+        // Only one sample is kept, as with the default config
+        for (time, force) in [(0., DVec3::X), (1., DVec3::X * 1.5)] {
+            sample.new_field_force(force, time, 1);
+            sleep.sampled(&sample);
+        }
+        assert_eq!(sample.field_forces().len(), 1);
+
+        sleep.extrapolate(&mut sample, 3., 1);
+        assert!(!sleep.last_sampled);
+        assert_eq!(sample.sample(0), Some(FieldForceSample { force: DVec3::X * 2.5, time: 3. }));
+
+        // Extrapolated forces are not used as computed ones
+        sleep.extrapolate(&mut sample, 4., 1);
+        assert_eq!(sample.field_force(0), Some(DVec3::X * 3.));
+
+        // A single computed force is held
+        let mut sleep = GravitySleep::default();
+        let mut sample = GravityFieldSample::default();
+        sample.new_field_force(DVec3::Y, 0., 1);
+        sleep.sampled(&sample);
+        sleep.extrapolate(&mut sample, 2., 1);
+        assert_eq!(sample.field_force(0), Some(DVec3::Y));
+    }
+}
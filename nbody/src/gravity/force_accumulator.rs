@@ -0,0 +1,186 @@
+use bevy::math::DVec3;
+
+/// Sums the gravity of point masses on a victim, which is the hot loop of
+/// the field computations.
+///
+/// With the `simd` feature masses are buffered and evaluated 4 at a time,
+/// otherwise they are evaluated as soon as they are added. Results of
+/// both only differ by the order of the additions.
+pub(crate) struct ForceAccumulator {
+    victim_pos: DVec3,
+    gravity_constant: f64,
+    /// Masses closer than this are ignored, see
+    /// [GravityFieldSample::min_affect_distance](crate::GravityFieldSample::min_affect_distance)
+    min_affect_distance: f64,
+    total: DVec3,
+
+    #[cfg(feature = "simd")]
+    buffer: simd::Buffer,
+}
+
+impl ForceAccumulator {
+    pub fn new(victim_pos: DVec3, gravity_constant: f64, min_affect_distance: f64) -> Self {
+        Self {
+            victim_pos,
+            gravity_constant,
+            min_affect_distance,
+            total: DVec3::ZERO,
+
+            #[cfg(feature = "simd")]
+            buffer: Default::default(),
+        }
+    }
+
+    /// Adds the force of the given mass, which may already be multiplied by
+    /// an [influence_factor](crate::influence_factor)
+    #[cfg(not(feature = "simd"))]
+    #[inline]
+    pub fn add(&mut self, pos: DVec3, mass: f64) {
+        let diff = pos - self.victim_pos;
+        let squared_distance = diff.length_squared();
+        let distance = squared_distance.sqrt();
+        if distance > self.min_affect_distance {
+            let force = mass / squared_distance;
+            self.total += (diff / distance) * self.gravity_constant * force;
+        }
+    }
+
+    /// Adds the force of the given mass, which may already be multiplied by
+    /// an [influence_factor](crate::influence_factor)
+    #[cfg(feature = "simd")]
+    #[inline]
+    pub fn add(&mut self, pos: DVec3, mass: f64) {
+        if self.buffer.push(pos, mass) {
+            self.flush();
+        }
+    }
+
+    #[cfg(feature = "simd")]
+    fn flush(&mut self) {
+        self.total += self.buffer.drain(
+            self.victim_pos, self.gravity_constant, self.min_affect_distance,
+        );
+    }
+
+    /// Total force of all added masses
+    #[cfg_attr(not(feature = "simd"), allow(unused_mut))]
+    pub fn finish(mut self) -> DVec3 {
+        #[cfg(feature = "simd")]
+        self.flush();
+        self.total
+    }
+}
+
+#[cfg(feature = "simd")]
+mod simd {
+    use std::simd::{prelude::*, StdFloat};
+
+    use bevy::math::DVec3;
+
+    /// Number of masses evaluated at once
+    pub const LANES: usize = 4;
+    type F64s = Simd<f64, LANES>;
+
+    /// Structure of arrays of the masses waiting to be evaluated
+    #[derive(Default)]
+    pub struct Buffer {
+        x: [f64; LANES],
+        y: [f64; LANES],
+        z: [f64; LANES],
+        mass: [f64; LANES],
+        len: usize,
+    }
+
+    impl Buffer {
+        /// Returns true when the buffer is full and must be drained
+        #[inline]
+        pub fn push(&mut self, pos: DVec3, mass: f64) -> bool {
+            self.x[self.len] = pos.x;
+            self.y[self.len] = pos.y;
+            self.z[self.len] = pos.z;
+            self.mass[self.len] = mass;
+            self.len += 1;
+            self.len == LANES
+        }
+
+        /// Force of all buffered masses, emptying the buffer
+        pub fn drain(
+            &mut self,
+            victim_pos: DVec3,
+            gravity_constant: f64,
+            min_affect_distance: f64,
+        ) -> DVec3 {
+            if self.len == 0 {
+                return DVec3::ZERO;
+            }
+            // Unused lanes still hold the masses of the previous batch
+            let used = Mask::from_array(std::array::from_fn(|i| i < self.len));
+            self.len = 0;
+
+            let dx = F64s::from_array(self.x) - F64s::splat(victim_pos.x);
+            let dy = F64s::from_array(self.y) - F64s::splat(victim_pos.y);
+            let dz = F64s::from_array(self.z) - F64s::splat(victim_pos.z);
+            let squared_distance = dx * dx + dy * dy + dz * dz;
+            let distance = squared_distance.sqrt();
+
+            let force = F64s::from_array(self.mass) / squared_distance;
+            // Equivalent to the scalar (diff / distance) * G * force
+            let factor = F64s::splat(gravity_constant) * force / distance;
+            let affected = used & distance.simd_gt(F64s::splat(min_affect_distance));
+            let factor = affected.select(factor, F64s::splat(0.));
+
+            DVec3::new(
+                (dx * factor).reduce_sum(),
+                (dy * factor).reduce_sum(),
+                (dz * factor).reduce_sum(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_force_accumulator() {
+        let victim_pos = DVec3::new(0.5, -1., 2.);
+        let gravity_constant = 3.;
+        let min_affect_distance = 1.;
+
+        // Counts that are not multiples of the simd lanes count
+        for count in [0, 1, 3, 4, 5, 11, 64] {
+            let masses = (0..count).map(|i| {
+                let i = i as f64;
+                let direction = DVec3::new((i * 1.3).sin(), (i * 0.7).cos(), (i * 2.1).sin())
+                    .normalize();
+                // Some masses are too close to count, some are negative
+                let distance = if i % 4. == 1. { 0.5 } else { 2. + i };
+                let mass = if i % 3. == 0. { -1. - i } else { 2. + i };
+                (victim_pos + direction * distance, mass)
+            }).collect::<Vec<_>>();
+
+            let mut accumulator = ForceAccumulator::new(
+                victim_pos, gravity_constant, min_affect_distance,
+            );
+            let mut expected = DVec3::ZERO;
+            let mut magnitude = 0.;
+            for &(pos, mass) in &masses {
+                accumulator.add(pos, mass);
+
+                let diff = pos - victim_pos;
+                if diff.length() > min_affect_distance {
+                    let force = diff.normalize() * gravity_constant * mass / diff.length_squared();
+                    expected += force;
+                    magnitude += force.length();
+                }
+            }
+
+            let total = accumulator.finish();
+            assert!(
+                total.distance(expected) <= magnitude * 1e-12,
+                "{count} masses: {total} != {expected}",
+            );
+        }
+    }
+}
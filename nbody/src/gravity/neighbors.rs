@@ -0,0 +1,135 @@
+use super::*;
+
+use std::collections::BinaryHeap;
+
+use bevy::{math::DVec3, prelude::*};
+
+/// An attractor found by [GravitySvoContext::knn] or
+/// [GravitySvoContext::within_radius]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AttractorNeighbor {
+    pub entity: Entity,
+    /// Position of the attractor when the svo was last updated
+    pub position: DVec3,
+    pub mass: f64,
+    pub squared_distance: f64,
+}
+
+enum CandidateItem<'a, 'b> {
+    Cell(&'a svo::BumpCell<'b, SvoData>),
+    Attractor(&'a SvoEntityRepr),
+}
+
+/// Cell or attractor of the search, ordered so the closest is the greatest
+struct Candidate<'a, 'b> {
+    /// Distance to the attractor, or a lower bound of the distance of the
+    /// cell's attractors
+    squared_distance: f64,
+    item: CandidateItem<'a, 'b>,
+}
+
+impl PartialEq for Candidate<'_, '_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Candidate<'_, '_> {}
+
+impl PartialOrd for Candidate<'_, '_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate<'_, '_> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.squared_distance.total_cmp(&self.squared_distance)
+    }
+}
+
+impl<'a, 'b> Candidate<'a, 'b> {
+    fn cell(cell: &'a svo::BumpCell<'b, SvoData>, pos: DVec3) -> Self {
+        let aabb = match cell {
+            svo::Cell::Internal(internal) => internal.data.aabb,
+            svo::Cell::Leaf(leaf) => leaf.data.aabb,
+            svo::Cell::Packed(_) => unreachable!("No packed cell"),
+        };
+        Self {
+            squared_distance: aabb.distance_squared_to(pos),
+            item: CandidateItem::Cell(cell),
+        }
+    }
+}
+
+/// Best first traversal of the svo, returning up to `max_count` attractors
+/// sorted by distance
+fn nearest_attractors(
+    root_cell: &svo::BumpCell<'_, SvoData>,
+    pos: DVec3,
+    max_count: usize,
+    max_squared_distance: f64,
+) -> Vec<AttractorNeighbor> {
+    let mut found = Vec::new();
+    let mut candidates = BinaryHeap::new();
+    candidates.push(Candidate::cell(root_cell, pos));
+
+    while found.len() < max_count {
+        let Some(candidate) = candidates.pop()
+        else { break; };
+        // Every remaining candidate is at least as far
+        if candidate.squared_distance > max_squared_distance {
+            break;
+        }
+
+        match candidate.item {
+            CandidateItem::Attractor(repr) => found.push(AttractorNeighbor {
+                entity: repr.entity,
+                position: repr.global_pos,
+                mass: repr.mass,
+                squared_distance: candidate.squared_distance,
+            }),
+            CandidateItem::Cell(svo::Cell::Internal(internal)) => {
+                if internal.data.count == 0 {
+                    continue;
+                }
+                for comp in svo::CellPath::components() {
+                    candidates.push(Candidate::cell(internal.get_child(comp), pos));
+                }
+            },
+            CandidateItem::Cell(svo::Cell::Leaf(leaf)) => {
+                candidates.extend(leaf.data.points.iter().map(|repr| Candidate {
+                    squared_distance: repr.global_pos.distance_squared(pos),
+                    item: CandidateItem::Attractor(repr),
+                }));
+            },
+            CandidateItem::Cell(svo::Cell::Packed(_)) => unreachable!("No packed cell"),
+        }
+    }
+
+    found
+}
+
+impl GravitySvoContext {
+    /// The `k` attractors closest to the given position, sorted by distance
+    ///
+    /// Only attractors in the svo are found, so none if
+    /// [GravityConfig::enabled_svo] is false and none of the members of
+    /// [VirtualAttractorGroup]s. Positions are the ones of the last svo
+    /// update, and an attractor at the given position is returned too.
+    pub fn knn(&self, pos: DVec3, k: usize) -> Vec<AttractorNeighbor> {
+        self.alloc.with_root_cell(|root_cell| match root_cell {
+            Some(root_cell) if k > 0 => nearest_attractors(root_cell, pos, k, f64::INFINITY),
+            _ => Vec::new(),
+        })
+    }
+
+    /// All attractors at most `radius` away from the given position, sorted
+    /// by distance, see [Self::knn] for which attractors can be found
+    pub fn within_radius(&self, pos: DVec3, radius: f64) -> Vec<AttractorNeighbor> {
+        self.alloc.with_root_cell(|root_cell| match root_cell {
+            Some(root_cell) => nearest_attractors(root_cell, pos, usize::MAX, radius * radius),
+            None => Vec::new(),
+        })
+    }
+}
@@ -1,6 +1,6 @@
 use super::*;
 
-use bevy::{math::DVec3, prelude::*};
+use bevy::{math::DVec3, prelude::*, tasks::Task, utils::HashMap};
 use utils::{DAabb, Vec3Ext};
 
 /// Configures how wether any svo cell is 'opened' or considered as a single cell
@@ -11,10 +11,76 @@ pub struct SvoSkipConfig {
     pub opening_angle: f64,
 }
 
+/// Configures the closed-loop control of [SvoSkipConfig::opening_angle]:
+/// every `interval` updates, the svo forces of `sample_count` random particles
+/// are compared to exactly computed ones, and the opening angle is adjusted
+/// to keep the mean relative error around `target_relative_error`
+#[derive(Debug, Clone, Copy, derivative::Derivative)]
+#[derivative(Default)]
+pub struct SvoErrorMonitorConfig {
+    /// If false the error is still measured but the angle is not changed
+    pub auto_adjust: bool,
+    /// Number of updates between two measurements, 0 disables monitoring
+    #[derivative(Default(value = "60"))]
+    pub interval: u32,
+    #[derivative(Default(value = "32"))]
+    pub sample_count: usize,
+    #[derivative(Default(value = "0.01"))]
+    pub target_relative_error: f64,
+    /// Relative change of the opening angle at each adjustment
+    #[derivative(Default(value = "0.1"))]
+    pub adjust_rate: f64,
+    #[derivative(Default(value = "0.1"))]
+    pub min_opening_angle: f64,
+    #[derivative(Default(value = "1.5"))]
+    pub max_opening_angle: f64,
+}
+
+/// First order post-Newtonian correction of the gravity of very massive
+/// attractors, which makes close orbits precess
+#[derive(Debug, Clone, Copy, derivative::Derivative)]
+#[derivative(Default)]
+pub struct RelativisticCorrectionConfig {
+    /// In simulation units, lower it to exaggerate the effect
+    #[derivative(Default(value = "299_792_458."))]
+    pub speed_of_light: f64,
+    /// Only attractors at least this massive are corrected
+    #[derivative(Default(value = "1e9"))]
+    pub min_attractor_mass: f64,
+    /// Only bodies closer than this to the attractor are corrected
+    #[derivative(Default(value = "10_000."))]
+    pub max_distance: f64,
+}
+
+/// Systems of units giving the value of [GravityConfig::gravity_constant]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GravityUnits {
+    /// Meters, kilograms and seconds, which needs huge masses for any
+    /// noticeable gravity
+    Si,
+    /// Meters and seconds but masses in units of 10^11 kg, so bodies of
+    /// game sizes have reasonable masses
+    #[default]
+    GameScaled,
+    /// Gravity constant of 1, e.g. for abstract simulations
+    Natural,
+}
+
+impl GravityUnits {
+    pub const fn gravity_constant(self) -> f64 {
+        match self {
+            GravityUnits::Si => 6.6743e-11,
+            GravityUnits::GameScaled => 6.6743,
+            GravityUnits::Natural => 1.,
+        }
+    }
+}
+
 #[derive(Resource, derivative::Derivative)]
 #[derivative(Default)]
 pub struct GravityConfig {
-    #[derivative(Default(value = "6.6743"))]
+    /// See [GravityUnits] for common values
+    #[derivative(Default(value = "GravityUnits::default().gravity_constant()"))]
     pub gravity_constant: f64,
     #[derivative(Default(value = "true"))]
     pub enabled_svo: bool,
@@ -23,11 +89,94 @@ pub struct GravityConfig {
     pub managed_varying_timesteps: bool,
     /// See [SvoSkipConfig]
     pub svo_skip_config: SvoSkipConfig,
-    /// The amount of old samples kept in `GravityFieldSample`
+    /// See [SvoErrorMonitorConfig]
+    pub svo_error_monitor: SvoErrorMonitorConfig,
+    /// Disabled if None, see [RelativisticCorrectionConfig]
+    pub relativistic_correction: Option<RelativisticCorrectionConfig>,
+    /// Field forces longer than this are scaled down to it, which keeps
+    /// simulations with very close encounters alive (e.g. to debug them) at
+    /// the cost of accuracy, disabled if None
+    pub max_field_force: Option<f64>,
+    /// Rebuild the svo on a background task, forces keep using the last
+    /// complete one meanwhile, so it can lag a few updates behind the bodies
+    /// but its build time no longer adds to the frame time. The first svo is
+    /// still built synchronously so forces are never computed without one.
+    pub background_svo_rebuild: bool,
+    /// Length of the history of [GravityFieldSample], at least one sample
+    /// is always kept
     #[derivative(Default(value = "1"))]
     pub gravity_field_sample_backlog_count: usize,
 }
 
+impl GravityConfig {
+    pub fn with_units(self, units: GravityUnits) -> Self {
+        self.with_gravity_constant(units.gravity_constant())
+    }
+
+    pub fn with_gravity_constant(self, gravity_constant: f64) -> Self {
+        Self {
+            gravity_constant,
+            ..self
+        }
+    }
+
+    pub fn with_svo(self, enabled_svo: bool) -> Self {
+        Self {
+            enabled_svo,
+            ..self
+        }
+    }
+
+    pub fn with_managed_varying_timesteps(self, managed_varying_timesteps: bool) -> Self {
+        Self {
+            managed_varying_timesteps,
+            ..self
+        }
+    }
+
+    pub fn with_svo_skip_config(self, svo_skip_config: SvoSkipConfig) -> Self {
+        Self {
+            svo_skip_config,
+            ..self
+        }
+    }
+
+    pub fn with_svo_error_monitor(self, svo_error_monitor: SvoErrorMonitorConfig) -> Self {
+        Self {
+            svo_error_monitor,
+            ..self
+        }
+    }
+
+    pub fn with_relativistic_correction(self, config: RelativisticCorrectionConfig) -> Self {
+        Self {
+            relativistic_correction: Some(config),
+            ..self
+        }
+    }
+
+    pub fn with_max_field_force(self, max_field_force: f64) -> Self {
+        Self {
+            max_field_force: Some(max_field_force),
+            ..self
+        }
+    }
+
+    pub fn with_background_svo_rebuild(self, background_svo_rebuild: bool) -> Self {
+        Self {
+            background_svo_rebuild,
+            ..self
+        }
+    }
+
+    pub fn with_gravity_field_sample_backlog_count(self, count: usize) -> Self {
+        Self {
+            gravity_field_sample_backlog_count: count,
+            ..self
+        }
+    }
+}
+
 #[ouroboros::self_referencing]
 pub(super) struct GravitySvoAlloc {
     pub(super) herd: bumpalo_herd::Herd,
@@ -50,6 +199,11 @@ impl GravitySvoAlloc {
             }.build()
         });
     }
+
+    /// Whether an svo was built in this allocator
+    pub fn has_svo(&self) -> bool {
+        self.with_root_cell(|root_cell| root_cell.is_some())
+    }
 }
 
 impl Default for GravitySvoAlloc {
@@ -58,11 +212,50 @@ impl Default for GravitySvoAlloc {
     }
 }
 
+/// A [VirtualAttractorGroup] as seen by the force computation
+#[derive(Debug, Clone)]
+pub(super) struct AttractorGroupRepr {
+    pub(super) far_distance: f64,
+    pub(super) mass: f64,
+    pub(super) center_of_mass: DVec3,
+    pub(super) members: Vec<SvoEntityRepr>,
+}
+
+/// Snapshot of all [VirtualAttractorGroup]s, updated before the svo
+#[derive(Resource, Debug, Default)]
+pub(crate) struct AttractorGroupsContext {
+    pub(super) groups: HashMap<Entity, AttractorGroupRepr>,
+}
+
+impl AttractorGroupsContext {
+    /// Whether the attractor is in a valid group, and so not in the svo
+    pub(super) fn is_grouped(&self, member: Option<&AttractorGroupMember>) -> bool {
+        member.is_some_and(|member| self.groups.contains_key(&member.0))
+    }
+}
+
+/// The svo is built from [GlobalTransform64](doprec::GlobalTransform64)
+/// translations, which are absolute: moving the
+/// [FloatingOrigin](doprec::FloatingOrigin) only changes the f32 bevy
+/// transforms and has no effect on the svo.
 #[derive(Resource)]
 pub struct GravitySvoContext {
     pub(super) alloc: GravitySvoAlloc,
     pub(super) root_aabb: DAabb,
     pub(super) max_depth: u32,
+    /// See [GravityConfig::background_svo_rebuild]
+    pub(super) rebuild_task: Option<Task<SvoRebuild>>,
+    /// The previous svo when rebuilding in the background, reused for the
+    /// next rebuild
+    pub(super) spare_alloc: Option<GravitySvoAlloc>,
+}
+
+/// Result of a background rebuild of the svo of a [GravitySvoContext]
+pub(super) struct SvoRebuild {
+    pub(super) alloc: GravitySvoAlloc,
+    pub(super) root_aabb: DAabb,
+    /// New [Attractor::last_svo_position] of the attractors in the svo
+    pub(super) positions: Vec<(Entity, svo::CellPath)>,
 }
 
 impl Default for GravitySvoContext {
@@ -71,6 +264,8 @@ impl Default for GravitySvoContext {
             alloc: default(),
             root_aabb: DAabb::new_center_size(DVec3::zero(), DVec3::splat(100_000f64)),
             max_depth: 20,
+            rebuild_task: None,
+            spare_alloc: None,
         }
     }
 }
@@ -0,0 +1,195 @@
+use bevy::{math::DVec3, prelude::*};
+
+/// Exponent of the mass ratio in the laplace sphere of influence radius
+const SOI_MASS_EXPONENT: f64 = 2. / 5.;
+
+/// State of a candidate attractor of [dominant_attractor]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AttractorState {
+    pub entity: Entity,
+    pub position: DVec3,
+    pub velocity: DVec3,
+    pub mass: f64,
+}
+
+/// Attractor dominating the motion of a body, and the state of the body
+/// relative to it, as used by patched conics
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoiState {
+    pub attractor: Entity,
+    /// Radius of the sphere of influence of the attractor, infinite for the
+    /// most massive attractor
+    pub soi_radius: f64,
+    /// Gravity constant times the mass of the attractor
+    pub gravitational_parameter: f64,
+    pub relative_position: DVec3,
+    pub relative_velocity: DVec3,
+}
+
+impl SoiState {
+    /// Speed needed to escape the attractor from the current distance
+    pub fn escape_speed(&self) -> f64 {
+        (2. * self.gravitational_parameter / self.relative_position.length()).sqrt()
+    }
+
+    /// Kinetic plus potential energy per unit of mass of the two body orbit,
+    /// negative if the body is bound to the attractor
+    pub fn specific_orbital_energy(&self) -> f64 {
+        self.relative_velocity.length_squared() / 2.
+            - self.gravitational_parameter / self.relative_position.length()
+    }
+
+    pub fn is_bound(&self) -> bool {
+        self.specific_orbital_energy() < 0.
+    }
+}
+
+/// Laplace radius of the sphere of influence of a body orbiting a more
+/// massive primary at the given distance
+pub fn sphere_of_influence_radius(distance: f64, mass: f64, primary_mass: f64) -> f64 {
+    distance * (mass / primary_mass).powf(SOI_MASS_EXPONENT)
+}
+
+/// Radius of the sphere of influence of each attractor, around the more
+/// massive attractor pulling it the most (its primary), infinite if there is
+/// none
+fn soi_radii(attractors: &[AttractorState]) -> Vec<f64> {
+    attractors.iter().map(|attractor| {
+        attractors.iter()
+            .filter(|primary| primary.mass > attractor.mass)
+            .map(|primary| {
+                let distance_squared = primary.position.distance_squared(attractor.position);
+                (primary.mass / distance_squared, distance_squared.sqrt(), primary.mass)
+            })
+            .max_by(|(a, ..), (b, ..)| a.total_cmp(b))
+            .map_or(f64::INFINITY, |(_, distance, primary_mass)| {
+                sphere_of_influence_radius(distance, attractor.mass, primary_mass)
+            })
+    }).collect()
+}
+
+/// Finds the attractor whose sphere of influence is the smallest one
+/// containing the given position, e.g. the moon rather than its planet when
+/// close to the moon, and gives the state of the body relative to it.
+///
+/// Spheres of influence are computed from the given attractors alone, in
+/// quadratic time, so only the few massive bodies that matter (stars,
+/// planets, moons) should be given. Attractors at the exact position are
+/// ignored so the body can be one of them.
+pub fn dominant_attractor(
+    position: DVec3,
+    velocity: DVec3,
+    attractors: &[AttractorState],
+    gravity_constant: f64,
+) -> Option<SoiState> {
+    let radii = soi_radii(attractors);
+    let (attractor, soi_radius) = attractors.iter().zip(radii)
+        .filter(|(attractor, radius)| {
+            let distance = attractor.position.distance(position);
+            distance > 0. && distance <= *radius
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+    Some(SoiState {
+        attractor: attractor.entity,
+        soi_radius,
+        gravitational_parameter: gravity_constant * attractor.mass,
+        relative_position: position - attractor.position,
+        relative_velocity: velocity - attractor.velocity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::{ApproxEq, Tolerance};
+
+    const STAR: Entity = Entity::from_raw(0);
+    const PLANET: Entity = Entity::from_raw(1);
+    const MOON: Entity = Entity::from_raw(2);
+
+    /// A planet orbiting a star at 1000 and a moon orbiting the planet at 20
+    fn star_system() -> [AttractorState; 3] {
+        let planet_position = DVec3::new(1000., 0., 0.);
+        let planet_velocity = DVec3::new(0., 30., 0.);
+        [
+            AttractorState {
+                entity: STAR,
+                position: DVec3::ZERO,
+                velocity: DVec3::ZERO,
+                mass: 1e6,
+            },
+            AttractorState {
+                entity: PLANET,
+                position: planet_position,
+                velocity: planet_velocity,
+                mass: 1e3,
+            },
+            AttractorState {
+                entity: MOON,
+                position: planet_position + DVec3::new(20., 0., 0.),
+                velocity: planet_velocity + DVec3::new(0., 5., 0.),
+                mass: 1.,
+            },
+        ]
+    }
+
+    #[test]
+    pub fn test_dominant_attractor_choice() {
+        let attractors = star_system();
+        let [star, planet, moon] = attractors;
+        let dominant = |position: DVec3| {
+            dominant_attractor(position, DVec3::ZERO, &attractors, 1.)
+                .map(|state| state.attractor)
+        };
+
+        // The moon is pulled the most by the planet, not by the star
+        let moon_soi = sphere_of_influence_radius(20., moon.mass, planet.mass);
+        let planet_soi = sphere_of_influence_radius(1000., planet.mass, star.mass);
+        assert!(moon_soi < 20. && planet_soi > 20.);
+
+        assert_eq!(dominant(moon.position + DVec3::new(0., moon_soi * 0.9, 0.)), Some(MOON));
+        assert_eq!(dominant(moon.position + DVec3::new(0., moon_soi * 1.1, 0.)), Some(PLANET));
+        assert_eq!(dominant(planet.position + DVec3::new(0., 0., planet_soi * 0.9)), Some(PLANET));
+        assert_eq!(dominant(planet.position + DVec3::new(0., 0., planet_soi * 1.1)), Some(STAR));
+        assert_eq!(dominant(DVec3::new(1e6, 0., 0.)), Some(STAR));
+
+        // Attractors at the exact position are ignored
+        assert_eq!(dominant(planet.position), Some(STAR));
+        assert_eq!(dominant(star.position), None);
+
+        let star_state = dominant_attractor(DVec3::new(1e6, 0., 0.), DVec3::ZERO, &attractors, 1.)
+            .unwrap();
+        assert_eq!(star_state.soi_radius, f64::INFINITY);
+    }
+
+    #[test]
+    pub fn test_dominant_attractor_relative_state() {
+        let attractors = star_system();
+        let [_, planet, moon] = attractors;
+        let gravity_constant = 2.;
+
+        let offset = DVec3::new(0., 0.5, -0.25);
+        let relative_velocity = DVec3::new(0.1, -0.2, 0.3);
+        let state = dominant_attractor(
+            moon.position + offset, moon.velocity + relative_velocity,
+            &attractors, gravity_constant,
+        ).unwrap();
+        assert_eq!(state.attractor, MOON);
+        assert!(state.relative_position.approx_eq(&offset, Tolerance::Absolute(1e-9)));
+        assert!(state.relative_velocity.approx_eq(&relative_velocity, Tolerance::Absolute(1e-9)));
+        assert_eq!(state.gravitational_parameter, gravity_constant * moon.mass);
+
+        // Alone with the planet the moon is in its infinite sphere of
+        // influence, and slower than the escape speed so bound to it
+        let moon_state = dominant_attractor(
+            moon.position, moon.velocity, &[planet], 1.,
+        ).unwrap();
+        assert_eq!(moon_state.attractor, PLANET);
+        assert!(moon_state.relative_position.approx_eq(&DVec3::new(20., 0., 0.), Tolerance::Absolute(1e-9)));
+        assert!(moon_state.relative_velocity.approx_eq(&DVec3::new(0., 5., 0.), Tolerance::Absolute(1e-9)));
+        assert!(moon_state.escape_speed().approx_eq(&10., Tolerance::Absolute(1e-9)));
+        assert!(moon_state.is_bound());
+        assert!(moon_state.specific_orbital_energy().approx_eq(&(12.5 - 50.), Tolerance::Absolute(1e-9)));
+    }
+}
@@ -1,19 +1,42 @@
 use super::*;
+use crate::Disabled;
 
 use std::time::Instant;
 
 use arbitrary_int::u3;
-use bevy::{diagnostic::Diagnostics, math::DVec3, prelude::*};
-use doprec::GlobalTransform64;
+use bevy::{
+    diagnostic::Diagnostics, math::DVec3, prelude::*,
+    tasks::{block_on, AsyncComputeTaskPool, Task, TaskPool},
+};
+use doprec::{GlobalTransform64, Transform64};
 #[cfg(feature = "rapier")]
 use rapier_overlay::*;
 use svo::SplittableData as _;
 use utils::{AabbExt, DAabb, IsZeroApprox};
 use bumpalo::boxed::Box as BumpBox;
+use rand::seq::IteratorRandom;
 
+/// Contains all nbody systems, run in [FixedUpdate], see [GravityStage] for
+/// finer ordering
 #[derive(SystemSet, Debug, PartialEq, Eq, Default, Hash, Clone, Copy)]
 pub struct GravitySystems;
 
+/// The successive parts of [GravitySystems]
+#[derive(SystemSet, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum GravityStage {
+    /// Bodies with non finite positions or velocities are [Quarantined],
+    /// attractor masses are validated and the svo is rebuilt
+    SvoUpdate,
+    /// [GravityFieldSample]s are computed
+    ForceCompute,
+    /// The svo error is measured, the relativistic correction is added,
+    /// forces are clamped (see [GravityConfig::max_field_force]) and applied
+    /// to rigid bodies
+    Finalize,
+    /// [IntegratedMotion] bodies are moved
+    Integrate,
+}
+
 #[cfg(feature = "rapier")]
 pub(crate) fn sync_attractor_masses_with_colliders_system(
     mut query: Query<(
@@ -28,45 +51,207 @@ pub(crate) fn sync_attractor_masses_with_colliders_system(
     }
 }
 
-pub(crate) fn update_svo_system(
-    mut diagnostics: Diagnostics,
-    cfg: Res<GravityConfig>,
-    mut svo_ctx: ResMut<GravitySvoContext>,
+/// Disables the bodies whose position or velocity is not finite before they
+/// get in the svo, see [Quarantined]
+#[allow(clippy::type_complexity)]
+pub(crate) fn quarantine_non_finite_bodies_system(
+    mut commands: Commands,
+    mut events: EventWriter<BodyQuarantined>,
 
-    transforms: Query<&GlobalTransform64, With<Attractor>>,
-    entity_transform_mass: Query<(Entity, &GlobalTransform64, &Massive), With<Attractor>>,
-    mut attractors: Query<&mut Attractor>,
+    bodies: Query<(Entity, &GlobalTransform64, Option<&Velocity>), (
+        Or<(With<Attractor>, With<GravityFieldSample>, With<IntegratedMotion>)>,
+        Without<Disabled>,
+    )>,
 ) {
-    let start = Instant::now();
+    for (entity, transform, velocity) in &bodies {
+        let position = transform.translation();
+        let reason = if !position.is_finite() {
+            QuarantineReason::Position(position)
+        }
+        else if let Some(velocity) = velocity.filter(|v| !v.velocity.is_finite()) {
+            QuarantineReason::Velocity(velocity.velocity)
+        }
+        else {
+            continue;
+        };
 
-    if !cfg.enabled_svo {
-        svo_ctx.alloc = default();
-        return;
+        bevy::log::warn!("Quarantining body {entity:?}: {reason}");
+        commands.entity(entity).insert((Disabled, Quarantined(reason)));
+        events.send(BodyQuarantined { entity, reason });
     }
+}
+
+/// Removes the [Attractor] component of entities with an invalid mass
+/// (see [Massive::validate]) so they cannot corrupt the svo stats
+#[allow(clippy::type_complexity)]
+pub(crate) fn validate_attractor_masses_system(
+    mut commands: Commands,
 
-    let root_aabb = transforms.iter()
-        .fold(DAabb::new_center_size(DVec3::ZERO, DVec3::ONE), |mut aabb, transform| {
-           aabb.expand_to_contain_point(transform.translation());
-           aabb
+    query: Query<(Entity, &Massive), (
+        With<Attractor>,
+        Or<(Changed<Massive>, Added<Attractor>)>,
+    )>,
+) {
+    for (entity, massive) in &query {
+        if let Err(error) = massive.validate() {
+            bevy::log::error!("Rejecting attractor {entity:?}: {error}");
+            commands.entity(entity).remove::<Attractor>();
+        }
+    }
+}
+
+/// Updates the stats of the [VirtualAttractorGroup]s and the
+/// [AttractorGroupsContext]
+pub(crate) fn update_attractor_groups_system(
+    mut groups_ctx: ResMut<AttractorGroupsContext>,
+
+    mut groups: Query<(Entity, &mut VirtualAttractorGroup)>,
+    mut members: Query<(
+        Entity, &AttractorGroupMember, &GlobalTransform64, &Massive, &mut Attractor,
+    ), Without<Disabled>>,
+) {
+    groups_ctx.groups.clear();
+    for (entity, group) in &groups {
+        groups_ctx.groups.insert(entity, AttractorGroupRepr {
+            far_distance: group.far_distance,
+            mass: 0.,
+            center_of_mass: DVec3::ZERO,
+            members: vec![],
         });
-    svo_ctx.root_aabb = root_aabb;
+    }
 
-    let max_depth = svo_ctx.max_depth;
-    svo_ctx.alloc.build_svo(|herd| {
-        let mut root_cell: svo::BumpCell<SvoData> = svo::LeafCell {
-            data: SvoData {
-                aabb: root_aabb,
-                entities: entity_transform_mass.iter()
-                    .map(|(entity, transform, massive)| SvoEntityRepr {
-                        entity,
-                        global_pos: transform.translation(),
-                        mass: massive.mass,
-                    })
-                    .collect(),
-                remaining_allowed_depth:
-                    u8::try_from(max_depth).expect("too deep"),
-            },
-        }.into();
+    for (entity, member, transform, massive, mut attractor) in &mut members {
+        let Some(group) = groups_ctx.groups.get_mut(&member.0)
+        else { continue; };
+        // Grouped attractors are not in the svo
+        attractor.last_svo_position = None;
+
+        group.mass += massive.mass;
+        group.center_of_mass += transform.translation() * massive.mass;
+        group.members.push(SvoEntityRepr {
+            entity,
+            global_pos: transform.translation(),
+            mass: massive.mass,
+            influence_radius: attractor.influence_radius,
+        });
+    }
+
+    for (entity, mut group) in &mut groups {
+        let repr = groups_ctx.groups.get_mut(&entity)
+            .expect("Inserted above");
+        // Negative masses can cancel out
+        repr.center_of_mass = if repr.mass.is_zero_approx() {
+            repr.members.iter().map(|member| member.global_pos).sum::<DVec3>()
+                / repr.members.len().max(1) as f64
+        }
+        else {
+            repr.center_of_mass / repr.mass
+        };
+
+        group.mass = repr.mass;
+        group.center_of_mass = repr.center_of_mass;
+        group.member_count = repr.members.len();
+    }
+}
+
+/// Adds the force of the [VirtualAttractorGroup]s, seen as a single body when
+/// far enough and member by member otherwise
+#[allow(clippy::too_many_arguments)]
+fn add_attractor_groups_force(
+    cfg: &GravityConfig,
+    groups_ctx: &AttractorGroupsContext,
+
+    victim_entity: Entity,
+    victim_pos: DVec3,
+    victim_sample: &mut GravityFieldSample,
+    victim_group: Option<Entity>,
+    dominant: Option<DominantAttractorRepr>,
+    total_force: &mut DVec3,
+) {
+    let mut add_body = |
+        sample: &mut GravityFieldSample, entity: Entity, pos: DVec3, mass: f64,
+        influence_radius: Option<f64>,
+    | {
+        let diff = pos - victim_pos;
+        if diff.is_zero_approx() {
+            return;
+        }
+        let squared_distance = diff.length_squared();
+        let distance = squared_distance.sqrt();
+        let factor = influence_factor(influence_radius, distance);
+        if factor == 0. {
+            return;
+        }
+        let force = factor * mass / squared_distance;
+
+        let info = AttractorInfo {
+            entity,
+            force,
+            squared_distance,
+        };
+        if sample.closest_attractor
+            .map(|oi| oi.squared_distance > info.squared_distance)
+            .unwrap_or(true)
+        {
+            sample.closest_attractor = Some(info);
+        }
+
+        if distance > sample.min_affect_distance {
+            *total_force += (diff / distance) * cfg.gravity_constant * force;
+        }
+    };
+
+    for (&group_entity, group) in &groups_ctx.groups {
+        let is_far = victim_group != Some(group_entity) &&
+            group.center_of_mass.distance(victim_pos) >= group.far_distance;
+
+        if is_far {
+            let mut mass = group.mass;
+            let mut center_of_mass = group.center_of_mass;
+            // The dominant attractor's force is computed separately
+            if let Some(dominant) = dominant.filter(|d| d.group == Some(group_entity)) {
+                let remaining = mass - dominant.mass;
+                if remaining.is_zero_approx() {
+                    continue;
+                }
+                center_of_mass = (center_of_mass * mass - dominant.pos * dominant.mass)
+                    / remaining;
+                mass = remaining;
+            }
+            // Reported as the closest attractor, as the members are unknown
+            add_body(victim_sample, group_entity, center_of_mass, mass, None);
+            continue;
+        }
+
+        for member in &group.members {
+            if member.entity == victim_entity ||
+                dominant.is_some_and(|d| d.entity == member.entity)
+            {
+                continue;
+            }
+            add_body(
+                victim_sample, member.entity, member.global_pos, member.mass,
+                member.influence_radius,
+            );
+        }
+    }
+}
+
+/// Builds the svo of the given attractors in the given allocator, returns
+/// the cell every attractor ended up in
+fn build_svo(
+    alloc: &mut GravitySvoAlloc,
+    root_aabb: DAabb,
+    max_depth: u32,
+    reprs: Vec<SvoEntityRepr>,
+) -> Vec<(Entity, svo::CellPath)> {
+    let mut positions = Vec::with_capacity(reprs.len());
+    alloc.build_svo(|herd| {
+        let mut root_cell: svo::BumpCell<SvoData> = svo::LeafCell::new(SvoData::new(
+            root_aabb,
+            reprs,
+            u8::try_from(max_depth).expect("too deep"),
+        )).into();
 
         let herd_local = thread_local::ThreadLocal::new();
 
@@ -98,16 +283,98 @@ pub(crate) fn update_svo_system(
         root_cell.auto_merge_borrow();
 
         for item in root_cell.iter() {
-            let mut iter = attractors.iter_many_mut(
-                item.data.entities.iter().map(|repr| repr.entity)
-            );
-            while let Some(mut attractor) = iter.fetch_next() {
-                attractor.last_svo_position = Some(item.path.clone());
-            }
+            positions.extend(item.data.points.iter()
+                .map(|repr| (repr.entity, item.path.clone())));
         }
 
         root_cell
     });
+    positions
+}
+
+/// Rebuilds the svo from the current attractors, or starts rebuilding it in
+/// the background and swaps it in once done, see
+/// [GravityConfig::background_svo_rebuild]
+pub(crate) fn update_svo_system(
+    mut diagnostics: Diagnostics,
+    cfg: Res<GravityConfig>,
+    mut svo_ctx: ResMut<GravitySvoContext>,
+    groups_ctx: Res<AttractorGroupsContext>,
+
+    transforms: Query<&GlobalTransform64, (With<Attractor>, Without<Disabled>)>,
+    mut attractors: Query<(
+        Entity, &GlobalTransform64, &Massive, &mut Attractor,
+        Option<&AttractorGroupMember>,
+    ), Without<Disabled>>,
+) {
+    let start = Instant::now();
+
+    if !cfg.enabled_svo {
+        svo_ctx.alloc = default();
+        svo_ctx.rebuild_task = None;
+        svo_ctx.spare_alloc = None;
+        return;
+    }
+
+    let finished = svo_ctx.rebuild_task.as_ref()
+        .is_some_and(Task::is_finished)
+        .then(|| svo_ctx.rebuild_task.take())
+        .flatten()
+        .map(block_on);
+    // Cells of the attractors in the svo that is used from now on, if it
+    // changed
+    let mut new_positions = finished.map(|rebuild| {
+        // The previous svo is kept to reuse its allocations
+        let previous = std::mem::replace(&mut svo_ctx.alloc, rebuild.alloc);
+        svo_ctx.spare_alloc = Some(previous);
+        svo_ctx.root_aabb = rebuild.root_aabb;
+        rebuild.positions
+    });
+
+    // Until there is an svo to use in the meantime it is built synchronously,
+    // e.g. on the first update or when the svo was just enabled
+    let background = cfg.background_svo_rebuild && svo_ctx.alloc.has_svo();
+    let rebuilding = background && svo_ctx.rebuild_task.is_some();
+    if !rebuilding {
+        let root_aabb = transforms.iter()
+            .fold(DAabb::new_center_size(DVec3::ZERO, DVec3::ONE), |mut aabb, transform| {
+               aabb.expand_to_contain_point(transform.translation());
+               aabb
+            });
+        let reprs = attractors.iter()
+            .filter(|(.., member)| !groups_ctx.is_grouped(*member))
+            .map(|(entity, transform, massive, attractor, _)| SvoEntityRepr {
+                entity,
+                global_pos: transform.translation(),
+                mass: massive.mass,
+                influence_radius: attractor.influence_radius,
+            })
+            .collect::<Vec<_>>();
+        let max_depth = svo_ctx.max_depth;
+
+        if background {
+            let mut alloc = svo_ctx.spare_alloc.take().unwrap_or_default();
+            let task = AsyncComputeTaskPool::get_or_init(TaskPool::default).spawn(async move {
+                let positions = build_svo(&mut alloc, root_aabb, max_depth, reprs);
+                SvoRebuild { alloc, root_aabb, positions }
+            });
+            svo_ctx.rebuild_task = Some(task);
+        }
+        else {
+            // Also cancels the background rebuild if it was just disabled
+            svo_ctx.rebuild_task = None;
+            svo_ctx.spare_alloc = None;
+            new_positions = Some(build_svo(&mut svo_ctx.alloc, root_aabb, max_depth, reprs));
+            svo_ctx.root_aabb = root_aabb;
+        }
+    }
+
+    for (entity, path) in new_positions.into_iter().flatten() {
+        // Attractors despawned since a background rebuild started are gone
+        if let Ok((_, _, _, mut attractor, _)) = attractors.get_mut(entity) {
+            attractor.last_svo_position = Some(path);
+        }
+    }
 
     diagnostics.add_measurement(
         &GRAVITY_SVO_UPDATE_SYSTEM_DURATION,
@@ -115,15 +382,50 @@ pub(crate) fn update_svo_system(
     );
 }
 
+/// Whether the force of the victim must be computed this update, if not its
+/// [GravitySleep] extrapolates it
+///
+/// `attractor_pos` only resolves attractors, the closest attractor can also
+/// be a far [VirtualAttractorGroup] whose center of mass is then used.
+fn should_compute_sleeping(
+    cfg: &GravityConfig,
+    groups_ctx: &AttractorGroupsContext,
+    now: f64,
+    victim_pos: DVec3,
+    victim_sample: &mut GravityFieldSample,
+    victim_sleep: Option<&mut GravitySleep>,
+    attractor_pos: impl FnOnce(Entity) -> Option<DVec3>,
+) -> bool {
+    let Some(sleep) = victim_sleep
+    else { return true; };
+
+    let should_sample = sleep.should_sample(|entity| {
+        attractor_pos(entity)
+            .or_else(|| groups_ctx.groups.get(&entity).map(|group| group.center_of_mass))
+            .map(|pos| pos.distance_squared(victim_pos))
+    });
+    if !should_sample {
+        sleep.extrapolate(victim_sample, now, cfg.gravity_field_sample_backlog_count);
+    }
+    should_sample
+}
+
+#[allow(clippy::type_complexity)]
 pub(crate) fn compute_gravity_field_system_no_svo(
     mut diagnostics: Diagnostics,
     cfg: Res<GravityConfig>,
+    time: Res<Time>,
+    groups_ctx: Res<AttractorGroupsContext>,
 
-    attractors: Query<(Entity, &GlobalTransform64, &Massive, &Attractor)>,
+    attractors: Query<(
+        Entity, &GlobalTransform64, &Massive, &Attractor,
+        Option<&AttractorGroupMember>,
+    ), Without<Disabled>>,
     mut victims: Query<(
         Entity, &GlobalTransform64, &mut GravityFieldSample,
-        Option<&mut TimeStep>,
-    )>,
+        Option<&mut TimeStep>, Option<&AttractorGroupMember>,
+        Option<&mut GravitySleep>,
+    ), Without<Disabled>>,
 
     mut update_counter: Local<u32>,
 ) {
@@ -131,11 +433,13 @@ pub(crate) fn compute_gravity_field_system_no_svo(
         return;
     }
     let start = Instant::now();
+    let now = time.elapsed_seconds_f64();
 
     *update_counter = update_counter.wrapping_add(1);
 
     victims.par_iter_mut().for_each(|(
-        victim_entity, victim_translation, mut victim_sample, victim_timestep
+        victim_entity, victim_translation, mut victim_sample, victim_timestep,
+        victim_member, mut victim_sleep,
     )| {
         if let Some(mut victim_timestep) = victim_timestep {
             victim_timestep.offset = victim_entity.index();
@@ -147,15 +451,25 @@ pub(crate) fn compute_gravity_field_system_no_svo(
             victim_timestep.last_updated = true;
         }
         let victim_pos = victim_translation.translation();
+        let should_compute = should_compute_sleeping(
+            &cfg, &groups_ctx, now, victim_pos, &mut victim_sample,
+            victim_sleep.as_deref_mut(),
+            |entity| attractors.get(entity).ok().map(|(_, pos, ..)| pos.translation()),
+        );
+        if !should_compute {
+            return;
+        }
 
-        let mut total_force = DVec3::ZERO;
+        let mut forces = ForceAccumulator::new(
+            victim_pos, cfg.gravity_constant, victim_sample.min_affect_distance,
+        );
 
         let mut closest_attractor = None::<AttractorInfo>;
 
         for (
-            attractor_entity, attractor_pos, attractor_mass, _attractor
+            attractor_entity, attractor_pos, attractor_mass, attractor, member
         ) in &attractors {
-            if victim_entity == attractor_entity {
+            if victim_entity == attractor_entity || groups_ctx.is_grouped(member) {
                 continue;
             }
 
@@ -165,31 +479,43 @@ pub(crate) fn compute_gravity_field_system_no_svo(
                 continue;
             }
             let distance_squared = diff.length_squared();
-            let distance = distance_squared.sqrt();
-            let force = attractor_mass.mass / distance_squared;
-
-            let info = AttractorInfo {
-                entity: attractor_entity,
-                force,
-                squared_distance: distance_squared,
+            let factor = match attractor.influence_radius {
+                None => 1.,
+                radius => influence_factor(radius, distance_squared.sqrt()),
             };
+            if factor == 0. {
+                continue;
+            }
+            let mass = factor * attractor_mass.mass;
 
             if closest_attractor
-                .map(|oi| oi.squared_distance > info.squared_distance)
+                .map(|oi| oi.squared_distance > distance_squared)
                 .unwrap_or(true)
             {
-                closest_attractor = Some(info);
+                closest_attractor = Some(AttractorInfo {
+                    entity: attractor_entity,
+                    force: mass / distance_squared,
+                    squared_distance: distance_squared,
+                });
             }
 
-            if distance > victim_sample.min_affect_distance {
-                total_force += (diff / distance) * cfg.gravity_constant * force;
-            }
+            forces.add(attractor_pos, mass);
         }
 
+        let mut total_force = forces.finish();
         victim_sample.closest_attractor = closest_attractor;
+        add_attractor_groups_force(
+            &cfg, &groups_ctx,
+            victim_entity, victim_pos, &mut victim_sample,
+            victim_member.map(|member| member.0), None,
+            &mut total_force,
+        );
         victim_sample.new_field_force(
-            total_force, cfg.gravity_field_sample_backlog_count
+            total_force, now, cfg.gravity_field_sample_backlog_count
         );
+        if let Some(mut sleep) = victim_sleep {
+            sleep.sampled(&victim_sample);
+        }
     });
 
     diagnostics.add_measurement(
@@ -198,9 +524,41 @@ pub(crate) fn compute_gravity_field_system_no_svo(
     );
 }
 
+/// The attractor of a [DominantAttractor] resolved for a given victim
+#[derive(Debug, Clone, Copy)]
+struct DominantAttractorRepr<'a> {
+    entity: Entity,
+    pos: DVec3,
+    mass: f64,
+    influence_radius: Option<f64>,
+    svo_position: Option<&'a svo::CellPath>,
+    /// Position it is aggregated with in the svo, see [svo_build_position]
+    svo_pos: Option<DVec3>,
+    /// See [AttractorGroupMember]
+    group: Option<Entity>,
+}
+
+/// Position the entity had when the svo was built, which is the one its
+/// cells' stats aggregate, as the svo lags behind the bodies with
+/// [GravityConfig::background_svo_rebuild]
+fn svo_build_position(
+    root_cell: &svo::BumpCell<'_, SvoData>,
+    path: &svo::CellPath,
+    entity: Entity,
+) -> Option<DVec3> {
+    let (_, cell) = root_cell.follow_path(path);
+    let svo::Cell::Leaf(leaf) = cell
+    else { return None; };
+    leaf.data.points.iter()
+        .find(|repr| repr.entity == entity)
+        .map(|repr| repr.global_pos)
+}
+
 /// Does the actual svo traversal for a given victim
+#[allow(clippy::too_many_arguments)]
 fn compute_svo_gravity_field_util(
     cfg: &GravityConfig,
+    groups_ctx: &AttractorGroupsContext,
     root_cell: &svo::BumpCell<'_, SvoData>,
     max_depth: u32,
 
@@ -208,10 +566,24 @@ fn compute_svo_gravity_field_util(
     victim_pos: &GlobalTransform64,
     mut victim_sample: Mut<GravityFieldSample>,
     victim_attractor_bundle: Option<(&Massive, &Attractor)>,
+    victim_group: Option<Entity>,
+    victim_quality: Option<&SampleQuality>,
+    dominant: Option<DominantAttractorRepr>,
+    now: f64,
 ) {
     let victim_pos = victim_pos.translation();
+    let opening_angle = victim_quality
+        .and_then(|quality| quality.opening_angle)
+        .unwrap_or(cfg.svo_skip_config.opening_angle);
+    let max_open_depth = victim_quality.and_then(|quality| quality.max_depth);
+    let victim_svo_pos = victim_attractor_bundle
+        .and_then(|(_, attractor)| attractor.last_svo_position.as_ref())
+        .and_then(|path| svo_build_position(root_cell, path, victim_entity))
+        .unwrap_or(victim_pos);
 
-    let mut total_force = DVec3::ZERO;
+    let mut forces = ForceAccumulator::new(
+        victim_pos, cfg.gravity_constant, victim_sample.min_affect_distance,
+    );
 
     #[derive(Debug, Clone)]
     struct CellStep<'a, 'b> {
@@ -248,9 +620,31 @@ fn compute_svo_gravity_field_util(
 
                 let mut stats = internal.data;
 
-                let diff_to_com = stats.center_of_mass - victim_pos;
-                let distance_to_com_squared = diff_to_com.length_squared();
-                let distance_to_com = distance_to_com_squared.sqrt();
+                // Remove the dominant attractor from the cell's stats as its
+                // force is computed separately
+                if let Some(dominant) = dominant {
+                    let contains_dominant = dominant.svo_position
+                        .is_some_and(|pos| step.path.is_prefix_of(pos));
+                    if contains_dominant {
+                        remove_from_stats(
+                            &mut stats, dominant.svo_pos.unwrap_or(dominant.pos),
+                            dominant.mass,
+                        );
+                        if stats.count == 0 || stats.aggregate.poles().next().is_none() {
+                            continue 'svo_loop;
+                        }
+                    }
+                }
+
+                // None of the cell's bodies can reach the victim
+                if stats.aggregate.influence_bounds
+                    .is_some_and(|bounds| bounds.closest_point(victim_pos) != victim_pos)
+                {
+                    continue 'svo_loop;
+                }
+
+                let center_of_mass = stats.aggregate.main_center_of_mass();
+                let distance_to_com = center_of_mass.distance(victim_pos);
 
                 let should_simplify = 'should_simplify: {
                     if let Some((victim_mass, victim_attractor)) = victim_attractor_bundle {
@@ -260,23 +654,30 @@ fn compute_svo_gravity_field_util(
                             break 'should_simplify false;
                         }
                         if contains_victim && SHOULD_CORRECT_STATS_ON_OWN_CELL {
-                            stats.center_of_mass -=
-                                (victim_pos * victim_mass.mass) / stats.total_mass;
-                            stats.total_mass -= victim_mass.mass;
-                            stats.count -= 1;
+                            remove_from_stats(&mut stats, victim_svo_pos, victim_mass.mass);
                         }
                     }
-                    let skip_cfg = &cfg.svo_skip_config;
+                    // The victim may be in the cutoff zone of some bodies,
+                    // which is only applied when they are visited directly
+                    let in_cutoff_zone = stats.aggregate.influence_bounds.is_some() &&
+                        !stats.aggregate.full_strength_bounds
+                            .is_some_and(|bounds| bounds.closest_point(victim_pos) == victim_pos);
+                    if in_cutoff_zone {
+                        break 'should_simplify false;
+                    }
 
                     if stats.count == 1 {
                         break 'should_simplify true;
                     }
 
-                    let r_max = stats.aabb.furthest_point(stats.center_of_mass)
-                        .distance(stats.center_of_mass);
+                    if max_open_depth.is_some_and(|max| step.path.depth() >= max) {
+                        break 'should_simplify true;
+                    }
+
+                    let r_max = stats.aabb.max_distance_to(center_of_mass);
                     // From "10.1111/j.1365-2966.2007.11427.x"
                     let factor = 2f64 / 3f64.sqrt();
-                    let r_open = factor * (r_max / skip_cfg.opening_angle);
+                    let r_open = factor * (r_max / opening_angle);
 
                     if distance_to_com < r_open {
                         break 'should_simplify false;
@@ -285,9 +686,9 @@ fn compute_svo_gravity_field_util(
                     true
                 };
                 if should_simplify {
-                    if distance_to_com > victim_sample.min_affect_distance {
-                        let force = stats.total_mass / distance_to_com_squared;
-                        total_force += (diff_to_com / distance_to_com) * cfg.gravity_constant * force;
+                    // Negative masses give a negative force, so a repulsion
+                    for pole in stats.aggregate.poles() {
+                        forces.add(pole.center_of_mass, pole.mass);
                     }
                 }
                 else {
@@ -299,10 +700,13 @@ fn compute_svo_gravity_field_util(
                 }
             },
             svo::Cell::Leaf(l) => {
-                'entity_loop: for entity_repr in &l.data.entities {
+                'entity_loop: for entity_repr in &l.data.points {
                     if entity_repr.entity == victim_entity {
                         continue 'entity_loop;
                     }
+                    if dominant.is_some_and(|d| d.entity == entity_repr.entity) {
+                        continue 'entity_loop;
+                    }
                     let attractor_pos = entity_repr.global_pos;
 
                     let diff = attractor_pos - victim_pos;
@@ -310,32 +714,71 @@ fn compute_svo_gravity_field_util(
                         continue 'entity_loop;
                     }
                     let squared_distance = diff.length_squared();
-                    let distance = squared_distance.sqrt();
-                    let force = entity_repr.mass / squared_distance;
-
-                    let info = AttractorInfo {
-                        entity: entity_repr.entity,
-                        force,
-                        squared_distance,
+                    // The distance is computed again by the accumulator
+                    let factor = match entity_repr.influence_radius {
+                        None => 1.,
+                        radius => influence_factor(radius, squared_distance.sqrt()),
                     };
+                    if factor == 0. {
+                        continue 'entity_loop;
+                    }
+                    let mass = factor * entity_repr.mass;
+
                     if victim_sample.closest_attractor
-                        .map(|oi| oi.squared_distance > info.squared_distance)
+                        .map(|oi| oi.squared_distance > squared_distance)
                         .unwrap_or(true)
                     {
-                        victim_sample.closest_attractor = Some(info);
+                        victim_sample.closest_attractor = Some(AttractorInfo {
+                            entity: entity_repr.entity,
+                            force: mass / squared_distance,
+                            squared_distance,
+                        });
                     }
 
-                    if distance > victim_sample.min_affect_distance {
-                        total_force += (diff / distance) * cfg.gravity_constant * force;
-                    }
+                    forces.add(attractor_pos, mass);
                 }
             },
             svo::Cell::Packed(_) => unreachable!("No packed cell"),
         }
     }
 
+    let mut total_force = forces.finish();
+
+    if let Some(dominant) = dominant {
+        let diff = dominant.pos - victim_pos;
+        let distance = diff.length();
+        let factor = influence_factor(dominant.influence_radius, distance);
+        if !diff.is_zero_approx() && factor != 0. {
+            let squared_distance = diff.length_squared();
+            let force = factor * dominant.mass / squared_distance;
+
+            let info = AttractorInfo {
+                entity: dominant.entity,
+                force,
+                squared_distance,
+            };
+            if victim_sample.closest_attractor
+                .map(|oi| oi.squared_distance > info.squared_distance)
+                .unwrap_or(true)
+            {
+                victim_sample.closest_attractor = Some(info);
+            }
+
+            if distance > victim_sample.min_affect_distance {
+                total_force += (diff / distance) * cfg.gravity_constant * force;
+            }
+        }
+    }
+
+    add_attractor_groups_force(
+        cfg, groups_ctx,
+        victim_entity, victim_pos, &mut victim_sample, victim_group, dominant,
+        &mut total_force,
+    );
+
     victim_sample.new_field_force(
         total_force, 
+        now,
         cfg.gravity_field_sample_backlog_count,
     );
 }
@@ -344,12 +787,19 @@ fn compute_svo_gravity_field_util(
 pub(crate) fn compute_gravity_field_system_yes_svo(
     mut diagnostics: Diagnostics,
     cfg: Res<GravityConfig>,
+    time: Res<Time>,
     svo_ctx: Res<GravitySvoContext>,
+    groups_ctx: Res<AttractorGroupsContext>,
 
     mut victims: Query<(
         Entity, &GlobalTransform64, &mut GravityFieldSample, Option<&mut TimeStep>,
-        Option<(&Massive, &Attractor)>
-    )>,
+        Option<(&Massive, &Attractor)>, Option<&DominantAttractor>,
+        Option<&AttractorGroupMember>, Option<&mut GravitySleep>,
+        Option<&SampleQuality>,
+    ), Without<Disabled>>,
+    attractors: Query<(
+        &GlobalTransform64, &Massive, &Attractor, Option<&AttractorGroupMember>,
+    ), Without<Disabled>>,
 
     mut update_counter: Local<u32>,
 ) {
@@ -357,6 +807,7 @@ pub(crate) fn compute_gravity_field_system_yes_svo(
         return;
     }
     let start = Instant::now();
+    let now = time.elapsed_seconds_f64();
 
     *update_counter = update_counter.wrapping_add(1);
 
@@ -365,9 +816,13 @@ pub(crate) fn compute_gravity_field_system_yes_svo(
         let Some(root_cell) = root_cell
         else { return; };
         victims.par_iter_mut().for_each(|(
-            victim_entity, victim_pos, victim_sample,
+            victim_entity, victim_pos, mut victim_sample,
             victim_timestep,
-            victim_attractor_bundle
+            victim_attractor_bundle,
+            victim_dominant,
+            victim_member,
+            mut victim_sleep,
+            victim_quality,
         )| {
             if let Some(mut victim_timestep) = victim_timestep {
                 victim_timestep.offset = victim_entity.index();
@@ -378,14 +833,43 @@ pub(crate) fn compute_gravity_field_system_yes_svo(
                 }
                 victim_timestep.last_updated = true;
             }
+            let should_compute = should_compute_sleeping(
+                &cfg, &groups_ctx, now, victim_pos.translation(), &mut victim_sample,
+                victim_sleep.as_deref_mut(),
+                |entity| attractors.get(entity).ok().map(|(pos, ..)| pos.translation()),
+            );
+            if !should_compute {
+                return;
+            }
+            let dominant = victim_dominant.and_then(|&DominantAttractor(entity)| {
+                let (pos, mass, attractor, member) = attractors.get(entity).ok()?;
+                Some(DominantAttractorRepr {
+                    entity,
+                    pos: pos.translation(),
+                    mass: mass.mass,
+                    influence_radius: attractor.influence_radius,
+                    svo_position: attractor.last_svo_position.as_ref(),
+                    svo_pos: attractor.last_svo_position.as_ref()
+                        .and_then(|path| svo_build_position(root_cell, path, entity)),
+                    group: member.map(|member| member.0)
+                        .filter(|&group| groups_ctx.groups.contains_key(&group)),
+                })
+            });
             compute_svo_gravity_field_util(
-                &cfg, root_cell,
+                &cfg, &groups_ctx, root_cell,
                 max_depth,
                 victim_entity,
                 victim_pos,
-                victim_sample,
+                victim_sample.reborrow(),
                 victim_attractor_bundle,
+                victim_member.map(|member| member.0),
+                victim_quality,
+                dominant,
+                now,
             );
+            if let Some(mut sleep) = victim_sleep {
+                sleep.sampled(&victim_sample);
+            }
         });
     });
 
@@ -395,13 +879,213 @@ pub(crate) fn compute_gravity_field_system_yes_svo(
     );
 }
 
+/// Adds the first order post-Newtonian correction of the most massive
+/// attractors to the latest field force, see [RelativisticCorrectionConfig]
+///
+/// Runs after [monitor_svo_error_system] so that it is not counted as an
+/// svo error.
+#[allow(clippy::type_complexity)]
+pub(crate) fn relativistic_correction_system(
+    cfg: Res<GravityConfig>,
+
+    attractors: Query<(
+        Entity, &GlobalTransform64, &Massive, Option<&Velocity>,
+    ), (With<Attractor>, Without<Disabled>)>,
+    mut victims: Query<(
+        Entity, &GlobalTransform64, &Velocity, &mut GravityFieldSample,
+        Option<&TimeStep>, Option<&GravitySleep>,
+    ), Without<Disabled>>,
+) {
+    let Some(correction_cfg) = cfg.relativistic_correction
+    else { return; };
+
+    let massive_attractors = attractors.iter()
+        .filter(|(_, _, massive, _)| massive.mass >= correction_cfg.min_attractor_mass)
+        .map(|(entity, transform, massive, velocity)| (
+            entity,
+            transform.translation(),
+            cfg.gravity_constant * massive.mass,
+            velocity.map_or(DVec3::ZERO, |v| v.velocity),
+        ))
+        .collect::<Vec<_>>();
+    if massive_attractors.is_empty() {
+        return;
+    }
+
+    victims.par_iter_mut().for_each(|(
+        victim_entity, victim_pos, victim_velocity, mut victim_sample, victim_timestep,
+        victim_sleep,
+    )| {
+        // Skipped entities still have the force of a previous update, and
+        // extrapolated forces already include the correction
+        if victim_timestep.is_some_and(|t| !t.last_updated) ||
+            victim_sleep.is_some_and(|s| !s.last_sampled)
+        {
+            return;
+        }
+
+        let correction = relativistic_correction(
+            &correction_cfg, &massive_attractors, victim_entity,
+            victim_pos.translation(), victim_velocity.velocity,
+            victim_sample.min_affect_distance,
+        );
+        if correction != DVec3::ZERO {
+            victim_sample.correct_latest_field_force(correction);
+        }
+    });
+}
+
+/// Sum of the corrections of the given (entity, position, gravity constant
+/// times mass, velocity) attractors to the field force of a body, see
+/// [relativistic_correction_system]
+fn relativistic_correction(
+    cfg: &RelativisticCorrectionConfig,
+    attractors: &[(Entity, DVec3, f64, DVec3)],
+    victim_entity: Entity,
+    victim_pos: DVec3,
+    victim_velocity: DVec3,
+    min_affect_distance: f64,
+) -> DVec3 {
+    let c_squared = cfg.speed_of_light.powi(2);
+    let mut correction = DVec3::ZERO;
+    for &(attractor_entity, attractor_pos, gm, attractor_velocity) in attractors {
+        if victim_entity == attractor_entity {
+            continue;
+        }
+        let r = victim_pos - attractor_pos;
+        let distance = r.length();
+        if distance > cfg.max_distance ||
+            distance <= min_affect_distance ||
+            distance.is_zero_approx()
+        {
+            continue;
+        }
+        let v = victim_velocity - attractor_velocity;
+
+        // Test particle limit of the 1PN equations of motion
+        correction += gm / (c_squared * distance.powi(3)) * (
+            (4. * gm / distance - v.length_squared()) * r +
+            4. * r.dot(v) * v
+        );
+    }
+    correction
+}
+
+/// Clamps the latest field forces, see [GravityConfig::max_field_force]
+pub(crate) fn clamp_field_forces_system(
+    cfg: Res<GravityConfig>,
+
+    mut victims: Query<&mut GravityFieldSample, Without<Disabled>>,
+) {
+    let Some(max_force) = cfg.max_field_force
+    else { return; };
+
+    victims.par_iter_mut().for_each(|mut sample| {
+        // Only mutably borrowed when needed to keep change detection useful
+        let too_strong = sample.field_force(0)
+            .is_some_and(|force| force.length_squared() > max_force * max_force);
+        if too_strong {
+            sample.clamp_latest_field_force(max_force);
+        }
+    });
+}
+
+/// Measures the error of the svo approximation on a few random particles
+/// and adjusts the opening angle accordingly, see [SvoErrorMonitorConfig]
+pub(crate) fn monitor_svo_error_system(
+    mut diagnostics: Diagnostics,
+    mut cfg: ResMut<GravityConfig>,
+
+    attractors: Query<(Entity, &GlobalTransform64, &Massive, &Attractor), Without<Disabled>>,
+    victims: Query<(
+        Entity, &GlobalTransform64, &GravityFieldSample, Option<&TimeStep>,
+        Option<&GravitySleep>,
+    ), (Without<Disabled>, Without<SampleQuality>)>,
+
+    mut update_counter: Local<u32>,
+) {
+    let monitor_cfg = cfg.svo_error_monitor;
+    if !cfg.enabled_svo || monitor_cfg.interval == 0 {
+        return;
+    }
+
+    *update_counter = update_counter.wrapping_add(1);
+    if *update_counter % monitor_cfg.interval != 0 {
+        return;
+    }
+
+    // Samples with a SampleQuality are excluded by the query, as their
+    // error is expected and must not tighten the opening angle
+    let samples = victims.iter()
+        // Only particles with a force computed this update are comparable
+        .filter(|(_, _, _, timestep, sleep)| {
+            timestep.map_or(true, |t| t.last_updated) &&
+                sleep.map_or(true, |s| s.last_sampled)
+        })
+        .choose_multiple(&mut rand::thread_rng(), monitor_cfg.sample_count);
+
+    let mut error_sum = 0.;
+    let mut error_count = 0usize;
+    for (victim_entity, victim_pos, victim_sample, ..) in samples {
+        let Some(approx_force) = victim_sample.field_force(0)
+        else { continue; };
+        let victim_pos = victim_pos.translation();
+
+        let mut exact_force = DVec3::ZERO;
+        for (attractor_entity, attractor_pos, attractor_mass, attractor) in &attractors {
+            if victim_entity == attractor_entity {
+                continue;
+            }
+            let diff = attractor_pos.translation() - victim_pos;
+            if diff.is_zero_approx() {
+                continue;
+            }
+            let distance_squared = diff.length_squared();
+            let distance = distance_squared.sqrt();
+            if distance > victim_sample.min_affect_distance {
+                exact_force += (diff / distance) * cfg.gravity_constant
+                    * influence_factor(attractor.influence_radius, distance)
+                    * (attractor_mass.mass / distance_squared);
+            }
+        }
+
+        if exact_force.is_zero_approx() {
+            continue;
+        }
+        error_sum += (approx_force - exact_force).length() / exact_force.length();
+        error_count += 1;
+    }
+
+    if error_count == 0 {
+        return;
+    }
+    let error = error_sum / error_count as f64;
+    diagnostics.add_measurement(&GRAVITY_SVO_RELATIVE_ERROR, || error);
+
+    if !monitor_cfg.auto_adjust {
+        return;
+    }
+
+    let theta = &mut cfg.svo_skip_config.opening_angle;
+    // Hysteresis so the angle doesn't oscillate around the target
+    if error > monitor_cfg.target_relative_error {
+        *theta *= 1. - monitor_cfg.adjust_rate;
+    }
+    else if error < monitor_cfg.target_relative_error / 2. {
+        *theta *= 1. + monitor_cfg.adjust_rate;
+    }
+    *theta = theta.clamp(
+        monitor_cfg.min_opening_angle, monitor_cfg.max_opening_angle
+    );
+}
+
 #[cfg(feature = "rapier")]
 pub(crate) fn apply_gravity_to_attracted_rigid_bodies_system(
     mut victims: Query<(
         &Massive, &GravityFieldSample,
         &mut RigidBodyExternalForceComp,
         Option<&TimeStep>,
-    ), With<Attracted>>,
+    ), (With<Attracted>, Without<Disabled>)>,
 ) {
     for (mass, gravity_sample, mut external_forces, timestep) in &mut victims {
         if let Some(timestep) = timestep {
@@ -412,3 +1096,106 @@ pub(crate) fn apply_gravity_to_attracted_rigid_bodies_system(
         external_forces.force = gravity_sample.field_force(0).unwrap_or_default() * mass.mass;
     }
 }
+
+/// Velocity verlet integration of [IntegratedMotion] bodies using their
+/// latest field force, [ExternalAcceleration]s being constant over the step
+#[allow(clippy::type_complexity)]
+pub(crate) fn integrate_motion_system(
+    mut diagnostics: Diagnostics,
+    time: Res<Time<Fixed>>,
+
+    mut bodies: Query<(
+        &GravityFieldSample, &mut IntegratedMotion,
+        &mut Velocity, &mut Transform64,
+        Option<&mut ExternalAcceleration>,
+    ), Without<Disabled>>,
+) {
+    let start = Instant::now();
+    let dt = time.delta_seconds_f64();
+
+    bodies.par_iter_mut().for_each(|(
+        sample, mut motion, mut velocity, mut transform, external,
+    )| {
+        // Cleared even when the step is skipped, as accumulated values are
+        // meant for a single step
+        let (external_acc, velocity_change) = match external {
+            Some(mut external) => {
+                let values = (external.acceleration, external.velocity_change);
+                external.clear();
+                values
+            },
+            None => (DVec3::ZERO, DVec3::ZERO),
+        };
+
+        let Some(field_acc) = sample.field_force(0)
+        else { return; };
+        let acceleration = field_acc + external_acc;
+
+        // Finishes the velocity update of the previous step now that the
+        // acceleration at its new position is known
+        let mut v = velocity.velocity + velocity_change;
+        if let Some(previous_acceleration) = motion.previous_acceleration {
+            v += 0.5 * (previous_acceleration + acceleration) * dt;
+        }
+
+        transform.translation += v * dt + 0.5 * acceleration * dt.powi(2);
+        velocity.velocity = v;
+        motion.previous_acceleration = Some(acceleration);
+    });
+
+    diagnostics.add_measurement(
+        &GRAVITY_INTEGRATION_SYSTEM_DURATION,
+        || start.elapsed().as_millis_f64(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::{ApproxEq, Tolerance};
+
+    #[test]
+    pub fn test_relativistic_correction() {
+        let cfg = RelativisticCorrectionConfig {
+            speed_of_light: 100.,
+            min_attractor_mass: 0.,
+            max_distance: 50.,
+        };
+        let attractor = Entity::from_raw(0);
+        let victim = Entity::from_raw(1);
+        let gm = 1e3;
+        let distance = 10.;
+        let attractor_pos = DVec3::new(3., -4., 7.);
+        let attractor_velocity = DVec3::new(5., 0., -2.);
+        let attractors = [(attractor, attractor_pos, gm, attractor_velocity)];
+
+        // On a circular orbit r.v is zero and v² = gm / r, so the correction
+        // is 3 (gm)² / (c² r²) pointing away from the attractor
+        let circular_speed = (gm / distance).sqrt();
+        let expected = 3. * gm.powi(2) / (cfg.speed_of_light.powi(2) * distance.powi(2));
+        for direction in [DVec3::X, DVec3::new(1., 2., -2.).normalize()] {
+            let tangent = direction.any_orthonormal_vector();
+            let correction = relativistic_correction(
+                &cfg, &attractors, victim,
+                attractor_pos + direction * distance,
+                attractor_velocity + tangent * circular_speed,
+                0.,
+            );
+            assert!(correction.length().approx_eq(&expected, Tolerance::Relative(1e-9)));
+            assert!(correction.normalize().approx_eq(&direction, Tolerance::Absolute(1e-9)));
+        }
+
+        let correction_at = |distance: f64, min_affect_distance: f64, victim: Entity| {
+            relativistic_correction(
+                &cfg, &attractors, victim,
+                attractor_pos + DVec3::X * distance,
+                attractor_velocity + DVec3::Y * (gm / distance).sqrt(),
+                min_affect_distance,
+            )
+        };
+        assert_ne!(correction_at(cfg.max_distance * 0.99, 0., victim), DVec3::ZERO);
+        assert_eq!(correction_at(cfg.max_distance * 1.01, 0., victim), DVec3::ZERO);
+        assert_eq!(correction_at(distance, distance * 2., victim), DVec3::ZERO);
+        assert_eq!(correction_at(distance, 0., attractor), DVec3::ZERO);
+    }
+}
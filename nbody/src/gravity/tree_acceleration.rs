@@ -1,169 +1,232 @@
 use super::*;
 
-use std::cell::RefCell;
 use bevy::{math::DVec3, prelude::*};
-use svo::AggregateData as _;
-use utils::DAabb;
-use either::Either;
-use arbitrary_int::*;
+use utils::{ApproxEq, DAabb, Tolerance};
 
 #[derive(Debug, Clone, Copy)]
 pub(super) struct SvoEntityRepr {
     pub entity: Entity,
     pub global_pos: DVec3,
     pub mass: f64,
+    /// See [Attractor::influence_radius]
+    pub influence_radius: Option<f64>,
 }
 
-#[derive(Debug, Default, Clone)]
-pub(super) struct SvoData {
-    pub aabb: DAabb,
-    pub entities: Vec<SvoEntityRepr>,
-    pub remaining_allowed_depth: u8,
+impl SvoEntityRepr {
+    /// Aabb containing every point this entity has an effect on, None if
+    /// its influence is unlimited
+    fn influence_bounds(&self) -> Option<DAabb> {
+        self.influence_radius.map(|radius| DAabb::new_center_size(
+            self.global_pos, DVec3::splat(radius * 2.)
+        ))
+    }
+
+    /// Aabb of points where the entity's force is not faded at all, the cube
+    /// inside the sphere of full strength, None if its influence is unlimited
+    fn full_strength_bounds(&self) -> Option<DAabb> {
+        self.influence_radius.map(|radius| {
+            let full_radius = radius * (1. - INFLUENCE_CUTOFF_WIDTH);
+            DAabb::new_center_size(
+                self.global_pos, DVec3::splat(full_radius * 2. / 3f64.sqrt())
+            )
+        })
+    }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
-pub(super) struct SvoInternalData {
-    pub aabb: DAabb,
-    pub count: u32,
-    pub total_mass: f64,
-    /// Relative to the AABB -> 0,0 for the min corner and 1,1 for the max corner
-    pub center_of_mass: DVec3,
+/// Common part of the two aabbs, None if they do not overlap
+fn intersection(a: DAabb, b: DAabb) -> Option<DAabb> {
+    let min = a.min().max(b.min());
+    let max = a.max().min(b.max());
+    min.cmple(max).all().then(|| DAabb::from_minmax(min, max))
 }
 
-impl svo::Data for SvoData {
-    type Internal = SvoInternalData;
+impl svo::IndexedPoint for SvoEntityRepr {
+    fn position(&self) -> DVec3 {
+        self.global_pos
+    }
 }
 
-impl svo::InternalData for SvoInternalData {
-    
+/// Leaf data of the gravity svo, the attractors in the cell
+pub(super) type SvoData = svo::PointLeafData<GravityAggregate>;
+/// Internal data of the gravity svo, see [GravityAggregate]
+pub(super) type SvoInternalData = svo::PointInternalData<GravityAggregate>;
+
+/// Sum of the masses of the same sign in a cell and their center of mass
+///
+/// Positive and negative masses are kept apart as their sum can be zero
+/// (and so the center of mass undefined) while they still have an effect
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct MassPole {
+    pub mass: f64,
+    pub center_of_mass: DVec3,
 }
 
-impl svo::AggregateData for SvoData {
-    fn aggregate<'a>(
-        children: [svo::EitherDataRef<Self>; 8]
-    ) -> Self::Internal {
-        let mut count = 0;
-        let mut total_mass = 0f64;
-        let mut weighed_pos_sum = DVec3::ZERO;
-
-        for cell in children.iter() {
-            match cell {
-                Either::Left(internal) => {
-                    count += internal.count;
-                    total_mass += internal.total_mass;
-                    weighed_pos_sum += internal.center_of_mass * internal.total_mass;
-                },
-                Either::Right(leaf) => {
-                    count += u32::try_from(leaf.entities.len()).expect("too much entities!!");
-                    total_mass += leaf.entities.iter().map(|e| e.mass).sum::<f64>();
-                    weighed_pos_sum += leaf.entities.iter().map(|e| e.global_pos * e.mass).sum::<DVec3>();
-                },
-            }
+impl MassPole {
+    fn from_weighed_sum(mass: f64, weighed_pos_sum: DVec3) -> Self {
+        if mass == 0. {
+            return Self::default();
         }
+        Self {
+            mass,
+            center_of_mass: weighed_pos_sum / mass,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mass == 0.
+    }
 
-        let aabb = children.iter()
-            .map(|c| match c {
-                Either::Left(l) => l.aabb,
-                Either::Right(r) => r.aabb,
-            })
-            .reduce(|mut a, b| {a.expand_to_contain_aabb(b); a})
-            .expect("non empty");
-
-        SvoInternalData {
-            aabb,
-            total_mass,
-            count,
-            center_of_mass: weighed_pos_sum / total_mass,
+    /// Removes the given body from the pole
+    pub fn remove(&mut self, pos: DVec3, mass: f64) {
+        // Also catches floating point errors after removing the last body
+        if mass.approx_eq(&self.mass, Tolerance::Relative(f64::EPSILON * 4.)) {
+            *self = Self::default();
+            return;
         }
+        let remaining = self.mass - mass;
+        self.center_of_mass = (self.center_of_mass * self.mass - pos * mass) / remaining;
+        self.mass = remaining;
     }
 }
 
-impl svo::SplittableData for SvoData {
-    fn should_auto_split(&self) -> bool {
-        self.remaining_allowed_depth > 0 &&
-        self.entities.len() > SVO_LEAF_MAX_PARTICLE_COUNT
+/// What the gravity computation needs to know of the attractors of a cell
+/// to skip it when it is far enough
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct GravityAggregate {
+    /// Positive masses
+    pub attractive: MassPole,
+    /// Negative masses
+    pub repulsive: MassPole,
+    /// Aabb containing every point affected by the cell's bodies, None if
+    /// any of them has an unlimited [Attractor::influence_radius]
+    pub influence_bounds: Option<DAabb>,
+    /// Conservative aabb of the points where no force of the cell's bodies
+    /// is faded by their [influence_factor], so where the cell can still be
+    /// approximated. None if there is none, only meaningful with
+    /// [Self::influence_bounds].
+    pub full_strength_bounds: Option<DAabb>,
+}
+
+impl GravityAggregate {
+    pub fn poles(&self) -> impl Iterator<Item = &MassPole> {
+        [&self.attractive, &self.repulsive].into_iter()
+            .filter(|pole| !pole.is_empty())
     }
 
-    fn split(self) -> (Self::Internal, [Self; 8]) {
-        std::thread_local! {
-            static TARGET_VEC: RefCell<Vec<u3>> = const { RefCell::new(Vec::new()) };
+    /// Center of mass of the attractive pole if any, of the repulsive one
+    /// otherwise
+    pub fn main_center_of_mass(&self) -> DVec3 {
+        if self.attractive.is_empty() {
+            self.repulsive.center_of_mass
+        }
+        else {
+            self.attractive.center_of_mass
         }
+    }
 
-        let mut children = svo::CellPath::components().map(|comp| SvoData {
-            aabb: self.aabb.octdivided(comp),
-            remaining_allowed_depth: self.remaining_allowed_depth.saturating_sub(1),
-            entities: vec![],
-        });
+    /// Removes the given body from the pole of its mass' sign
+    pub fn remove(&mut self, pos: DVec3, mass: f64) {
+        if mass >= 0. {
+            self.attractive.remove(pos, mass);
+        }
+        else {
+            self.repulsive.remove(pos, mass);
+        }
+    }
+}
 
-        let half_size = self.aabb.size / 2.;
-        let middle = self.aabb.position + half_size;
-
-        TARGET_VEC.with(|targets| {
-            let mut targets = targets.borrow_mut();
-            targets.clear();
-            targets.reserve(self.entities.len());
-
-            let mut counts = [0usize; 8];
-            self.entities.iter()
-                .map(|entity| {
-                    let mut comp = 0b000u8;
-                    if entity.global_pos.x > middle.x {
-                        comp |= 0b001;
-                    }
-                    if entity.global_pos.y > middle.y {
-                        comp |= 0b010;
-                    }
-                    if entity.global_pos.z > middle.z {
-                        comp |= 0b100;
-                    }
-                    counts[comp as usize] += 1;
-                    u3::new(comp)
-                })
-                .collect_into(&mut *targets);
-
-            for i in 0..8usize {
-                children[i].entities.reserve_exact(counts[i]);
-            }
+/// Removes the given body from the cell's stats, see
+/// [GravityAggregate::remove]
+pub(super) fn remove_from_stats(stats: &mut SvoInternalData, pos: DVec3, mass: f64) {
+    stats.aggregate.remove(pos, mass);
+    stats.count = stats.count.saturating_sub(1);
+}
 
-            for (entity, comp) in self.entities.into_iter().zip(targets.iter()) {
-                children[comp.value() as usize].entities.push(entity);
-            }
+/// (mass, weighed position sum) for positive and negative masses, the
+/// influence bounds which become None as soon as any body has an unlimited
+/// influence, and the intersection of the full strength bounds of the
+/// bounded bodies (None until one is added, then Some(None) if empty)
+#[derive(Default)]
+struct AggregateSums {
+    attractive: (f64, DVec3),
+    repulsive: (f64, DVec3),
+    influence_bounds: Option<Option<DAabb>>,
+    full_strength_bounds: Option<Option<DAabb>>,
+}
+
+impl AggregateSums {
+    fn new() -> Self {
+        Self {
+            influence_bounds: Some(None),
+            ..Default::default()
+        }
+    }
+
+    fn add_influence(&mut self, bounds: Option<DAabb>) {
+        self.influence_bounds = self.influence_bounds.zip(bounds).map(|(acc, b)| {
+            Some(acc.map_or(b, |mut acc| { acc.expand_to_contain_aabb(b); acc }))
         });
+    }
 
-        let internal = SvoData::aggregate(
-            children.each_ref().map(Either::Right)
-        );
+    /// `bounds` is the full strength region of a bounded body or part, None
+    /// if it is empty
+    fn add_full_strength(&mut self, bounds: Option<DAabb>) {
+        self.full_strength_bounds = Some(match self.full_strength_bounds {
+            None => bounds,
+            Some(acc) => acc.zip(bounds).and_then(|(acc, b)| intersection(acc, b)),
+        });
+    }
 
-        (internal, children)
+    fn finish(self) -> GravityAggregate {
+        GravityAggregate {
+            attractive: MassPole::from_weighed_sum(self.attractive.0, self.attractive.1),
+            repulsive: MassPole::from_weighed_sum(self.repulsive.0, self.repulsive.1),
+            influence_bounds: self.influence_bounds.flatten(),
+            full_strength_bounds: self.full_strength_bounds.flatten(),
+        }
     }
 }
 
-impl svo::BorrowedMergeableData for SvoData {
-    fn should_auto_merge(
-        _this: &Self::Internal,
-        children: [&Self; 8]
-    ) -> bool {
-        let small_count = children.iter()
-            .filter(|data| data.entities.len() < SVO_LEAF_MIN_PARTICLE_COUNT)
-            .count();
-
-        // Should merge if all but one leaf is under the minimum
-        // so that if there is 100 in one leaf but only 5 in the other its not worth splitting
-        small_count >= 7
+impl svo::PointAggregate for GravityAggregate {
+    type Point = SvoEntityRepr;
+
+    const LEAF_MAX_POINTS: usize = SVO_LEAF_MAX_PARTICLE_COUNT;
+    const LEAF_MIN_POINTS: usize = SVO_LEAF_MIN_PARTICLE_COUNT;
+
+    fn from_points(points: &[SvoEntityRepr]) -> Self {
+        let mut sums = AggregateSums::new();
+        for entity in points {
+            sums.add_influence(entity.influence_bounds());
+            if entity.influence_radius.is_some() {
+                sums.add_full_strength(entity.full_strength_bounds());
+            }
+            let sum = if entity.mass >= 0. {
+                &mut sums.attractive
+            } else {
+                &mut sums.repulsive
+            };
+            sum.0 += entity.mass;
+            sum.1 += entity.global_pos * entity.mass;
+        }
+        sums.finish()
     }
 
-    fn merge(
-        this: &Self::Internal,
-        children: [&Self; 8]
-    ) -> Self {
-        Self {
-            aabb: this.aabb,
-            remaining_allowed_depth: children.iter()
-                .map(|p| p.remaining_allowed_depth)
-                .max().unwrap_or_default() + 1,
-            entities: children.into_iter()
-                .flat_map(|data| data.entities.iter().copied())
-                .collect(),
+    fn combine<'a>(parts: impl Iterator<Item = &'a Self>) -> Self {
+        let mut sums = AggregateSums::new();
+        for part in parts {
+            sums.add_influence(part.influence_bounds);
+            // Parts with unbounded bodies make the whole cell unbounded
+            if part.influence_bounds.is_some() {
+                sums.add_full_strength(part.full_strength_bounds);
+            }
+            for (sum, pole) in [
+                (&mut sums.attractive, &part.attractive),
+                (&mut sums.repulsive, &part.repulsive),
+            ] {
+                sum.0 += pole.mass;
+                sum.1 += pole.center_of_mass * pole.mass;
+            }
         }
+        sums.finish()
     }
 }
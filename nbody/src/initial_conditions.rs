@@ -0,0 +1,265 @@
+//! Initial conditions of common gravity simulations, so that demos and
+//! benchmarks don't each have their own ad-hoc distributions
+//!
+//! All generators give bodies in their center of mass frame (zero total
+//! momentum and mass weighted position at the origin), discs are in the xz
+//! plane and rotate around +y.
+//!
+//! ```
+//! use nbody::initial_conditions::PlummerSphere;
+//! use rand::SeedableRng;
+//!
+//! let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+//! let bodies = PlummerSphere { count: 100, ..Default::default() }
+//!     .generate(&mut rng, 1.);
+//! assert_eq!(bodies.len(), 100);
+//! ```
+
+use bevy::math::{DQuat, DVec3};
+use rand::{distributions::Open01, Rng};
+
+/// A body generated by one of the initial conditions of this module
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InitialBody {
+    pub position: DVec3,
+    pub velocity: DVec3,
+    pub mass: f64,
+}
+
+impl InitialBody {
+    pub fn momentum(&self) -> DVec3 {
+        self.velocity * self.mass
+    }
+}
+
+/// Moves the bodies into their center of mass frame
+pub fn recenter(bodies: &mut [InitialBody]) {
+    let mass = bodies.iter().map(|body| body.mass).sum::<f64>();
+    if mass == 0. {
+        return;
+    }
+    let center = bodies.iter()
+        .map(|body| body.position * body.mass)
+        .sum::<DVec3>() / mass;
+    let velocity = bodies.iter()
+        .map(InitialBody::momentum)
+        .sum::<DVec3>() / mass;
+    for body in bodies {
+        body.position -= center;
+        body.velocity -= velocity;
+    }
+}
+
+/// Sample of the standard normal distribution (Box-Muller transform)
+fn gaussian(rng: &mut impl Rng) -> f64 {
+    let u1: f64 = rng.sample(Open01);
+    let u2: f64 = rng.sample(Open01);
+    (-2. * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+}
+
+/// Uniformly distributed direction
+fn random_direction(rng: &mut impl Rng) -> DVec3 {
+    let z = rng.gen_range(-1f64..=1.);
+    let angle = rng.gen_range(0.0..std::f64::consts::TAU);
+    let r = (1. - z * z).sqrt();
+    DVec3::new(r * angle.cos(), r * angle.sin(), z)
+}
+
+/// Spherical cluster in equilibrium whose density is
+/// `3M / (4πa³) * (1 + r²/a²)^(-5/2)`, sampled as described by Aarseth,
+/// Hénon and Wielen (1974)
+#[derive(Debug, Clone, Copy, PartialEq, derivative::Derivative)]
+#[derivative(Default)]
+pub struct PlummerSphere {
+    #[derivative(Default(value = "1000"))]
+    pub count: usize,
+    /// Total mass, shared equally by all bodies
+    #[derivative(Default(value = "1."))]
+    pub mass: f64,
+    /// Plummer radius `a`, the core of the cluster
+    #[derivative(Default(value = "1."))]
+    pub scale_radius: f64,
+    /// Bodies further than this many scale radii are sampled again, the
+    /// distribution has no bound otherwise
+    #[derivative(Default(value = "20."))]
+    pub max_radius_ratio: f64,
+}
+
+impl PlummerSphere {
+    pub fn generate(&self, rng: &mut impl Rng, gravity_constant: f64) -> Vec<InitialBody> {
+        let body_mass = self.mass / self.count as f64;
+        let a = self.scale_radius;
+
+        let mut bodies = (0..self.count).map(|_| {
+            let radius = loop {
+                // Inverse of the cumulative mass M(r)/M = r³ / (r² + a²)^(3/2)
+                let enclosed = rng.gen::<f64>();
+                let radius = a / (enclosed.powf(-2. / 3.) - 1.).sqrt();
+                if radius <= a * self.max_radius_ratio {
+                    break radius;
+                }
+            };
+
+            // Speeds relative to the escape speed are distributed as
+            // q² (1 - q²)^(7/2), which is at most 0.1
+            let q = loop {
+                let q = rng.gen::<f64>();
+                if rng.gen::<f64>() * 0.1 < q * q * (1. - q * q).powf(3.5) {
+                    break q;
+                }
+            };
+            let escape_speed = (2. * gravity_constant * self.mass
+                / (radius * radius + a * a).sqrt()).sqrt();
+
+            InitialBody {
+                position: random_direction(rng) * radius,
+                velocity: random_direction(rng) * q * escape_speed,
+                mass: body_mass,
+            }
+        }).collect::<Vec<_>>();
+
+        recenter(&mut bodies);
+        bodies
+    }
+}
+
+/// Rotating disc whose surface density decreases exponentially with the
+/// distance to its center, optionally around a central body (e.g. a star or
+/// a galaxy's bulge)
+///
+/// Bodies are given the circular speed of the mass enclosed by their orbit
+/// (treated as spherical, which slightly overestimates it) plus a random
+/// velocity, so that the disc is stable with enough dispersion.
+#[derive(Debug, Clone, Copy, PartialEq, derivative::Derivative)]
+#[derivative(Default)]
+pub struct ExponentialDisc {
+    /// Number of bodies of the disc, the central body not included
+    #[derivative(Default(value = "1000"))]
+    pub count: usize,
+    /// Total mass of the disc, shared equally by its bodies
+    #[derivative(Default(value = "1."))]
+    pub mass: f64,
+    /// Distance at which the surface density is divided by e
+    #[derivative(Default(value = "1."))]
+    pub scale_length: f64,
+    /// Thickness of the disc, heights have a sech² distribution
+    #[derivative(Default(value = "0.05"))]
+    pub scale_height: f64,
+    /// Mass of the body at the center, none if 0
+    pub central_mass: f64,
+    /// Standard deviation of each component of the random velocity, relative
+    /// to the circular speed
+    #[derivative(Default(value = "0.05"))]
+    pub velocity_dispersion: f64,
+}
+
+impl ExponentialDisc {
+    pub fn total_mass(&self) -> f64 {
+        self.mass + self.central_mass
+    }
+
+    /// The central body first, if any
+    pub fn generate(&self, rng: &mut impl Rng, gravity_constant: f64) -> Vec<InitialBody> {
+        let body_mass = self.mass / self.count as f64;
+
+        let mut bodies = Vec::with_capacity(self.count + 1);
+        if self.central_mass > 0. {
+            bodies.push(InitialBody {
+                position: DVec3::ZERO,
+                velocity: DVec3::ZERO,
+                mass: self.central_mass,
+            });
+        }
+
+        bodies.extend((0..self.count).map(|_| {
+            // The radial distribution R e^(-R/Rd) is a gamma distribution of
+            // shape 2, so the sum of two exponential distributions
+            let x = -(rng.sample::<f64, _>(Open01) * rng.sample::<f64, _>(Open01)).ln();
+            let radius = x * self.scale_length;
+            let angle = rng.gen_range(0.0..std::f64::consts::TAU);
+            let height = self.scale_height * (2. * rng.sample::<f64, _>(Open01) - 1.).atanh();
+            let position = DVec3::new(angle.cos() * radius, height, angle.sin() * radius);
+
+            let enclosed = self.central_mass + self.mass * (1. - (1. + x) * (-x).exp());
+            let circular_speed = if radius > 0. {
+                (gravity_constant * enclosed / radius).sqrt()
+            } else {
+                0.
+            };
+            let tangent = DVec3::new(angle.sin(), 0., -angle.cos());
+            let dispersion = DVec3::new(gaussian(rng), gaussian(rng), gaussian(rng))
+                * self.velocity_dispersion * circular_speed;
+
+            InitialBody {
+                position,
+                velocity: tangent * circular_speed + dispersion,
+                mass: body_mass,
+            }
+        }));
+
+        recenter(&mut bodies);
+        bodies
+    }
+}
+
+/// Two [ExponentialDisc]s on a collision course
+#[derive(Debug, Clone, Copy, PartialEq, derivative::Derivative)]
+#[derivative(Default)]
+pub struct GalaxyCollision {
+    #[derivative(Default(value = "ExponentialDisc { central_mass: 0.5, ..Default::default() }"))]
+    pub first: ExponentialDisc,
+    #[derivative(Default(value = "ExponentialDisc { central_mass: 0.5, ..Default::default() }"))]
+    pub second: ExponentialDisc,
+    /// Initial distance between the centers of the galaxies
+    #[derivative(Default(value = "20."))]
+    pub separation: f64,
+    /// Distance between the centers perpendicular to their relative
+    /// velocity, 0 for a head-on collision
+    #[derivative(Default(value = "2."))]
+    pub impact_parameter: f64,
+    /// Relative speed of the galaxies, the one of a parabolic orbit (as if
+    /// they were point masses) if None
+    pub approach_speed: Option<f64>,
+    /// Rotation of the second disc relative to the first
+    pub inclination: DQuat,
+}
+
+impl GalaxyCollision {
+    /// The bodies of the first galaxy first
+    pub fn generate(&self, rng: &mut impl Rng, gravity_constant: f64) -> Vec<InitialBody> {
+        let first_mass = self.first.total_mass();
+        let second_mass = self.second.total_mass();
+        let total_mass = first_mass + second_mass;
+
+        let impact_parameter = self.impact_parameter.min(self.separation);
+        let relative_position = DVec3::new(
+            (self.separation.powi(2) - impact_parameter.powi(2)).sqrt(),
+            0.,
+            impact_parameter,
+        );
+        let speed = self.approach_speed.unwrap_or_else(||
+            (2. * gravity_constant * total_mass / self.separation).sqrt()
+        );
+        let relative_velocity = DVec3::NEG_X * speed;
+
+        // Galaxies are placed in their center of mass frame
+        let first_offset = -relative_position * (second_mass / total_mass);
+        let first_velocity = -relative_velocity * (second_mass / total_mass);
+        let second_offset = relative_position * (first_mass / total_mass);
+        let second_velocity = relative_velocity * (first_mass / total_mass);
+
+        let mut bodies = self.first.generate(rng, gravity_constant);
+        for body in &mut bodies {
+            body.position += first_offset;
+            body.velocity += first_velocity;
+        }
+        bodies.extend(self.second.generate(rng, gravity_constant).into_iter()
+            .map(|body| InitialBody {
+                position: self.inclination * body.position + second_offset,
+                velocity: self.inclination * body.velocity + second_velocity,
+                mass: body.mass,
+            }));
+
+        bodies
+    }
+}
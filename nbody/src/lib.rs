@@ -1,9 +1,17 @@
 #![feature(duration_millis_float)]
 #![feature(closure_lifetime_binder)]
 #![feature(iter_collect_into)]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
 
 mod plugin;
 pub use plugin::*;
 
 mod gravity;
 pub use gravity::*;
+mod snapshot;
+pub use snapshot::*;
+mod pool;
+pub use pool::*;
+
+#[cfg(feature = "examples")]
+pub mod initial_conditions;
@@ -6,16 +6,36 @@ pub struct NBodyPlugin;
 
 impl Plugin for NBodyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(FixedUpdate, (
-            #[cfg(feature = "rapier")]
-            sync_attractor_masses_with_colliders_system,
-            update_svo_system,
-            compute_gravity_field_system_no_svo,
-            compute_gravity_field_system_yes_svo,
-            #[cfg(feature = "rapier")]
-            apply_gravity_to_attracted_rigid_bodies_system,
+        app.configure_sets(FixedUpdate, (
+            GravityStage::SvoUpdate,
+            GravityStage::ForceCompute,
+            GravityStage::Finalize,
+            GravityStage::Integrate,
         ).chain().in_set(GravitySystems));
 
+        app.add_systems(FixedUpdate, (
+            (
+                quarantine_non_finite_bodies_system,
+                #[cfg(feature = "rapier")]
+                sync_attractor_masses_with_colliders_system,
+                validate_attractor_masses_system,
+                update_attractor_groups_system,
+                update_svo_system,
+            ).chain().in_set(GravityStage::SvoUpdate),
+            (
+                compute_gravity_field_system_no_svo,
+                compute_gravity_field_system_yes_svo,
+            ).chain().in_set(GravityStage::ForceCompute),
+            (
+                monitor_svo_error_system,
+                relativistic_correction_system,
+                clamp_field_forces_system,
+                #[cfg(feature = "rapier")]
+                apply_gravity_to_attracted_rigid_bodies_system,
+            ).chain().in_set(GravityStage::Finalize),
+            integrate_motion_system.in_set(GravityStage::Integrate),
+        ));
+
         app.register_diagnostic(
             Diagnostic::new(GRAVITY_COMPUTE_SYSTEM_DURATION)
                 .with_suffix(" ms")
@@ -24,8 +44,20 @@ impl Plugin for NBodyPlugin {
             Diagnostic::new(GRAVITY_SVO_UPDATE_SYSTEM_DURATION)
                 .with_suffix(" ms")
         );
+        app.register_diagnostic(
+            Diagnostic::new(GRAVITY_INTEGRATION_SYSTEM_DURATION)
+                .with_suffix(" ms")
+        );
+        app.register_diagnostic(
+            Diagnostic::new(GRAVITY_SVO_RELATIVE_ERROR)
+                .with_max_history_length(1)
+        );
  
+        app.add_event::<BodyQuarantined>();
+
         app.init_resource::<GravitySvoContext>();
+        app.init_resource::<AttractorGroupsContext>();
         app.init_resource::<GravityConfig>();
+        app.init_resource::<BodyPool>();
     }
 }
@@ -0,0 +1,64 @@
+use bevy::prelude::*;
+
+/// Bodies with this component are ignored by all the systems of this crate
+/// and so are not part of the gravity svo, see [BodyPool]
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Disabled;
+
+/// Recycles the entities of short lived bodies (e.g. particles that keep
+/// merging) instead of despawning them and spawning new ones, which is
+/// costly when there are many of them.
+///
+/// Released entities get [Disabled] and keep all their components, so other
+/// systems must ignore them too (e.g. not render them). Spawning reuses one
+/// of them by inserting the whole given bundle, which must reinitialize
+/// every component that was changed during the previous life of the body.
+/// Released entities must not be despawned by anything else, use
+/// [Self::clear] instead.
+#[derive(Resource, Debug, derivative::Derivative)]
+#[derivative(Default)]
+pub struct BodyPool {
+    free: Vec<Entity>,
+    /// Entities released while this many are already free are despawned
+    #[derivative(Default(value = "4096"))]
+    pub max_free: usize,
+}
+
+impl BodyPool {
+    /// Spawns the bundle on a free entity if there is one, on a new entity
+    /// otherwise
+    pub fn spawn(&mut self, commands: &mut Commands, bundle: impl Bundle) -> Entity {
+        match self.free.pop() {
+            Some(entity) => {
+                commands.entity(entity)
+                    .remove::<Disabled>()
+                    .insert(bundle);
+                entity
+            },
+            None => commands.spawn(bundle).id(),
+        }
+    }
+
+    /// Disables the entity so it can be reused by [Self::spawn]
+    pub fn release(&mut self, commands: &mut Commands, entity: Entity) {
+        debug_assert!(!self.free.contains(&entity), "Entity released twice");
+        if self.free.len() >= self.max_free {
+            commands.entity(entity).despawn();
+            return;
+        }
+        commands.entity(entity).insert(Disabled);
+        self.free.push(entity);
+    }
+
+    /// Number of entities waiting to be reused
+    pub fn free_count(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Despawns all free entities
+    pub fn clear(&mut self, commands: &mut Commands) {
+        for entity in self.free.drain(..) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
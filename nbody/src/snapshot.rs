@@ -0,0 +1,127 @@
+use crate::*;
+
+use std::path::Path;
+
+use bevy::math::DVec3;
+use doprec::Transform64;
+use serde::{Deserialize, Serialize};
+use utils::RonFileError;
+
+/// State of a single body in a [GravitySnapshot]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BodySnapshot {
+    pub position: [f64; 3],
+    pub velocity: [f64; 3],
+    pub mass: f64,
+    /// See [TimeStep::multiplier]
+    pub timestep_multiplier: u32,
+    /// See [GravityFieldSample::min_affect_distance]
+    pub min_affect_distance: f64,
+    /// See [IntegratedMotion], None for bodies not integrated by this crate
+    /// or before their first step
+    #[serde(default)]
+    pub previous_acceleration: Option<[f64; 3]>,
+}
+
+impl BodySnapshot {
+    pub fn position(&self) -> DVec3 {
+        DVec3::from_array(self.position)
+    }
+
+    pub fn velocity(&self) -> DVec3 {
+        DVec3::from_array(self.velocity)
+    }
+
+    /// The [IntegratedMotion] to respawn the body with, so its next velocity
+    /// update is finished like if the simulation never stopped
+    pub fn integrated_motion(&self) -> IntegratedMotion {
+        IntegratedMotion::default()
+            .with_previous_acceleration(self.previous_acceleration.map(DVec3::from_array))
+    }
+}
+
+/// Full state of a gravity simulation that can be saved to a file and
+/// loaded back to resume it later.
+///
+/// Respawning the bodies is left to the user as only it knows what other
+/// components they need.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GravitySnapshot {
+    /// Seed of the rng used by the simulation, if any
+    pub seed: u64,
+    pub bodies: Vec<BodySnapshot>,
+}
+
+impl GravitySnapshot {
+    /// Captures all given bodies, entities without a [TimeStep] are
+    /// saved with a multiplier of 1 and ones without [IntegratedMotion]
+    /// without a previous acceleration
+    pub fn capture<'a>(
+        seed: u64,
+        bodies: impl IntoIterator<Item = (
+            &'a Transform64, &'a Velocity, &'a Massive,
+            &'a GravityFieldSample, Option<&'a TimeStep>,
+            Option<&'a IntegratedMotion>,
+        )>,
+    ) -> Self {
+        let bodies = bodies.into_iter()
+            .map(|(transform, velocity, massive, sample, timestep, motion)| BodySnapshot {
+                position: transform.translation.to_array(),
+                velocity: velocity.velocity.to_array(),
+                mass: massive.mass,
+                timestep_multiplier: timestep.map(|t| t.multiplier).unwrap_or(1),
+                min_affect_distance: sample.min_affect_distance,
+                previous_acceleration: motion
+                    .and_then(|motion| motion.previous_acceleration())
+                    .map(|acceleration| acceleration.to_array()),
+            })
+            .collect();
+
+        Self { seed, bodies }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RonFileError> {
+        utils::save_ron(path, self, true)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RonFileError> {
+        utils::load_ron(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_snapshot_round_trip() {
+        let transforms = [DVec3::new(1., -2., 3.5), DVec3::new(1e6, 0.1, -7.)]
+            .map(|translation| Transform64 { translation, ..Default::default() });
+        let velocities = [DVec3::new(0.5, 0., -1.), DVec3::new(-3., 2., 1e-3)]
+            .map(|velocity| Velocity { velocity });
+        let massives = [Massive { mass: 10. }, Massive { mass: -0.25 }];
+        let samples = [0.5, 2.].map(|distance|
+            GravityFieldSample::default().with_min_affect_distance(distance)
+        );
+        let timestep = TimeStep { multiplier: 4, ..Default::default() };
+        let motion = IntegratedMotion::default()
+            .with_previous_acceleration(Some(DVec3::new(0.1, -9.81, 1e-9)));
+
+        let snapshot = GravitySnapshot::capture(42, [
+            (&transforms[0], &velocities[0], &massives[0], &samples[0], Some(&timestep), Some(&motion)),
+            (&transforms[1], &velocities[1], &massives[1], &samples[1], None, None),
+        ]);
+        assert_eq!(snapshot.bodies[0].timestep_multiplier, 4);
+        assert_eq!(snapshot.bodies[1].timestep_multiplier, 1);
+        assert_eq!(snapshot.bodies[0].integrated_motion(), motion);
+        assert_eq!(snapshot.bodies[1].integrated_motion(), IntegratedMotion::default());
+
+        let path = std::env::temp_dir()
+            .join(format!("nbody_snapshot_round_trip_{}.ron", std::process::id()));
+        snapshot.save(&path).unwrap();
+        let loaded = GravitySnapshot::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), snapshot);
+    }
+}
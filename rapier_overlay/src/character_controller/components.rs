@@ -26,6 +26,10 @@ pub struct CharacterControllerComp {
 
     pub filter_flags: QueryFilterFlags,
     pub filter_groups: Option<InteractionGroups>,
+
+    /// Wether the character moves along with the body it stands on, see
+    /// [CharacterResultsComp::platform_velocity]
+    pub carried_by_platform: bool,
 }
 
 impl CharacterControllerComp {
@@ -63,6 +67,8 @@ impl From<KinematicCharacterController> for CharacterControllerComp {
 
             filter_flags: default(),
             filter_groups: default(),
+
+            carried_by_platform: true,
         }
     }
 }
@@ -80,4 +86,11 @@ pub struct CharacterResultsComp {
     pub(crate) on_ground: bool,
     pub(crate) is_sliding: bool,
     pub(crate) translation: Vector3,
+    /// Entity of the collider the character stands on, if any
+    pub(crate) platform: Option<Entity>,
+    /// Velocity of the [platform](Self::platform) under the character, added
+    /// to its next translation (and so included in
+    /// [translation](Self::translation)) when
+    /// [CharacterControllerComp::carried_by_platform] is set
+    pub(crate) platform_velocity: Vector3,
 }
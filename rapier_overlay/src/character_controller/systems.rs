@@ -18,7 +18,7 @@ pub fn characher_controllers_physics_step_system(
     let dt = time.delta_seconds_f64();
 
     let RapierContext {
-        rigid_body_set, collider_set, query_pipeline, ..
+        rigid_body_set, collider_set, query_pipeline, entities2colliders, ..
     } = &mut *context;
 
     for (
@@ -46,6 +46,20 @@ pub fn characher_controllers_physics_step_system(
             filter = filter.exclude_rigid_body(rb_handle.handle());
         }
 
+        // Like godot's platform velocity, the character follows what it
+        // stood on at the last step
+        results.platform_velocity = results.platform
+            .filter(|_| controller.carried_by_platform)
+            .and_then(|platform| entities2colliders.get_by_left(&platform))
+            .and_then(|&handle| collider_set.get(handle))
+            .and_then(|platform| platform.parent())
+            .and_then(|handle| rigid_body_set.get(handle))
+            .map(|body| body.velocity_at_point(&collider.position().translation.vector.into()))
+            .map(|velocity| velocity.to_bevy())
+            .unwrap_or_default();
+        let desired_translation =
+            next_translation.next_translation + results.platform_velocity * dt;
+
         let moved = rapier_controller.move_shape(
             dt,
             rigid_body_set,
@@ -53,7 +67,7 @@ pub fn characher_controllers_physics_step_system(
             query_pipeline,
             collider.shape(),
             collider.position(),
-            next_translation.next_translation.to_rapier(),
+            desired_translation.to_rapier(),
             filter,
             |c| {
                 collisions.push(c);
@@ -77,6 +91,16 @@ pub fn characher_controllers_physics_step_system(
         results.is_sliding = moved.is_sliding_down_slope;
         results.translation = moved.translation.to_bevy();
 
+        let up = controller.up.normalize();
+        let min_ground_cos = controller.max_slope_climb_angle.cos();
+        results.platform = collisions.iter()
+            // normal1 is the character's normal, pointing at the obstacle
+            .filter(|collision| {
+                -collision.hit.normal1.into_inner().to_bevy().dot(up) >= min_ground_cos
+            })
+            .find_map(|collision| entities2colliders.get_by_right(&collision.handle))
+            .copied();
+
         if let Some(rb) = rigid_body_comp
             .and_then(|rb| rigid_body_set.get_mut(rb.handle))
         {
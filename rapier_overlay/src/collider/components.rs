@@ -1,13 +1,14 @@
 use bevy::prelude::*;
 
-use crate::{rapier, Float};
+use crate::{rapier, Float, Vector3};
 
+use rapier::dynamics::CoefficientCombineRule;
 use rapier::geometry::{Collider, ColliderBuilder, ColliderHandle, SharedShape};
 
 #[derive(Debug, Bundle, Clone)]
 pub struct ColliderBundle {
     pub shape: ColliderShapeComp,
-    pub friction: ColliderFrictionComp,
+    pub material: ColliderMaterialComp,
     pub mass: ColliderMassComp,
 }
 
@@ -21,7 +22,12 @@ impl From<Collider> for ColliderBundle {
     fn from(value: Collider) -> Self {
         Self {
             shape: ColliderShapeComp { shape: value.shared_shape().clone() },
-            friction: ColliderFrictionComp { friction: value.friction() },
+            material: ColliderMaterialComp {
+                friction: value.friction(),
+                restitution: value.restitution(),
+                friction_combine_rule: value.friction_combine_rule(),
+                restitution_combine_rule: value.restitution_combine_rule(),
+            },
             mass: ColliderMassComp { mass: value.mass(), },
         }
     }
@@ -38,15 +44,26 @@ pub struct ColliderShapeComp {
     pub shape: SharedShape,
 }
 
+/// Physical material of the collider, can be changed at runtime
 #[derive(Debug, Component, Clone)]
-pub struct ColliderFrictionComp {
+pub struct ColliderMaterialComp {
     pub friction: Float,
+    /// 'Bounciness' of the collider, 0 for no bounce
+    pub restitution: Float,
+    /// How the friction of two colliders are combined, the rule with the
+    /// highest priority of the two is used
+    pub friction_combine_rule: CoefficientCombineRule,
+    /// Same as `friction_combine_rule` but for restitution
+    pub restitution_combine_rule: CoefficientCombineRule,
 }
 
-impl Default for ColliderFrictionComp {
+impl Default for ColliderMaterialComp {
     fn default() -> Self {
         Self {
             friction: ColliderBuilder::default_friction(),
+            restitution: 0.,
+            friction_combine_rule: default(),
+            restitution_combine_rule: default(),
         }
     }
 }
@@ -63,3 +80,71 @@ impl Default for ColliderMassComp {
         }
     }
 }
+
+/// Makes the collider of the entity the compound of the shapes
+/// ([ColliderShapeComp]) of its direct children, placed with their
+/// [Transform64](doprec::Transform64) (ignoring scale), e.g. for a vehicle
+/// assembled from several parts
+///
+/// The entity's [ColliderShapeComp] is managed by
+/// [compound_collider_system], which rebuilds it when a part is added,
+/// removed or has its shape or transform changed. Parts only need a
+/// [ColliderShapeComp], without the other components of a [ColliderBundle]
+/// they don't get a collider of their own. Children that are compounds
+/// themselves or have a composite shape (like a trimesh) are ignored.
+#[derive(getset::Getters, Debug, Component, Clone, Default)]
+pub struct CompoundColliderComp {
+    /// Children whose shapes are in the current compound, in order
+    #[getset(get = "pub")]
+    pub(super) parts: Vec<Entity>,
+}
+
+#[derive(Debug, Bundle, Clone, Default)]
+pub struct CompoundColliderBundle {
+    pub compound: CompoundColliderComp,
+    pub material: ColliderMaterialComp,
+    pub mass: ColliderMassComp,
+}
+
+/// Makes [ContactForceEvent]s be sent for the contacts of the collider of the
+/// entity (its compound collider for [CompoundColliderComp]s), if both
+/// colliders of a contact have one the least restrictive values are used
+#[derive(Debug, Component, Clone, Copy, PartialEq)]
+pub struct ContactForceEventsComp {
+    /// Contacts with a smaller total impulse during a step are ignored
+    pub min_impulse: Float,
+    /// Minimum time in seconds between two events of the same pair of
+    /// colliders, so that resting or sliding contacts don't send one every
+    /// step
+    pub min_interval: f64,
+}
+
+impl Default for ContactForceEventsComp {
+    fn default() -> Self {
+        Self {
+            min_impulse: 1.,
+            min_interval: 0.1,
+        }
+    }
+}
+
+/// Sent after a physics step for a pair of colliders in contact, one of
+/// which has a [ContactForceEventsComp], e.g. to play a sound or spawn
+/// particles proportional to the strength of an impact
+#[derive(Debug, Event, Clone, Copy, PartialEq)]
+pub struct ContactForceEvent {
+    pub entity1: Entity,
+    pub entity2: Entity,
+    /// Global position of the contact point with the largest impulse
+    pub point: Vector3,
+    /// Total impulse applied to [Self::entity2] by [Self::entity1] during the
+    /// step (along the contact normals), the opposite is applied to
+    /// [Self::entity1]
+    pub impulse: Vector3,
+}
+
+impl ContactForceEvent {
+    pub fn impulse_magnitude(&self) -> Float {
+        self.impulse.length()
+    }
+}
@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 use doprec::{GlobalTransform64, Transform64};
-use rapier::geometry::{ColliderBuilder, ColliderMassProps};
+use rapier::{geometry::{ColliderBuilder, ColliderMassProps, SharedShape}, na::Isometry3};
 
 use crate::*;
 
@@ -13,7 +13,7 @@ pub fn collider_init_system(
         Entity,
         &GlobalTransform64,
         &ColliderShapeComp,
-        &ColliderFrictionComp,
+        &ColliderMaterialComp,
         &ColliderMassComp,
 
         Option<&RigidBodyHandleComp>,
@@ -23,13 +23,16 @@ pub fn collider_init_system(
 ) {
     for (
         entity, global_transform,
-        shape, friction_comp, mass_comp,
+        shape, material_comp, mass_comp,
 
         rigid_body,
     ) in &new_colliders_query {
         let mut collider = ColliderBuilder {
             mass_properties: ColliderMassProps::Mass(mass_comp.mass),
-            friction: friction_comp.friction,
+            friction: material_comp.friction,
+            restitution: material_comp.restitution,
+            friction_combine_rule: material_comp.friction_combine_rule,
+            restitution_combine_rule: material_comp.restitution_combine_rule,
             ..ColliderBuilder::new(shape.shape.clone())
         };
 
@@ -49,7 +52,10 @@ pub fn collider_init_system(
         if let Some(rigid_body) = rigid_body {
             // Partial borrow because we need two mut borrows to context
             let RapierContext { collider_set, rigid_body_set, .. } = &mut *context;
-            collider_set.set_parent(handle, Some(rigid_body.handle()), rigid_body_set);
+            // The rigid body may have been removed this frame
+            if rigid_body_set.contains(rigid_body.handle()) {
+                collider_set.set_parent(handle, Some(rigid_body.handle()), rigid_body_set);
+            }
         }
     }
 }
@@ -61,7 +67,7 @@ pub fn collider_remove_system(
 
     invalid_handles: Query<Entity, (With<ColliderHandleComp>, Or<(
         Without<ColliderShapeComp>,
-        Without<ColliderFrictionComp>,
+        Without<ColliderMaterialComp>,
         Without<ColliderMassComp>,
     )>)>,
         
@@ -96,10 +102,10 @@ pub fn collider_update_system(
     ), (
         Changed<ColliderShapeComp>,
     )>,
-    friction_changed_query: Query<(
-        &ColliderHandleComp, &ColliderFrictionComp,
+    material_changed_query: Query<(
+        &ColliderHandleComp, &ColliderMaterialComp,
     ), (
-        Changed<ColliderFrictionComp>,
+        Changed<ColliderMaterialComp>,
     )>,
     mass_changed_query: Query<(
         &ColliderHandleComp, &ColliderMassComp,
@@ -116,14 +122,17 @@ pub fn collider_update_system(
 
         collider.set_shape(shape.shape.clone());
     }
-    for (handle, friction) in &friction_changed_query {
+    for (handle, material) in &material_changed_query {
         let Some(collider) = context.collider_set.get_mut(handle.handle)
         else {
             log::warn!("Invalid collider handle");
             continue;
         };
 
-        collider.set_friction(friction.friction);
+        collider.set_friction(material.friction);
+        collider.set_restitution(material.restitution);
+        collider.set_friction_combine_rule(material.friction_combine_rule);
+        collider.set_restitution_combine_rule(material.restitution_combine_rule);
     }
     for (handle, mass) in &mass_changed_query {
         let Some(collider) = context.collider_set.get_mut(handle.handle)
@@ -136,3 +145,63 @@ pub fn collider_update_system(
     }
 }
 
+
+/// See [CompoundColliderComp]
+#[allow(clippy::type_complexity)]
+pub fn compound_collider_system(
+    mut commands: Commands,
+
+    mut compounds_query: Query<(
+        Entity,
+        &mut CompoundColliderComp,
+        Option<&Children>,
+        Option<&mut ColliderShapeComp>,
+    )>,
+    parts_query: Query<(
+        Entity,
+        Ref<ColliderShapeComp>,
+        Ref<Transform64>,
+    ), (
+        Without<CompoundColliderComp>,
+    )>,
+) {
+    for (entity, mut compound, children, shape_comp) in &mut compounds_query {
+        let parts = children.into_iter()
+            .flat_map(|children| children.iter())
+            .filter_map(|&child| parts_query.get(child).ok())
+            .filter(|(_, shape, _)| shape.shape.as_composite_shape().is_none())
+            .collect::<Vec<_>>();
+
+        let changed = compound.is_added()
+            || parts.len() != compound.parts.len()
+            || parts.iter().zip(&compound.parts).any(|((part, shape, transform), old_part)| {
+                part != old_part || shape.is_changed() || transform.is_changed()
+            });
+        if !changed {
+            continue;
+        }
+        compound.parts = parts.iter().map(|(part, ..)| *part).collect();
+
+        if parts.is_empty() {
+            commands.entity(entity).remove::<ColliderShapeComp>();
+            continue;
+        }
+        let shape = SharedShape::compound(parts.iter()
+            .map(|(_, shape, transform)| (
+                Isometry3::from_parts(
+                    transform.translation.to_rapier().into(),
+                    transform.rotation.to_rapier(),
+                ),
+                shape.shape.clone(),
+            ))
+            .collect()
+        );
+
+        match shape_comp {
+            Some(mut shape_comp) => shape_comp.shape = shape,
+            None => {
+                commands.entity(entity).insert(ColliderShapeComp { shape });
+            },
+        }
+    }
+}
@@ -5,12 +5,22 @@ use crate::*;
 #[derive(Resource)]
 pub struct RapierConfig {
     pub gravity: Vector3,
+    /// With the `parallel` feature, the physics step runs on rayon's global
+    /// pool (shared with the rest of the app) if None, and on a dedicated
+    /// pool of this many threads otherwise
+    pub solver_threads: Option<usize>,
+    /// Active islands with less dynamic bodies are merged together before
+    /// being solved, smaller islands can be solved on more threads at once
+    /// but each one has an overhead
+    pub min_island_size: usize,
 }
 
 impl Default for RapierConfig {
     fn default() -> Self {
         Self {
             gravity: DVec3::new(0., -9.8, 0.),
+            solver_threads: None,
+            min_island_size: 128,
         }
     }
 }
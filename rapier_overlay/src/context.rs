@@ -1,13 +1,28 @@
-use bevy::{prelude::*, utils::HashMap};
+use bevy::{math::DQuat, prelude::*, utils::HashMap};
 use doprec::GlobalTransform64;
 
 use crate::*;
 use rapier::{
     dynamics::{CCDSolver, ImpulseJointSet, IslandManager, MultibodyJointSet, RigidBodyHandle, RigidBodySet},
     geometry::{BroadPhaseMultiSap, Collider, ColliderHandle, ColliderSet, NarrowPhase, Ray},
+    math::{Isometry, Point},
+    parry::{query::{NonlinearRigidMotion, ShapeCastHit, ShapeCastOptions}, shape::Shape},
     pipeline::{PhysicsPipeline, QueryFilter as RapierQFilter, QueryPipeline}
 };
 
+/// First collider hit by a shape cast, see [RapierContext::cast_shape]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShapeHit {
+    pub entity: Entity,
+    /// Time of impact, for [RapierContext::cast_shape] it is the fraction of
+    /// the displacement done before the hit
+    pub toi: Float,
+    /// Global position of the impact on the hit collider
+    pub point: Vector3,
+    /// Outward normal of the hit collider at [Self::point]
+    pub normal: Vector3,
+}
+
 #[derive(Resource, Default)]
 pub struct RapierContext {
     // Note: If needed outside the crate a util wrapper function should be
@@ -29,9 +44,89 @@ pub struct RapierContext {
     pub(crate) entities2rigidbodies: utils::BiHashMap<Entity, RigidBodyHandle>,
 
     pub(crate) entities_last_set_transform: HashMap<Entity, GlobalTransform64>,
+    /// Positions the user moved kinematic velocity based bodies to, reached
+    /// with a velocity by [kinematic_velocity_targets_system]
+    pub(crate) kinematic_velocity_targets: HashMap<RigidBodyHandle, Isometry<Float>>,
+    /// Elapsed fixed time before which no other [ContactForceEvent] can be
+    /// sent for a pair of colliders, see
+    /// [ContactForceEventsComp::min_interval]
+    pub(crate) contact_force_cooldowns: HashMap<(ColliderHandle, ColliderHandle), f64>,
+
+    /// Pool of [RapierConfig::solver_threads] along with its thread count
+    #[cfg(feature = "parallel")]
+    pub(crate) solver_pool: Option<(usize, rayon::ThreadPool)>,
 }
 
 impl RapierContext {
+    /// Removes every rigid body, collider and joint, e.g. to reload a level.
+    ///
+    /// Handles are invalidated and not reused, so entities that still have
+    /// handle components are not affected by the physics anymore and should
+    /// be despawned (or have their handle components removed to be
+    /// re-created).
+    pub fn clear(&mut self) {
+        let Self {
+            rigid_body_set, collider_set, island_manager, impulse_joint_set,
+            multibody_joint_set, ..
+        } = self;
+
+        let rigid_bodies = rigid_body_set.iter()
+            .map(|(handle, _)| handle)
+            .collect::<Vec<_>>();
+        for handle in rigid_bodies {
+            rigid_body_set.remove(
+                handle,
+                island_manager,
+                collider_set,
+                impulse_joint_set,
+                multibody_joint_set,
+                true,
+            );
+        }
+
+        // Colliders without rigid bodies
+        let colliders = collider_set.iter()
+            .map(|(handle, _)| handle)
+            .collect::<Vec<_>>();
+        for handle in colliders {
+            collider_set.remove(handle, island_manager, rigid_body_set, false);
+        }
+
+        self.query_pipeline = QueryPipeline::new();
+        self.entities2colliders.clear();
+        self.entities2rigidbodies.clear();
+        self.entities_last_set_transform.clear();
+        self.kinematic_velocity_targets.clear();
+        self.contact_force_cooldowns.clear();
+    }
+
+    pub fn rigid_body_count(&self) -> usize {
+        self.rigid_body_set.len()
+    }
+
+    pub fn collider_count(&self) -> usize {
+        self.collider_set.len()
+    }
+
+    fn collider_entity(&self, handle: ColliderHandle) -> Option<Entity> {
+        let entity = self.entities2colliders.get_by_right(&handle).copied();
+        if entity.is_none() {
+            log::warn!("Collider has no registered entity");
+        }
+        entity
+    }
+
+    fn shape_hit(&self, handle: ColliderHandle, hit: ShapeCastHit) -> Option<ShapeHit> {
+        let entity = self.collider_entity(handle)?;
+        let position = self.collider_set.get(handle)?.position();
+        Some(ShapeHit {
+            entity,
+            toi: hit.time_of_impact,
+            point: (position * hit.witness2).coords.to_bevy(),
+            normal: (position * hit.normal2).to_bevy(),
+        })
+    }
+
     /// See [QueryPipeline::cast_ray]
     pub fn cast_ray(
         &self,
@@ -56,12 +151,88 @@ impl RapierContext {
             solid,
             rapier_filter,
         )?;
-        let Some(&entity) = self.entities2colliders.get_by_right(&handle)
-        else {
-            log::warn!("Collider has no registered entity");
-            return None;
+
+        Some((self.collider_entity(handle)?, dist))
+    }
+
+    /// Sweeps the shape from the given position and rotation along the
+    /// displacement and returns the first collider it hits, with a toi
+    /// between 0 and 1, e.g. for fast projectiles that could go through thin
+    /// colliders between two frames
+    ///
+    /// With `stop_at_penetration` a shape starting inside of a collider hits
+    /// it at toi 0, otherwise it is ignored if the shape is moving out of it.
+    ///
+    /// See [QueryPipeline::cast_shape]
+    pub fn cast_shape(
+        &self,
+        shape: &dyn Shape,
+        position: Vector3,
+        rotation: DQuat,
+        displacement: Vector3,
+        stop_at_penetration: bool,
+        filter: QueryFilter,
+    ) -> Option<ShapeHit> {
+        let shape_pos = Isometry::from_parts(
+            position.to_rapier().into(), rotation.to_rapier(),
+        );
+        let options = ShapeCastOptions {
+            stop_at_penetration,
+            ..ShapeCastOptions::with_max_time_of_impact(1.)
         };
 
-        Some((entity, dist))
+        to_rapier_query!(rapier_filter = filter, self);
+
+        let (handle, hit) = self.query_pipeline.cast_shape(
+            &self.rigid_body_set,
+            &self.collider_set,
+            &shape_pos,
+            &displacement.to_rapier(),
+            shape,
+            options,
+            rapier_filter,
+        )?;
+
+        self.shape_hit(handle, hit)
+    }
+
+    /// Like [Self::cast_shape] but the shape also rotates around its origin
+    /// at the given angular velocity (in radians per second), and the toi is
+    /// in seconds, at most `max_toi`
+    ///
+    /// See [QueryPipeline::nonlinear_cast_shape]
+    #[allow(clippy::too_many_arguments)]
+    pub fn cast_shape_nonlinear(
+        &self,
+        shape: &dyn Shape,
+        position: Vector3,
+        rotation: DQuat,
+        linvel: Vector3,
+        angvel: Vector3,
+        max_toi: Float,
+        stop_at_penetration: bool,
+        filter: QueryFilter,
+    ) -> Option<ShapeHit> {
+        let motion = NonlinearRigidMotion::new(
+            Isometry::from_parts(position.to_rapier().into(), rotation.to_rapier()),
+            Point::origin(),
+            linvel.to_rapier(),
+            angvel.to_rapier(),
+        );
+
+        to_rapier_query!(rapier_filter = filter, self);
+
+        let (handle, hit) = self.query_pipeline.nonlinear_cast_shape(
+            &self.rigid_body_set,
+            &self.collider_set,
+            &motion,
+            shape,
+            0.,
+            max_toi,
+            stop_at_penetration,
+            rapier_filter,
+        )?;
+
+        self.shape_hit(handle, hit)
     }
 }
@@ -26,6 +26,9 @@ pub use character_controller::*;
 mod query_filter;
 pub use query_filter::*;
 
+mod snapshot;
+pub use snapshot::*;
+
 pub use rapier3d_f64 as rapier;
 
 pub type Float = rapier::math::Real;
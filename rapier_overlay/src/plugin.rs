@@ -1,6 +1,40 @@
-use bevy::prelude::*;
+use bevy::{diagnostic::{Diagnostic, DiagnosticPath, RegisterDiagnostic}, prelude::*};
 use crate::*;
 
+/// Duration of the whole physics step
+pub const PHYSICS_STEP_DURATION: DiagnosticPath =
+    DiagnosticPath::const_new("physics_step");
+/// Part of [PHYSICS_STEP_DURATION] spent in the broad phase
+pub const PHYSICS_BROAD_PHASE_DURATION: DiagnosticPath =
+    DiagnosticPath::const_new("physics_broad_phase");
+/// Part of [PHYSICS_STEP_DURATION] spent in the narrow phase
+pub const PHYSICS_NARROW_PHASE_DURATION: DiagnosticPath =
+    DiagnosticPath::const_new("physics_narrow_phase");
+/// Part of [PHYSICS_STEP_DURATION] spent in the constraints solver
+pub const PHYSICS_SOLVER_DURATION: DiagnosticPath =
+    DiagnosticPath::const_new("physics_solver");
+/// Number of dynamic bodies that are awake
+pub const PHYSICS_ACTIVE_BODIES: DiagnosticPath =
+    DiagnosticPath::const_new("physics_active_bodies");
+/// Number of dynamic bodies that are sleeping
+pub const PHYSICS_SLEEPING_BODIES: DiagnosticPath =
+    DiagnosticPath::const_new("physics_sleeping_bodies");
+/// Number of collider pairs with at least one active contact
+pub const PHYSICS_CONTACT_PAIRS: DiagnosticPath =
+    DiagnosticPath::const_new("physics_contact_pairs");
+/// Number of active islands, solved in parallel with the `parallel`
+/// feature
+pub const PHYSICS_ACTIVE_ISLANDS: DiagnosticPath =
+    DiagnosticPath::const_new("physics_active_islands");
+/// Number of threads the physics step can run on
+pub const PHYSICS_SOLVER_THREADS: DiagnosticPath =
+    DiagnosticPath::const_new("physics_solver_threads");
+/// Estimated part of the [PHYSICS_SOLVER_THREADS] busy during the solver
+/// stage, in percents, from the number of active islands as each island is
+/// solved by one thread
+pub const PHYSICS_SOLVER_UTILIZATION: DiagnosticPath =
+    DiagnosticPath::const_new("physics_solver_utilization");
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemSet)]
 pub struct PhysicsStepSystems;
 
@@ -16,6 +50,7 @@ impl Plugin for RapierPlugin {
             .insert_resource(RapierContext::default())
             .add_systems(PostStartup, (
                 rigid_body_init_system,
+                compound_collider_system,
                 collider_init_system,
             ).chain().after(doprec::TransformSystems))
             .add_systems(PostUpdate, (
@@ -25,15 +60,422 @@ impl Plugin for RapierPlugin {
                 rigid_body_update_system,
                 rigid_body_init_system,
 
+                compound_collider_system,
                 collider_remove_system,
                 collider_update_system,
                 collider_init_system,
             ).chain().after(doprec::TransformSystems))
             .add_systems(FixedUpdate, (
+                // Entities despawned since the last step (possibly by another
+                // fixed update) must not be simulated anymore
+                rigid_body_remove_system,
+                collider_remove_system,
+
+                kinematic_velocity_targets_system,
                 characher_controllers_physics_step_system,
                 physics_step_system,
+                contact_force_events_system,
                 physics_rapier2bevy_sync_system,
+                render_interpolation_record_system,
             ).chain().in_set(PhysicsStepSystems))
+            .add_systems(PostUpdate,
+                render_interpolation_system.before(doprec::TransformSystems)
+            )
+            .add_event::<ContactForceEvent>()
         ;
+
+        for path in [
+            PHYSICS_STEP_DURATION,
+            PHYSICS_BROAD_PHASE_DURATION,
+            PHYSICS_NARROW_PHASE_DURATION,
+            PHYSICS_SOLVER_DURATION,
+        ] {
+            app.register_diagnostic(Diagnostic::new(path).with_suffix(" ms"));
+        }
+        for path in [
+            PHYSICS_ACTIVE_BODIES,
+            PHYSICS_SLEEPING_BODIES,
+            PHYSICS_CONTACT_PAIRS,
+            PHYSICS_ACTIVE_ISLANDS,
+            PHYSICS_SOLVER_THREADS,
+        ] {
+            app.register_diagnostic(Diagnostic::new(path).with_max_history_length(1));
+        }
+        app.register_diagnostic(Diagnostic::new(PHYSICS_SOLVER_UTILIZATION).with_suffix(" %"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use doprec::{DoprecPlugin, Transform64Bundle};
+    use rapier::geometry::ColliderBuilder;
+    use utils::{ApproxEq, Tolerance};
+
+    fn test_app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            DoprecPlugin::default(),
+            RapierPlugin::default(),
+        ));
+        app.update();
+        app
+    }
+
+    fn spawn_body(app: &mut App) -> Entity {
+        app.world.spawn((
+            Transform64Bundle::default(),
+            RigidBodyBundle::dynamic(),
+            ColliderBundle::from(ColliderBuilder::ball(1.)),
+        )).id()
+    }
+
+    /// Runs a single fixed update, like the fixed loop does when catching up
+    fn step(app: &mut App) {
+        app.world.resource_mut::<Time<Fixed>>()
+            .advance_by(Duration::from_secs_f64(1. / 64.));
+        app.world.run_schedule(FixedUpdate);
+    }
+
+    fn assert_counts(app: &App, count: usize) {
+        let context = app.world.resource::<RapierContext>();
+        assert_eq!(context.rigid_body_count(), count);
+        assert_eq!(context.collider_count(), count);
+        assert_eq!(context.entities2rigidbodies.len(), count);
+        assert_eq!(context.entities2colliders.len(), count);
+        assert!(context.entities_last_set_transform.len() <= count);
+    }
+
+    #[test]
+    fn test_despawn_removes_handles() {
+        let mut app = test_app();
+        let body = spawn_body(&mut app);
+        spawn_body(&mut app);
+        app.update();
+        assert_counts(&app, 2);
+
+        app.world.despawn(body);
+        app.update();
+        assert_counts(&app, 1);
+    }
+
+    #[test]
+    fn test_despawn_during_step() {
+        #[derive(Resource)]
+        struct ToDespawn(Entity);
+
+        fn despawn_system(mut commands: Commands, to_despawn: Option<Res<ToDespawn>>) {
+            let Some(to_despawn) = to_despawn
+            else { return; };
+            commands.entity(to_despawn.0).despawn();
+            commands.remove_resource::<ToDespawn>();
+        }
+
+        let mut app = test_app();
+        app.add_systems(FixedUpdate, despawn_system.after(PhysicsStepSystems));
+        let body = spawn_body(&mut app);
+        let other = spawn_body(&mut app);
+        app.update();
+        step(&mut app);
+
+        app.insert_resource(ToDespawn(body));
+        step(&mut app);
+        // Removed by the next step, before the end of the frame
+        step(&mut app);
+        assert_counts(&app, 1);
+
+        app.update();
+        step(&mut app);
+        assert_counts(&app, 1);
+        assert!(app.world.get::<RigidBodyHandleComp>(other).is_some());
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut app = test_app();
+        let old = [spawn_body(&mut app), spawn_body(&mut app)];
+        app.update();
+        let old_handles = old.map(|entity| {
+            app.world.get::<RigidBodyHandleComp>(entity).unwrap().handle()
+        });
+
+        app.world.resource_mut::<RapierContext>().clear();
+        assert_counts(&app, 0);
+        for entity in old {
+            app.world.despawn(entity);
+        }
+        app.update();
+        step(&mut app);
+        assert_counts(&app, 0);
+
+        let new = spawn_body(&mut app);
+        app.update();
+        step(&mut app);
+        assert_counts(&app, 1);
+        let new_handle = app.world.get::<RigidBodyHandleComp>(new).unwrap().handle();
+        assert!(!old_handles.contains(&new_handle));
+    }
+
+    #[test]
+    fn test_kinematic_velocity_target() {
+        use doprec::Transform64;
+        use rapier::dynamics::RigidBodyType;
+
+        let mut app = test_app();
+        let body = app.world.spawn((
+            Transform64Bundle::default(),
+            RigidBodyBundle::new(RigidBodyType::KinematicVelocityBased),
+            ColliderBundle::from(ColliderBuilder::cuboid(5., 0.5, 5.)),
+        )).id();
+        app.update();
+
+        app.world.get_mut::<Transform64>(body).unwrap().translation.x = 1.;
+        app.update();
+
+        // Moves to the target with a velocity during the step
+        step(&mut app);
+        let linvel = app.world.get::<VelocityComp>(body).unwrap().linvel();
+        assert!(linvel.x.approx_eq(&64., Tolerance::Absolute(1e-6)));
+        let translation = app.world.get::<Transform64>(body).unwrap().translation;
+        assert!(translation.x.approx_eq(&1., Tolerance::Absolute(1e-6)));
+
+        // Then stops
+        step(&mut app);
+        let linvel = app.world.get::<VelocityComp>(body).unwrap().linvel();
+        assert!(linvel.length() < 1e-6);
+        assert!(app.world.resource::<RapierContext>().kinematic_velocity_targets.is_empty());
+    }
+
+    #[test]
+    fn test_compound_collider() {
+        use bevy::math::DVec3;
+        use doprec::Transform64;
+        use rapier::geometry::SharedShape;
+
+        let mut app = test_app();
+        let mut parts = Vec::new();
+        let ship = app.world.spawn((
+            Transform64Bundle::default(),
+            RigidBodyBundle::dynamic(),
+            CompoundColliderBundle::default(),
+        )).with_children(|ship| {
+            for x in [-2., 2.] {
+                parts.push(ship.spawn((
+                    Transform64Bundle {
+                        local: Transform64::from_translation(DVec3::new(x, 0., 0.)),
+                        ..default()
+                    },
+                    ColliderShapeComp { shape: SharedShape::ball(1.) },
+                )).id());
+            }
+        }).id();
+        app.update();
+
+        // Parts don't get their own collider
+        assert_counts(&app, 1);
+        let compound_len = |app: &App| app.world.get::<ColliderShapeComp>(ship).unwrap()
+            .shape.as_compound().unwrap().shapes().len();
+        assert_eq!(compound_len(&app), 2);
+        assert_eq!(app.world.get::<CompoundColliderComp>(ship).unwrap().parts(), &parts);
+
+        app.world.despawn(parts[0]);
+        app.update();
+        assert_eq!(compound_len(&app), 1);
+        assert_counts(&app, 1);
+
+        app.world.despawn(parts[1]);
+        app.update();
+        assert!(app.world.get::<ColliderShapeComp>(ship).is_none());
+        assert_eq!(app.world.resource::<RapierContext>().collider_count(), 0);
+    }
+
+    #[test]
+    fn test_contact_force_events() {
+        use bevy::math::DVec3;
+        use doprec::Transform64;
+
+        let mut app = test_app();
+        let ground = app.world.spawn((
+            Transform64Bundle::default(),
+            RigidBodyBundle::fixed(),
+            ColliderBundle::from(ColliderBuilder::cuboid(5., 0.5, 5.)),
+        )).id();
+        let ball = app.world.spawn((
+            Transform64Bundle {
+                local: Transform64::from_translation(DVec3::new(0., 2., 0.)),
+                ..default()
+            },
+            RigidBodyBundle::dynamic(),
+            ColliderBundle::from(ColliderBuilder::ball(1.)),
+            ContactForceEventsComp {
+                min_impulse: 0.1,
+                min_interval: 2.,
+            },
+        )).id();
+        app.update();
+
+        let mut reader = app.world.resource::<Events<ContactForceEvent>>().get_reader();
+        let mut received = Vec::new();
+        for _ in 0..64 {
+            step(&mut app);
+            let events = app.world.resource::<Events<ContactForceEvent>>();
+            received.extend(reader.read(events).copied());
+        }
+
+        // The ball keeps resting on the ground but the pair is rate limited
+        let [event] = received[..]
+        else { panic!("expected a single event, got {received:?}") };
+        let ball_is_second = event.entity2 == ball;
+        assert_eq!(
+            (event.entity1, event.entity2),
+            if ball_is_second { (ground, ball) } else { (ball, ground) },
+        );
+        assert!((event.point.y - 0.5).abs() < 0.1);
+        // Pushes the ball up
+        let ball_impulse = if ball_is_second { event.impulse } else { -event.impulse };
+        assert!(ball_impulse.y > 0.1);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        use bevy::math::DVec3;
+        use doprec::{GlobalTransform64, Transform64};
+
+        let mut app = test_app();
+        let body = app.world.spawn((
+            Transform64Bundle::default(),
+            RigidBodyBundle {
+                linvel: VelocityComp::new(DVec3::new(1., 0., 0.)),
+                ..RigidBodyBundle::dynamic()
+            },
+            ColliderBundle::from(ColliderBuilder::ball(1.)),
+        )).id();
+        app.update();
+        for _ in 0..4 {
+            step(&mut app);
+        }
+
+        let snapshot = app.world.resource::<RapierContext>().snapshot();
+        let [saved] = snapshot.bodies[..]
+        else { panic!("expected a single body, got {:?}", snapshot.bodies) };
+        assert_eq!(saved.entity, body);
+        assert!(!saved.sleeping);
+        for _ in 0..4 {
+            step(&mut app);
+        }
+        assert_ne!(app.world.get::<Transform64>(body).unwrap().translation, saved.translation());
+
+        assert_eq!(snapshot.restore(&mut app.world), 1);
+        assert_eq!(app.world.get::<Transform64>(body).unwrap().translation, saved.translation());
+        assert_eq!(app.world.get::<VelocityComp>(body).unwrap().linvel(), saved.linvel());
+        assert_eq!(app.world.resource::<RapierContext>().snapshot(), snapshot);
+        // Not seen as moved by the user, which would wake it up
+        app.update();
+        let context = app.world.resource::<RapierContext>();
+        let global = *app.world.get::<GlobalTransform64>(body).unwrap();
+        assert_eq!(context.entities_last_set_transform.get(&body), Some(&global));
+
+        // Sleeping bodies are not woken up by gravity
+        let mut asleep = snapshot.clone();
+        asleep.bodies[0].linvel = [0.; 3];
+        asleep.bodies[0].angvel = [0.; 3];
+        asleep.bodies[0].sleeping = true;
+        assert_eq!(asleep.restore(&mut app.world), 1);
+        step(&mut app);
+        assert!(app.world.get::<RigidBodySleepingComp>(body).unwrap().sleeping());
+        assert_eq!(app.world.get::<Transform64>(body).unwrap().translation, saved.translation());
+
+        // Entities are remapped, e.g. when respawned on load
+        let mut mapped = snapshot.clone();
+        mapped.map_entities(|_| None);
+        assert!(mapped.bodies.is_empty());
+        mapped = snapshot.clone();
+        let other = app.world.spawn_empty().id();
+        mapped.map_entities(|_| Some(other));
+        assert_eq!(mapped.bodies[0].entity, other);
+        // Without a rigid body
+        assert_eq!(mapped.restore(&mut app.world), 0);
+    }
+
+    #[test]
+    fn test_render_interpolation() {
+        use bevy::math::DVec3;
+        use doprec::Transform64;
+        use rapier::dynamics::RigidBodyType;
+
+        let mut app = test_app();
+        let body = app.world.spawn((
+            Transform64Bundle::default(),
+            RigidBodyBundle {
+                linvel: VelocityComp::new(DVec3::new(1., 0., 0.)),
+                ..RigidBodyBundle::new(RigidBodyType::KinematicVelocityBased)
+            },
+            ColliderBundle::from(ColliderBuilder::ball(1.)),
+        )).id();
+        let modes = [RenderInterpolationMode::Interpolate, RenderInterpolationMode::Extrapolate];
+        let renders = modes.map(|mode| app.world.spawn((
+            Transform64Bundle::default(),
+            RenderInterpolationComp::new(body).with_mode(mode),
+        )).id());
+        app.update();
+        for _ in 0..4 {
+            step(&mut app);
+        }
+        app.world.run_schedule(PostUpdate);
+
+        let body_x = app.world.get::<Transform64>(body).unwrap().translation.x;
+        let step_x = 1. / 64.;
+        let fraction = app.world.resource::<Time<Fixed>>().overstep_fraction_f64();
+        let render_x = |render: Entity| app.world.get::<Transform64>(render).unwrap().translation.x;
+        // One step late, or ahead of the body by the time since the step
+        assert!(render_x(renders[0]).approx_eq(&(body_x - step_x * (1. - fraction)), Tolerance::Absolute(1e-9)));
+        assert!(render_x(renders[1]).approx_eq(&(body_x + step_x * fraction), Tolerance::Absolute(1e-9)));
+        // The body itself stays where the physics put it
+        assert_eq!(app.world.get::<Transform64>(body).unwrap().translation.x, body_x);
+    }
+
+    #[test]
+    fn test_cast_shape() {
+        use bevy::math::{DQuat, DVec3};
+        use doprec::Transform64;
+        use rapier::geometry::Ball;
+
+        let mut app = test_app();
+        let wall = app.world.spawn((
+            Transform64Bundle {
+                local: Transform64::from_translation(DVec3::new(10., 0., 0.)),
+                ..default()
+            },
+            RigidBodyBundle::fixed(),
+            ColliderBundle::from(ColliderBuilder::cuboid(0.5, 5., 5.)),
+        )).id();
+        app.update();
+        step(&mut app);
+
+        let context = app.world.resource::<RapierContext>();
+        let ball = Ball::new(1.);
+        let hit = context.cast_shape(
+            &ball, DVec3::ZERO, DQuat::IDENTITY, DVec3::new(20., 0., 0.),
+            true, QueryFilter::new(),
+        ).expect("Should hit the wall");
+        assert_eq!(hit.entity, wall);
+        assert!(hit.toi.approx_eq(&(8.5 / 20.), Tolerance::Absolute(1e-6)));
+        assert!((hit.point - DVec3::new(9.5, 0., 0.)).length() < 1e-6);
+        assert!((hit.normal - DVec3::NEG_X).length() < 1e-6);
+
+        // Too short to reach the wall
+        assert!(context.cast_shape(
+            &ball, DVec3::ZERO, DQuat::IDENTITY, DVec3::new(8., 0., 0.),
+            true, QueryFilter::new(),
+        ).is_none());
+
+        let hit = context.cast_shape_nonlinear(
+            &ball, DVec3::ZERO, DQuat::IDENTITY, DVec3::new(20., 0., 0.),
+            DVec3::new(0., 1., 0.), 1., true, QueryFilter::new(),
+        ).expect("Should hit the wall");
+        assert_eq!(hit.entity, wall);
+        assert!(hit.toi.approx_eq(&(8.5 / 20.), Tolerance::Absolute(1e-3)));
     }
 }
@@ -1,7 +1,7 @@
 use bevy::prelude::*;
 use crate::*;
 
-use rapier::dynamics::{RigidBodyHandle, RigidBodyType};
+use rapier::{dynamics::{RigidBodyHandle, RigidBodyType}, math::Isometry};
 
 #[derive(Debug, Bundle, Clone)]
 pub struct RigidBodyBundle {
@@ -99,6 +99,12 @@ impl VelocityComp {
             linvel,
         }
     }
+
+    /// Sets the velocity of the rigid body, e.g. to drive kinematic velocity
+    /// based bodies
+    pub fn set_linvel(&mut self, linvel: Vector3) {
+        self.linvel = linvel;
+    }
 }
 
 #[derive(getset::CopyGetters, Default, Debug, Component, Clone)]
@@ -107,8 +113,77 @@ pub struct AngularVelocityComp {
     pub(crate) angvel: Vector3,
 }
 
+impl AngularVelocityComp {
+    pub fn new(angvel: Vector3) -> Self {
+        Self {
+            angvel,
+        }
+    }
+
+    /// See [VelocityComp::set_linvel]
+    pub fn set_angvel(&mut self, angvel: Vector3) {
+        self.angvel = angvel;
+    }
+}
+
 #[derive(Default, Debug, Component, Clone)]
 pub struct RigidBodyExternalForceComp {
     pub force: Vector3,
     pub torque: Vector3,
 }
+
+/// How a [RenderInterpolationComp] places its entity between physics steps
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderInterpolationMode {
+    /// Blends the last two physics poses, always on the path of the body but
+    /// one step late
+    #[default]
+    Interpolate,
+    /// Moves the last physics pose along the velocities of the body, up to
+    /// date but goes a bit through what the body is about to hit
+    Extrapolate,
+}
+
+/// Sets the [Transform64](doprec::Transform64) of its entity every frame to
+/// the pose of the rigid body of `body` between physics steps, so it moves
+/// smoothly when rendered more often than the physics runs
+///
+/// Meant for a separate entity holding the visuals of the body (e.g. a child
+/// of it, or a top level entity), the transform of the body itself is the
+/// physics one and is never changed.
+#[derive(Debug, Component, Clone)]
+pub struct RenderInterpolationComp {
+    pub body: Entity,
+    pub mode: RenderInterpolationMode,
+    pub(crate) previous: Option<Isometry<Float>>,
+    pub(crate) current: Option<Isometry<Float>>,
+    pub(crate) linvel: RapierVector3,
+    pub(crate) angvel: RapierVector3,
+}
+
+impl RenderInterpolationComp {
+    pub fn new(body: Entity) -> Self {
+        Self {
+            body,
+            mode: default(),
+            previous: None,
+            current: None,
+            linvel: RapierVector3::zeros(),
+            angvel: RapierVector3::zeros(),
+        }
+    }
+
+    pub fn with_mode(self, mode: RenderInterpolationMode) -> Self {
+        Self {
+            mode,
+            ..self
+        }
+    }
+
+    /// Forgets the previous poses so the next one isn't blended with them,
+    /// e.g. when the body is teleported
+    pub fn reset(&mut self) {
+        self.previous = None;
+        self.current = None;
+    }
+}
@@ -1,6 +1,7 @@
 use bevy::prelude::*;
 use doprec::{GlobalTransform64, Transform64};
-use rapier::{dynamics::{RigidBodyActivation, RigidBodyBuilder, RigidBodyType}, na::Translation3};
+use rapier::{dynamics::{RigidBodyActivation, RigidBodyBuilder, RigidBodyType}, math::Isometry, na::{Translation3, UnitQuaternion}};
+use utils::IsZeroApprox;
 
 use crate::*;
 
@@ -46,6 +47,7 @@ pub fn rigid_body_init_system(
         rigid_body.angvel = angular_velocity.angvel.to_rapier();
 
         let handle = context.rigid_body_set.insert(rigid_body);
+        context.entities2rigidbodies.insert(entity, handle);
 
         commands.entity(entity)
             .insert(RigidBodyHandleComp {
@@ -55,7 +57,9 @@ pub fn rigid_body_init_system(
         if let Some(col_comp) = collider {
             // Partial borrow because we need two mut borrows to context
             let RapierContext { collider_set, rigid_body_set, .. } = &mut *context;
-            collider_set.set_parent(col_comp.handle(), Some(handle), rigid_body_set);
+            if collider_set.contains(col_comp.handle()) {
+                collider_set.set_parent(col_comp.handle(), Some(handle), rigid_body_set);
+            }
         }
     }
 }
@@ -83,6 +87,7 @@ pub fn rigid_body_remove_system(
             })
         )
     {
+        context.entities_last_set_transform.remove(&entity);
         let Some((_, handle)) = context.entities2rigidbodies.remove_by_left(&entity)
         else { continue; };
 
@@ -124,6 +129,12 @@ pub fn rigid_body_update_system(
     ), (
         Changed<RigidBodyExternalForceComp>,
     )>,
+    velocity_changed_query: Query<(
+        &RigidBodyHandleComp, &VelocityComp, &AngularVelocityComp,
+    ), Or<(
+        Changed<VelocityComp>,
+        Changed<AngularVelocityComp>,
+    )>>,
     transform_changed_query: Query<(
         Entity, &RigidBodyHandleComp, &GlobalTransform64,
     ), (
@@ -182,9 +193,28 @@ pub fn rigid_body_update_system(
         rigid_body.add_torque(comp.torque.to_rapier(), true);
     }
 
+    for (handle, linvel, angvel) in &velocity_changed_query {
+        let Some(rigid_body) = context.rigid_body_set.get_mut(handle.handle)
+        else {
+            log::warn!("Invlid Rigid Body handle");
+            continue;
+        };
+
+        // Also changed by the physics sync, in which case it is already
+        // the rigid body's velocity
+        if *rigid_body.linvel() != linvel.linvel.to_rapier() {
+            rigid_body.set_linvel(linvel.linvel.to_rapier(), true);
+        }
+        if *rigid_body.angvel() != angvel.angvel.to_rapier() {
+            rigid_body.set_angvel(angvel.angvel.to_rapier(), true);
+        }
+    }
+
     for (entity, handle, comp) in &transform_changed_query {
-        let RapierContext { rigid_body_set, entities_last_set_transform, .. }
-            = &mut *context;
+        let RapierContext {
+            rigid_body_set, entities_last_set_transform,
+            kinematic_velocity_targets, ..
+        } = &mut *context;
 
         let Some(rigid_body) = rigid_body_set.get_mut(handle.handle)
         else {
@@ -197,7 +227,7 @@ pub fn rigid_body_update_system(
             let trans = Transform64::from(*comp);
 
             match rigid_body.body_type() {
-                RigidBodyType::Dynamic | RigidBodyType::Fixed | RigidBodyType::KinematicVelocityBased => {
+                RigidBodyType::Dynamic | RigidBodyType::Fixed => {
                     let tt = trans.translation.to_rapier();
                     rigid_body.set_translation(tt, true);
                     let rr = trans.rotation.to_rapier();
@@ -207,8 +237,122 @@ pub fn rigid_body_update_system(
                     rigid_body.set_next_kinematic_rotation(trans.rotation.to_rapier());
                     rigid_body.set_next_kinematic_translation(trans.translation.to_rapier());
                 },
+                // Moved with a velocity instead of teleported so what touches
+                // it is carried along
+                RigidBodyType::KinematicVelocityBased => {
+                    kinematic_velocity_targets.insert(handle.handle, Isometry::from_parts(
+                        Translation3::from(trans.translation.to_rapier()),
+                        trans.rotation.to_rapier(),
+                    ));
+                },
             }
         }
     }
 }
 
+/// Gives kinematic velocity based bodies moved by the user the velocity
+/// reaching their new position at the end of the step, and stops them once
+/// they reached it
+///
+/// Overrides the velocity set with [VelocityComp] while there is a target.
+pub fn kinematic_velocity_targets_system(
+    time: Res<Time<Fixed>>,
+    mut context: ResMut<RapierContext>,
+) {
+    let dt = time.delta_seconds_f64();
+    if dt <= 0. {
+        return;
+    }
+
+    let RapierContext { rigid_body_set, kinematic_velocity_targets, .. }
+        = &mut *context;
+
+    kinematic_velocity_targets.retain(|&handle, target| {
+        let Some(rigid_body) = rigid_body_set.get_mut(handle)
+        else { return false; };
+        if rigid_body.body_type() != RigidBodyType::KinematicVelocityBased {
+            return false;
+        }
+
+        let position = rigid_body.position();
+        let delta_translation = target.translation.vector - position.translation.vector;
+        let delta_rotation = (target.rotation * position.rotation.inverse()).scaled_axis();
+
+        let reached = delta_translation.to_bevy().is_zero_within(1e-9)
+            && delta_rotation.to_bevy().is_zero_within(1e-9);
+        if reached {
+            rigid_body.set_linvel(RapierVector3::zeros(), true);
+            rigid_body.set_angvel(RapierVector3::zeros(), true);
+        }
+        else {
+            rigid_body.set_linvel(delta_translation / dt, true);
+            rigid_body.set_angvel(delta_rotation / dt, true);
+        }
+        !reached
+    });
+}
+
+
+/// Stores the pose and velocities of the bodies of the
+/// [RenderInterpolationComp]s after each physics step
+pub fn render_interpolation_record_system(
+    context: Res<RapierContext>,
+    mut interpolations: Query<&mut RenderInterpolationComp>,
+) {
+    for mut interpolation in &mut interpolations {
+        let Some(rigid_body) = context.entities2rigidbodies.get_by_left(&interpolation.body)
+            .and_then(|&handle| context.rigid_body_set.get(handle))
+        else { continue; };
+
+        let position = *rigid_body.position();
+        interpolation.previous = Some(interpolation.current.unwrap_or(position));
+        interpolation.current = Some(position);
+        interpolation.linvel = *rigid_body.linvel();
+        interpolation.angvel = *rigid_body.angvel();
+    }
+}
+
+/// Places the entities of the [RenderInterpolationComp]s at the pose of
+/// their body at the time elapsed since the last physics step
+pub fn render_interpolation_system(
+    time: Res<Time<Fixed>>,
+
+    globals_transes_query: Query<&GlobalTransform64>,
+    mut interpolations: Query<(
+        &RenderInterpolationComp, &mut Transform64, Option<&Parent>,
+    )>,
+) {
+    let fraction = time.overstep_fraction_f64().clamp(0., 1.);
+    let overstep = time.overstep().as_secs_f64();
+
+    for (interpolation, mut transform_comp, parent_comp) in &mut interpolations {
+        let (Some(previous), Some(current)) = (interpolation.previous, interpolation.current)
+        else { continue; };
+
+        let position = match interpolation.mode {
+            RenderInterpolationMode::Interpolate => previous.lerp_slerp(&current, fraction),
+            RenderInterpolationMode::Extrapolate => Isometry::from_parts(
+                Translation3::from(current.translation.vector + interpolation.linvel * overstep),
+                UnitQuaternion::new(interpolation.angvel * overstep) * current.rotation,
+            ),
+        };
+
+        let parent_trans = parent_comp
+            .and_then(|parent| globals_transes_query.get(parent.get()).ok())
+            .copied()
+            .unwrap_or_default();
+        let new_transform = Transform64 {
+            // Keeps the scale of the visuals
+            scale: transform_comp.scale,
+            ..Transform64::from(parent_trans.inverse()) * Transform64 {
+                translation: position.translation.vector.to_bevy(),
+                rotation: position.rotation.to_bevy(),
+                scale: Vector3::ONE,
+            }
+        };
+
+        if new_transform != *transform_comp {
+            *transform_comp = new_transform;
+        }
+    }
+}
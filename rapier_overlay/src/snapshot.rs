@@ -0,0 +1,168 @@
+use bevy::{math::DQuat, prelude::*};
+use doprec::{GlobalTransform64, Transform64};
+use rapier::{math::Isometry, na::Translation3};
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Entities are saved as their bits, see [PhysicsSnapshot::map_entities]
+mod entity_bits {
+    use bevy::prelude::Entity;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(entity: &Entity, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(entity.to_bits())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Entity, D::Error> {
+        Ok(Entity::from_bits(u64::deserialize(deserializer)?))
+    }
+}
+
+/// State of a single rigid body in a [PhysicsSnapshot]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RigidBodySnapshot {
+    #[serde(with = "entity_bits")]
+    pub entity: Entity,
+    /// Global position
+    pub translation: [Float; 3],
+    pub rotation: [Float; 4],
+    pub linvel: [Float; 3],
+    pub angvel: [Float; 3],
+    pub sleeping: bool,
+}
+
+impl RigidBodySnapshot {
+    pub fn translation(&self) -> Vector3 {
+        Vector3::from_array(self.translation)
+    }
+
+    pub fn rotation(&self) -> DQuat {
+        DQuat::from_array(self.rotation)
+    }
+
+    pub fn linvel(&self) -> Vector3 {
+        Vector3::from_array(self.linvel)
+    }
+
+    pub fn angvel(&self) -> Vector3 {
+        Vector3::from_array(self.angvel)
+    }
+}
+
+/// Dynamic state of all rigid bodies of a [RapierContext], to be saved with
+/// the rest of the game and restored on load.
+///
+/// Respawning the bodies (with their colliders and other components) is
+/// left to the user, the snapshot then puts them back exactly where they
+/// were, with the same velocities and sleeping bodies still asleep, so
+/// stacked objects don't jump when loading.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PhysicsSnapshot {
+    pub bodies: Vec<RigidBodySnapshot>,
+}
+
+impl PhysicsSnapshot {
+    /// Replaces the saved entities, e.g. with the ones respawned on load,
+    /// bodies mapped to None are dropped
+    pub fn map_entities(&mut self, mut f: impl FnMut(Entity) -> Option<Entity>) {
+        self.bodies.retain_mut(|body| {
+            let Some(entity) = f(body.entity)
+            else { return false; };
+            body.entity = entity;
+            true
+        });
+    }
+
+    /// Restores the state of the bodies in the physics and in their
+    /// components, returns the number of restored bodies.
+    ///
+    /// Bodies must already have been created by the [RapierPlugin] (i.e. have
+    /// a [RigidBodyHandleComp]), others are ignored.
+    pub fn restore(&self, world: &mut World) -> usize {
+        world.resource_scope(|world, mut context: Mut<RapierContext>| {
+            let RapierContext {
+                rigid_body_set, entities2rigidbodies, entities_last_set_transform,
+                kinematic_velocity_targets, ..
+            } = &mut *context;
+
+            let mut restored = 0;
+            for body in &self.bodies {
+                let Some(&handle) = entities2rigidbodies.get_by_left(&body.entity)
+                else { continue; };
+                let Some(rigid_body) = rigid_body_set.get_mut(handle)
+                else { continue; };
+
+                rigid_body.set_position(Isometry::from_parts(
+                    Translation3::from(body.translation().to_rapier()),
+                    body.rotation().to_rapier(),
+                ), false);
+                rigid_body.set_linvel(body.linvel().to_rapier(), false);
+                rigid_body.set_angvel(body.angvel().to_rapier(), false);
+                if body.sleeping {
+                    rigid_body.sleep();
+                }
+                else {
+                    rigid_body.wake_up(true);
+                }
+                kinematic_velocity_targets.remove(&handle);
+
+                // Same as physics_rapier2bevy_sync_system, which doesn't
+                // sync sleeping bodies
+                let parent_trans = world.get::<Parent>(body.entity)
+                    .and_then(|parent| world.get::<GlobalTransform64>(parent.get()))
+                    .copied()
+                    .unwrap_or_default();
+                let new_transform = Transform64::from(parent_trans.inverse()) *
+                    Transform64 {
+                        translation: body.translation(),
+                        rotation: body.rotation(),
+                        scale: Vector3::ONE,
+                    };
+                let new_global_transform = parent_trans * new_transform;
+                entities_last_set_transform.insert(body.entity, new_global_transform);
+
+                let mut entity = world.entity_mut(body.entity);
+                if let Some(mut transform) = entity.get_mut::<Transform64>() {
+                    *transform = new_transform;
+                }
+                if let Some(mut global_transform) = entity.get_mut::<GlobalTransform64>() {
+                    *global_transform = new_global_transform;
+                }
+                if let Some(mut linvel) = entity.get_mut::<VelocityComp>() {
+                    linvel.linvel = body.linvel();
+                }
+                if let Some(mut angvel) = entity.get_mut::<AngularVelocityComp>() {
+                    angvel.angvel = body.angvel();
+                }
+                if let Some(mut sleeping) = entity.get_mut::<RigidBodySleepingComp>() {
+                    sleeping.sleeping = body.sleeping;
+                }
+
+                restored += 1;
+            }
+            restored
+        })
+    }
+}
+
+impl RapierContext {
+    /// Captures the state of all rigid bodies, see [PhysicsSnapshot]
+    pub fn snapshot(&self) -> PhysicsSnapshot {
+        let bodies = self.entities2rigidbodies.iter()
+            .filter_map(|(&entity, &handle)| {
+                let rigid_body = self.rigid_body_set.get(handle)?;
+                Some(RigidBodySnapshot {
+                    entity,
+                    translation: rigid_body.translation().to_bevy().to_array(),
+                    rotation: rigid_body.rotation().to_bevy().to_array(),
+                    linvel: rigid_body.linvel().to_bevy().to_array(),
+                    angvel: rigid_body.angvel().to_bevy().to_array(),
+                    sleeping: rigid_body.is_sleeping(),
+                })
+            })
+            .collect();
+
+        PhysicsSnapshot { bodies }
+    }
+}
@@ -1,10 +1,13 @@
-use bevy::{math::DVec3, prelude::*};
+use std::time::Instant;
+
+use bevy::{diagnostic::Diagnostics, math::DVec3, prelude::*};
 use doprec::{GlobalTransform64, Transform64};
 use rapier::dynamics::IntegrationParameters;
 
 use crate::*;
 
 pub fn physics_step_system(
+    mut diagnostics: Diagnostics,
     time: Res<Time<Fixed>>,
     mut context: ResMut<RapierContext>,
     cfg: Option<Res<RapierConfig>>,
@@ -17,16 +20,51 @@ pub fn physics_step_system(
         // and other in user-land may do to, so we can't do anything about it
         // (other than add a time_scale, but in that case we need to add it everywhere)
         dt: time.delta_seconds_f64(),
+        min_island_size: cfg.min_island_size,
         ..default()
     };
 
     let RapierContext {
         rigid_body_set, collider_set, physics_pipeline, island_manager,
         broad_phase, narrow_phase, impulse_joint_set, multibody_joint_set,
-        ccd_solver, query_pipeline, ..
+        ccd_solver, query_pipeline,
+        #[cfg(feature = "parallel")]
+        solver_pool,
+        ..
     } = &mut *context;
 
-    physics_pipeline.step(
+    #[cfg(feature = "parallel")]
+    let solver_pool = match cfg.solver_threads {
+        Some(threads) if solver_pool.as_ref().is_some_and(|(t, _)| *t == threads) =>
+            solver_pool.as_ref().map(|(_, pool)| pool),
+        Some(threads) => {
+            *solver_pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|i| format!("rapier solver {i}"))
+                .build()
+                .inspect_err(|e| log::warn!("Could not create the solver pool: {e}"))
+                .ok()
+                .map(|pool| (threads, pool));
+            solver_pool.as_ref().map(|(_, pool)| pool)
+        },
+        None => {
+            *solver_pool = None;
+            None
+        },
+    };
+    #[cfg(feature = "parallel")]
+    let solver_threads = solver_pool
+        .map_or_else(rayon::current_num_threads, |pool| pool.current_num_threads());
+    #[cfg(not(feature = "parallel"))]
+    let solver_threads = 1;
+
+    // Needed for the phases timings
+    if !physics_pipeline.counters.enabled() {
+        physics_pipeline.counters.enable();
+    }
+
+    let start = Instant::now();
+    let step = || physics_pipeline.step(
         &cfg.gravity.to_rapier(),
         &params,
         island_manager,
@@ -41,6 +79,119 @@ pub fn physics_step_system(
         &(),
         &(),
     );
+    // Without a dedicated pool rapier uses the current one, the global pool
+    #[cfg(feature = "parallel")]
+    match solver_pool {
+        Some(pool) => pool.install(step),
+        None => step(),
+    }
+    #[cfg(not(feature = "parallel"))]
+    step();
+
+    diagnostics.add_measurement(
+        &PHYSICS_STEP_DURATION,
+        || start.elapsed().as_secs_f64() * 1000.,
+    );
+    // Rapier's timers are in milliseconds
+    let counters = &physics_pipeline.counters;
+    diagnostics.add_measurement(
+        &PHYSICS_BROAD_PHASE_DURATION, || counters.cd.broad_phase_time.time(),
+    );
+    diagnostics.add_measurement(
+        &PHYSICS_NARROW_PHASE_DURATION, || counters.cd.narrow_phase_time.time(),
+    );
+    diagnostics.add_measurement(
+        &PHYSICS_SOLVER_DURATION, || counters.stages.solver_time.time(),
+    );
+
+    diagnostics.add_measurement(&PHYSICS_ACTIVE_BODIES, || {
+        island_manager.active_dynamic_bodies().len() as f64
+    });
+    diagnostics.add_measurement(&PHYSICS_SLEEPING_BODIES, || {
+        rigid_body_set.iter()
+            .filter(|(_, body)| body.is_dynamic() && body.is_sleeping())
+            .count() as f64
+    });
+    let active_islands = island_manager.num_islands();
+    diagnostics.add_measurement(&PHYSICS_ACTIVE_ISLANDS, || active_islands as f64);
+    diagnostics.add_measurement(&PHYSICS_SOLVER_THREADS, || solver_threads as f64);
+    diagnostics.add_measurement(&PHYSICS_SOLVER_UTILIZATION, || {
+        active_islands.min(solver_threads) as f64 / solver_threads as f64 * 100.
+    });
+    diagnostics.add_measurement(&PHYSICS_CONTACT_PAIRS, || {
+        narrow_phase.contact_pairs()
+            .filter(|pair| pair.has_any_active_contact)
+            .count() as f64
+    });
+}
+
+/// Sends the [ContactForceEvent]s of the last step
+pub fn contact_force_events_system(
+    time: Res<Time<Fixed>>,
+    mut context: ResMut<RapierContext>,
+    mut events: EventWriter<ContactForceEvent>,
+
+    comps: Query<&ContactForceEventsComp>,
+) {
+    let now = time.elapsed_seconds_f64();
+    let RapierContext {
+        narrow_phase, collider_set, entities2colliders, contact_force_cooldowns, ..
+    } = &mut *context;
+
+    contact_force_cooldowns.retain(|_, until| *until > now);
+    if comps.is_empty() {
+        return;
+    }
+
+    for pair in narrow_phase.contact_pairs() {
+        if !pair.has_any_active_contact {
+            continue;
+        }
+        let key = (pair.collider1, pair.collider2);
+        if contact_force_cooldowns.contains_key(&key) {
+            continue;
+        }
+        let (Some(&entity1), Some(&entity2)) = (
+            entities2colliders.get_by_right(&pair.collider1),
+            entities2colliders.get_by_right(&pair.collider2),
+        ) else { continue; };
+        // The least restrictive of the two if both have one
+        let Some(comp) = [comps.get(entity1).ok(), comps.get(entity2).ok()]
+            .into_iter().flatten().copied()
+            .reduce(|a, b| ContactForceEventsComp {
+                min_impulse: a.min_impulse.min(b.min_impulse),
+                min_interval: a.min_interval.min(b.min_interval),
+            })
+        else { continue; };
+        let Some(collider1) = collider_set.get(pair.collider1)
+        else { continue; };
+
+        let mut impulse = DVec3::ZERO;
+        let mut strongest: Option<(Float, Vector3)> = None;
+        for manifold in &pair.manifolds {
+            let normal = manifold.data.normal.to_bevy();
+            let position = manifold.subshape_pos1
+                .map_or(*collider1.position(), |pos| collider1.position() * pos);
+            for point in &manifold.points {
+                impulse += normal * point.data.impulse;
+                if strongest.map_or(true, |(max, _)| point.data.impulse > max) {
+                    let global = position * point.local_p1;
+                    strongest = Some((
+                        point.data.impulse,
+                        Vector3::new(global.x, global.y, global.z),
+                    ));
+                }
+            }
+        }
+        let Some((_, point)) = strongest
+        else { continue; };
+        if impulse.length() < comp.min_impulse {
+            continue;
+        }
+
+        events.send(ContactForceEvent { entity1, entity2, point, impulse });
+        contact_force_cooldowns.insert(key, now + comp.min_interval);
+    }
 }
 
 #[allow(clippy::type_complexity)]
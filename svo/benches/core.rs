@@ -0,0 +1,112 @@
+//! Benchmarks of the core svo operations, run with `cargo bench -p svo`
+
+use bevy_math::DVec3;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use half::f16;
+use svo::{mesh_generation::marching_cubes, ArcPtr, BoxPtr, Cell, CellPath, ChunkView, OwnedSvoPtr, PackedCell, TerrainCellData, TerrainCellKind};
+use utils::DAabb;
+
+const DEPTH: u32 = 6;
+
+/// Terrain of a sphere filling a third of the root cell, with the same
+/// sampling as the generators (distances at the min corner of the leaves)
+fn sphere<Ptr: OwnedSvoPtr<TerrainCellData>>(depth: u32) -> (Cell<TerrainCellData, Ptr>, DAabb) {
+    let root_aabb = DAabb::new_center_size(DVec3::ZERO, DVec3::splat(2f64.powi(depth as i32)));
+    let radius = root_aabb.size.x / 3.;
+    let cell = Cell::build_with(depth, |path| {
+        let distance = path.get_aabb(root_aabb).position.length() - radius;
+        let kind = if distance < 0. { TerrainCellKind::Stone } else { TerrainCellKind::Air };
+        TerrainCellData {
+            kind,
+            distance: f16::from_f64(distance),
+            empty: kind.empty(),
+            error: f16::ZERO,
+        }
+    });
+    (cell, root_aabb)
+}
+
+/// Paths of the leaves spread over the whole svo
+fn sample_paths() -> Vec<CellPath> {
+    CellPath::all_iter(DEPTH).step_by(7).collect()
+}
+
+fn paths(c: &mut Criterion) {
+    let (cell, _) = sphere::<ArcPtr<_>>(DEPTH);
+    let paths = sample_paths();
+
+    c.bench_function("follow_path", |b| b.iter(|| {
+        for path in &paths {
+            black_box(cell.follow_path(black_box(path)));
+        }
+    }));
+    c.bench_function("get_path", |b| b.iter(|| {
+        for path in &paths {
+            black_box(cell.get_path(black_box(path.clone())));
+        }
+    }));
+    c.bench_function("neighbor", |b| b.iter(|| {
+        for path in &paths {
+            black_box(path.neighbor(1, -1, 1));
+        }
+    }));
+}
+
+fn updates(c: &mut Criterion) {
+    // Boxed so that the clones of the batches are deep copies, updating a
+    // clone of an arc svo would also measure its copy on write
+    let (cell, _) = sphere::<BoxPtr<_>>(DEPTH);
+
+    c.bench_function("update_all", |b| b.iter_batched(
+        || cell.clone(),
+        |mut cell| {
+            cell.update_all();
+            cell
+        },
+        BatchSize::LargeInput,
+    ));
+    c.bench_function("iter", |b| b.iter(|| {
+        black_box(&cell).iter().count()
+    }));
+}
+
+fn packed(c: &mut Criterion) {
+    let (cell, _) = sphere::<ArcPtr<_>>(DEPTH);
+    let packed = PackedCell::from_cell(cell)
+        .unwrap_or_else(|_| panic!("Built with a uniform depth"));
+
+    c.bench_function("packed_split", |b| b.iter_batched(
+        || packed.clone(),
+        |packed| packed.split(),
+        BatchSize::LargeInput,
+    ));
+    let (root, children) = packed.clone().split();
+    c.bench_function("packed_repack", |b| b.iter(|| {
+        PackedCell::new_repack(black_box(children.each_ref()), root)
+    }));
+}
+
+fn mesh_generation(c: &mut Criterion) {
+    let (cell, root_aabb) = sphere::<ArcPtr<_>>(DEPTH);
+    let view = ChunkView::from_root(&cell, CellPath::new());
+
+    c.bench_function("marching_cubes", |b| {
+        let mut out = marching_cubes::Out::new(true, false);
+        b.iter(|| {
+            out.clear();
+            marching_cubes::run(&mut out, &view, root_aabb, DEPTH);
+            black_box(out.vertices.len())
+        })
+    });
+    c.bench_function("marching_cubes_collision", |b| {
+        let mut out = marching_cubes::CollisionOut::new();
+        b.iter(|| {
+            out.clear();
+            marching_cubes::run_collision(&mut out, &view, root_aabb, DEPTH, 1);
+            black_box(out.triangles.len())
+        })
+    });
+}
+
+criterion_group!(benches, paths, updates, packed, mesh_generation);
+criterion_main!(benches);
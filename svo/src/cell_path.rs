@@ -1,11 +1,77 @@
 use std::iter::FusedIterator;
 
 use arbitrary_int::*;
-use bevy_math::UVec3;
+use bevy_math::{DVec3, UVec3};
 use utils::{ AsVecExt, DAabb, GlamFloat, Vec3Ext };
 
 type CellPathInner = u64;
 
+/// Error returned by the fallible operations of [CellPath]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellPathError {
+    /// The operation would make the path longer than [CellPath::MAX_CAPACITY]
+    CapacityExceeded {
+        /// Length the path would have had
+        len: u32,
+    },
+    /// The given depth is higher than the length of the path
+    DepthOutOfRange {
+        depth: u32,
+        len: u32,
+    },
+}
+
+impl std::fmt::Display for CellPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CapacityExceeded { len } => write!(
+                f, "path of length {len} exceeds the capacity of {}",
+                CellPath::MAX_CAPACITY,
+            ),
+            Self::DepthOutOfRange { depth, len } => write!(
+                f, "depth {depth} is out of range for a path of length {len}",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CellPathError {}
+
+/// Error returned when parsing a [CellPath] from its text or compact form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseCellPathError {
+    /// A component of the text form isn't a digit from 0 to 7
+    InvalidComponent(String),
+    /// The compact form contains a character outside of its alphabet or
+    /// doesn't encode a valid path
+    InvalidCompact(String),
+    Path(CellPathError),
+}
+
+impl std::fmt::Display for ParseCellPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidComponent(c) => write!(
+                f, "invalid path component '{c}', expected a digit from 0 to 7",
+            ),
+            Self::InvalidCompact(s) => write!(f, "invalid compact path '{s}'"),
+            Self::Path(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseCellPathError {}
+
+impl From<CellPathError> for ParseCellPathError {
+    fn from(value: CellPathError) -> Self {
+        Self::Path(value)
+    }
+}
+
+/// Url safe base64 alphabet used by [CellPath::to_compact]
+const COMPACT_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
 /// Represent a path on the stack by packing a u3 array into a number with
 /// a leading 1 bit as terminator
 #[derive(Clone, Hash, PartialEq, Eq)]
@@ -58,11 +124,60 @@ impl CellPath {
         self.0 = (self.0 << 3) | CellPathInner::from(v.value());
     }
 
+    /// Like [push](Self::push) but returns an error instead of panicking
+    /// if the path is already full
+    #[inline]
+    pub fn try_push(&mut self, v: u3) -> Result<(), CellPathError> {
+        if self.len() >= Self::MAX_CAPACITY {
+            return Err(CellPathError::CapacityExceeded { len: self.len() + 1 });
+        }
+        self.push(v);
+        Ok(())
+    }
+
     #[inline]
     pub const fn with_push(self, v: u3) -> Self {
         Self((self.0 << 3) | v.value() as CellPathInner)
     }
 
+    #[inline]
+    pub fn try_with_push(mut self, v: u3) -> Result<Self, CellPathError> {
+        self.try_push(v)?;
+        Ok(self)
+    }
+
+    /// Short text form of the path (at most 11 characters), which is its
+    /// packed value written in url safe base64, see [Self::from_compact]
+    pub fn to_compact(&self) -> String {
+        let digits = (CellPathInner::BITS - self.0.leading_zeros()).div_ceil(6);
+        (0..digits).rev()
+            .map(|i| COMPACT_ALPHABET[((self.0 >> (i * 6)) & 0b111111) as usize] as char)
+            .collect()
+    }
+
+    /// Parses the form given by [Self::to_compact]
+    pub fn from_compact(s: &str) -> Result<Self, ParseCellPathError> {
+        let invalid = || ParseCellPathError::InvalidCompact(s.to_string());
+
+        let mut value: CellPathInner = 0;
+        for c in s.bytes() {
+            let digit = COMPACT_ALPHABET.iter().position(|&a| a == c)
+                .ok_or_else(invalid)?;
+            if value.leading_zeros() < 6 {
+                return Err(invalid());
+            }
+            value = (value << 6) | digit as CellPathInner;
+        }
+
+        // The marker bit must exist and be right above a whole component
+        let marker_ok = value != 0 &&
+            (CellPathInner::BITS - value.leading_zeros() - 1) % 3 == 0;
+        if !marker_ok {
+            return Err(invalid());
+        }
+        Ok(Self(value))
+    }
+
     #[inline]
     pub fn push_back(&mut self, v: u3) {
         let mbp = self.mark_bit_position();
@@ -146,7 +261,7 @@ impl CellPath {
     #[inline]
     pub fn get_aabb(&self, root: DAabb) -> DAabb {
         self.into_iter()
-            .fold(root, |aabb, x| aabb.octdivided(x))
+            .fold(root, |aabb, x| aabb.octant(x))
     }
 
     /// Get the position of the cell considering one unit per cell of the current
@@ -188,48 +303,90 @@ impl CellPath {
         result
     }
 
-    pub fn neighbor(&self, dx: i8, dy: i8, dz: i8) -> Option<Self> {
+    /// Moves the path by one cell (d in -1..=1) along the given axis (0 for
+    /// x, 1 for y and 2 for z), returns false if it went past the border of
+    /// the root cell in which case it wrapped around to the other side
+    fn offset_axis(&mut self, d: i8, axis: u32) -> bool {
+        if d == 0 {
+            return true;
+        }
+        let mut diff: CellPathInner = 0;
+        let bit: CellPathInner = 1 << axis;
+        loop {
+            if (diff / 3) >= self.len().into() {
+                return false;
+            }
+            // HAHAHAHAHA
+            if (d == 1 && (self.0 >> diff) & bit == 0) ||
+                (d == -1 && (self.0 >> diff) & bit != 0) {
+                if d == 1 {
+                    self.0 |= bit << diff;
+                }
+                else {
+                    self.0 &= !(bit << diff);
+                }
+                return true;
+            }
+            else {
+                if d == 1 {
+                    self.0 &= !(bit << diff);
+                }
+                else {
+                    self.0 |= bit << diff;
+                }
+                diff += 3;
+            }
+        }
+    }
+
+    fn assert_neighbor_offset(dx: i8, dy: i8, dz: i8) {
         assert!(
             (-1..=1).contains(&dx) &&
             (-1..=1).contains(&dy) &&
             (-1..=1).contains(&dz)
         );
+    }
+
+    /// Returns the path of the cell of the same depth offseted by the given
+    /// amounts (which must be in -1..=1), or None if it would be outside of
+    /// the root cell
+    pub fn neighbor(&self, dx: i8, dy: i8, dz: i8) -> Option<Self> {
+        Self::assert_neighbor_offset(dx, dy, dz);
 
         let mut new = self.clone();
+        for (d, axis) in [(dx, 0), (dy, 1), (dz, 2)] {
+            if !new.offset_axis(d, axis) {
+                return None;
+            }
+        }
+        Some(new)
+    }
 
-        for (d, i) in [(dx, 0), (dy, 1), (dz, 2)].into_iter() {
-            if d == 0
-            { continue; }
-            let mut diff: CellPathInner = 0;
-            let bit: CellPathInner = 1 << i;
-            loop {
-                if (diff / 3) >= self.len().into() {
-                    return None;
-                }
-                // HAHAHAHAHA
-                if (d == 1 && (new.0 >> diff) & bit == 0) ||
-                    (d == -1 && (new.0 >> diff) & bit != 0) {
-                    if d == 1 {
-                        new.0 |= bit << diff;
-                    }
-                    else {
-                        new.0 &= !(bit << diff);
-                    }
-                    break;
-                }
-                else {
-                    if d == 1 {
-                        new.0 &= !(bit << diff);
-                    }
-                    else {
-                        new.0 |= bit << diff;
-                    }
-                    diff += 3;
-                }
+    /// Like [Self::neighbor] but offsets that would go outside of the root
+    /// cell are ignored, so the path stays on the border along those axes
+    pub fn neighbor_clamped(&self, dx: i8, dy: i8, dz: i8) -> Self {
+        Self::assert_neighbor_offset(dx, dy, dz);
+
+        let mut new = self.clone();
+        for (d, axis) in [(dx, 0), (dy, 1), (dz, 2)] {
+            let before = new.clone();
+            if !new.offset_axis(d, axis) {
+                new = before;
             }
         }
+        new
+    }
 
-        Some(new)
+    /// Like [Self::neighbor] but going outside of the root cell wraps around
+    /// to the other side, as if the root cell was tiled in all directions
+    pub fn neighbor_wrapping(&self, dx: i8, dy: i8, dz: i8) -> Self {
+        Self::assert_neighbor_offset(dx, dy, dz);
+
+        let mut new = self.clone();
+        for (d, axis) in [(dx, 0), (dy, 1), (dz, 2)] {
+            new.offset_axis(d, axis);
+        }
+        new
     }
 
     /// Iterator over all neighbors of this path, excluding itself
@@ -243,6 +400,67 @@ impl CellPath {
                 .map(|xx| ((x, y, z), xx)))
     }
 
+    /// Inverse of [get_pos](Self::get_pos), None if the position is outside
+    /// of the root cell or the depth is above the capacity
+    pub fn from_pos(depth: u32, pos: UVec3) -> Option<Self> {
+        if depth > Self::MAX_CAPACITY || pos.max_element() >= 1 << depth {
+            return None;
+        }
+
+        let mut result = Self::new();
+        for level in (0..depth).rev() {
+            let bits = (pos >> level) & UVec3::ONE;
+            result.push(u3::new((bits.x | bits.y << 1 | bits.z << 2) as u8));
+        }
+        Some(result)
+    }
+
+    /// Iterates over the cells crossed by the segment going from the center
+    /// of this cell to the center of the target, both included, each cell
+    /// sharing a face with the previous one (like a 3D DDA over the grid of
+    /// the depth)
+    ///
+    /// When the segment crosses an edge or a corner, the cells around it
+    /// are visited by stepping along x then y then z.
+    ///
+    /// Panics if the paths don't have the same depth
+    pub fn walk_towards(&self, target: &Self) -> CellPathWalk {
+        assert_eq!(
+            self.depth(), target.depth(),
+            "Cannot walk between paths of different depths ({self:?} and {target:?})",
+        );
+
+        let current = self.get_pos().to_array().map(i64::from);
+        let target = target.get_pos().to_array().map(i64::from);
+        CellPathWalk {
+            depth: self.depth(),
+            current,
+            step: std::array::from_fn(|i| (target[i] - current[i]).signum()),
+            delta: std::array::from_fn(|i| target[i].abs_diff(current[i])),
+            crossed: [0; 3],
+            started: false,
+        }
+    }
+
+    /// Cell of the same depth at the given fraction of the way from the
+    /// center of this one to the center of the target
+    ///
+    /// Panics if the paths don't have the same depth
+    pub fn lerp(&self, target: &Self, t: f64) -> Self {
+        assert_eq!(
+            self.depth(), target.depth(),
+            "Cannot interpolate between paths of different depths ({self:?} and {target:?})",
+        );
+
+        let max = (1u32 << self.depth()) - 1;
+        let pos = self.get_pos().as_dvec3()
+            .lerp(target.get_pos().as_dvec3(), t)
+            .round()
+            .clamp(DVec3::ZERO, DVec3::splat(f64::from(max)))
+            .as_uvec3();
+        Self::from_pos(self.depth(), pos).expect("Clamped in the root cell")
+    }
+
     #[inline]
     pub const fn components() -> [u3; 8] {
         [
@@ -255,6 +473,15 @@ impl CellPath {
         Self::components().map(|p| self.clone().with_push(p))
     }
 
+    /// Like [children](Self::children) but returns an error if self is
+    /// already full
+    pub fn try_children(&self) -> Result<[Self; 8], CellPathError> {
+        if self.len() >= Self::MAX_CAPACITY {
+            return Err(CellPathError::CapacityExceeded { len: self.len() + 1 });
+        }
+        Ok(self.children())
+    }
+
     /// Returns an iterator over all paths possible with the given depth
     pub fn all_iter(depth: u32) -> impl DoubleEndedIterator<Item = Self> {
         let sections = depth * 3;
@@ -290,33 +517,68 @@ impl CellPath {
         Self(self.0 >> (3 * to_remove))
     }
 
+    /// Like [take](Self::take) but returns an error instead of panicking
+    pub fn try_take(&self, depth: u32) -> Result<Self, CellPathError> {
+        if depth > self.len() {
+            return Err(CellPathError::DepthOutOfRange { depth, len: self.len() });
+        }
+        Ok(self.take(depth))
+    }
+
     /// Return a new CellPath with the first [depth_to_remove] elements of self removed
     /// so with only the last (len - depth_to_remove) elements remaining
     /// Panics if [depth_to_remove] is higher than [len](Self::len)
     /// the inverse operation of [take_depth]
     pub fn reparent(self, depth_to_remove: u32) -> Self {
         assert!(depth_to_remove <= self.len());
-        // mask of used-bits for the original path
-        let full_mask = 1 << ((self.len() * 3) - 1);
-        let new_mask = full_mask >> depth_to_remove;
+        let new_depth = self.len() - depth_to_remove;
+        // mask of the bits of the elements that are kept
+        let new_mask = (1 << (new_depth * 3)) - 1;
 
-        let new_depth = self.len() * depth_to_remove;
-        let new_end_bit = 1 << new_depth;
+        let new_end_bit = 1 << (new_depth * 3);
         Self((self.0 & new_mask) | new_end_bit)
     }
 
+    /// Like [reparent](Self::reparent) but returns an error instead of panicking
+    pub fn try_reparent(self, depth_to_remove: u32) -> Result<Self, CellPathError> {
+        if depth_to_remove > self.len() {
+            return Err(CellPathError::DepthOutOfRange {
+                depth: depth_to_remove, len: self.len(),
+            });
+        }
+        Ok(self.reparent(depth_to_remove))
+    }
+
     #[inline]
     pub fn extend(&mut self, other: &Self) {
-        assert!(Self::MAX_CAPACITY > self.len() + other.len());
+        assert!(Self::MAX_CAPACITY >= self.len() + other.len());
         self.0 = (self.0 << (other.len() * 3)) | (other.index() as CellPathInner);
     }
 
+    /// Like [extend](Self::extend) but returns an error instead of panicking
+    /// if the resulting path would be too long
+    #[inline]
+    pub fn try_extend(&mut self, other: &Self) -> Result<(), CellPathError> {
+        let len = self.len() + other.len();
+        if len > Self::MAX_CAPACITY {
+            return Err(CellPathError::CapacityExceeded { len });
+        }
+        self.extend(other);
+        Ok(())
+    }
+
     #[inline]
     pub fn extended(mut self, other: &Self) -> Self {
         self.extend(other);
         self
     }
 
+    #[inline]
+    pub fn try_extended(mut self, other: &Self) -> Result<Self, CellPathError> {
+        self.try_extend(other)?;
+        Ok(self)
+    }
+
     #[inline]
     pub fn is_prefix_of(&self, other: &Self) -> bool {
         let self_bit = self.mark_bit_position();
@@ -333,24 +595,16 @@ impl CellPath {
     pub fn in_unit_cube<T>(depth: u32, mut coords: T::Vec3) -> Option<Self>
         where T: GlamFloat
     {
-        if coords.x() < T::new(0.) || coords.x() > T::new(1.)
-        || coords.y() < T::new(0.) || coords.y() > T::new(1.)
-        || coords.z() < T::new(0.) || coords.z() > T::new(1.) {
+        if coords.min_element() < T::new(0.) || coords.max_element() > T::new(1.) {
             return None;
         }
 
+        let half = T::Vec3::splat(T::new(0.5));
         let mut result = CellPath::new();
         for _ in 0..depth {
-            let dd = coords.array_mut().map(|x| {
-                if *x <= T::new(0.5) {
-                    *x *= T::new(2.);
-                    0
-                } else {
-                    *x -= T::new(0.5);
-                    *x *= T::new(2.);
-                    1
-                }
-            });
+            let mask = coords.array().map(|x| x > T::new(0.5));
+            coords = T::Vec3::select(mask, coords - half, coords) * T::new(2.);
+            let dd = mask.map(u8::from);
             result.push(u3::new(dd[0] | dd[1] << 1 | dd[2] << 2));
         }
 
@@ -379,6 +633,44 @@ impl std::fmt::Debug for CellPath {
     }
 }
 
+/// Writes the components from the root separated by slashes, like `1/5/7/0`,
+/// or a single slash for the root path
+impl std::fmt::Display for CellPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return f.write_str("/");
+        }
+        for (i, comp) in self.into_iter().enumerate() {
+            if i != 0 {
+                f.write_str("/")?;
+            }
+            write!(f, "{}", comp.value())?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the form written by [Display](std::fmt::Display), an empty string
+/// is also accepted for the root path
+impl std::str::FromStr for CellPath {
+    type Err = ParseCellPathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut path = Self::new();
+        if s.is_empty() || s == "/" {
+            return Ok(path);
+        }
+        for comp in s.split('/') {
+            let value = match comp.as_bytes() {
+                &[c @ b'0'..=b'7'] => c - b'0',
+                _ => return Err(ParseCellPathError::InvalidComponent(comp.to_string())),
+            };
+            path.try_push(u3::new(value))?;
+        }
+        Ok(path)
+    }
+}
+
 impl IntoIterator for &CellPath {
     type Item = u3;
     type IntoIter = CellPathIterator;
@@ -424,10 +716,66 @@ impl DoubleEndedIterator for CellPathIterator {
 impl ExactSizeIterator for CellPathIterator {  }
 impl FusedIterator for CellPathIterator {  }
 
+/// Iterator returned by [CellPath::walk_towards]
+#[derive(Debug, Clone)]
+pub struct CellPathWalk {
+    depth: u32,
+    current: [i64; 3],
+    step: [i64; 3],
+    /// Number of cell boundaries to cross along each axis
+    delta: [u64; 3],
+    crossed: [u64; 3],
+    started: bool,
+}
+
+impl CellPathWalk {
+    fn remaining(&self) -> usize {
+        let steps = (0..3).map(|i| self.delta[i] - self.crossed[i]).sum::<u64>();
+        steps as usize + usize::from(!self.started)
+    }
+
+    fn path(&self) -> CellPath {
+        let pos = UVec3::from_array(self.current.map(|c| c as u32));
+        CellPath::from_pos(self.depth, pos).expect("Walk stays in the root cell")
+    }
+}
+
+impl Iterator for CellPathWalk {
+    type Item = CellPath;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            return Some(self.path());
+        }
+
+        // The next boundary of axis i is crossed at the fraction
+        // (2 * crossed + 1) / (2 * delta) of the segment, compared without
+        // division to stay exact
+        let axis = (0..3)
+            .filter(|&i| self.crossed[i] < self.delta[i])
+            .min_by(|&a, &b| {
+                let ta = (2 * self.crossed[a] + 1) * self.delta[b];
+                let tb = (2 * self.crossed[b] + 1) * self.delta[a];
+                ta.cmp(&tb)
+            })?;
+        self.crossed[axis] += 1;
+        self.current[axis] += self.step[axis];
+        Some(self.path())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining();
+        (remaining, Some(remaining))
+    }
+}
+impl ExactSizeIterator for CellPathWalk {  }
+impl FusedIterator for CellPathWalk {  }
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bevy_math::{dvec3, DVec3};
+    use bevy_math::{dvec3, DVec3, IVec3};
     use itertools::Itertools;
 
     #[test]
@@ -472,6 +820,39 @@ mod tests {
         assert_eq!(path.neighbor(-1, -1, -1), Some(CellPath(0b1_000_000)));
     }
 
+    #[test]
+    fn test_neighbor_clamped_wrapping() {
+        for depth in 0..=3 {
+            let size = 1i32 << depth;
+            for path in CellPath::all_iter(depth) {
+                let pos = path.get_pos().as_ivec3();
+                for (dx, dy, dz) in (-1..=1).cartesian_product(-1..=1)
+                    .cartesian_product(-1..=1)
+                    .map(|((x, y), z)| (x, y, z))
+                {
+                    let offset = IVec3::new(dx.into(), dy.into(), dz.into());
+                    let clamped = path.neighbor_clamped(dx, dy, dz);
+                    let wrapping = path.neighbor_wrapping(dx, dy, dz);
+                    assert_eq!(clamped.depth(), depth);
+                    assert_eq!(wrapping.depth(), depth);
+
+                    // Same as neighbor inside of the root cell
+                    if let Some(neighbor) = path.neighbor(dx, dy, dz) {
+                        assert_eq!(clamped, neighbor);
+                        assert_eq!(wrapping, neighbor);
+                    }
+
+                    let expected = (pos + offset).clamp(IVec3::ZERO, IVec3::splat(size - 1));
+                    assert_eq!(clamped.get_pos().as_ivec3(), expected, "{path:?} {offset}");
+                    let expected = IVec3::from_array(
+                        (pos + offset).to_array().map(|x| x.rem_euclid(size))
+                    );
+                    assert_eq!(wrapping.get_pos().as_ivec3(), expected, "{path:?} {offset}");
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_extended() {
         let path_a = CellPath(0b1_000_000);
@@ -600,6 +981,58 @@ mod tests {
         assert_eq!(path, CellPath(0b1_111_000_000_000_000));
     }
 
+    #[test]
+    fn test_reparent() {
+        assert_eq!(
+            CellPath(0b1_000_010_010_111).reparent(1),
+            CellPath(0b1_010_010_111)
+        );
+        assert_eq!(
+            CellPath(0b1_010_110_101_010).reparent(3),
+            CellPath(0b1_010)
+        );
+        assert_eq!(
+            CellPath(0b1_010_110_101_010).reparent(4),
+            CellPath::new()
+        );
+        let path = CellPath(0b1_010_110_101_010);
+        assert_eq!(path.take(1).extended(&path.clone().reparent(1)), path);
+    }
+
+    #[test]
+    fn test_checked_ops() {
+        let full = CellPath::all_iter(CellPath::MAX_CAPACITY).next_back().unwrap();
+        assert_eq!(full.len(), CellPath::MAX_CAPACITY);
+
+        let mut path = full.clone();
+        assert_eq!(
+            path.try_push(u3::new(0)),
+            Err(CellPathError::CapacityExceeded { len: CellPath::MAX_CAPACITY + 1 })
+        );
+        assert_eq!(path, full);
+
+        let short = CellPath(0b1_011_101);
+        assert_eq!(
+            full.take(20).try_extended(&short),
+            Err(CellPathError::CapacityExceeded { len: 22 })
+        );
+        assert_eq!(
+            full.take(19).try_extended(&short).map(|p| p.len()),
+            Ok(CellPath::MAX_CAPACITY)
+        );
+
+        assert_eq!(
+            short.try_take(3),
+            Err(CellPathError::DepthOutOfRange { depth: 3, len: 2 })
+        );
+        assert_eq!(short.try_take(1), Ok(CellPath(0b1_011)));
+        assert_eq!(
+            short.clone().try_reparent(3),
+            Err(CellPathError::DepthOutOfRange { depth: 3, len: 2 })
+        );
+        assert_eq!(short.try_reparent(1), Ok(CellPath(0b1_101)));
+    }
+
     #[test]
     fn test_pop() {
         let mut path = CellPath(0b1);
@@ -805,4 +1238,71 @@ mod tests {
         assert!(CellPath(0b1_100_011_111).is_prefix_of(&CellPath(0b1_100_011_111_000)));
         assert!(CellPath(0b1_100_011_111).is_prefix_of(&CellPath(0b1_100_011_111_000_000)));
     }
+
+    #[test]
+    fn test_text_forms() {
+        let path = CellPath(0b1_001_101_111_000);
+        assert_eq!(path.to_string(), "1/5/7/0");
+        assert_eq!("1/5/7/0".parse::<CellPath>(), Ok(path.clone()));
+        assert_eq!(CellPath::new().to_string(), "/");
+        assert_eq!("/".parse::<CellPath>(), Ok(CellPath::new()));
+        assert_eq!("".parse::<CellPath>(), Ok(CellPath::new()));
+        assert!("1/8".parse::<CellPath>().is_err());
+        assert!("1//2".parse::<CellPath>().is_err());
+        assert!("12".parse::<CellPath>().is_err());
+        assert_eq!(
+            vec!["1"; 22].join("/").parse::<CellPath>(),
+            Err(ParseCellPathError::Path(CellPathError::CapacityExceeded { len: 22 })),
+        );
+
+        assert_eq!(CellPath::new().to_compact(), "B");
+        for path in [path, CellPath::new(), CellPath(u64::MAX)] {
+            assert_eq!(CellPath::from_compact(&path.to_compact()), Ok(path));
+        }
+        assert!(CellPath(u64::MAX).to_compact().len() <= 11);
+        assert!(CellPath::from_compact("").is_err());
+        assert!(CellPath::from_compact("C").is_err());
+        assert!(CellPath::from_compact("B*").is_err());
+        assert!(CellPath::from_compact("BBBBBBBBBBBB").is_err());
+    }
+
+    #[test]
+    fn test_walk_towards() {
+        for path in [CellPath::new(), CellPath(0b1_101_011), CellPath(0b1_111_000_110)] {
+            assert_eq!(CellPath::from_pos(path.depth(), path.get_pos()), Some(path.clone()));
+            assert_eq!(path.walk_towards(&path).collect_vec(), vec![path.clone()]);
+        }
+        assert_eq!(CellPath::from_pos(2, UVec3::new(0, 4, 0)), None);
+
+        let from = CellPath::from_pos(3, UVec3::new(0, 0, 0)).unwrap();
+        let to = CellPath::from_pos(3, UVec3::new(3, 1, 0)).unwrap();
+        let walk = from.walk_towards(&to);
+        assert_eq!(walk.len(), 5);
+        assert_eq!(
+            walk.map(|p| p.get_pos()).collect_vec(),
+            vec![
+                UVec3::new(0, 0, 0),
+                UVec3::new(1, 0, 0),
+                // The segment goes through the corner, x first
+                UVec3::new(2, 0, 0),
+                UVec3::new(2, 1, 0),
+                UVec3::new(3, 1, 0),
+            ],
+        );
+
+        let from = CellPath::from_pos(4, UVec3::new(15, 2, 7)).unwrap();
+        let to = CellPath::from_pos(4, UVec3::new(1, 9, 0)).unwrap();
+        let walk = from.walk_towards(&to).collect_vec();
+        assert_eq!(walk.first(), Some(&from));
+        assert_eq!(walk.last(), Some(&to));
+        assert_eq!(walk.len(), 14 + 7 + 7 + 1);
+        for (a, b) in walk.iter().tuple_windows() {
+            let diff = a.get_pos().as_ivec3() - b.get_pos().as_ivec3();
+            assert_eq!(diff.abs().element_sum(), 1);
+        }
+
+        assert_eq!(from.lerp(&to, 0.), from);
+        assert_eq!(from.lerp(&to, 1.), to);
+        assert_eq!(from.lerp(&to, 0.5).get_pos(), UVec3::new(8, 6, 4));
+    }
 }
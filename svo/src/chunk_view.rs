@@ -0,0 +1,84 @@
+use bevy_math::IVec3;
+
+use super::*;
+
+/// Read-only view of the data of a chunk and of the cells just across its
+/// borders, so that sampling near the borders of the chunk (like
+/// [mesh_generation] does) gets the neighbors' data instead of nothing.
+///
+/// All paths given to the view are absolute (from the root of the whole svo).
+pub struct ChunkView<'a, D: Data, Ptr: SvoPtr<D> = ArcPtr<D>> {
+    chunk: CellPath,
+    source: ChunkViewSource<'a, D, Ptr>,
+}
+
+enum ChunkViewSource<'a, D: Data, Ptr: SvoPtr<D>> {
+    Root(&'a Cell<D, Ptr>),
+    /// Indexed with [neighbor_index]
+    Subtrees([Option<&'a Cell<D, Ptr>>; 27]),
+}
+
+/// Index of the neighbor at the given offset (in -1..=1) in the subtrees array
+fn neighbor_index(offset: IVec3) -> Option<usize> {
+    if offset.abs().max_element() > 1 {
+        return None;
+    }
+    let o = offset + IVec3::ONE;
+    Some((o.x + o.y * 3 + o.z * 9) as usize)
+}
+
+impl<'a, D: Data, Ptr: SvoPtr<D>> ChunkView<'a, D, Ptr> {
+    /// View of the given chunk in the root svo, all cells are available
+    pub fn from_root(root: &'a Cell<D, Ptr>, chunk: CellPath) -> Self {
+        Self {
+            chunk,
+            source: ChunkViewSource::Root(root),
+        }
+    }
+
+    /// Assembles a view from the chunk's subtree and the subtrees of any
+    /// number of its neighbors, given with their offset as returned by
+    /// [CellPath::neighbors]
+    pub fn from_subtrees(
+        chunk: CellPath,
+        chunk_cell: &'a Cell<D, Ptr>,
+        neighbors: impl IntoIterator<Item = ((i8, i8, i8), &'a Cell<D, Ptr>)>,
+    ) -> Self {
+        let mut subtrees = [None; 27];
+        subtrees[neighbor_index(IVec3::ZERO).expect("in range")] = Some(chunk_cell);
+        for ((dx, dy, dz), cell) in neighbors {
+            let offset = IVec3::new(dx.into(), dy.into(), dz.into());
+            let Some(index) = neighbor_index(offset)
+            else { continue; };
+            subtrees[index] = Some(cell);
+        }
+
+        Self {
+            chunk,
+            source: ChunkViewSource::Subtrees(subtrees),
+        }
+    }
+
+    pub fn chunk(&self) -> &CellPath {
+        &self.chunk
+    }
+
+    /// Like [Cell::get_path], returns None if the path is not part of the
+    /// view (outside the root, or in a missing neighbor)
+    pub fn get_path(&self, path: CellPath) -> Option<EitherDataRef<'a, D>> {
+        match &self.source {
+            ChunkViewSource::Root(root) => Some(root.get_path(path)),
+            ChunkViewSource::Subtrees(subtrees) => {
+                let depth = self.chunk.depth();
+                if path.depth() < depth {
+                    return None;
+                }
+                let offset = path.take(depth).get_pos().as_ivec3()
+                    - self.chunk.get_pos().as_ivec3();
+                let subtree = subtrees[neighbor_index(offset)?]?;
+
+                Some(subtree.get_path(path.reparent(depth)))
+            },
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! Dumps terrain svos to files readable by other tools, for debugging and
+//! interchange with DCC software
+//!
+//! - [write_vox]: MagicaVoxel .vox, quantized to a dense grid
+//! - [write_esvo] / [read_esvo]: a simple homemade binary keeping the exact
+//!   svo structure
+//! - [write_gltf_points]: leaf centers as a binary glTF (.glb) point cloud
+
+use std::io::{self, Read, Write};
+
+use arbitrary_int::u3;
+use bevy_math::{DVec3, UVec3};
+use half::f16;
+use utils::DAabb;
+
+use super::*;
+
+/// Error returned by the functions of [this module](self)
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    /// The requested .vox resolution is higher than what MagicaVoxel supports
+    ResolutionTooHigh {
+        depth: u32,
+        max_depth: u32,
+    },
+    /// The read file is not a valid .esvo
+    InvalidFormat(&'static str),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "io error: {error}"),
+            Self::ResolutionTooHigh { depth, max_depth } => write!(
+                f, "depth {depth} is higher than the maximum of {max_depth}",
+            ),
+            Self::InvalidFormat(reason) => write!(f, "invalid format: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+fn kind_from_u8(value: u8) -> Option<TerrainCellKind> {
+    use TerrainCellKind as K;
+    [K::Invalid, K::Air, K::StoneDarker, K::Stone, K::Pink, K::Blue]
+        .into_iter()
+        .find(|kind| *kind as u8 == value)
+}
+
+/// Position and size (in cells of the given depth) of the region covered by
+/// the cell at the given path
+fn quantize(path: &CellPath, depth: u32) -> (UVec3, u32) {
+    let pos = path.get_pos();
+    if path.depth() <= depth {
+        let shift = depth - path.depth();
+        (pos << shift, 1 << shift)
+    }
+    else {
+        (pos >> (path.depth() - depth), 1)
+    }
+}
+
+/// MagicaVoxel models can't be bigger than 256 voxels
+pub const VOX_MAX_DEPTH: u32 = 8;
+
+/// Writes the svo as a MagicaVoxel .vox model of `2^depth` voxels per side
+///
+/// Leaves bigger than a voxel fill all voxels they cover, when several leaves
+/// are in the same voxel the last one wins. Empty kinds are skipped and the
+/// palette index of a voxel is its [TerrainCellKind] discriminant.
+///
+/// MagicaVoxel is z-up so the y and z axes are swapped.
+pub fn write_vox<Ptr: SvoPtr<TerrainCellData>>(
+    root: &Cell<TerrainCellData, Ptr>,
+    depth: u32,
+    mut writer: impl Write,
+) -> Result<(), ExportError> {
+    if depth > VOX_MAX_DEPTH {
+        return Err(ExportError::ResolutionTooHigh {
+            depth, max_depth: VOX_MAX_DEPTH,
+        });
+    }
+    let side = 1u32 << depth;
+
+    let mut grid = vec![0u8; (side * side * side) as usize];
+    for item in root {
+        if item.data.kind.empty() {
+            continue;
+        }
+        let (pos, size) = quantize(&item.path, depth);
+        for z in pos.z..pos.z + size {
+            for y in pos.y..pos.y + size {
+                for x in pos.x..pos.x + size {
+                    grid[(x + side * (y + side * z)) as usize] = item.data.kind as u8;
+                }
+            }
+        }
+    }
+
+    let mut xyzi = vec![];
+    let mut count = 0u32;
+    for (i, &index) in grid.iter().enumerate() {
+        if index == 0 {
+            continue;
+        }
+        let i = i as u32;
+        let (x, y, z) = (i % side, i / side % side, i / (side * side));
+        // side is at most 256 so coordinates fit in a byte
+        xyzi.extend([x as u8, z as u8, y as u8, index]);
+        count += 1;
+    }
+
+    let mut size_chunk = vec![];
+    for value in [side, side, side] {
+        size_chunk.extend(value.to_le_bytes());
+    }
+    let mut xyzi_chunk = count.to_le_bytes().to_vec();
+    xyzi_chunk.extend(xyzi);
+    // Entry i is the color of palette index i+1
+    let mut rgba_chunk = vec![];
+    for index in 1..=256u32 {
+        let color = u8::try_from(index).ok()
+            .and_then(kind_from_u8)
+            .map(|kind| kind.color().as_rgba_u8())
+            .unwrap_or([0; 4]);
+        rgba_chunk.extend(color);
+    }
+
+    let chunks = [
+        (b"SIZE", size_chunk),
+        (b"XYZI", xyzi_chunk),
+        (b"RGBA", rgba_chunk),
+    ];
+    let children_size: usize = chunks.iter()
+        .map(|(_, content)| 12 + content.len())
+        .sum();
+
+    writer.write_all(b"VOX ")?;
+    writer.write_all(&150u32.to_le_bytes())?;
+    writer.write_all(b"MAIN")?;
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&(children_size as u32).to_le_bytes())?;
+    for (id, content) in chunks {
+        writer.write_all(id)?;
+        writer.write_all(&(content.len() as u32).to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&content)?;
+    }
+
+    Ok(())
+}
+
+const ESVO_MAGIC: &[u8; 4] = b"ESVO";
+const ESVO_VERSION: u32 = 1;
+const ESVO_INTERNAL_TAG: u8 = 0;
+const ESVO_LEAF_TAG: u8 = 1;
+
+fn write_esvo_leaf(
+    data: &TerrainCellData, writer: &mut impl Write,
+) -> io::Result<()> {
+    writer.write_all(&[ESVO_LEAF_TAG, data.kind as u8])?;
+    writer.write_all(&data.distance.to_bits().to_le_bytes())?;
+    writer.write_all(&[u8::from(data.empty)])
+}
+
+fn write_esvo_packed(
+    packed: &PackedCell<TerrainCellData>,
+    path: CellPath,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    if path.depth() == packed.depth() {
+        return write_esvo_leaf(packed.leaf_level().get(&path), writer);
+    }
+    writer.write_all(&[ESVO_INTERNAL_TAG])?;
+    for comp in 0..8 {
+        write_esvo_packed(packed, path.clone().with_push(u3::new(comp)), writer)?;
+    }
+    Ok(())
+}
+
+fn write_esvo_cell<Ptr: SvoPtr<TerrainCellData>, W: Write>(
+    cell: &Cell<TerrainCellData, Ptr>,
+    writer: &mut W,
+) -> io::Result<()> {
+    match cell {
+        Cell::Internal(internal) => {
+            writer.write_all(&[ESVO_INTERNAL_TAG])?;
+            for child in internal.iter_children() {
+                write_esvo_cell::<Ptr, W>(child, writer)?;
+            }
+            Ok(())
+        },
+        Cell::Leaf(leaf) => write_esvo_leaf(&leaf.data, writer),
+        Cell::Packed(packed) => write_esvo_packed(packed, CellPath::new(), writer),
+    }
+}
+
+/// Writes the svo in the .esvo format
+///
+/// All values are little endian:
+/// - `ESVO` magic and the u32 version (1)
+/// - the root aabb as 6 f64 (min then size)
+/// - cells in depth first order, each starting with a tag byte: 0 for
+///   internal cells, followed by their 8 children, or 1 for leaves, followed
+///   by the kind (u8), the distance (f16) and empty (u8)
+///
+/// Packed cells are written as internal cells and internal data is not
+/// stored, it is recomputed by [read_esvo].
+pub fn write_esvo<Ptr: SvoPtr<TerrainCellData>>(
+    root: &Cell<TerrainCellData, Ptr>,
+    root_aabb: DAabb,
+    mut writer: impl Write,
+) -> Result<(), ExportError> {
+    writer.write_all(ESVO_MAGIC)?;
+    writer.write_all(&ESVO_VERSION.to_le_bytes())?;
+    for value in root_aabb.position.to_array().into_iter()
+        .chain(root_aabb.size.to_array())
+    {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    write_esvo_cell(root, &mut writer)?;
+    Ok(())
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_esvo_cell(
+    reader: &mut impl Read, depth: u32,
+) -> Result<TerrainCell, ExportError> {
+    let [tag] = read_bytes(reader)?;
+    match tag {
+        ESVO_INTERNAL_TAG => {
+            if depth >= CellPath::MAX_CAPACITY {
+                return Err(ExportError::InvalidFormat("svo is too deep"));
+            }
+            let children = [(); 8]
+                .try_map(|()| read_esvo_cell(reader, depth + 1))?;
+            Ok(InternalCell::from_children(children).into())
+        },
+        ESVO_LEAF_TAG => {
+            let [kind] = read_bytes(reader)?;
+            let distance = f16::from_bits(u16::from_le_bytes(read_bytes(reader)?));
+            let [empty] = read_bytes(reader)?;
+            Ok(LeafCell::new(TerrainCellData {
+                kind: kind_from_u8(kind)
+                    .ok_or(ExportError::InvalidFormat("unknown cell kind"))?,
+                distance,
+                empty: empty != 0,
+                error: f16::ZERO,
+            }).into())
+        },
+        _ => Err(ExportError::InvalidFormat("unknown cell tag")),
+    }
+}
+
+/// Reads a file written by [write_esvo], returning the root aabb and the svo
+pub fn read_esvo(
+    mut reader: impl Read,
+) -> Result<(DAabb, TerrainCell), ExportError> {
+    if &read_bytes::<4>(&mut reader)? != ESVO_MAGIC {
+        return Err(ExportError::InvalidFormat("wrong magic"));
+    }
+    if u32::from_le_bytes(read_bytes(&mut reader)?) != ESVO_VERSION {
+        return Err(ExportError::InvalidFormat("unsupported version"));
+    }
+    let mut values = [0f64; 6];
+    for value in &mut values {
+        *value = f64::from_le_bytes(read_bytes(&mut reader)?);
+    }
+    let root_aabb = DAabb {
+        position: DVec3::from_slice(&values[..3]),
+        size: DVec3::from_slice(&values[3..]),
+    };
+
+    Ok((root_aabb, read_esvo_cell(&mut reader, 0)?))
+}
+
+/// Writes the centers of all non-empty leaves as a binary glTF (.glb) point
+/// cloud, colored with [TerrainCellKind::color]
+///
+/// Positions are converted to f32 in the space of `root_aabb`.
+pub fn write_gltf_points<Ptr: SvoPtr<TerrainCellData>>(
+    root: &Cell<TerrainCellData, Ptr>,
+    root_aabb: DAabb,
+    mut writer: impl Write,
+) -> Result<(), ExportError> {
+    let mut positions = vec![];
+    let mut colors = vec![];
+    let mut min = [f32::INFINITY; 3];
+    let mut max = [f32::NEG_INFINITY; 3];
+    for item in root {
+        if item.data.kind.empty() {
+            continue;
+        }
+        let aabb = item.path.get_aabb(root_aabb);
+        let center = (aabb.position + aabb.size / 2.).as_vec3().to_array();
+        for axis in 0..3 {
+            min[axis] = min[axis].min(center[axis]);
+            max[axis] = max[axis].max(center[axis]);
+        }
+        positions.extend(center);
+        colors.extend(item.data.kind.color().as_linear_rgba_f32());
+    }
+    let count = positions.len() / 3;
+
+    let mut bin = Vec::with_capacity((positions.len() + colors.len()) * 4);
+    for value in positions.iter().chain(&colors) {
+        bin.extend(value.to_le_bytes());
+    }
+    let positions_length = positions.len() * 4;
+    let colors_length = colors.len() * 4;
+
+    // glTF requires min and max for positions and forbids empty accessors
+    let (min, max) = if count == 0 { ([0.; 3], [0.; 3]) } else { (min, max) };
+    let accessors = if count == 0 { String::new() } else { format!(
+        r#","accessors":[{{"bufferView":0,"componentType":5126,"count":{count},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}},{{"bufferView":1,"componentType":5126,"count":{count},"type":"VEC4"}}],"bufferViews":[{{"buffer":0,"byteOffset":0,"byteLength":{positions_length}}},{{"buffer":0,"byteOffset":{positions_length},"byteLength":{colors_length}}}],"buffers":[{{"byteLength":{}}}],"meshes":[{{"primitives":[{{"attributes":{{"POSITION":0,"COLOR_0":1}},"mode":0}}]}}]"#,
+        min[0], min[1], min[2], max[0], max[1], max[2], bin.len(),
+    ) };
+    let node = if count == 0 { "{}" } else { r#"{"mesh":0}"# };
+    let mut json = format!(
+        r#"{{"asset":{{"version":"2.0","generator":"svo"}},"scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{node}]{accessors}}}"#,
+    ).into_bytes();
+
+    // Chunks are 4 bytes aligned, json with spaces and binary with zeros
+    json.resize(json.len().next_multiple_of(4), b' ');
+    bin.resize(bin.len().next_multiple_of(4), 0);
+
+    let chunks = [
+        (b"JSON", json),
+        (b"BIN\0", bin),
+    ];
+    let chunks = if count == 0 { &chunks[..1] } else { &chunks[..] };
+    let total_length = 12 + chunks.iter()
+        .map(|(_, content)| 8 + content.len())
+        .sum::<usize>();
+
+    writer.write_all(b"glTF")?;
+    writer.write_all(&2u32.to_le_bytes())?;
+    writer.write_all(&(total_length as u32).to_le_bytes())?;
+    for (kind, content) in chunks {
+        writer.write_all(&(content.len() as u32).to_le_bytes())?;
+        writer.write_all(*kind)?;
+        writer.write_all(content)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::terrain;
+
+    #[test]
+    pub fn test_export() {
+        let root_aabb = DAabb::new_center_size(DVec3::ZERO, DVec3::splat(4.));
+        let root = terrain(2, |pos| pos.y as f32 - 2., |pos| {
+            if pos.y < 2 { TerrainCellKind::Stone } else { TerrainCellKind::Air }
+        });
+
+        let mut esvo = vec![];
+        write_esvo(&root, root_aabb, &mut esvo).expect("write");
+        let (read_aabb, read_root) = read_esvo(esvo.as_slice()).expect("read");
+        assert_eq!(read_aabb, root_aabb);
+        assert_eq!(
+            read_root.iter().map(|item| (item.path, *item.data))
+                .collect::<std::collections::HashMap<_, _>>(),
+            root.iter().map(|item| (item.path, *item.data))
+                .collect::<std::collections::HashMap<_, _>>(),
+        );
+        assert!(read_esvo(&esvo[..esvo.len() - 1]).is_err());
+
+        let mut vox = vec![];
+        write_vox(&root, 3, &mut vox).expect("write");
+        assert_eq!(&vox[..4], b"VOX ");
+        // SIZE chunk then the voxel count of XYZI, half of the 8^3 voxels
+        // are stone
+        assert_eq!(&vox[20..24], b"SIZE");
+        assert_eq!(&vox[44..48], b"XYZI");
+        assert_eq!(u32::from_le_bytes(vox[56..60].try_into().unwrap()), 256);
+        assert!(write_vox(&root, 9, &mut vec![]).is_err());
+
+        let mut glb = vec![];
+        write_gltf_points(&root, root_aabb, &mut glb).expect("write");
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize, glb.len());
+    }
+}
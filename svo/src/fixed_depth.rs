@@ -0,0 +1,286 @@
+//! Full svo with a depth known at compile time, see [FixedDepthCell]
+
+use super::*;
+
+/// Full svo whose leaves are all at depth `DEPTH`, e.g. for small lookup
+/// grids where following pointers would cost more than reading the data
+///
+/// Like a [PackedCell] but the internal cells of all levels are in a single
+/// buffer, and indices are computed from the depth known at compile time,
+/// so there is no indirection at all. The buffers are still boxed as the
+/// 8^DEPTH leaves quickly get too big for the stack.
+#[derive(Debug)]
+pub struct FixedDepthCell<D: Data, const DEPTH: u32> {
+    /// Internal cells of all levels, root first, each level in the order of
+    /// [PackedIndexIterator]
+    internal: Box<[D::Internal]>,
+    leaves: Box<[D]>,
+}
+
+impl<D: Data, const DEPTH: u32> FixedDepthCell<D, DEPTH> {
+    pub const LEAF_COUNT: usize = 8usize.pow(DEPTH);
+    pub const INTERNAL_COUNT: usize = Self::level_offset(DEPTH);
+
+    /// Index of the first cell of the given level in the internal buffer
+    const fn level_offset(depth: u32) -> usize {
+        (8usize.pow(depth) - 1) / 7
+    }
+
+    pub fn new_filled(internal_data: D::Internal, data: D) -> Self
+        where D: Clone,
+              D::Internal: Clone,
+    {
+        Self {
+            internal: vec![internal_data; Self::INTERNAL_COUNT].into_boxed_slice(),
+            leaves: vec![data; Self::LEAF_COUNT].into_boxed_slice(),
+        }
+    }
+
+    /// Builds the leaves with the given function, given their paths, and
+    /// aggregates the internal cells
+    pub fn build_with(mut leaf_fn: impl FnMut(CellPath) -> D) -> Self
+        where D: AggregateData
+    {
+        let leaves = PackedIndexIterator::new(DEPTH)
+            .map(|(_, path)| leaf_fn(path))
+            .collect::<Box<[D]>>();
+
+        // Built from the leaves up
+        let mut levels = Vec::<Box<[D::Internal]>>::with_capacity(DEPTH as usize);
+        for depth in (0..DEPTH).rev() {
+            let below = levels.last().map_or(&[][..], |level| &level[..]);
+            let level = (0..8usize.pow(depth))
+                .map(|index| Self::aggregate_at(&leaves, below, depth, index))
+                .collect();
+            levels.push(level);
+        }
+
+        Self {
+            internal: levels.into_iter().rev().flat_map(<[_]>::into_vec).collect(),
+            leaves,
+        }
+    }
+
+    /// Aggregate of the children of the cell at the given index of the given
+    /// level, `below` being the level under it if it is internal
+    fn aggregate_at(leaves: &[D], below: &[D::Internal], depth: u32, index: usize) -> D::Internal
+        where D: AggregateData
+    {
+        D::aggregate(std::array::from_fn(|comp| {
+            let child = index * 8 + comp;
+            if depth + 1 == DEPTH {
+                Either::Right(&leaves[child])
+            } else {
+                Either::Left(&below[child])
+            }
+        }))
+    }
+
+    pub fn depth(&self) -> u32 {
+        DEPTH
+    }
+
+    pub fn get(&self, path: &CellPath) -> EitherDataRef<'_, D> {
+        use std::cmp::Ordering as Ord;
+        match path.len().cmp(&DEPTH) {
+            Ord::Less => Either::Left(
+                &self.internal[Self::level_offset(path.len()) + path.index()]
+            ),
+            Ord::Equal => Either::Right(&self.leaves[path.index()]),
+            Ord::Greater => panic!("Depth is out of range"),
+        }
+    }
+
+    pub fn get_mut(&mut self, path: &CellPath) -> EitherDataMut<'_, D> {
+        use std::cmp::Ordering as Ord;
+        match path.len().cmp(&DEPTH) {
+            Ord::Less => Either::Left(
+                &mut self.internal[Self::level_offset(path.len()) + path.index()]
+            ),
+            Ord::Equal => Either::Right(&mut self.leaves[path.index()]),
+            Ord::Greater => panic!("Depth is out of range"),
+        }
+    }
+
+    /// Fallible version of [Self::get]
+    pub fn try_get(&self, path: &CellPath) -> Result<EitherDataRef<'_, D>, SvoIndexError> {
+        if path.len() > DEPTH {
+            return Err(SvoIndexError::TooDeep { depth: path.len(), max_depth: DEPTH });
+        }
+        Ok(self.get(path))
+    }
+
+    /// Fallible version of [Self::get_mut]
+    pub fn try_get_mut(&mut self, path: &CellPath) -> Result<EitherDataMut<'_, D>, SvoIndexError> {
+        if path.len() > DEPTH {
+            return Err(SvoIndexError::TooDeep { depth: path.len(), max_depth: DEPTH });
+        }
+        Ok(self.get_mut(path))
+    }
+
+    /// Internal cells of the given level in the order of
+    /// [PackedIndexIterator]
+    pub fn internal_level(&self, depth: u32) -> &[D::Internal] {
+        assert!(
+            depth < DEPTH,
+            "Depth is out of internal cells range (to get leaf node use leaves)",
+        );
+        &self.internal[Self::level_offset(depth)..Self::level_offset(depth + 1)]
+    }
+
+    /// Leaves in the order of [PackedIndexIterator]
+    pub fn leaves(&self) -> &[D] {
+        &self.leaves
+    }
+
+    /// Leaves in the order of [PackedIndexIterator], don't forget to
+    /// [update](Self::update_all) the internal cells after changing them
+    pub fn leaves_mut(&mut self) -> &mut [D] {
+        &mut self.leaves
+    }
+
+    pub fn iter_leaves(&self) -> impl Iterator<Item = (CellPath, &D)> {
+        PackedIndexIterator::new(DEPTH)
+            .map(|(index, path)| (path, &self.leaves[index]))
+    }
+
+    /// Re-aggregates all internal cells
+    pub fn update_all(&mut self)
+        where D: AggregateData
+    {
+        for depth in (0..DEPTH).rev() {
+            let (above, below) = self.internal.split_at_mut(Self::level_offset(depth + 1));
+            for (index, data) in above[Self::level_offset(depth)..].iter_mut().enumerate() {
+                *data = Self::aggregate_at(&self.leaves, below, depth, index);
+            }
+        }
+    }
+
+    /// Like [Self::update_all] but only for the cells on the given path
+    pub fn update_on_path(&mut self, path: &CellPath)
+        where D: AggregateData
+    {
+        if path.len() < DEPTH {
+            self.update_cell(path);
+        }
+        path.parents().for_each(|parent| self.update_cell(&parent));
+    }
+
+    fn update_cell(&mut self, path: &CellPath)
+        where D: AggregateData
+    {
+        let depth = path.len();
+        let (above, below) = self.internal.split_at_mut(Self::level_offset(depth + 1));
+        above[Self::level_offset(depth) + path.index()] =
+            Self::aggregate_at(&self.leaves, below, depth, path.index());
+    }
+
+    /// Gives the packed cell back if its depth is not `DEPTH`
+    pub fn from_packed(packed: PackedCell<D>) -> Result<Self, PackedCell<D>> {
+        if packed.depth() != DEPTH {
+            return Err(packed);
+        }
+        let (levels, leaves) = packed.into_levels();
+        Ok(Self {
+            internal: levels.into_iter().flat_map(<[_]>::into_vec).collect(),
+            leaves,
+        })
+    }
+
+    pub fn into_packed(self) -> PackedCell<D> {
+        let mut internal = Vec::from(self.internal);
+        let mut levels = Vec::with_capacity(DEPTH as usize);
+        for depth in (0..DEPTH).rev() {
+            levels.push(internal.split_off(Self::level_offset(depth)).into_boxed_slice());
+        }
+        levels.reverse();
+        PackedCell::from_levels(levels, self.leaves)
+            .expect("levels have the sizes of a packed cell")
+    }
+}
+
+// Custom impls because the derive macros don't bound D::Internal
+impl<D, const DEPTH: u32> Clone for FixedDepthCell<D, DEPTH>
+    where D: Data + Clone,
+          D::Internal: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            internal: self.internal.clone(),
+            leaves: self.leaves.clone(),
+        }
+    }
+}
+
+impl<D, const DEPTH: u32> PartialEq for FixedDepthCell<D, DEPTH>
+    where D: Data + PartialEq,
+          D::Internal: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.internal == other.internal && self.leaves == other.leaves
+    }
+}
+
+impl<D, const DEPTH: u32> Default for FixedDepthCell<D, DEPTH>
+    where D: Data + Clone + Default,
+          D::Internal: Clone + Default,
+{
+    fn default() -> Self {
+        Self::new_filled(Default::default(), Default::default())
+    }
+}
+
+impl<D: Data, const DEPTH: u32> TryFrom<PackedCell<D>> for FixedDepthCell<D, DEPTH> {
+    type Error = PackedCell<D>;
+
+    fn try_from(value: PackedCell<D>) -> Result<Self, Self::Error> {
+        Self::from_packed(value)
+    }
+}
+
+impl<D: Data, const DEPTH: u32> From<FixedDepthCell<D, DEPTH>> for PackedCell<D> {
+    fn from(value: FixedDepthCell<D, DEPTH>) -> Self {
+        value.into_packed()
+    }
+}
+
+impl<D: Data, Ptr: SvoPtr<D>, const DEPTH: u32> From<FixedDepthCell<D, DEPTH>> for Cell<D, Ptr> {
+    fn from(value: FixedDepthCell<D, DEPTH>) -> Self {
+        Cell::Packed(value.into_packed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::SumData;
+
+    #[test]
+    pub fn test_fixed_depth_cell() {
+        let leaf_value = |path: CellPath| path.index() as i32;
+        let mut fixed = FixedDepthCell::<SumData, 2>::build_with(|path| SumData(leaf_value(path)));
+        assert_eq!(FixedDepthCell::<SumData, 2>::INTERNAL_COUNT, 9);
+        assert_eq!(fixed.get(&CellPath::new()).into_inner(), &((0..64).sum::<i32>()));
+        let path = CellPath::new().with_push(u3::new(3)).with_push(u3::new(5));
+        assert_eq!(fixed.get(&path).into_inner(), &(3 * 8 + 5));
+        assert_eq!(fixed.internal_level(1)[3], (24..32).sum::<i32>());
+        assert!(fixed.try_get(&path.clone().with_push(u3::new(0))).is_err());
+
+        // Same as packed cells
+        let mut packed = PackedCell::<SumData>::new_default(2);
+        for (index, path) in PackedIndexIterator::new(2) {
+            *packed.leaf_level_mut().get_mut(&path) = SumData(index as i32);
+        }
+        packed.update_all();
+        assert_eq!(fixed.clone().into_packed(), packed);
+        assert_eq!(FixedDepthCell::<SumData, 2>::try_from(packed.clone()).as_ref(), Ok(&fixed));
+        assert!(FixedDepthCell::<SumData, 3>::try_from(packed).is_err());
+
+        *fixed.get_mut(&path).unwrap_right() = SumData(0);
+        fixed.update_on_path(&path);
+        assert_eq!(fixed.get(&CellPath::new()).into_inner(), &((0..64).sum::<i32>() - 29));
+        let mut updated = fixed.clone();
+        updated.update_all();
+        assert_eq!(updated, fixed);
+    }
+}
@@ -0,0 +1,178 @@
+//! Water stored in an svo parallel to the terrain one (same root aabb),
+//! relaxed towards equilibrium with [FluidRelaxation]
+
+use bevy_math::DVec3;
+use utils::DAabb;
+
+use super::*;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct FluidCellData {
+    /// Part of the cell filled with water, in 0..=1
+    ///
+    /// For internal cells the average of the children, so that cells split
+    /// from a leaf or aggregated from children hold the same water
+    pub volume: f32,
+}
+
+impl FluidCellData {
+    pub fn new(volume: f32) -> Self {
+        Self { volume }
+    }
+
+    pub fn is_dry(&self) -> bool {
+        self.volume <= 0.
+    }
+}
+
+impl Data for FluidCellData {
+    type Internal = Self;
+}
+
+impl InternalData for FluidCellData {  }
+
+impl SplittableData for FluidCellData {
+    fn split(self) -> (Self::Internal, [Self; 8]) {
+        (self, [self; 8])
+    }
+}
+
+impl AggregateData for FluidCellData {
+    fn aggregate(d: [EitherDataRef<Self>; 8]) -> Self {
+        Self {
+            volume: d.iter().map(|d| d.volume).sum::<f32>() / 8.,
+        }
+    }
+}
+
+pub type FluidCell = Cell<FluidCellData>;
+
+/// One step of a simple cellular automaton moving water between cells
+/// sharing a face until the water surface is level
+///
+/// Water flows towards cells with a lower potential, the height of the cell
+/// (its distance to the center of gravity, in cells) plus its volume, so it
+/// falls down first and then spreads. Cells whose terrain is not empty are
+/// walls: they neither give nor receive any water. The flow between two
+/// cells is the same seen from both, so water is never created or lost.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FluidRelaxation {
+    /// Part of the potential difference between two cells that flows at
+    /// each step, higher converges faster but oscillates more
+    pub flow_rate: f32,
+}
+
+impl Default for FluidRelaxation {
+    fn default() -> Self {
+        Self { flow_rate: 0.25 }
+    }
+}
+
+/// Number of neighbors a cell can give water to in a step, each flow is
+/// bounded so that a cell cannot give more than it has nor receive more
+/// than it can hold
+const FLOW_NEIGHBORS: f32 = 6.;
+
+impl FluidRelaxation {
+    /// Water flowing from a cell to its neighbor, negative if it flows the
+    /// other way, each cell is given as (volume, height)
+    fn flow(&self, (volume, height): (f32, f32), (n_volume, n_height): (f32, f32)) -> f32 {
+        let difference = (height + volume) - (n_height + n_volume);
+        if difference > 0. {
+            (self.flow_rate * difference)
+                .min(volume / FLOW_NEIGHBORS)
+                .min((1. - n_volume) / FLOW_NEIGHBORS)
+                .max(0.)
+        }
+        else if difference < 0. {
+            -self.flow((n_volume, n_height), (volume, height))
+        }
+        else {
+            0.
+        }
+    }
+
+    /// Returns the fluid after one step, as an svo of the given depth
+    ///
+    /// The terrain must have the same root aabb as the fluid but can have
+    /// any depth, its cells are sampled at the fluid's depth with
+    /// [Cell::get_path]. Gravity pulls towards `gravity_center`.
+    pub fn relax(
+        &self,
+        fluid: &FluidCell,
+        terrain: &TerrainCell,
+        root_aabb: DAabb,
+        gravity_center: DVec3,
+        depth: u32,
+    ) -> FluidCell {
+        let cell_size = root_aabb.size.x / 2f64.powi(depth as i32);
+        let height = |path: &CellPath| {
+            let aabb = path.get_aabb(root_aabb);
+            ((aabb.position + aabb.size / 2.).distance(gravity_center) / cell_size) as f32
+        };
+        let is_wall = |path: &CellPath| !terrain.get_path(path.clone()).into_inner().kind.empty();
+
+        FluidCell::par_build_with(depth, |path| {
+            let stencil = fluid.stencil(path, StencilShape::Faces);
+            let data = *stencil.data.into_inner();
+            if is_wall(&stencil.path) {
+                return data;
+            }
+            let this = (data.volume, height(&stencil.path));
+
+            let mut volume = data.volume;
+            for ((dx, dy, dz), neighbor) in stencil.neighbors() {
+                let Some(neighbor) = neighbor
+                else { continue; };
+                let neighbor_path = stencil.path.neighbor(dx, dy, dz)
+                    .expect("Neighbor has data");
+                if is_wall(&neighbor_path) {
+                    continue;
+                }
+                volume -= self.flow(this, (neighbor.volume, height(&neighbor_path)));
+            }
+
+            FluidCellData::new(volume.clamp(0., 1.))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::{ApproxEq, Tolerance};
+
+    use super::*;
+    use crate::tests::terrain;
+
+    #[test]
+    pub fn test_fluid_relaxation() {
+        // Bottom layer is stone, a single full water cell floats above it
+        let terrain = terrain(3, |_| 0., |pos| {
+            if pos.y == 0 { TerrainCellKind::Stone } else { TerrainCellKind::Air }
+        });
+        let drop = UVec3::new(3, 5, 3);
+        let mut fluid = FluidCell::build_with(3, |path| {
+            FluidCellData::new(if path.get_pos() == drop { 1. } else { 0. })
+        });
+        let root_aabb = DAabb::from_minmax(DVec3::ZERO, DVec3::splat(8.));
+        let gravity_center = DVec3::new(4., -100., 4.);
+
+        let volume_where = |fluid: &FluidCell, f: &dyn Fn(UVec3) -> bool| {
+            let mut volume = 0.;
+            fluid.for_each_at_depth(3, &mut |path, data| if f(path.get_pos()) {
+                volume += data.volume;
+            });
+            volume
+        };
+
+        let relaxation = FluidRelaxation::default();
+        for _ in 0..200 {
+            fluid = relaxation.relax(&fluid, &terrain, root_aabb, gravity_center, 3);
+            assert!(volume_where(&fluid, &|_| true).approx_eq(&1., Tolerance::Absolute(1e-4)), "Water is conserved");
+        }
+
+        assert_eq!(volume_where(&fluid, &|pos| pos.y == 0), 0., "Walls stay dry");
+        assert!(volume_where(&fluid, &|pos| pos.y >= 2) < 1e-2, "Water fell down");
+        assert!(fluid.get_path(CellPath::from_pos(3, UVec3::new(3, 1, 3)).unwrap()).volume > 0.);
+    }
+}
@@ -0,0 +1,126 @@
+use arbitrary_int::u3;
+use bevy_math::DVec3;
+use utils::{AsVecExt, DAabb};
+
+use crate::{count_stat, Cell, CellPath, StatCounter, SvoPtr, TerrainCellData};
+
+/// Interpolated distance field value, see [sample_trilinear]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TrilinearSample {
+    pub distance: f64,
+    /// Not normalized
+    pub gradient: DVec3,
+}
+
+fn octant(aabb: DAabb, pos: DVec3) -> u3 {
+    let mask = pos.cmpge(aabb.position + aabb.size / 2.);
+    u3::new(u8::from(mask.x) | u8::from(mask.y) << 1 | u8::from(mask.z) << 2)
+}
+
+/// Returns the aabb and data of the leaf containing the given position
+/// (continuing into packed cells), or None if it is outside of the root aabb
+///
+/// Positions on the border of two leaves belong to the one with the biggest
+/// coordinates (so the leaf whose min corner is the position).
+pub fn leaf_at<Ptr: SvoPtr<TerrainCellData>>(
+    root: &Cell<TerrainCellData, Ptr>,
+    root_aabb: DAabb,
+    pos: DVec3,
+) -> Option<(DAabb, &TerrainCellData)> {
+    count_stat(StatCounter::SamplerLookups);
+    if !(pos.cmpge(root_aabb.min()).all() && pos.cmple(root_aabb.max()).all()) {
+        return None;
+    }
+
+    let mut cell = root;
+    let mut aabb = root_aabb;
+    loop {
+        match cell {
+            Cell::Internal(internal) => {
+                let comp = octant(aabb, pos);
+                cell = internal.get_child(comp);
+                aabb = aabb.octant(comp);
+            },
+            Cell::Leaf(leaf) => return Some((aabb, &leaf.data)),
+            Cell::Packed(packed) => {
+                let mut path = CellPath::new();
+                for _ in 0..packed.depth() {
+                    let comp = octant(aabb, pos);
+                    path.push(comp);
+                    aabb = aabb.octant(comp);
+                }
+                return Some((aabb, packed.get(&path).into_inner()));
+            },
+        }
+    }
+}
+
+/// Samples the distance field at any position by trilinear interpolation of
+/// the 8 samples around it (like marching cubes, samples are located at the
+/// min corner of their leaf).
+///
+/// The samples are on the lattice of the leaf containing `pos` and are read
+/// from the leaves containing each lattice point whatever their depth, so
+/// this interpolates over the dual grid of the octree. When a neighbor is
+/// coarser, its distance is used as is.
+///
+/// Returns None if `pos` is outside of the root aabb.
+pub fn sample_trilinear<Ptr: SvoPtr<TerrainCellData>>(
+    root: &Cell<TerrainCellData, Ptr>,
+    root_aabb: DAabb,
+    pos: DVec3,
+) -> Option<TrilinearSample> {
+    let (leaf_aabb, _) = leaf_at(root, root_aabb, pos)?;
+    let size = leaf_aabb.size;
+    let t = (pos - leaf_aabb.position) / size;
+
+    let samples = CellPath::components().try_map(|comp| {
+        let corner = (leaf_aabb.position + comp.as_uvec().as_dvec3() * size)
+            .clamp(root_aabb.min(), root_aabb.max());
+        leaf_at(root, root_aabb, corner)
+            .map(|(_, data)| (comp.as_bvec(), data.distance.to_f64()))
+    })?;
+
+    let mut result = TrilinearSample::default();
+    for (mask, distance) in samples {
+        // weight of the sample along each axis, and its derivative
+        let weights = DVec3::select(mask, t, 1. - t);
+        let derivatives = DVec3::select(mask, DVec3::ONE, -DVec3::ONE);
+
+        result.distance += distance * weights.x * weights.y * weights.z;
+        result.gradient += distance * DVec3::new(
+            derivatives.x * weights.y * weights.z,
+            weights.x * derivatives.y * weights.z,
+            weights.x * weights.y * derivatives.z,
+        ) / size;
+    }
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::{ApproxEq, Tolerance};
+
+    use super::*;
+    use crate::{tests::terrain, TerrainCellKind};
+
+    #[test]
+    pub fn test_sample_trilinear() {
+        let root_aabb = DAabb::new_center_size(DVec3::splat(4.), DVec3::splat(8.));
+        // Distances of a plane, sampled at the min corner of the leaves
+        let root = terrain(3, |pos| pos.x as f32 + 2. * pos.y as f32, |_| TerrainCellKind::Air);
+
+        let sample = sample_trilinear(&root, root_aabb, DVec3::new(2.25, 3.5, 1.75))
+            .expect("inside");
+        assert!(sample.distance.approx_eq(&(2.25 + 2. * 3.5), Tolerance::Absolute(1e-9)));
+        assert!(sample.gradient.abs_diff_eq(DVec3::new(1., 2., 0.), 1e-9));
+
+        // On the lattice the sample is returned as is
+        let sample = sample_trilinear(&root, root_aabb, DVec3::new(3., 1., 5.))
+            .expect("inside");
+        assert_eq!(sample.distance, 5.);
+
+        assert_eq!(sample_trilinear(&root, root_aabb, DVec3::new(-0.5, 1., 1.)), None);
+    }
+}
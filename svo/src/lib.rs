@@ -21,8 +21,39 @@ mod packed;
 pub use packed::*;
 mod ptr;
 pub use ptr::*;
-
+mod memory_usage;
+pub use memory_usage::*;
+mod chunk_view;
+pub use chunk_view::*;
+mod interpolation;
+pub use interpolation::*;
+mod palette;
+pub use palette::*;
+mod structural;
+pub use structural::*;
+mod versioning;
+pub use versioning::*;
+mod stencil;
+pub use stencil::*;
+mod fluid;
+pub use fluid::*;
+mod spatial_index;
+pub use spatial_index::*;
+mod subdivs;
+pub use subdivs::*;
+mod sidecar;
+pub use sidecar::*;
+mod fixed_depth;
+pub use fixed_depth::*;
+mod stats;
+pub use stats::*;
+mod tree_stats;
+pub use tree_stats::*;
+
+pub mod export;
 pub mod mesh_generation;
+pub mod quad;
+pub mod scatter;
 
 use std::fmt::Debug;
 use std::sync::Arc;
@@ -53,6 +84,7 @@ impl<D: Data, Ptr: SvoPtr<D>> InternalCell<D, Ptr> {
     
     #[inline]
     pub fn get_child(&self, pos: u3) -> &Ptr {
+        count_stat(StatCounter::NodeVisits);
         &self.children[usize::from(pos.value())]
     }
 
@@ -60,6 +92,7 @@ impl<D: Data, Ptr: SvoPtr<D>> InternalCell<D, Ptr> {
     pub fn get_child_mut(&mut self, pos: u3) -> &mut Cell<D, Ptr>
         where Ptr: MutableSvoPtr<D>,
     {
+        count_stat(StatCounter::NodeVisits);
         self.children[usize::from(pos.value())].make_mut()
     }
 
@@ -629,6 +662,66 @@ impl<D: Data, Ptr: SvoPtr<D>> Cell<D, Ptr> {
         }
     }
 
+    /// Like [get_path] but fails if the path is deeper than the cells it
+    /// goes through instead of ignoring the rest of it
+    pub fn try_get_path(&self, mut path: CellPath) -> Result<EitherDataRef<D>, SvoIndexError> {
+        let depth = path.len();
+        let mut current = self;
+        loop {
+            let max_depth = depth - path.len();
+            match current {
+                Cell::Internal(i) => {
+                    let Some(comp) = path.pop_back()
+                    else { return Ok(current.data()); };
+
+                    current = i.get_child(comp);
+                },
+                Cell::Leaf(l) if path.len() == 0 => {
+                    return Ok(Either::Right(&l.data));
+                },
+                Cell::Leaf(_) => {
+                    return Err(SvoIndexError::TooDeep { depth, max_depth });
+                },
+                Cell::Packed(p) => {
+                    return p.try_get(&path).map_err(|_| SvoIndexError::TooDeep {
+                        depth, max_depth: max_depth + p.depth(),
+                    });
+                },
+            }
+        }
+    }
+
+    /// mut version of [try_get_path]
+    pub fn try_get_path_mut(&mut self, mut path: CellPath) -> Result<EitherDataMut<D>, SvoIndexError>
+        where Ptr: MutableSvoPtr<D>,
+    {
+        let depth = path.len();
+        let mut current = self;
+        loop {
+            let max_depth = depth - path.len();
+            match current {
+                Cell::Internal(i) => {
+                    let Some(comp) = path.pop_back()
+                    else { return Ok(Either::Left(&mut i.data)); };
+
+                    current = i.get_child_mut(comp);
+                },
+                Cell::Leaf(l) if path.len() == 0 => {
+                    return Ok(Either::Right(&mut l.data));
+                },
+                Cell::Leaf(_) => {
+                    return Err(SvoIndexError::TooDeep { depth, max_depth });
+                },
+                Cell::Packed(p) => {
+                    let packed_depth = p.depth();
+                    return p.try_get_mut(&path).map_err(|_| SvoIndexError::TooDeep {
+                        depth, max_depth: max_depth + packed_depth,
+                    });
+                },
+            }
+        }
+    }
+
     /// mut version of [get_path]
     pub fn get_path_mut(&mut self, mut path: CellPath) -> EitherDataMut<D>
         where Ptr: MutableSvoPtr<D>,
@@ -744,6 +837,41 @@ impl<D: Data, Ptr: SvoPtr<D>> Cell<D, Ptr> {
         }
     }
 
+    /// Copy of the top `max_depth` levels of the tree, the cells at
+    /// `max_depth` becoming leaves whose data is given by `leaf_data`
+    ///
+    /// Unlike [Clone::clone], which shares the children, the copy doesn't
+    /// keep deeper cells alive, and is cheap to give to tasks that only need
+    /// a low level of detail.
+    pub fn clone_to_depth_with<F>(&self, max_depth: u32, leaf_data: &F) -> Self
+        where D: Clone,
+              D::Internal: Clone,
+              Ptr: OwnedSvoPtr<D>,
+              F: Fn(EitherDataRef<D>) -> D,
+    {
+        match self {
+            Cell::Leaf(leaf) => leaf.clone().into(),
+            _ if max_depth == 0 => LeafCell::new(leaf_data(self.data())).into(),
+            Cell::Internal(internal) => InternalCell {
+                children: internal.children.each_ref().map(|child|
+                    Ptr::new(child.clone_to_depth_with(max_depth - 1, leaf_data))
+                ),
+                data: internal.data.clone(),
+            }.into(),
+            Cell::Packed(packed) =>
+                Cell::Packed(packed.clone_to_depth_with(max_depth, leaf_data)),
+        }
+    }
+
+    /// [Self::clone_to_depth_with] for data that is its own internal data,
+    /// cells at `max_depth` keeping theirs
+    pub fn clone_to_depth(&self, max_depth: u32) -> Self
+        where D: Data<Internal = D> + Clone,
+              Ptr: OwnedSvoPtr<D>,
+    {
+        self.clone_to_depth_with(max_depth, &|data| data.into_inner().clone())
+    }
+
     /// The given data is used for leaf values, and the default for internal
     /// values.
     pub fn new_with_depth(depth: u32, data: D) -> Self
@@ -766,6 +894,63 @@ impl<D: Data, Ptr: SvoPtr<D>> Cell<D, Ptr> {
         }.into()
     }
 
+    /// Builds a full tree of the given depth, calling `leaf_fn` with the path
+    /// of each leaf and aggregating the internal data on the way up, so no
+    /// [update_all](Self::update_all) is needed afterward.
+    pub fn build_with<F>(depth: u32, leaf_fn: F) -> Self
+        where D: AggregateData,
+              Ptr: OwnedSvoPtr<D>,
+              F: Fn(CellPath) -> D,
+    {
+        fn build<D, Ptr, F>(path: CellPath, remaining: u32, leaf_fn: &F) -> Cell<D, Ptr>
+            where D: AggregateData,
+                  Ptr: OwnedSvoPtr<D>,
+                  F: Fn(CellPath) -> D,
+        {
+            if remaining == 0 {
+                return LeafCell::new(leaf_fn(path)).into();
+            }
+
+            InternalCell::<D, Ptr>::from_children(path.children().map(|child|
+                Ptr::new(build(child, remaining - 1, leaf_fn))
+            )).into()
+        }
+
+        build(CellPath::new(), depth, &leaf_fn)
+    }
+
+    /// Parallel version of [build_with](Self::build_with), building the
+    /// children of each internal cell with rayon
+    pub fn par_build_with<F>(depth: u32, leaf_fn: F) -> Self
+        where D: AggregateData,
+              Ptr: Send + OwnedSvoPtr<D>,
+              F: Sync + Fn(CellPath) -> D,
+    {
+        fn build<D, Ptr, F>(path: CellPath, remaining: u32, leaf_fn: &F) -> Cell<D, Ptr>
+            where D: AggregateData,
+                  Ptr: Send + OwnedSvoPtr<D>,
+                  F: Sync + Fn(CellPath) -> D,
+        {
+            if remaining == 0 {
+                return LeafCell::new(leaf_fn(path)).into();
+            }
+
+            let mut children: [Option<Ptr>; 8] = Default::default();
+            children.as_mut_slice()
+                .par_iter_mut()
+                .zip(path.children().into_par_iter())
+                .for_each(|(slot, child)| {
+                    *slot = Some(Ptr::new(build(child, remaining - 1, leaf_fn)));
+                });
+
+            InternalCell::<D, Ptr>::from_children(
+                children.map(|child| child.expect("just built"))
+            ).into()
+        }
+
+        build(CellPath::new(), depth, &leaf_fn)
+    }
+
     pub fn iter(&self) -> SvoIterator<'_, D, Ptr> {
         self.into_iter()
     }
@@ -868,9 +1053,10 @@ impl<'a, D: Data, Ptr: SvoPtr<D>> Iterator for SvoIterator<'a, D, Ptr> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use utils::{ApproxEq, Tolerance};
 
     #[derive(Default, Clone, Copy, PartialEq, Eq)]
-    struct SumData(pub i32);
+    pub(crate) struct SumData(pub i32);
 
     impl Debug for SumData {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -906,10 +1092,34 @@ mod tests {
         }
     }
 
-    fn mc(val: i32) -> Cell<SumData> {
+    pub(crate) fn mc(val: i32) -> Cell<SumData> {
         LeafCell::new(SumData(val)).into()
     }
 
+    /// Full terrain of the given depth whose leaves get the distance and kind
+    /// at their position
+    pub(crate) fn terrain(
+        depth: u32,
+        distance: impl Fn(UVec3) -> f32,
+        kind: impl Fn(UVec3) -> TerrainCellKind,
+    ) -> TerrainCell {
+        TerrainCell::build_with(depth, |path| {
+            let pos = path.get_pos();
+            let kind = kind(pos);
+            TerrainCellData {
+                kind,
+                distance: half::f16::from_f32(distance(pos)),
+                empty: kind.empty(),
+                error: half::f16::ZERO,
+            }
+        })
+    }
+
+    /// Stone where the distance is negative, air elsewhere
+    pub(crate) fn solid_inside(distance: f32) -> TerrainCellKind {
+        if distance < 0. { TerrainCellKind::Stone } else { TerrainCellKind::Air }
+    }
+
     #[test]
     pub fn test_update_all_unpacked() {
         let mut cell: Cell<_> = InternalCell::new_full(
@@ -1031,6 +1241,244 @@ mod tests {
         );
     }
 
+    #[test]
+    pub fn test_packed_bulk_transform() {
+        let mut packed = PackedCell::<SumData>::new_default(2);
+        for (i, val) in packed.leaf_level_mut().raw_array_mut().iter_mut().enumerate() {
+            *val = SumData(i as i32);
+        }
+
+        packed.leaf_level_mut().map_in_place(|data| SumData(data.0 * 2));
+        packed.update_all();
+        assert_eq!(
+            packed.leaf_level().raw_array().iter().map(|data| data.0).collect_vec(),
+            (0..64).map(|i| i * 2).collect_vec(),
+        );
+        assert_eq!(packed.internal_level(0).raw_array()[0].0, (0..64).sum::<i32>() * 2);
+
+        // Chunks are whole subtrees
+        let chunks = packed.leaf_level().subtree_chunks(1)
+            .map(|(path, chunk)| (path, chunk.len()))
+            .collect_vec();
+        assert_eq!(chunks, PackedIndexIterator::new(1).map(|(_, path)| (path, 8)).collect_vec());
+        for (path, chunk) in packed.leaf_level_mut().subtree_chunks_mut(1) {
+            for (child, data) in path.children().into_iter().zip(chunk) {
+                *data = SumData(child.index() as i32);
+            }
+        }
+        for (data, path) in packed.leaf_level() {
+            assert_eq!(data.0, path.index() as i32);
+        }
+
+        let whole = packed.leaf_level().subtree_chunks(2).collect_vec();
+        assert_eq!(whole.len(), 1);
+        assert_eq!(whole[0].0, CellPath::new());
+    }
+
+    #[test]
+    pub fn test_build_with() {
+        let leaf = |path: CellPath| SumData(path.index() as i32);
+
+        let cell = Cell::<SumData>::build_with(2, leaf);
+        assert_eq!(cell.depth(), 2);
+        assert_eq!(*cell.data().into_inner(), (0..64).sum::<i32>());
+        assert_eq!(
+            cell.iter().map(|i| i.data.0).collect_vec(),
+            cell.iter().map(|i| i.path.index() as i32).collect_vec(),
+        );
+
+        let mut updated = cell.clone();
+        updated.update_all();
+        let par_cell = Cell::<SumData>::par_build_with(2, leaf);
+        for path in CellPath::all_iter(0).chain(CellPath::all_iter(1)) {
+            assert_eq!(
+                cell.get_path(path.clone()).into_inner(),
+                updated.get_path(path.clone()).into_inner(),
+            );
+            assert_eq!(
+                cell.get_path(path.clone()).into_inner(),
+                par_cell.get_path(path).into_inner(),
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_memory_usage() {
+        let cell_size = std::mem::size_of::<Cell<SumData>>();
+        let arc_overhead = ArcPtr::<SumData>::allocation_overhead();
+
+        let cell = Cell::<SumData>::build_with(1, |_| SumData(0));
+        let usage = cell.memory_usage();
+        assert_eq!(usage.internal_count, 1);
+        assert_eq!(usage.leaf_count, 8);
+        assert_eq!(usage.nodes, cell_size);
+        assert_eq!(usage.leaf_data, 8 * cell_size);
+        assert_eq!(usage.ptr_overhead, 8 * arc_overhead);
+
+        // Shared children are only counted once
+        let child = ArcPtr::new(LeafCell::new(SumData(1)).into());
+        let shared: Cell<SumData> = InternalCell::new_full(SumData(0), child).into();
+        let usage = shared.memory_usage();
+        assert_eq!(usage.leaf_count, 1);
+        assert_eq!(usage.total(), 2 * cell_size + arc_overhead);
+
+        let packed: Cell<SumData> = PackedCell::<SumData>::new_filled(
+            2, SumData(0), SumData(0)
+        ).into();
+        let usage = packed.memory_usage();
+        assert_eq!(usage.packed_count, 1);
+        assert!(usage.packed_levels >= (1 + 8 + 64) * std::mem::size_of::<SumData>());
+    }
+
+    #[test]
+    pub fn test_tree_stats() {
+        let cell: Cell<SumData> = InternalCell::from_children(std::array::from_fn(|i| {
+            if i == 0 {
+                PackedCell::<SumData>::new_filled(2, SumData(0), SumData(0)).into()
+            }
+            else {
+                mc(1)
+            }
+        })).into();
+        let stats = cell.tree_stats();
+        assert_eq!(stats.cells_per_depth, vec![1, 8, 8, 64]);
+        assert_eq!(stats.depth(), cell.depth());
+        assert_eq!(stats.internal_count, 1 + 1 + 8);
+        assert_eq!(stats.leaf_count, 7 + 64);
+        assert_eq!(stats.packed_count, 1);
+        assert_eq!(stats.packed_cell_count, 1 + 8 + 64);
+        assert!(stats.packed_ratio().approx_eq(&(73. / 81.), Tolerance::Absolute(1e-9)));
+        assert!(stats.average_branching().approx_eq(&71f64.cbrt(), Tolerance::Absolute(1e-9)));
+        assert_eq!(stats.memory, cell.memory_usage());
+        assert!(stats.to_string().ends_with("depth 3: 64"));
+
+        // Shared cells are counted everywhere they are but use memory once
+        let child = ArcPtr::new(mc(1));
+        let shared: Cell<SumData> = InternalCell::new_full(SumData(0), child).into();
+        let stats = shared.tree_stats();
+        assert_eq!(stats.cells_per_depth, vec![1, 8]);
+        assert_eq!(stats.leaf_count, 8);
+        assert!(stats.average_branching().approx_eq(&8., Tolerance::Absolute(1e-9)));
+        assert_eq!(stats.memory, shared.memory_usage());
+        assert_eq!(stats.memory.leaf_count, 1);
+    }
+
+    #[test]
+    pub fn test_chunk_view() {
+        let root = Cell::<SumData>::build_with(2, |path| SumData(path.index() as i32));
+        let chunk = CellPath::new().with_push(u3::new(0b000));
+
+        let subtree_view = ChunkView::from_subtrees(
+            chunk.clone(),
+            root.follow_path(&chunk).1,
+            chunk.clone().neighbors()
+                .map(|(offset, path)| (offset, root.follow_path(&path).1)),
+        );
+        let root_view = ChunkView::from_root(&root, chunk.clone());
+
+        for path in CellPath::all_iter(2) {
+            assert_eq!(
+                subtree_view.get_path(path.clone()).map(|d| *d.into_inner()),
+                Some(*root.get_path(path.clone()).into_inner()),
+            );
+            assert_eq!(
+                root_view.get_path(path.clone()).map(|d| *d.into_inner()),
+                Some(*root.get_path(path).into_inner()),
+            );
+        }
+
+        // Without neighbors only the chunk's cells are available
+        let lonely_view = ChunkView::from_subtrees(
+            chunk.clone(), root.follow_path(&chunk).1, [],
+        );
+        for path in CellPath::all_iter(2) {
+            assert_eq!(
+                lonely_view.get_path(path.clone()).is_some(),
+                chunk.is_prefix_of(&path),
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_subdivs() {
+        let chunk = CellPath::new().with_push(u3::new(2)).with_push(u3::new(5));
+        let relative = AbsoluteSubdivs(6).relative_to(&chunk);
+        assert_eq!(relative, RelativeSubdivs(4));
+        assert_eq!(relative.absolute_from(&chunk), AbsoluteSubdivs(6));
+
+        // Cells shallower than the chunk have no subdivs relative to it
+        assert_eq!(AbsoluteSubdivs(1).relative_to(&chunk), RelativeSubdivs(0));
+        assert_eq!(AbsoluteSubdivs(1).checked_relative_to(&chunk), None);
+
+        assert_eq!(
+            RelativeSubdivs(3).cell_size(bevy_math::DVec3::splat(16.)),
+            bevy_math::DVec3::splat(2.),
+        );
+    }
+
+    #[test]
+    pub fn test_packed_index_iterator() {
+        let iter = PackedIndexIterator::new(2);
+        assert_eq!(iter.len(), 64);
+        assert_eq!(
+            iter.clone().rev().map(|p| p.0).collect_vec(),
+            (0..64).rev().collect_vec(),
+        );
+        assert_eq!(
+            iter.clone().par_iter().collect::<Vec<_>>(),
+            iter.collect_vec(),
+        );
+
+        let strided = PackedIndexIterator::strided(2, 5);
+        assert_eq!(strided.len(), 13);
+        assert_eq!(strided.clone().next_back().map(|p| p.0), Some(60));
+        assert_eq!(
+            strided.clone().map(|p| p.0).collect_vec(),
+            (0..64).step_by(5).collect_vec(),
+        );
+        assert_eq!(
+            strided.clone().map(|p| p.1).collect_vec(),
+            strided.par_iter().map(|p| p.1).collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    pub fn test_normalize() {
+        // Half of the tree is 1s
+        let build = || -> Cell<StatInt<u8>> {
+            Cell::build_with(2, |path| StatInt(if path.get_pos().x < 2 { 1 } else { 0 }))
+        };
+
+        let mut a = build();
+        // Same leaves after splits and merges
+        let mut b: Cell<StatInt<u8>> = LeafCell::new(StatInt(0)).into();
+        b.split();
+        let Cell::Internal(internal) = &mut b
+        else { panic!("just split") };
+        for comp in CellPath::components().into_iter().filter(|comp| comp.value() & 1 == 0) {
+            *internal.get_child_mut(comp) = LeafCell::new(StatInt(1)).into();
+        }
+        internal.get_child_mut(u3::new(0)).split();
+        let mut c: Cell<StatInt<u8>> = PackedCell::from_cell(build())
+            .unwrap_or_else(|_| panic!("uniform depth")).into();
+        assert!(a != b && b != c);
+
+        for cell in [&mut a, &mut b, &mut c] {
+            cell.update_all();
+            cell.normalize(1);
+        }
+        assert!(a == b && b == c);
+        assert_eq!(a.structural_hash(), b.structural_hash());
+        assert_eq!(b.structural_hash(), c.structural_hash());
+        assert!(matches!(&a, Cell::Packed(packed) if packed.depth() == 1));
+
+        // Too shallow to be packed
+        let mut d = build();
+        d.normalize(2);
+        assert!(matches!(d, Cell::Internal(_)));
+        assert!(d.iter_children().all(|child| matches!(**child, Cell::Leaf(_))));
+    }
+
     #[test]
     pub fn test_to_internal() {
         let mut c: Cell<_> = LeafCell::new(SumData(5)).into();
@@ -1071,4 +1519,133 @@ mod tests {
         c.update_all();
         assert_eq!(*c.data().into_inner(), 8i32 * 3);
     }
+
+    /// Data that isn't Copy to test moving data around
+    #[derive(Debug, PartialEq)]
+    struct NameData(String);
+
+    impl Data for NameData {
+        type Internal = NameData;
+    }
+    impl InternalData for NameData {  }
+
+    fn name(s: impl Into<String>) -> NameData {
+        NameData(s.into())
+    }
+
+    #[test]
+    pub fn test_packed_from_children() {
+        let children = CellPath::components().map(|a| {
+            let leaves = CellPath::components()
+                .map(|b| PackedCell::new_leaf(name(format!("{}{}", a.value(), b.value()))));
+            PackedCell::from_children(leaves, name(a.value().to_string())).unwrap()
+        });
+        let packed = PackedCell::from_children(children, name("root")).unwrap();
+        assert_eq!(packed.depth(), 2);
+        assert_eq!(packed.get(&CellPath::new()).unwrap_left(), &name("root"));
+        for a in CellPath::components() {
+            let path = CellPath::new().with_push(a);
+            assert_eq!(packed.get(&path).unwrap_left(), &name(a.value().to_string()));
+            for b in CellPath::components() {
+                assert_eq!(
+                    packed.get(&path.clone().with_push(b)).unwrap_right(),
+                    &name(format!("{}{}", a.value(), b.value())),
+                );
+            }
+        }
+
+        let mut mixed = CellPath::components().map(|_| PackedCell::new_leaf(name("")));
+        mixed[3] = packed;
+        let mixed = PackedCell::from_children(mixed, name("root")).unwrap_err();
+        assert_eq!(mixed[3].depth(), 2);
+
+        let leaves = || CellPath::components().map(|a| BoxPtr::from(
+            Cell::from(LeafCell::new(name(a.value().to_string())))
+        ));
+        let tree: BoxCell<NameData> = InternalCell {
+            children: leaves(),
+            data: name("root"),
+        }.into();
+        assert_eq!(PackedCell::packed_depth(&tree), Some(1));
+        let packed = PackedCell::from_cell(tree).unwrap();
+        assert_eq!(packed.depth(), 1);
+        assert_eq!(packed.get(&CellPath::new()).unwrap_left(), &name("root"));
+        assert_eq!(packed.get(&CellPath::new().with_push(u3::new(5))).unwrap_right(), &name("5"));
+
+        let mut children = leaves();
+        children[7] = BoxPtr::from(Cell::from(InternalCell {
+            children: leaves(),
+            data: name("7"),
+        }));
+        let tree: BoxCell<NameData> = InternalCell { children, data: name("root") }.into();
+        assert_eq!(PackedCell::packed_depth(&tree), None);
+        assert!(PackedCell::from_cell(tree).is_err());
+    }
+
+    #[test]
+    pub fn test_clone_to_depth() {
+        let leaf = |path: CellPath| SumData(path.index() as i32);
+        let cell = Cell::<SumData>::build_with(3, leaf);
+
+        let shallow = cell.clone_to_depth(1);
+        assert_eq!(shallow.depth(), 1);
+        assert_eq!(shallow.data().into_inner(), cell.data().into_inner());
+        for path in CellPath::all_iter(1) {
+            assert!(shallow.get_path(path.clone()).is_right());
+            assert_eq!(
+                shallow.get_path(path.clone()).into_inner(),
+                cell.get_path(path).into_inner(),
+            );
+        }
+        assert_eq!(cell.clone_to_depth(5).iter().count(), 8 * 8 * 8);
+
+        let negated = cell.clone_to_depth_with(2, &|data| SumData(-data.into_inner().0));
+        let path = CellPath::new().with_push(u3::new(3)).with_push(u3::new(1));
+        assert_eq!(negated.get_path(path.clone()).into_inner().0, -cell.get_path(path).into_inner().0);
+
+        let mut packed = PackedCell::<SumData>::new_default(3);
+        for (i, data) in packed.leaf_level_mut().raw_array_mut().iter_mut().enumerate() {
+            data.0 = i as i32;
+        }
+        let packed: Cell<SumData> = Cell::Packed(packed);
+        let shallow = packed.clone_to_depth(2);
+        assert_eq!(shallow.depth(), 2);
+        assert!(matches!(shallow, Cell::Packed(_)));
+    }
+
+    #[test]
+    pub fn test_try_get() {
+        let deep = CellPath::from_index(0, 3);
+        let cell = Cell::<SumData>::build_with(2, |path| SumData(path.index() as i32));
+        assert_eq!(
+            cell.try_get_path(deep.clone()),
+            Err(SvoIndexError::TooDeep { depth: 3, max_depth: 2 }),
+        );
+        let path = CellPath::from_index(5, 2);
+        assert_eq!(cell.try_get_path(path.clone()), Ok(Either::Right(&SumData(5))));
+
+        let packed = PackedCell::<SumData>::new_default(2);
+        assert!(packed.try_get(&path).is_ok());
+        assert_eq!(
+            packed.try_get(&deep).map(|_| ()),
+            Err(SvoIndexError::TooDeep { depth: 3, max_depth: 2 }),
+        );
+        assert!(packed.try_level(2).is_ok());
+        assert!(packed.try_level(3).is_err());
+        assert!(packed.try_internal_level(1).is_ok());
+        assert_eq!(
+            packed.try_internal_level(2).map(|_| ()),
+            Err(SvoIndexError::NotInternal { depth: 2 }),
+        );
+
+        // Packed cells below unpacked ones
+        let mut cell: Cell<SumData> = InternalCell::from_children(
+            [(); 8].map(|_| Cell::Packed(packed.clone()))
+        ).into();
+        assert!(cell.try_get_path_mut(deep.clone()).is_ok());
+        assert_eq!(
+            cell.try_get_path(CellPath::from_index(0, 4)).map(|_| ()),
+            Err(SvoIndexError::TooDeep { depth: 4, max_depth: 3 }),
+        );
+    }
 }
@@ -0,0 +1,122 @@
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::ops::{ Add, AddAssign };
+
+use super::*;
+
+/// Byte counts of an svo, see [Cell::memory_usage]
+///
+/// Only the memory owned by the svo structures is counted, heap allocations
+/// made by the data itself (e.g. a Vec in a leaf) are not.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Size of internal and packed cells (including the internal data and
+    /// the pointers to the children)
+    pub nodes: usize,
+    /// Size of leaf cells, which contain their data
+    pub leaf_data: usize,
+    /// Heap memory of the levels of packed cells
+    pub packed_levels: usize,
+    /// Memory used by the pointers in addition to the pointed cell
+    /// (see [SvoPtr::allocation_overhead])
+    pub ptr_overhead: usize,
+
+    pub internal_count: usize,
+    pub leaf_count: usize,
+    pub packed_count: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.nodes + self.leaf_data + self.packed_levels + self.ptr_overhead
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.internal_count + self.leaf_count + self.packed_count
+    }
+}
+
+impl Add for MemoryUsage {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self {
+        self += rhs;
+        self
+    }
+}
+
+impl AddAssign for MemoryUsage {
+    fn add_assign(&mut self, rhs: Self) {
+        self.nodes += rhs.nodes;
+        self.leaf_data += rhs.leaf_data;
+        self.packed_levels += rhs.packed_levels;
+        self.ptr_overhead += rhs.ptr_overhead;
+        self.internal_count += rhs.internal_count;
+        self.leaf_count += rhs.leaf_count;
+        self.packed_count += rhs.packed_count;
+    }
+}
+
+impl std::iter::Sum for MemoryUsage {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+/// Accumulates the memory usage of any number of svos, counting cells that
+/// are shared (e.g. by cloned [ArcPtr]s) only once
+#[derive(Debug, Default)]
+pub struct MemoryUsageCounter {
+    seen: HashSet<usize>,
+    usage: MemoryUsage,
+}
+
+impl MemoryUsageCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        self.usage
+    }
+
+    /// Adds the given cell and all its descendants that have not been
+    /// counted yet
+    pub fn add<D: Data, Ptr: SvoPtr<D>>(&mut self, cell: &Cell<D, Ptr>) {
+        if !self.seen.insert(cell as *const Cell<D, Ptr> as usize) {
+            return;
+        }
+
+        let cell_size = size_of::<Cell<D, Ptr>>();
+        match cell {
+            Cell::Internal(internal) => {
+                self.usage.nodes += cell_size;
+                self.usage.internal_count += 1;
+                for child in &internal.children {
+                    if self.seen.contains(&(&**child as *const Cell<D, Ptr> as usize)) {
+                        continue;
+                    }
+                    self.usage.ptr_overhead += Ptr::allocation_overhead();
+                    self.add(&**child);
+                }
+            },
+            Cell::Leaf(_) => {
+                self.usage.leaf_data += cell_size;
+                self.usage.leaf_count += 1;
+            },
+            Cell::Packed(packed) => {
+                self.usage += packed.memory_usage();
+                self.usage.nodes += cell_size;
+            },
+        }
+    }
+}
+
+impl<D: Data, Ptr: SvoPtr<D>> Cell<D, Ptr> {
+    /// Computes the memory used by this cell and all its descendants
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut counter = MemoryUsageCounter::new();
+        counter.add(self);
+        counter.usage()
+    }
+}
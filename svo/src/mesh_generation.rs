@@ -1 +1,2 @@
 pub mod marching_cubes;
+pub mod fluid_surface;
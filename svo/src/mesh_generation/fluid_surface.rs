@@ -0,0 +1,70 @@
+//! Mesh of the surface of the water of a [svo::FluidCell], generated with
+//! the same marching cubes as the terrain
+
+use bevy_math::DVec3;
+use bevy_render::color::Color;
+use utils::{AabbExt, DAabb};
+
+use crate::{self as svo, ChunkView, TerrainCellKind};
+use super::marching_cubes::{self, Out, Sampled, State};
+
+/// Volume of the water surface, cells more filled are inside of it
+pub const SURFACE_VOLUME: f32 = 0.5;
+
+impl Sampled for svo::FluidCellData {
+    fn empty(&self) -> bool {
+        self.is_dry()
+    }
+
+    /// The kind only tells the kernel which side of the surface the cell is
+    fn sample(&self) -> (f64, TerrainCellKind) {
+        let kind = if self.volume >= SURFACE_VOLUME {
+            TerrainCellKind::Blue
+        } else {
+            TerrainCellKind::Air
+        };
+        ((SURFACE_VOLUME - self.volume).into(), kind)
+    }
+}
+
+/// Generates the water surface of the chunk of the given view, relative to
+/// its [mesh_origin](marching_cubes::mesh_origin), see [marching_cubes::run]
+///
+/// All vertices get the given color, the mesh is meant to be rendered with
+/// a translucent material.
+pub fn run(
+    out: &mut Out,
+    view: &ChunkView<svo::FluidCellData>,
+    root_aabb: DAabb,
+    depth: u32,
+    color: Color,
+) {
+    let chunk = view.chunk();
+    let chunk_aabb = chunk.get_aabb(root_aabb);
+    let cube_size = chunk_aabb.size() / 2f64.powi(depth as i32);
+
+    let mut state = State::new(out, marching_cubes::mesh_origin(chunk, root_aabb));
+    state.set_color(color);
+    marching_cubes::run_rec(
+        &mut |samples, positions| marching_cubes::kernel(samples, positions, |triangle, _| {
+            let a = triangle[0] - triangle[1];
+            let b = triangle[2] - triangle[1];
+            let normal: DVec3 = -a.cross(b).normalize();
+
+            state.set_normal(normal);
+            for vertex in triangle {
+                state.add_vertex(vertex);
+            }
+        }),
+
+        view,
+        &root_aabb,
+
+        &cube_size,
+        None,
+
+        chunk.clone(),
+
+        depth,
+    )
+}
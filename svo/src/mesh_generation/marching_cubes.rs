@@ -1,11 +1,11 @@
 use std::collections::HashMap;
 
-use bevy_math::{DVec3, UVec3, Vec3, Vec4};
+use bevy_math::{DVec2, DVec3, UVec3, Vec2, Vec3, Vec4};
 use bevy_render::{color::Color, mesh::{self, Mesh}, render_asset::RenderAssetUsages};
 use ordered_float::OrderedFloat;
 use utils::{AabbExt, DAabb};
 
-use crate::{self as svo, CellPath, TerrainCellKind};
+use crate::{self as svo, CellPath, ChunkView, TerrainCellKind};
 
 const EDGE_TABLE: [u16; 256] = [
 0x0  , 0x109, 0x203, 0x30a, 0x406, 0x50f, 0x605, 0x70c,
@@ -317,15 +317,55 @@ const TRIANGULATIONS: [[i8; 15]; 256] = [
     [-1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1 ],
 ];
 
+/// Texture coordinates generation of an [Out], for textured and normal
+/// mapped materials
+///
+/// Uvs are a planar projection of the positions along the main axis of the
+/// face normals, and tangents follow the u axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvOptions {
+    /// Size of a repetition of the texture, in the units of the positions
+    pub scale: f64,
+    /// Position where the uvs are 0, so that the uvs of chunks meshed with
+    /// different root aabbs match
+    pub origin: DVec3,
+}
+
+impl UvOptions {
+    /// Uv of the position and tangent (with its handedness in w) of a face
+    /// with the given normal
+    fn project(&self, pos: DVec3, normal: Vec3) -> (Vec2, Vec4) {
+        let p = (pos - self.origin) / self.scale;
+        let abs = normal.abs();
+        let (uv, u_axis, v_axis) = if abs.x >= abs.y && abs.x >= abs.z {
+            (DVec2::new(p.z, p.y), Vec3::Z, Vec3::Y)
+        }
+        else if abs.y >= abs.z {
+            (DVec2::new(p.x, p.z), Vec3::X, Vec3::Z)
+        }
+        else {
+            (DVec2::new(p.x, p.y), Vec3::X, Vec3::Y)
+        };
+
+        let tangent = (u_axis - normal * normal.dot(u_axis)).normalize_or_zero();
+        let handedness = if normal.cross(tangent).dot(v_axis) < 0. { -1. } else { 1. };
+        (uv.as_vec2(), tangent.extend(handedness))
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Out {
     pub indexed: bool,
     pub smooth: bool,
+    /// Uvs and tangents are only generated if set
+    pub uv_options: Option<UvOptions>,
 
     pub indices: Vec<u32>,
     pub vertices: Vec<Vec3>,
     pub normals: Vec<Vec3>,
     pub colors: Vec<Vec4>,
+    pub uvs: Vec<Vec2>,
+    pub tangents: Vec<Vec4>,
 }
 
 impl Out {
@@ -337,6 +377,14 @@ impl Out {
         }
     }
 
+    /// Sets [Self::uv_options]
+    pub fn with_uvs(self, options: UvOptions) -> Self {
+        Self {
+            uv_options: Some(options),
+            ..self
+        }
+    }
+
     pub fn into_mesh(&mut self) -> Mesh {
         let vertices = std::mem::take(&mut self.vertices);
         let normals = std::mem::take(&mut self.normals);
@@ -348,12 +396,190 @@ impl Out {
             .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
             .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, colors);
 
+        if self.uv_options.is_some() {
+            m = m
+                .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, std::mem::take(&mut self.uvs))
+                .with_inserted_attribute(Mesh::ATTRIBUTE_TANGENT, std::mem::take(&mut self.tangents));
+        }
+
         if self.indexed {
             m = m.with_inserted_indices(mesh::Indices::U32(indices))
         }
 
         m
     }
+
+    /// Empties all buffers while keeping their allocations
+    pub fn clear(&mut self) {
+        self.indices.clear();
+        self.vertices.clear();
+        self.normals.clear();
+        self.colors.clear();
+        self.uvs.clear();
+        self.tangents.clear();
+    }
+
+    /// Vertex indices of all the triangles
+    fn triangles(&self) -> Vec<[u32; 3]> {
+        if self.indexed {
+            self.indices.chunks_exact(3)
+                .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                .collect()
+        }
+        else {
+            (0..self.vertices.len() as u32 / 3)
+                .map(|triangle| [triangle * 3, triangle * 3 + 1, triangle * 3 + 2])
+                .collect()
+        }
+    }
+
+    /// Copies the attributes of the vertex of the given index of `other`,
+    /// returns the index of the copy
+    fn push_vertex_from(&mut self, other: &Out, index: u32) -> u32 {
+        let index = index as usize;
+        self.vertices.push(other.vertices[index]);
+        self.normals.push(other.normals[index]);
+        self.colors.push(other.colors[index]);
+        if self.uv_options.is_some() {
+            self.uvs.push(other.uvs[index]);
+            self.tangents.push(other.tangents[index]);
+        }
+        (self.vertices.len() - 1).try_into().unwrap()
+    }
+
+    /// Replaces the triangles in the given region (relative to the mesh
+    /// origin, as given by [run_region]) with all the ones of `partial`,
+    /// which must have been generated with the same options
+    ///
+    /// Vertices are not shared between the two parts, so smooth normals are
+    /// not averaged across the border of the region.
+    pub fn stitch(&mut self, region: &DAabb, partial: &Out) {
+        debug_assert_eq!(self.indexed, partial.indexed);
+        debug_assert_eq!(self.uv_options.is_some(), partial.uv_options.is_some());
+
+        let old = std::mem::replace(self, Self {
+            indexed: self.indexed,
+            smooth: self.smooth,
+            uv_options: self.uv_options,
+            ..Default::default()
+        });
+        // Triangles are inside the cube they were generated in
+        let kept = old.triangles().into_iter().filter(|triangle| {
+            let center = triangle.iter()
+                .map(|&index| old.vertices[index as usize])
+                .sum::<Vec3>() / 3.;
+            region.distance_squared_to(center.as_dvec3()) > 0.
+        }).collect();
+
+        let mut copy_triangles = |from: &Out, triangles: Vec<[u32; 3]>| {
+            let mut copies = HashMap::<u32, u32>::new();
+            for index in triangles.into_iter().flatten() {
+                if self.indexed {
+                    let copy = *copies.entry(index)
+                        .or_insert_with(|| self.push_vertex_from(from, index));
+                    self.indices.push(copy);
+                }
+                else {
+                    self.push_vertex_from(from, index);
+                }
+            }
+        };
+        copy_triangles(&old, kept);
+        copy_triangles(partial, partial.triangles());
+    }
+
+    /// Like [Self::into_mesh] but replaces the content of an existing mesh
+    /// instead of creating a new one.
+    /// The mesh's previous buffers are cleared and kept in self so they can
+    /// be reused by the next run.
+    pub fn write_to_mesh(&mut self, mesh: &mut Mesh) {
+        debug_assert_eq!(mesh.primitive_topology(), mesh::PrimitiveTopology::TriangleList);
+
+        let old_vertices = mesh.remove_attribute(Mesh::ATTRIBUTE_POSITION);
+        let old_normals = mesh.remove_attribute(Mesh::ATTRIBUTE_NORMAL);
+        let old_colors = mesh.remove_attribute(Mesh::ATTRIBUTE_COLOR);
+        let old_uvs = mesh.remove_attribute(Mesh::ATTRIBUTE_UV_0);
+        let old_tangents = mesh.remove_attribute(Mesh::ATTRIBUTE_TANGENT);
+        let old_indices = mesh.remove_indices();
+
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, std::mem::take(&mut self.vertices));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, std::mem::take(&mut self.normals));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, std::mem::take(&mut self.colors));
+        if self.uv_options.is_some() {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, std::mem::take(&mut self.uvs));
+            mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, std::mem::take(&mut self.tangents));
+        }
+        if self.indexed {
+            mesh.insert_indices(mesh::Indices::U32(std::mem::take(&mut self.indices)));
+        }
+
+        // Collecting an empty vec into one with the same layout reuses its
+        // allocation
+        if let Some(mesh::VertexAttributeValues::Float32x3(mut v)) = old_vertices {
+            v.clear();
+            self.vertices = v.into_iter().map(Vec3::from).collect();
+        }
+        if let Some(mesh::VertexAttributeValues::Float32x3(mut v)) = old_normals {
+            v.clear();
+            self.normals = v.into_iter().map(Vec3::from).collect();
+        }
+        if let Some(mesh::VertexAttributeValues::Float32x4(mut v)) = old_colors {
+            v.clear();
+            self.colors = v.into_iter().map(Vec4::from).collect();
+        }
+        if let Some(mesh::VertexAttributeValues::Float32x2(mut v)) = old_uvs {
+            v.clear();
+            self.uvs = v.into_iter().map(Vec2::from).collect();
+        }
+        if let Some(mesh::VertexAttributeValues::Float32x4(mut v)) = old_tangents {
+            v.clear();
+            self.tangents = v.into_iter().map(Vec4::from).collect();
+        }
+        if let Some(mesh::Indices::U32(mut v)) = old_indices {
+            v.clear();
+            self.indices = v;
+        }
+    }
+}
+
+/// Output of [run_collision]: positions only, with vertices shared between
+/// all triangles, meant to be turned into a collider's trimesh
+#[derive(Debug, Default)]
+pub struct CollisionOut {
+    pub vertices: Vec<Vec3>,
+    pub triangles: Vec<[u32; 3]>,
+
+    indices: HashMap<[OrderedFloat<f32>; 3], u32>,
+}
+
+impl CollisionOut {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Empties all buffers while keeping their allocations
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.triangles.clear();
+        self.indices.clear();
+    }
+
+    fn add_triangle(&mut self, triangle: [DVec3; 3]) {
+        let triangle = triangle.map(|pos| {
+            let pos = pos.as_vec3();
+            *self.indices.entry([pos.x, pos.y, pos.z].map(OrderedFloat))
+                .or_insert_with(|| {
+                    self.vertices.push(pos);
+                    (self.vertices.len() - 1).try_into().unwrap()
+                })
+        });
+        // Triangles collapsed by the welding are useless for collisions
+        if triangle[0] != triangle[1] && triangle[1] != triangle[2]
+            && triangle[2] != triangle[0]
+        {
+            self.triangles.push(triangle);
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
@@ -368,20 +594,23 @@ struct Index {
     sum_normal: Vec3,
 }
 
-struct State<'a> {
+pub(super) struct State<'a> {
     indices: HashMap<IndexKey, Index>,
     color: Color,
     normal: Vec3,
+    /// Subtracted from the positions of the vertices, see [mesh_origin]
+    origin: DVec3,
     out: &'a mut Out,
 }
 
 impl<'a> State<'a> {
-    pub fn new(out: &'a mut Out) -> Self {
+    pub fn new(out: &'a mut Out, origin: DVec3) -> Self {
         Self {
             indices: HashMap::new(),
             out,
             color: Color::rgba(1.,1.,1.,0.),
             normal: Vec3::ZERO,
+            origin,
         }
     }
 
@@ -393,13 +622,28 @@ impl<'a> State<'a> {
         self.normal = normal.as_vec3();
     }
 
-    pub fn add_vertex(&mut self, pos: DVec3) {
-        let pos = pos.as_vec3();
+    /// Pushes the uv and tangent of a new vertex, if enabled
+    fn push_uv(&mut self, pos: DVec3) {
+        if let Some(options) = &self.out.uv_options {
+            let (uv, tangent) = options.project(pos, self.normal);
+            self.out.uvs.push(uv);
+            self.out.tangents.push(tangent);
+        }
+    }
+
+    /// Uvs are computed from the given position, before the origin is
+    /// removed, so they match across chunks
+    pub fn add_vertex(&mut self, pos_f64: DVec3) {
+        let pos = (pos_f64 - self.origin).as_vec3();
         if self.out.indexed && self.out.smooth {
             let key = IndexKey {
                 pos: [pos.x, pos.y, pos.z].map(OrderedFloat),
                 color: [self.color.r(), self.color.g(), self.color.b(), self.color.a()].map(OrderedFloat)
             };
+            if !self.indices.contains_key(&key) {
+                // Shared vertices keep the uv of their first face
+                self.push_uv(pos_f64);
+            }
             let entry = self.indices.entry(key).or_insert_with(|| {
                 let idx = self.out.vertices.len();
                 self.out.normals.push(self.normal);
@@ -419,12 +663,14 @@ impl<'a> State<'a> {
         }
         else if self.out.indexed && !self.out.smooth {
             let index = self.out.indices.len();
+            self.push_uv(pos_f64);
             self.out.normals.push(self.normal);
             self.out.colors.push(self.color.rgba_to_vec4());
             self.out.vertices.push(pos);
             self.out.indices.push(index.try_into().unwrap());
         }
         else {
+            self.push_uv(pos_f64);
             self.out.normals.push(self.normal);
             self.out.colors.push(self.color.rgba_to_vec4());
             self.out.vertices.push(pos);
@@ -432,10 +678,11 @@ impl<'a> State<'a> {
     }
 }
 
-fn kernel(
-    state: &mut State,
+/// Calls f with the vertices and materials of every triangle of the cube
+pub(super) fn kernel(
     vertices_samples: [(f64, TerrainCellKind); 8],
-    vertices_positions: [DVec3; 8]
+    vertices_positions: [DVec3; 8],
+    mut f: impl FnMut([DVec3; 3], [Color; 3]),
 ) {
     let id = vertices_samples.iter().rev().fold(0u8, |id, (_, k)| {
         (id << 1) | if *k == TerrainCellKind::Air || *k == TerrainCellKind::Invalid { 0 } else { 1 }
@@ -463,23 +710,30 @@ fn kernel(
         .take_while(|&x| x != -1)
         .array_chunks::<3>()
         .for_each(|v| {
-            let arr = v.map(|i| edges[i as usize]);
-            let mat = v.map(|i| edges_mats[i as usize]);
+            f(v.map(|i| edges[i as usize]), v.map(|i| edges_mats[i as usize]));
+        });
+}
 
-            let a = arr[0] - arr[1];
-            let b = arr[2] - arr[1];
-            let normal = -a.cross(b).normalize();
+fn render_kernel(
+    state: &mut State,
+    vertices_samples: [(f64, TerrainCellKind); 8],
+    vertices_positions: [DVec3; 8]
+) {
+    kernel(vertices_samples, vertices_positions, |arr, mat| {
+        let a = arr[0] - arr[1];
+        let b = arr[2] - arr[1];
+        let normal = -a.cross(b).normalize();
 
-            state.set_normal(normal);
+        state.set_normal(normal);
 
-            let color: Color = mat.iter().copied().reduce(|a, b| a + b).unwrap();
+        let color: Color = mat.iter().copied().reduce(|a, b| a + b).unwrap();
 
-            state.set_color(color * (1./3.));
+        state.set_color(color * (1./3.));
 
-            state.add_vertex(arr[0]);
-            state.add_vertex(arr[1]);
-            state.add_vertex(arr[2]);
-        });
+        state.add_vertex(arr[0]);
+        state.add_vertex(arr[1]);
+        state.add_vertex(arr[2]);
+    });
 }
 
 const VERTICES: [UVec3; 8] = [
@@ -489,17 +743,47 @@ const VERTICES: [UVec3; 8] = [
     UVec3::new(1, 1, 1), UVec3::new(0, 1, 1),
 ];
 
-fn run_rec(
-    state: &mut State<'_>,
-    root_cell: &svo::TerrainCell,
+/// Data of the svos the marching cubes can be run on
+pub(super) trait Sampled: svo::Data<Internal = Self> {
+    /// Whether the cell (and all its children) is outside of the surface,
+    /// cells only surrounded by empty cells are skipped
+    fn empty(&self) -> bool;
+
+    /// Distance to the surface and kind of the cell, only non-empty kinds
+    /// are inside the surface
+    fn sample(&self) -> (f64, TerrainCellKind);
+}
+
+impl Sampled for svo::TerrainCellData {
+    fn empty(&self) -> bool {
+        self.empty
+    }
+
+    fn sample(&self) -> (f64, TerrainCellKind) {
+        (self.distance.to_f64(), self.kind)
+    }
+}
+
+pub(super) fn run_rec<D: Sampled>(
+    kernel: &mut impl FnMut([(f64, TerrainCellKind); 8], [DVec3; 8]),
+    view: &ChunkView<D>,
     root_aabb: &DAabb,
 
     cube_size: &DVec3,
+    /// Cells not overlapping it are skipped, see [run_region]
+    region: Option<&DAabb>,
 
     path: CellPath,
 
     depth: u32,
 ) {
+    if let Some(region) = region {
+        let aabb = path.get_aabb(*root_aabb);
+        if aabb.min().cmpge(region.max()).any() || aabb.max().cmple(region.min()).any() {
+            return;
+        }
+    }
+
     // let data = root_cell.get_path(path.clone()).into_inner();
 
     // if data.empty && depth > 2 {
@@ -508,7 +792,7 @@ fn run_rec(
     {
         let all_empty = path.clone().neighbors().map(|(_, x)| x)
             .chain(std::iter::once(path.clone()))
-            .all(|path| root_cell.get_path(path).into_inner().empty);
+            .all(|path| view.get_path(path).map_or(true, |d| d.into_inner().empty()));
         if all_empty {
             return;
         }
@@ -520,13 +804,13 @@ fn run_rec(
         let samples = VERTICES
             .map(|v| {
                 path.neighbor(v.x as _, v.y as _, v.z as _)
-                    .map(|n| root_cell.get_path(n).into_inner())
-                    .map(|cell| (cell.distance.to_f64(), cell.kind))
+                    .and_then(|n| view.get_path(n))
+                    .map(|cell| cell.into_inner().sample())
                     .unwrap_or_default()
             });
 
         kernel(
-            state, samples, VERTICES
+            samples, VERTICES
                 .map(|offset| (path_cube_pos + offset).as_dvec3() * *cube_size + root_aabb.min())
         );
         return;
@@ -534,10 +818,11 @@ fn run_rec(
 
     for comp in CellPath::components() {
         run_rec(
-            state,
-            root_cell, root_aabb,
+            kernel,
+            view, root_aabb,
 
             cube_size,
+            region,
 
             path.clone().with_push(comp),
 
@@ -546,26 +831,269 @@ fn run_rec(
     }
 }
 
+/// Origin of the vertices of the meshes of the given chunk: the center of
+/// its aabb
+///
+/// Vertices are stored in f32, so relative to their chunk they keep their
+/// precision however far the chunk is from the origin of the root aabb. The
+/// entity of a mesh should be translated by this (or by the difference
+/// with the origin of its parent chunk) to put it back in place.
+pub fn mesh_origin(chunk: &CellPath, root_aabb: DAabb) -> DVec3 {
+    chunk.get_aabb(root_aabb).center()
+}
+
+/// Generates the mesh of the chunk of the given view, cells across the
+/// chunk's borders are sampled through the view so that the meshes of
+/// neighboring chunks connect
+///
+/// Vertices are relative to the [mesh_origin] of the chunk, uvs are not.
 pub fn run(
     out: &mut Out,
-    chunk: CellPath,
-    root_cell: &svo::TerrainCell,
+    view: &ChunkView<svo::TerrainCellData>,
     root_aabb: DAabb,
     depth: u32,
 ) {
+    let chunk = view.chunk();
     let chunk_aabb = chunk.get_aabb(root_aabb);
     let cube_size = chunk_aabb.size() / 2f64.powi(depth as i32);
 
+    let mut state = State::new(out, mesh_origin(chunk, root_aabb));
     run_rec(
-        &mut State::new(out),
+        &mut |samples, positions| render_kernel(&mut state, samples, positions),
 
-        root_cell,
+        view,
         &root_aabb,
 
         &cube_size,
+        None,
 
         chunk.clone(),
 
         depth,
     )
 }
+
+/// Smallest box of the cubes of the given size of the chunk touching the
+/// region, None if the region is out of the chunk
+fn snap_to_cubes(chunk_aabb: DAabb, cube_size: DVec3, region: DAabb) -> Option<DAabb> {
+    let cube_counts = (chunk_aabb.size() / cube_size).round();
+    // Cubes on the border of the region touch it too
+    let first = ((region.min() - chunk_aabb.min()) / cube_size - 1.).ceil()
+        .max(DVec3::ZERO);
+    let last = ((region.max() - chunk_aabb.min()) / cube_size).floor()
+        .min(cube_counts - 1.);
+    if first.cmpgt(last).any() {
+        return None;
+    }
+    Some(DAabb::from_minmax(
+        chunk_aabb.min() + first * cube_size,
+        chunk_aabb.min() + (last + 1.) * cube_size,
+    ))
+}
+
+/// Like [run] but only generates the cubes touching the given region (in
+/// the space of the root aabb), e.g. after a small edit
+///
+/// Returns the box of the generated cubes relative to the [mesh_origin],
+/// to give to [Out::stitch] with the output to replace them in the mesh of
+/// the whole chunk, or None if the region is out of the chunk.
+pub fn run_region(
+    out: &mut Out,
+    view: &ChunkView<svo::TerrainCellData>,
+    root_aabb: DAabb,
+    depth: u32,
+    region: DAabb,
+) -> Option<DAabb> {
+    let chunk = view.chunk();
+    let chunk_aabb = chunk.get_aabb(root_aabb);
+    let cube_size = chunk_aabb.size() / 2f64.powi(depth as i32);
+    let snapped = snap_to_cubes(chunk_aabb, cube_size, region)?;
+    let origin = mesh_origin(chunk, root_aabb);
+
+    let mut state = State::new(out, origin);
+    run_rec(
+        &mut |samples, positions| render_kernel(&mut state, samples, positions),
+
+        view,
+        &root_aabb,
+
+        &cube_size,
+        Some(&snapped),
+
+        chunk.clone(),
+
+        depth,
+    );
+
+    Some(snapped.translated(-origin))
+}
+
+/// Like [run] but only generates what a collider needs (see [CollisionOut]),
+/// with the same origin.
+///
+/// The chunk is sampled `simplification` levels less deep than the visual
+/// mesh, which gives a coarser mesh that still connects with the ones of
+/// neighboring chunks generated with the same simplification.
+pub fn run_collision(
+    out: &mut CollisionOut,
+    view: &ChunkView<svo::TerrainCellData>,
+    root_aabb: DAabb,
+    depth: u32,
+    simplification: u32,
+) {
+    let depth = depth.saturating_sub(simplification);
+    let chunk = view.chunk();
+    let chunk_aabb = chunk.get_aabb(root_aabb);
+    let cube_size = chunk_aabb.size() / 2f64.powi(depth as i32);
+    let origin = mesh_origin(chunk, root_aabb);
+
+    run_rec(
+        &mut |samples, positions| kernel(
+            samples, positions, |triangle, _| out.add_triangle(triangle.map(|v| v - origin))
+        ),
+
+        view,
+        &root_aabb,
+
+        &cube_size,
+        None,
+
+        chunk.clone(),
+
+        depth,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{solid_inside, terrain};
+
+    /// Sphere of radius 5 in the middle of a 16 units wide root
+    fn sphere_terrain() -> (svo::TerrainCell, DAabb) {
+        let center = DVec3::splat(8.);
+        let distance = |pos: UVec3| ((pos.as_dvec3() + 0.5).distance(center) - 5.) as f32;
+        (
+            terrain(4, distance, |pos| solid_inside(distance(pos))),
+            DAabb::new_center_size(center, DVec3::splat(16.)),
+        )
+    }
+
+    #[test]
+    pub fn test_marching_cubes_collision() {
+        let (root, root_aabb) = sphere_terrain();
+        let view = ChunkView::from_root(&root, CellPath::new());
+
+        let mut render = Out::new(true, false);
+        run(&mut render, &view, root_aabb, 4);
+        let render_triangles = render.indices.len() / 3;
+        assert!(render_triangles > 0);
+
+        let mut full = CollisionOut::new();
+        run_collision(&mut full, &view, root_aabb, 4, 0);
+        assert!(!full.triangles.is_empty());
+        assert!(full.triangles.len() <= render_triangles);
+        // Vertices are shared between triangles
+        assert!(full.vertices.len() < full.triangles.len() * 3);
+
+        let mut simplified = CollisionOut::new();
+        run_collision(&mut simplified, &view, root_aabb, 4, 1);
+        assert!(!simplified.triangles.is_empty());
+        assert!(simplified.triangles.len() < full.triangles.len());
+        for triangle in &simplified.triangles {
+            assert!(triangle.iter().all(|&i| (i as usize) < simplified.vertices.len()));
+        }
+    }
+
+    #[test]
+    pub fn test_marching_cubes_uvs() {
+        let (root, root_aabb) = sphere_terrain();
+        let view = ChunkView::from_root(&root, CellPath::new());
+
+        // Uvs and tangents are only generated when asked for
+        let mut plain = Out::new(true, false);
+        run(&mut plain, &view, root_aabb, 4);
+        assert!(plain.uvs.is_empty() && plain.tangents.is_empty());
+
+        let mut textured = Out::new(true, false)
+            .with_uvs(UvOptions { scale: 4., origin: DVec3::ZERO });
+        run(&mut textured, &view, root_aabb, 4);
+        assert_eq!(textured.uvs.len(), textured.vertices.len());
+        assert_eq!(textured.tangents.len(), textured.vertices.len());
+        // Normals of degenerate triangles are NaN
+        let vertices = textured.tangents.iter().zip(&textured.normals)
+            .filter(|(_, normal)| normal.is_finite());
+        for (tangent, normal) in vertices {
+            assert!(tangent.truncate().dot(*normal).abs() < 1e-4);
+            assert!(tangent.w.abs() == 1.);
+        }
+    }
+
+    #[test]
+    pub fn test_marching_cubes_chunk_relative() {
+        let (root, root_aabb) = sphere_terrain();
+        let view = ChunkView::from_root(&root, CellPath::new());
+
+        let mut near = Out::new(true, false);
+        run(&mut near, &view, root_aabb, 4);
+
+        // Vertices are relative to the center of the chunk, so they are the
+        // same however far the root is
+        let far_aabb = root_aabb.translated(DVec3::splat(1e9));
+        let mut far = Out::new(true, false);
+        run(&mut far, &view, far_aabb, 4);
+        assert_eq!(mesh_origin(&CellPath::new(), far_aabb), root_aabb.center() + 1e9);
+        assert_eq!(far.vertices.len(), near.vertices.len());
+        for (far, near) in far.vertices.iter().zip(&near.vertices) {
+            assert!(far.distance(*near) < 1e-3);
+            assert!(near.abs().max_element() <= 8.);
+        }
+    }
+
+    #[test]
+    pub fn test_marching_cubes_region() {
+        let center = DVec3::splat(8.);
+        let sphere = |pos: UVec3| ((pos.as_dvec3() + 0.5).distance(center) - 5.) as f32;
+        // Fills the cells of the region
+        let edited_sphere = |pos: UVec3| {
+            let in_region = pos.cmpge(UVec3::splat(10)).all() && pos.cmplt(UVec3::splat(12)).all();
+            if in_region { -1. } else { sphere(pos) }
+        };
+        let root = terrain(4, sphere, |pos| solid_inside(sphere(pos)));
+        let edited = terrain(4, edited_sphere, |pos| solid_inside(edited_sphere(pos)));
+        let root_aabb = DAabb::new_center_size(center, DVec3::splat(16.));
+        let region = DAabb::from_minmax(DVec3::splat(10.), DVec3::splat(12.));
+
+        // Sorted positions of all triangles
+        let triangles = |out: &Out| {
+            let positions = |index: u32| out.vertices[index as usize].to_array().map(f32::to_bits);
+            let mut triangles = out.indices.chunks_exact(3)
+                .map(|triangle| triangle.iter().copied().map(positions).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            triangles.sort();
+            triangles
+        };
+
+        let mut mesh = Out::new(true, false);
+        run(&mut mesh, &ChunkView::from_root(&root, CellPath::new()), root_aabb, 4);
+        let mut expected = Out::new(true, false);
+        let edited_view = ChunkView::from_root(&edited, CellPath::new());
+        run(&mut expected, &edited_view, root_aabb, 4);
+        assert_ne!(triangles(&mesh), triangles(&expected));
+
+        let mut partial = Out::new(true, false);
+        let snapped = run_region(&mut partial, &edited_view, root_aabb, 4, region)
+            .unwrap();
+        // Cubes touching the region, relative to the center
+        assert_eq!(snapped, DAabb::from_minmax(DVec3::splat(1.), DVec3::splat(5.)));
+        assert!(!partial.indices.is_empty());
+        assert!(partial.indices.len() < expected.indices.len());
+
+        mesh.stitch(&snapped, &partial);
+        assert_eq!(triangles(&mesh), triangles(&expected));
+        assert!(mesh.indices.iter().all(|&index| (index as usize) < mesh.vertices.len()));
+
+        let outside = DAabb::from_minmax(DVec3::splat(20.), DVec3::splat(30.));
+        assert!(run_region(&mut partial, &edited_view, root_aabb, 4, outside).is_none());
+    }
+}
@@ -1,5 +1,6 @@
 use std::{collections::VecDeque, fmt::Debug, mem::MaybeUninit};
 
+use rayon::iter::{ IntoParallelIterator, ParallelIterator };
 use utils::AsVecExt;
 
 use super::*;
@@ -94,39 +95,92 @@ pub fn path_to_depth_and_pos(cell_path: &CellPath) -> (u32, UVec3) {
     (cell_path.len(), pos)
 }
 
-/// Gives indices and coordinates to all cells in levels of given depth
-/// in the order they are in memory.
+/// Gives the indices and paths of all cells of a given depth, in the order
+/// they are in memory in packed levels, optionally only every few ones
+/// (see [PackedIndexIterator::strided])
+#[derive(Debug, Clone)]
 pub struct PackedIndexIterator {
     depth: u32,
-    index: usize,
+    /// Next index returned from the front
+    front: usize,
+    /// Number of items remaining
+    remaining: usize,
+    stride: usize,
 }
 
 impl PackedIndexIterator {
     pub fn new(depth: u32) -> Self {
+        Self::strided(depth, 1)
+    }
+
+    /// Only yields every `stride`-th cell, starting with the first one
+    pub fn strided(depth: u32, stride: usize) -> Self {
+        assert!(stride > 0, "stride must be positive");
         Self {
             depth,
-            index: 0,
+            front: 0,
+            remaining: (level_size(depth) as usize).div_ceil(stride),
+            stride,
         }
     }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    fn item(&self, index: usize) -> (usize, CellPath) {
+        (index, CellPath::from_index(index as _, self.depth))
+    }
+
+    /// Parallel version of this iterator, yielding the same items in the
+    /// same order
+    pub fn par_iter(self) -> impl rayon::iter::IndexedParallelIterator<Item = (usize, CellPath)> {
+        (0..self.remaining).into_par_iter()
+            .map(move |i| self.item(self.front + i * self.stride))
+    }
 }
 
 impl Iterator for PackedIndexIterator {
     type Item = (usize, CellPath);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.index == level_size(self.depth) as usize {
+        if self.remaining == 0 {
             return None;
         }
 
-        let path = CellPath::from_index(self.index as _, self.depth);
-        let current = (self.index, path);
-
-        self.index += 1;
+        let current = self.item(self.front);
+        self.front += self.stride;
+        self.remaining -= 1;
 
         Some(current)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let n = n.min(self.remaining);
+        self.front += n * self.stride;
+        self.remaining -= n;
+        self.next()
+    }
+}
+
+impl DoubleEndedIterator for PackedIndexIterator {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        self.remaining -= 1;
+        Some(self.item(self.front + self.remaining * self.stride))
+    }
 }
 
+impl ExactSizeIterator for PackedIndexIterator {  }
+impl std::iter::FusedIterator for PackedIndexIterator {  }
+
 /// See [PackedCellLevelMutx]
 pub struct PackedCellLevelRef<'a, D> {
     level: &'a PackedCellLevel<D>,
@@ -154,6 +208,25 @@ impl<'a, D> PackedCellLevelRef<'a, D> {
     pub fn get(&self, path: &CellPath) -> &'a D {
         &self.level.data[self.index(path)]
     }
+
+    /// Iterates over the cells of all subtrees of the given depth, which are
+    /// contiguous in memory, along with the paths of their roots
+    ///
+    /// Panics if the subtrees are deeper than the level
+    pub fn subtree_chunks(&self, subtree_depth: u32) -> impl Iterator<Item = (CellPath, &'a [D])> {
+        let level = self.level;
+        let root_depth = subtree_root_depth(self.depth, subtree_depth);
+        level.data.chunks_exact(8usize.pow(subtree_depth))
+            .enumerate()
+            .map(move |(index, chunk)| (CellPath::from_index(index as _, root_depth), chunk))
+    }
+}
+
+/// Depth of the roots of the subtrees of the given depth in a level
+fn subtree_root_depth(level_depth: u32, subtree_depth: u32) -> u32 {
+    level_depth.checked_sub(subtree_depth).unwrap_or_else(|| panic!(
+        "Subtrees of depth {subtree_depth} are deeper than the level ({level_depth})"
+    ))
 }
 
 impl<'a, D> IntoIterator for PackedCellLevelRef<'a, D> {
@@ -200,8 +273,59 @@ impl<'a, D> PackedCellLevelMut<'a, D> {
     pub fn get_mut(&mut self, path: &CellPath) -> &mut D {
         &mut self.level.data[self.index(path)]
     }
+
+    /// Replaces every cell with the result of `f`, in memory order, which
+    /// lets simple transforms (e.g. quantizing all distances) be vectorized
+    ///
+    /// Internal levels are not updated, see [PackedCell::update_all]
+    pub fn map_in_place(&mut self, mut f: impl FnMut(D) -> D)
+        where D: Copy
+    {
+        for data in self.level.data.iter_mut() {
+            *data = f(*data);
+        }
+    }
+
+    /// Mutable version of [PackedCellLevelRef::subtree_chunks]
+    pub fn subtree_chunks_mut(&mut self, subtree_depth: u32) -> impl Iterator<Item = (CellPath, &mut [D])> {
+        let root_depth = subtree_root_depth(self.depth, subtree_depth);
+        self.level.data.chunks_exact_mut(8usize.pow(subtree_depth))
+            .enumerate()
+            .map(move |(index, chunk)| (CellPath::from_index(index as _, root_depth), chunk))
+    }
 }
 
+/// Error returned by the fallible accessors of svos (e.g.
+/// [PackedCell::try_get] or [Cell::try_get_path])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SvoIndexError {
+    /// The path or level is deeper than the svo (or than the branch the path
+    /// goes through for unpacked cells)
+    TooDeep {
+        depth: u32,
+        max_depth: u32,
+    },
+    /// An internal level was requested at the depth of the leaves
+    NotInternal {
+        depth: u32,
+    },
+}
+
+impl std::fmt::Display for SvoIndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooDeep { depth, max_depth } => write!(
+                f, "depth {depth} is deeper than the maximum depth of {max_depth}",
+            ),
+            Self::NotInternal { depth } => write!(
+                f, "depth {depth} is the leaf level and has no internal data",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SvoIndexError {}
+
 /// Compacted version of a full svo
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct PackedCell<D: Data> {
@@ -214,7 +338,34 @@ pub struct PackedCell<D: Data> {
     leaf_level: PackedCellLevel<D>,
 }
 
+impl<D> PartialEq for PackedCell<D>
+    where D: Data + PartialEq,
+          D::Internal: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.levels.len() == other.levels.len() &&
+            self.levels.iter().zip(&other.levels).all(|(a, b)| a.data == b.data) &&
+            self.leaf_level.data == other.leaf_level.data
+    }
+}
+
 impl<D: Data> PackedCell<D> {
+    /// See [StructuralHasher]
+    pub(crate) fn structural_hash<H: std::hash::Hasher>(&self, state: &mut H)
+        where D: StructuralHash,
+              D::Internal: StructuralHash,
+    {
+        use std::hash::Hash;
+
+        self.depth().hash(state);
+        for data in self.levels.iter().flat_map(|level| level.data.iter()) {
+            data.structural_hash(state);
+        }
+        for data in self.leaf_level.data.iter() {
+            data.structural_hash(state);
+        }
+    }
+
     pub fn new_filled(depth: u32, internal_data: D::Internal, data: D) -> Self
         where D: Clone,
               D::Internal: Clone,
@@ -244,6 +395,23 @@ impl<D: Data> PackedCell<D> {
         }
     }
 
+    /// Heap memory used by the levels, the size of the PackedCell itself
+    /// is counted by [Cell::memory_usage]
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let levels_vec = self.levels.capacity()
+            * std::mem::size_of::<PackedCellLevel<D::Internal>>();
+        let internal_levels = self.levels.iter()
+            .map(|level| level.data.len() * std::mem::size_of::<D::Internal>())
+            .sum::<usize>();
+        let leaf_level = self.leaf_level.data.len() * std::mem::size_of::<D>();
+
+        MemoryUsage {
+            packed_levels: levels_vec + internal_levels + leaf_level,
+            packed_count: 1,
+            ..Default::default()
+        }
+    }
+
     pub fn new_uninit(depth: u32) -> PackedCell<MaybeUninit<D>> {
         PackedCell::<MaybeUninit<D>>::uninit(depth)
     }
@@ -295,6 +463,116 @@ impl<D: Data> PackedCell<D> {
         Some(unsafe { out.assume_init() })
     }
 
+    /// Like [Self::new_repack] but moves the data of the children instead of
+    /// copying it, so D doesn't have to be Copy
+    ///
+    /// Gives the children back if they do not all have the same depth
+    pub fn from_children(
+        children: [PackedCell<D>; 8],
+        new_root: D::Internal,
+    ) -> Result<Self, [PackedCell<D>; 8]> {
+        let depth = children[0].depth();
+        if !children.iter().all(|c| c.depth() == depth) {
+            return Err(children);
+        }
+        let target_depth = depth + 1;
+
+        let mut levels = (0..target_depth)
+            .map(|levelu| Vec::with_capacity(8usize.pow(levelu)))
+            .collect_vec();
+        let mut leaf_level = Vec::with_capacity(8usize.pow(target_depth));
+        levels[0].push(new_root);
+
+        // Children are in component order, which is the order of their
+        // cells in the new levels
+        for child in children {
+            for (leveli, level) in child.levels.into_iter().enumerate() {
+                levels[leveli + 1].extend(Vec::from(level.data));
+            }
+            leaf_level.extend(Vec::from(child.leaf_level.data));
+        }
+
+        Ok(Self {
+            levels: levels.into_iter()
+                .map(|data| PackedCellLevel { data: data.into_boxed_slice() })
+                .collect(),
+            leaf_level: PackedCellLevel { data: leaf_level.into_boxed_slice() },
+        })
+    }
+
+    /// Packed cell with the given internal levels (root first) and leaf
+    /// level, each one in the order of [PackedIndexIterator]
+    ///
+    /// Gives the levels back if they don't have the sizes of a packed cell
+    /// of depth `levels.len()`.
+    #[allow(clippy::type_complexity)]
+    pub fn from_levels(
+        levels: Vec<Box<[D::Internal]>>, leaf_level: Box<[D]>,
+    ) -> Result<Self, (Vec<Box<[D::Internal]>>, Box<[D]>)> {
+        let valid = levels.iter().enumerate()
+            .all(|(depth, level)| level.len() == level_size(depth as u32) as usize) &&
+            leaf_level.len() == level_size(levels.len() as u32) as usize;
+        if !valid {
+            return Err((levels, leaf_level));
+        }
+
+        Ok(Self {
+            levels: levels.into_iter()
+                .map(|data| PackedCellLevel { data })
+                .collect(),
+            leaf_level: PackedCellLevel { data: leaf_level },
+        })
+    }
+
+    /// Inverse of [Self::from_levels]
+    pub fn into_levels(self) -> (Vec<Box<[D::Internal]>>, Box<[D]>) {
+        (
+            self.levels.into_iter().map(|level| level.data).collect(),
+            self.leaf_level.data,
+        )
+    }
+
+    /// Depth the tree would have once packed, None if its leaves are not
+    /// all at the same depth, see [Self::from_cell]
+    pub fn packed_depth<Ptr: SvoPtr<D>>(cell: &Cell<D, Ptr>) -> Option<u32> {
+        match cell {
+            Cell::Leaf(_) => Some(0),
+            Cell::Packed(packed) => Some(packed.depth()),
+            Cell::Internal(internal) => {
+                let depth = Self::packed_depth(&internal.children[0])?;
+                internal.children[1..].iter()
+                    .all(|child| Self::packed_depth(child) == Some(depth))
+                    .then_some(depth + 1)
+            },
+        }
+    }
+
+    /// Packs the whole tree, moving its data, internal data is kept as is
+    /// (and not re-aggregated)
+    ///
+    /// Gives the cell back if its leaves are not all at the same depth (see
+    /// [Self::packed_depth])
+    pub fn from_cell<Ptr: OwnedSvoPtr<D>>(cell: Cell<D, Ptr>) -> Result<Self, Cell<D, Ptr>> {
+        if Self::packed_depth(&cell).is_none() {
+            return Err(cell);
+        }
+        Ok(Self::from_uniform_cell(cell))
+    }
+
+    /// [Self::from_cell] for cells whose depth has been checked
+    fn from_uniform_cell<Ptr: OwnedSvoPtr<D>>(cell: Cell<D, Ptr>) -> Self {
+        match cell {
+            Cell::Leaf(leaf) => Self::new_leaf(leaf.data),
+            Cell::Packed(packed) => packed,
+            Cell::Internal(internal) => {
+                let children = internal.children
+                    .map(|child| Self::from_uniform_cell(child.into_inner()));
+                Self::from_children(children, internal.data)
+                    .unwrap_or_else(|_| unreachable!("Depth was checked"))
+            },
+        }
+    }
+
     /// The given level will become the new leaf level and the cell's depth
     /// will increase by one.
     /// 
@@ -309,6 +587,27 @@ impl<D: Data> PackedCell<D> {
         self.levels.push(old_leaf);
     }
 
+    /// See [Cell::clone_to_depth_with], only the levels above `max_depth`
+    /// are copied
+    pub fn clone_to_depth_with(
+        &self, max_depth: u32, leaf_data: impl Fn(EitherDataRef<D>) -> D,
+    ) -> Self
+        where D: Clone,
+              D::Internal: Clone,
+    {
+        if max_depth >= self.depth() {
+            return self.clone();
+        }
+        Self {
+            levels: self.levels[..max_depth as usize].to_vec(),
+            leaf_level: PackedCellLevel {
+                data: self.levels[max_depth as usize].data.iter()
+                    .map(|data| leaf_data(Either::Left(data)))
+                    .collect(),
+            },
+        }
+    }
+
     /// used for update_{all, on_path}
     /// updates a single cell
     // TODO: Maybe optimize to not recompute indices from the path everytime ?
@@ -348,7 +647,7 @@ impl<D: Data> PackedCell<D> {
     pub fn internal_level(&self, depth: u32) -> PackedCellLevelRef<'_, D::Internal> {
         // not debug_assert as this assert optimizes away levels indexing check
         assert!(
-            (depth as usize) >= self.levels.len(),
+            (depth as usize) < self.levels.len(),
             "Depth is out of internal cells range (to get leaf node use leaf_level)",
         );
         PackedCellLevelRef {
@@ -360,7 +659,7 @@ impl<D: Data> PackedCell<D> {
     pub fn internal_level_mut(&mut self, depth: u32) -> PackedCellLevelMut<'_, D::Internal> {
         // not debug_assert as this assert optimizes away levels indexing check
         assert!(
-            (depth as usize) >= self.levels.len(),
+            (depth as usize) < self.levels.len(),
             "Depth is out of internal cells range (to get leaf node use leaf_level)",
         );
         PackedCellLevelMut {
@@ -369,6 +668,38 @@ impl<D: Data> PackedCell<D> {
         }
     }
 
+    fn check_internal_depth(&self, depth: u32) -> Result<(), SvoIndexError> {
+        use std::cmp::Ordering as Ord;
+        match depth.cmp(&self.depth()) {
+            Ord::Less => Ok(()),
+            Ord::Equal => Err(SvoIndexError::NotInternal { depth }),
+            Ord::Greater => Err(SvoIndexError::TooDeep { depth, max_depth: self.depth() }),
+        }
+    }
+
+    fn check_depth(&self, depth: u32) -> Result<(), SvoIndexError> {
+        if depth > self.depth() {
+            return Err(SvoIndexError::TooDeep { depth, max_depth: self.depth() });
+        }
+        Ok(())
+    }
+
+    /// Fallible version of [Self::internal_level]
+    pub fn try_internal_level(
+        &self, depth: u32,
+    ) -> Result<PackedCellLevelRef<'_, D::Internal>, SvoIndexError> {
+        self.check_internal_depth(depth)?;
+        Ok(self.internal_level(depth))
+    }
+
+    /// Fallible version of [Self::internal_level_mut]
+    pub fn try_internal_level_mut(
+        &mut self, depth: u32,
+    ) -> Result<PackedCellLevelMut<'_, D::Internal>, SvoIndexError> {
+        self.check_internal_depth(depth)?;
+        Ok(self.internal_level_mut(depth))
+    }
+
     pub fn leaf_level(&self) -> PackedCellLevelRef<'_, D> {
         PackedCellLevelRef { depth: self.depth(), level: &self.leaf_level }
     }
@@ -401,6 +732,22 @@ impl<D: Data> PackedCell<D> {
         }
     }
 
+    /// Fallible version of [Self::level]
+    pub fn try_level(&self, depth: u32) -> Result<PackedCellLevelRef<'_, D>, SvoIndexError>
+        where D: Data<Internal = D>
+    {
+        self.check_depth(depth)?;
+        Ok(self.level(depth))
+    }
+
+    /// Fallible version of [Self::level_mut]
+    pub fn try_level_mut(&mut self, depth: u32) -> Result<PackedCellLevelMut<'_, D>, SvoIndexError>
+        where D: Data<Internal = D>
+    {
+        self.check_depth(depth)?;
+        Ok(self.level_mut(depth))
+    }
+
     /// Like using self.internal_level or self.leaf_level but has different
     /// lifetime requirements.
     pub fn get(&self, path: &CellPath) -> EitherDataRef<'_, D> {
@@ -427,6 +774,19 @@ impl<D: Data> PackedCell<D> {
         }
     }
 
+    /// Fallible version of [Self::get], e.g. for paths that come from the
+    /// network
+    pub fn try_get(&self, path: &CellPath) -> Result<EitherDataRef<'_, D>, SvoIndexError> {
+        self.check_depth(path.len())?;
+        Ok(self.get(path))
+    }
+
+    /// Fallible version of [Self::get_mut]
+    pub fn try_get_mut(&mut self, path: &CellPath) -> Result<EitherDataMut<'_, D>, SvoIndexError> {
+        self.check_depth(path.len())?;
+        Ok(self.get_mut(path))
+    }
+
     /// If there is only one leaf the depth is 0
     pub fn depth(&self) -> u32 {
         self.levels.len() as u32
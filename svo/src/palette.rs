@@ -0,0 +1,251 @@
+use super::*;
+
+/// Data small enough to be worth storing in a [PalettePackedLevel] instead of
+/// one value per cell, like booleans or enum kinds
+pub trait PaletteData: Copy + Eq + Debug {  }
+
+impl PaletteData for bool {  }
+impl PaletteData for StatBool {  }
+impl PaletteData for TerrainCellKind {  }
+
+/// Packed level of only booleans, using one bit per cell (or none if they are
+/// all equal)
+pub type BitPackedLevel = PalettePackedLevel<bool>;
+
+/// Alternative storage of a packed level where each distinct value is stored
+/// once in a palette and cells only store the index of theirs, using as few
+/// bits as the palette size allows (like minecraft's chunk palettes)
+///
+/// Cells are in the same order as [PackedCell]'s levels, but as they are not
+/// addressable values are read and written by copy.
+///
+/// Indices never cross the 64 bits words they are packed in, so a few bits
+/// per word may be lost for palettes whose bit count doesn't divide 64.
+#[derive(Debug, Clone)]
+pub struct PalettePackedLevel<D: PaletteData> {
+    depth: u32,
+    palette: Vec<D>,
+    /// Bits per index, 0 when the palette has a single value
+    bits: u32,
+    words: Box<[u64]>,
+}
+
+fn palette_bits(palette_len: usize) -> u32 {
+    if palette_len <= 1 {
+        0
+    }
+    else {
+        usize::BITS - (palette_len - 1).leading_zeros()
+    }
+}
+
+fn word_count(len: usize, bits: u32) -> usize {
+    if bits == 0 {
+        0
+    }
+    else {
+        len.div_ceil((u64::BITS / bits) as usize)
+    }
+}
+
+impl<D: PaletteData> PalettePackedLevel<D> {
+    pub fn new_filled(depth: u32, data: D) -> Self {
+        Self {
+            depth,
+            palette: vec![data],
+            bits: 0,
+            words: Box::new([]),
+        }
+    }
+
+    /// Panics if the length of `data` is not the cell count of the depth
+    pub fn from_slice(depth: u32, data: &[D]) -> Self {
+        assert_eq!(data.len(), 8usize.pow(depth), "Wrong data length for depth {depth}");
+
+        let mut palette = Vec::<D>::new();
+        let indices = data.iter()
+            .map(|value| match palette.iter().position(|p| p == value) {
+                Some(index) => index,
+                None => {
+                    palette.push(*value);
+                    palette.len() - 1
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let mut this = Self {
+            depth,
+            bits: palette_bits(palette.len()),
+            palette,
+            words: Box::new([]),
+        };
+        this.words = vec![0; word_count(indices.len(), this.bits)].into_boxed_slice();
+        for (i, index) in indices.into_iter().enumerate() {
+            this.set_raw(i, index);
+        }
+        this
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn len(&self) -> usize {
+        8usize.pow(self.depth)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    pub fn palette(&self) -> &[D] {
+        &self.palette
+    }
+
+    pub fn bits_per_cell(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn index(&self, path: &CellPath) -> usize {
+        assert_eq!(
+            path.len(), self.depth,
+            "Wrong cellpath ({path:?}) depth for accessing level ({})",
+            self.depth,
+        );
+        path.index()
+    }
+
+    fn get_raw(&self, i: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = (u64::BITS / self.bits) as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        ((self.words[i / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn set_raw(&mut self, i: usize, index: usize) {
+        if self.bits == 0 {
+            debug_assert_eq!(index, 0);
+            return;
+        }
+        let per_word = (u64::BITS / self.bits) as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        let word = &mut self.words[i / per_word];
+        *word = (*word & !mask) | ((index as u64) << shift);
+    }
+
+    /// Rewrites all indices with the given bit count, which must be enough
+    /// for the palette
+    fn repack(&mut self, bits: u32) {
+        let indices = (0..self.len()).map(|i| self.get_raw(i)).collect::<Vec<_>>();
+        self.bits = bits;
+        self.words = vec![0; word_count(indices.len(), bits)].into_boxed_slice();
+        for (i, index) in indices.into_iter().enumerate() {
+            self.set_raw(i, index);
+        }
+    }
+
+    pub fn get(&self, path: &CellPath) -> D {
+        self.palette[self.get_raw(self.index(path))]
+    }
+
+    /// Adds the value to the palette if needed, repacking all cells when
+    /// it needs more bits
+    pub fn set(&mut self, path: &CellPath, data: D) {
+        let i = self.index(path);
+        let index = match self.palette.iter().position(|p| *p == data) {
+            Some(index) => index,
+            None => {
+                self.palette.push(data);
+                let bits = palette_bits(self.palette.len());
+                if bits != self.bits {
+                    self.repack(bits);
+                }
+                self.palette.len() - 1
+            },
+        };
+        self.set_raw(i, index);
+    }
+
+    /// Removes the palette values no cell uses anymore
+    pub fn compact(&mut self) {
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..self.len() {
+            used[self.get_raw(i)] = true;
+        }
+        if used.iter().all(|&u| u) {
+            return;
+        }
+
+        let values = (0..self.len())
+            .map(|i| self.palette[self.get_raw(i)])
+            .collect::<Vec<_>>();
+        *self = Self::from_slice(self.depth, &values);
+    }
+
+    /// Iterates over all cells in memory order, like [PackedCellLevelRef]
+    pub fn iter(&self) -> impl Iterator<Item = (D, CellPath)> + '_ {
+        PackedIndexIterator::new(self.depth)
+            .map(|(index, path)| (self.palette[self.get_raw(index)], path))
+    }
+
+    /// Heap memory used by the palette and the indices
+    pub fn memory_usage(&self) -> usize {
+        self.palette.capacity() * std::mem::size_of::<D>() +
+            self.words.len() * std::mem::size_of::<u64>()
+    }
+}
+
+impl<D: Data + PaletteData> PackedCell<D> {
+    /// Copy of the leaf level stored with a palette, see [PalettePackedLevel]
+    pub fn palette_leaf_level(&self) -> PalettePackedLevel<D> {
+        PalettePackedLevel::from_slice(self.depth(), self.leaf_level().raw_array())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_palette_packed_level() {
+        let mut level = PalettePackedLevel::new_filled(3, TerrainCellKind::Air);
+        assert_eq!(level.bits_per_cell(), 0);
+        assert_eq!(level.memory_usage(), std::mem::size_of::<TerrainCellKind>());
+
+        let paths = CellPath::all_iter(3).collect_vec();
+        for path in paths.iter().step_by(3) {
+            level.set(path, TerrainCellKind::Stone);
+        }
+        // 2 kinds in 1 bit, about 8 times less than one byte per cell
+        assert_eq!(level.bits_per_cell(), 1);
+        assert!(level.memory_usage() < 512 / 4);
+
+        level.set(&paths[1], TerrainCellKind::Pink);
+        assert_eq!(level.bits_per_cell(), 2);
+        for (i, path) in paths.iter().enumerate() {
+            let expected = match i {
+                1 => TerrainCellKind::Pink,
+                i if i % 3 == 0 => TerrainCellKind::Stone,
+                _ => TerrainCellKind::Air,
+            };
+            assert_eq!(level.get(path), expected);
+        }
+        assert_eq!(level.iter().count(), 512);
+        for (kind, path) in level.iter() {
+            assert_eq!(kind, level.get(&path));
+        }
+
+        level.set(&paths[1], TerrainCellKind::Air);
+        level.compact();
+        assert_eq!(level.palette().len(), 2);
+        assert_eq!(level.bits_per_cell(), 1);
+
+        let packed = PackedCell::new_filled(2, InnerStatBool::default(), StatBool(true));
+        let bits = packed.palette_leaf_level();
+        assert_eq!(bits.bits_per_cell(), 0);
+        assert!(bits.iter().all(|(value, _)| value == StatBool(true)));
+    }
+}
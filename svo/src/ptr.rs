@@ -1,5 +1,7 @@
 mod arc;
 pub use arc::*;
+mod arena;
+pub use arena::*;
 mod boxed;
 pub use boxed::*;
 mod bumpbox;
@@ -13,7 +15,13 @@ use std::ops::Deref;
 
 use super::*;
 
-pub trait SvoPtr<D: Data>: Sized + Deref<Target = Cell<D, Self>> { }
+pub trait SvoPtr<D: Data>: Sized + Deref<Target = Cell<D, Self>> {
+    /// Bytes allocated for each pointer in addition to the pointed cell
+    /// (e.g. the reference counts of an Arc), see [Cell::memory_usage]
+    fn allocation_overhead() -> usize {
+        0
+    }
+}
 
 pub trait MutableSvoPtr<D: Data>: SvoPtr<D> {
     /// Explicit DerefMut as it can be costly like with Arc::make_mut
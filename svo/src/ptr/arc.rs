@@ -1,9 +1,16 @@
 use super::*;
 use crate::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct ArcPtr<D: Data>(pub Arc<Cell<D, ArcPtr<D>>>);
 
+impl<D: Data> Clone for ArcPtr<D> {
+    fn clone(&self) -> Self {
+        count_stat(StatCounter::ArcClones);
+        Self(Arc::clone(&self.0))
+    }
+}
+
 impl<D> From<Arc<Cell<D, ArcPtr<D>>>> for ArcPtr<D>
     where D: Data + Clone,
           D::Internal: Clone,
@@ -30,13 +37,21 @@ impl<D: Data> Deref for ArcPtr<D> {
     }
 }
 
-impl<D: Data> SvoPtr<D> for ArcPtr<D> { }
+impl<D: Data> SvoPtr<D> for ArcPtr<D> {
+    fn allocation_overhead() -> usize {
+        // strong and weak counts
+        2 * std::mem::size_of::<usize>()
+    }
+}
 
 impl<D> MutableSvoPtr<D> for ArcPtr<D>
     where D: Data + Clone,
           D::Internal: Clone,
 {
     fn make_mut(&mut self) -> &mut Cell<D, Self> {
+        if STATS_ENABLED && Arc::get_mut(&mut self.0).is_none() {
+            count_stat(StatCounter::MakeMutCopies);
+        }
         Arc::make_mut(&mut self.0)
     }
 }
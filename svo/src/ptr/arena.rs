@@ -0,0 +1,356 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use super::*;
+use crate::*;
+
+/// A cell whose sub cells are allocated in an [SvoArena]
+pub type ArenaCell<D> = Cell<D, ArenaPtr<D>>;
+
+/// Slots of the first segment, each next one being twice as big
+const FIRST_SEGMENT_LEN: usize = 64;
+/// Enough segments for every u32 index
+const SEGMENT_COUNT: usize = 27;
+
+/// Segment of the slot at the given index and its offset in it
+fn locate(index: u32) -> (usize, usize) {
+    let index = index as usize;
+    let segment = (index / FIRST_SEGMENT_LEN + 1).ilog2() as usize;
+    (segment, index - FIRST_SEGMENT_LEN * ((1 << segment) - 1))
+}
+
+fn segment_len(segment: usize) -> usize {
+    FIRST_SEGMENT_LEN << segment
+}
+
+struct Slot<D: Data> {
+    /// Incremented each time the slot is freed
+    generation: AtomicU32,
+    cell: UnsafeCell<MaybeUninit<ArenaCell<D>>>,
+}
+
+#[derive(Default)]
+struct SlotAllocator {
+    /// Freed slots, reused before new ones
+    free: Vec<u32>,
+    /// Slots from this index on were never used
+    len: u32,
+}
+
+struct ArenaStorage<D: Data> {
+    /// Segments never move once allocated, so cells are borrowed without
+    /// locking anything
+    segments: [AtomicPtr<Slot<D>>; SEGMENT_COUNT],
+    allocator: Mutex<SlotAllocator>,
+}
+
+// Slots are only accessed through the unique ArenaPtr owning them, like the
+// content of a Box
+unsafe impl<D: Data> Send for ArenaStorage<D>
+    where D: Send + Sync, D::Internal: Send + Sync { }
+unsafe impl<D: Data> Sync for ArenaStorage<D>
+    where D: Send + Sync, D::Internal: Send + Sync { }
+
+impl<D: Data> ArenaStorage<D> {
+    fn new() -> Self {
+        Self {
+            segments: std::array::from_fn(|_| AtomicPtr::new(std::ptr::null_mut())),
+            allocator: Mutex::new(SlotAllocator::default()),
+        }
+    }
+
+    fn slot(&self, index: u32) -> &Slot<D> {
+        let (segment, offset) = locate(index);
+        let segment = self.segments[segment].load(Ordering::Acquire);
+        debug_assert!(!segment.is_null());
+        unsafe { &*segment.add(offset) }
+    }
+
+    fn alloc(self: &Arc<Self>, cell: ArenaCell<D>) -> ArenaPtr<D> {
+        let index = {
+            let mut allocator = self.allocator.lock().unwrap();
+            match allocator.free.pop() {
+                Some(index) => index,
+                None => {
+                    let index = allocator.len;
+                    assert!(index != u32::MAX, "Svo arena is full");
+                    allocator.len += 1;
+
+                    let (segment, _) = locate(index);
+                    let segment_ptr = &self.segments[segment];
+                    if segment_ptr.load(Ordering::Acquire).is_null() {
+                        let slots = (0..segment_len(segment))
+                            .map(|_| Slot {
+                                generation: AtomicU32::new(0),
+                                cell: UnsafeCell::new(MaybeUninit::uninit()),
+                            })
+                            .collect::<Box<[_]>>();
+                        segment_ptr.store(Box::into_raw(slots).cast(), Ordering::Release);
+                    }
+                    index
+                },
+            }
+        };
+
+        let slot = self.slot(index);
+        unsafe { (*slot.cell.get()).write(cell); }
+        ArenaPtr {
+            arena: Arc::clone(self),
+            index,
+            generation: slot.generation.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Makes the slot reusable, its cell must already be dropped or moved out
+    fn release(&self, index: u32) {
+        self.slot(index).generation.fetch_add(1, Ordering::Relaxed);
+        self.allocator.lock().unwrap().free.push(index);
+    }
+}
+
+impl<D: Data> Drop for ArenaStorage<D> {
+    fn drop(&mut self) {
+        // Every pointer keeps the storage alive, so all cells are already
+        // dropped
+        for (segment, ptr) in self.segments.iter_mut().enumerate() {
+            let ptr = *ptr.get_mut();
+            if ptr.is_null() {
+                continue;
+            }
+            let slots = std::ptr::slice_from_raw_parts_mut(ptr, segment_len(segment));
+            drop(unsafe { Box::from_raw(slots) });
+        }
+    }
+}
+
+/// Storage of the cells of [ArenaCell] trees, where cells are slots of a few
+/// big segments instead of separate heap allocations, with better locality
+/// and no allocation at all for trees rebuilt in place of a dropped one as
+/// their slots are reused
+///
+/// As an [ArenaPtr] cannot be created without its arena it does not implement
+/// [OwnedSvoPtr], so the allocating [Cell] methods like [Cell::build_with] or
+/// [Cell::split] are not available to arena cells, see
+/// [SvoArena::build_with] and [SvoArena::split] instead.
+pub struct SvoArena<D: Data> {
+    storage: Arc<ArenaStorage<D>>,
+}
+
+impl<D: Data> SvoArena<D> {
+    pub fn new() -> Self {
+        Self {
+            storage: Arc::new(ArenaStorage::new()),
+        }
+    }
+
+    pub fn alloc(&self, cell: ArenaCell<D>) -> ArenaPtr<D> {
+        self.storage.alloc(cell)
+    }
+
+    /// Like [Cell::build_with], with all sub cells allocated in this arena
+    pub fn build_with<F>(&self, depth: u32, leaf_fn: F) -> ArenaCell<D>
+        where D: AggregateData,
+              F: Fn(CellPath) -> D,
+    {
+        fn build<D, F>(arena: &SvoArena<D>, path: CellPath, remaining: u32, leaf_fn: &F) -> ArenaCell<D>
+            where D: AggregateData,
+                  F: Fn(CellPath) -> D,
+        {
+            if remaining == 0 {
+                return LeafCell::new(leaf_fn(path)).into();
+            }
+
+            InternalCell::<D, ArenaPtr<D>>::from_children(path.children().map(|child|
+                arena.alloc(build(arena, child, remaining - 1, leaf_fn))
+            )).into()
+        }
+
+        build(self, CellPath::new(), depth, &leaf_fn)
+    }
+
+    /// Like [Cell::split], with the new children allocated in this arena
+    pub fn split(&self, cell: &mut ArenaCell<D>) -> bool
+        where D: SplittableData,
+    {
+        let mut did = false;
+        utils::replace_with(cell, |this| {
+            let leaf_data = match this {
+                Cell::Internal(_) => return this,
+                Cell::Leaf(l) => l.data,
+                Cell::Packed(p) => match p.try_into_leaf() {
+                    Ok(l) => l.data,
+                    Err(p) => return Cell::Packed(p),
+                },
+            };
+
+            did = true;
+
+            let (data, children) = leaf_data.split();
+
+            InternalCell::<D, ArenaPtr<D>> {
+                children: children
+                    .map(|data| self.alloc(LeafCell::new(data).into())),
+                data,
+            }.into()
+        });
+        did
+    }
+
+    /// Number of cells currently allocated
+    pub fn cell_count(&self) -> usize {
+        let allocator = self.storage.allocator.lock().unwrap();
+        allocator.len as usize - allocator.free.len()
+    }
+
+    /// Number of slots used so far, by current cells or free for reuse
+    pub fn slot_count(&self) -> usize {
+        self.storage.allocator.lock().unwrap().len as usize
+    }
+}
+
+impl<D: Data> Default for SvoArena<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handle to the same arena
+impl<D: Data> Clone for SvoArena<D> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: Arc::clone(&self.storage),
+        }
+    }
+}
+
+impl<D: Data> fmt::Debug for SvoArena<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SvoArena")
+            .field("cell_count", &self.cell_count())
+            .field("slot_count", &self.slot_count())
+            .finish()
+    }
+}
+
+/// Generational index of a cell in an [SvoArena], owning it like a Box
+pub struct ArenaPtr<D: Data> {
+    arena: Arc<ArenaStorage<D>>,
+    index: u32,
+    generation: u32,
+}
+
+impl<D: Data> ArenaPtr<D> {
+    fn slot(&self) -> &Slot<D> {
+        let slot = self.arena.slot(self.index);
+        debug_assert_eq!(slot.generation.load(Ordering::Relaxed), self.generation);
+        slot
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// The arena the cell is allocated in
+    pub fn arena(&self) -> SvoArena<D> {
+        SvoArena {
+            storage: Arc::clone(&self.arena),
+        }
+    }
+}
+
+impl<D: Data> fmt::Debug for ArenaPtr<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArenaPtr")
+            .field("index", &self.index)
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+/// Copies the cell in the same arena
+impl<D> Clone for ArenaPtr<D>
+    where D: Data + Clone,
+          D::Internal: Clone,
+{
+    fn clone(&self) -> Self {
+        self.arena.alloc((**self).clone())
+    }
+}
+
+impl<D: Data> Drop for ArenaPtr<D> {
+    fn drop(&mut self) {
+        // Dropped before releasing the slot as it drops the children, which
+        // lock the allocator too
+        unsafe { (*self.slot().cell.get()).assume_init_drop(); }
+        self.arena.release(self.index);
+    }
+}
+
+impl<D: Data> Deref for ArenaPtr<D> {
+    type Target = ArenaCell<D>;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { (*self.slot().cell.get()).assume_init_ref() }
+    }
+}
+
+impl<D: Data> SvoPtr<D> for ArenaPtr<D> {
+    fn allocation_overhead() -> usize {
+        // generation of the slot
+        std::mem::size_of::<AtomicU32>()
+    }
+}
+
+impl<D: Data> MutableSvoPtr<D> for ArenaPtr<D> {
+    fn make_mut(&mut self) -> &mut Cell<D, Self> {
+        unsafe { (*self.slot().cell.get()).assume_init_mut() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestData = StatInt<u32>;
+
+    #[test]
+    pub fn test_arena_ptr() {
+        let arena = SvoArena::<TestData>::new();
+        let leaf = |path: CellPath| StatInt(path.index() as u32);
+
+        let mut cell = arena.build_with(2, leaf);
+        let reference = Cell::<TestData>::build_with(2, leaf);
+        assert_eq!(arena.cell_count(), 8 + 64);
+        assert_eq!(cell.data().left(), reference.data().left());
+        for path in CellPath::all_iter(2) {
+            assert_eq!(cell.get_path(path.clone()).right(), Some(&leaf(path)));
+        }
+
+        let first_leaf = CellPath::from_index(0, 2);
+        assert!(arena.split(cell.follow_path_mut(&first_leaf).1));
+        assert_eq!(arena.cell_count(), 8 + 64 + 8);
+        assert!(!arena.split(cell.follow_path_mut(&first_leaf).1), "Already split");
+        *cell.follow_path_mut(&first_leaf).1 = LeafCell::new(leaf(first_leaf.clone())).into();
+        assert_eq!(arena.cell_count(), 8 + 64);
+
+        let copy = cell.clone();
+        assert_eq!(arena.cell_count(), 2 * (8 + 64));
+        drop(cell);
+        assert_eq!(arena.cell_count(), 8 + 64);
+        assert_eq!(copy.data().left(), reference.data().left());
+
+        // Slots of dropped trees are reused
+        drop(copy);
+        let rebuilt = arena.build_with(2, leaf);
+        assert_eq!(arena.cell_count(), 8 + 64);
+        assert_eq!(arena.slot_count(), 2 * (8 + 64));
+        assert_eq!(rebuilt.data().left(), reference.data().left());
+    }
+}
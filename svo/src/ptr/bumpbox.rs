@@ -3,7 +3,7 @@ use crate::*;
 
 use bumpalo::boxed::Box as BumpBox;
 
-/// A cell that uses Boxes as Pointers to sub cells
+/// A cell that uses bump allocated Boxes as Pointers to sub cells
 pub type BumpCell<'a, D> = Cell<D, BumpBoxPtr<'a, D>>;
 
 #[derive(Debug)]
@@ -0,0 +1,221 @@
+//! 2D quadtree sibling of the octree, for heightmap-style data (e.g. surface
+//! heights extracted from the octree columns)
+
+mod quad_path;
+pub use quad_path::*;
+
+use either::Either;
+use arbitrary_int::*;
+
+use crate::{ Cell, CellPath, Data, EitherDataMut, EitherDataRef, SvoPtr };
+
+/// Quadtree equivalent of [AggregateData](crate::AggregateData)
+pub trait QuadAggregateData: Data {
+    fn aggregate(
+        children: [EitherDataRef<Self>; 4]
+    ) -> Self::Internal;
+}
+
+impl<D: Data<Internal = ()>> QuadAggregateData for D {
+    fn aggregate(
+        _d: [EitherDataRef<D>; 4]
+    ) { }
+}
+
+#[derive(Clone, Debug)]
+pub struct QuadInternalCell<D: Data> {
+    pub children: [Box<QuadCell<D>>; 4],
+    pub data: D::Internal,
+}
+
+impl<D: Data> QuadInternalCell<D> {
+    pub fn from_children(children: [impl Into<QuadCell<D>>; 4]) -> Self
+        where D: QuadAggregateData
+    {
+        let children = children.map(|c| Box::new(c.into()));
+        let data = D::aggregate(children.each_ref().map(|c| c.data()));
+        Self {
+            children,
+            data,
+        }
+    }
+
+    #[inline]
+    pub fn get_child(&self, pos: u2) -> &QuadCell<D> {
+        &self.children[usize::from(pos.value())]
+    }
+
+    #[inline]
+    pub fn get_child_mut(&mut self, pos: u2) -> &mut QuadCell<D> {
+        &mut self.children[usize::from(pos.value())]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum QuadCell<D: Data> {
+    Internal(QuadInternalCell<D>),
+    Leaf(D),
+}
+
+impl<D: Data> From<QuadInternalCell<D>> for QuadCell<D> {
+    fn from(value: QuadInternalCell<D>) -> Self {
+        Self::Internal(value)
+    }
+}
+
+impl<D: Data> QuadCell<D> {
+    pub fn data(&self) -> EitherDataRef<D> {
+        match self {
+            QuadCell::Internal(i) => Either::Left(&i.data),
+            QuadCell::Leaf(l)     => Either::Right(l),
+        }
+    }
+
+    pub fn data_mut(&mut self) -> EitherDataMut<D> {
+        match self {
+            QuadCell::Internal(i) => Either::Left(&mut i.data),
+            QuadCell::Leaf(l)     => Either::Right(l),
+        }
+    }
+
+    pub fn has_children(&self) -> bool {
+        matches!(self, QuadCell::Internal(_))
+    }
+
+    /// Follows the given path, until a leaf is reached
+    pub fn follow_path(&self, path: &QuadPath) -> (QuadPath, &Self) {
+        let mut found = QuadPath::new();
+        let mut current = self;
+        for comp in path.iter() {
+            let QuadCell::Internal(i) = current
+            else { break; };
+            found.push(comp);
+            current = i.get_child(comp);
+        }
+        (found, current)
+    }
+
+    /// Like [follow_path](Self::follow_path) but only returns the data
+    pub fn get_path(&self, path: &QuadPath) -> EitherDataRef<D> {
+        self.follow_path(path).1.data()
+    }
+
+    /// Re-computes the data of all internal cells from their children
+    pub fn update_all(&mut self)
+        where D: QuadAggregateData
+    {
+        let QuadCell::Internal(i) = self
+        else { return; };
+
+        for child in &mut i.children {
+            child.update_all();
+        }
+        i.data = D::aggregate(i.children.each_ref().map(|c| c.data()));
+    }
+
+    /// Builds a full quadtree of the given depth by projecting the octree
+    /// along the `up` axis.
+    ///
+    /// `f` is given, for each quadtree leaf, the data of every octree cell of
+    /// the same depth in its column, from the lowest to the highest along `up`
+    /// (see [QuadPath::column]).
+    /// As with [Cell::get_path], cells deeper than the octree return the data
+    /// of the deepest available cell.
+    pub fn from_octree_columns<OD, Ptr, F>(
+        octree: &Cell<OD, Ptr>,
+        up: OctreeAxis,
+        depth: u32,
+        mut f: F,
+    ) -> Self
+        where D: QuadAggregateData,
+              OD: Data,
+              Ptr: SvoPtr<OD>,
+              F: FnMut(&QuadPath, &[EitherDataRef<OD>]) -> D,
+    {
+        assert!(depth <= CellPath::MAX_CAPACITY);
+
+        fn build<'a, D, OD, Ptr, F>(
+            octree: &'a Cell<OD, Ptr>,
+            up: OctreeAxis,
+            path: QuadPath,
+            remaining: u32,
+            column: &mut Vec<EitherDataRef<'a, OD>>,
+            f: &mut F,
+        ) -> QuadCell<D>
+            where D: QuadAggregateData,
+                  OD: Data,
+                  Ptr: SvoPtr<OD>,
+                  F: FnMut(&QuadPath, &[EitherDataRef<OD>]) -> D,
+        {
+            if remaining == 0 {
+                column.clear();
+                column.extend(path.column(up).map(|p| octree.get_path(p)));
+                return QuadCell::Leaf(f(&path, column));
+            }
+
+            QuadInternalCell::from_children(path.children().map(|child|
+                build(octree, up, child, remaining - 1, column, f)
+            )).into()
+        }
+
+        let mut column = Vec::with_capacity(1 << depth);
+        build(octree, up, QuadPath::new(), depth, &mut column, &mut f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ InternalCell, InternalData, LeafCell };
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Solid(bool);
+
+    impl Data for Solid {
+        type Internal = ();
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Height(u32);
+
+    impl Data for Height {
+        type Internal = Height;
+    }
+    impl InternalData for Height {  }
+
+    impl QuadAggregateData for Height {
+        fn aggregate(
+            children: [EitherDataRef<Self>; 4]
+        ) -> Self::Internal {
+            Height(children.iter().map(|c| c.into_inner().0).max().expect("4"))
+        }
+    }
+
+    #[test]
+    fn test_from_octree_columns() {
+        // Solid at the bottom layer, with one column solid on both layers
+        let octree: Cell<Solid> = InternalCell::from_children(
+            CellPath::components().map(|comp| {
+                let v = comp.value();
+                let solid = v & 0b010 == 0 || v == 0b011;
+                Cell::<Solid>::from(LeafCell::new(Solid(solid)))
+            })
+        ).into();
+
+        let mut calls = 0;
+        let quad = QuadCell::<Height>::from_octree_columns(
+            &octree, OctreeAxis::Y, 1, |_, column| {
+                calls += 1;
+                assert_eq!(column.len(), 2);
+                Height(column.iter().filter(|c| c.right().is_some_and(|s| s.0)).count() as u32)
+            }
+        );
+        assert_eq!(calls, 4);
+
+        assert_eq!(quad.data().into_inner(), &Height(2));
+        let expected = [1, 2, 1, 1];
+        for (child, expected) in QuadPath::new().children().iter().zip(expected) {
+            assert_eq!(quad.get_path(child).into_inner(), &Height(expected));
+        }
+    }
+}
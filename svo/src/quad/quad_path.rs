@@ -0,0 +1,261 @@
+use arbitrary_int::*;
+use bevy_math::UVec2;
+
+use crate::CellPath;
+
+type QuadPathInner = u64;
+
+/// Axis of an octree, used to select the 'up' axis that is dropped when
+/// projecting octree paths to quadtree paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OctreeAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl OctreeAxis {
+    /// Bit of this axis in a [CellPath] component
+    #[inline]
+    pub const fn bit(self) -> u8 {
+        match self {
+            Self::X => 0,
+            Self::Y => 1,
+            Self::Z => 2,
+        }
+    }
+
+    /// Bits of the two other axes, the first one becomes the first bit of
+    /// [QuadPath] components and the second one the second bit
+    #[inline]
+    pub const fn other_bits(self) -> [u8; 2] {
+        match self {
+            Self::X => [1, 2],
+            Self::Y => [0, 2],
+            Self::Z => [0, 1],
+        }
+    }
+}
+
+/// 2D equivalent of [CellPath], packs a u2 array into a number with
+/// a leading 1 bit as terminator
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub struct QuadPath(QuadPathInner);
+impl QuadPath {
+    pub const MAX_CAPACITY: u32 = (QuadPathInner::BITS - 1) / 2;
+
+    #[inline]
+    pub fn new() -> Self {
+        Self(0x1)
+    }
+
+    #[inline]
+    const fn mark_bit_position(&self) -> u32 {
+        debug_assert!(
+            self.0.leading_zeros() < QuadPathInner::BITS,
+            "invalid inner value"
+        );
+        let sb = QuadPathInner::BITS - self.0.leading_zeros() - 1;
+        debug_assert!(sb % 2 == 0, "invalid inner value");
+        sb
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 1
+    }
+
+    #[doc(alias = "depth")]
+    #[inline]
+    pub const fn len(&self) -> u32 {
+        self.mark_bit_position() / 2
+    }
+
+    #[doc(alias = "len")]
+    #[inline]
+    pub const fn depth(&self) -> u32 {
+        self.len()
+    }
+
+    #[inline]
+    pub fn push(&mut self, v: u2) {
+        assert!(self.len() < Self::MAX_CAPACITY);
+
+        self.0 = (self.0 << 2) | QuadPathInner::from(v.value());
+    }
+
+    #[inline]
+    pub fn with_push(mut self, v: u2) -> Self {
+        self.push(v);
+        self
+    }
+
+    #[inline]
+    pub fn peek(&self) -> Option<u2> {
+        if self.is_empty() {
+            return None;
+        }
+
+        Some(u2::new((self.0 & 0b11) as u8))
+    }
+
+    pub fn pop(&mut self) -> Option<u2> {
+        let val = self.peek()?;
+        self.0 >>= 2;
+        Some(val)
+    }
+
+    #[inline]
+    pub fn parent(&self) -> Option<Self> {
+        if self.is_empty()
+        { return None; }
+
+        Some(Self(self.0 >> 2))
+    }
+
+    /// Component at the given depth, 0 being the one closest to the root
+    pub fn get(&self, depth: u32) -> Option<u2> {
+        if depth >= self.len() {
+            return None;
+        }
+        let shift = (self.len() - depth - 1) * 2;
+        Some(u2::new(((self.0 >> shift) & 0b11) as u8))
+    }
+
+    /// Iterator over all components, from the root to the deepest
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = u2> + ExactSizeIterator + '_ {
+        (0..self.len()).map(|depth| self.get(depth).expect("in range"))
+    }
+
+    #[inline]
+    pub const fn components() -> [u2; 4] {
+        [u2::new(0b00), u2::new(0b01), u2::new(0b10), u2::new(0b11)]
+    }
+
+    pub fn children(&self) -> [Self; 4] {
+        Self::components().map(|p| self.clone().with_push(p))
+    }
+
+    /// See [CellPath::index]
+    pub fn index(&self) -> usize {
+        let marker_bit = self.mark_bit_position();
+
+        usize::try_from(self.0 & !(QuadPathInner::MAX << marker_bit)).unwrap()
+    }
+
+    /// Get the position of the cell considering one unit per cell of the
+    /// current depth
+    pub fn get_pos(&self) -> UVec2 {
+        let mut size = UVec2::splat(2u32.pow(self.depth()));
+        let mut result = UVec2::ZERO;
+        for x in self.iter() {
+            size /= 2;
+            let v = u32::from(x.value());
+            result += size * UVec2::new(v & 1, v >> 1);
+        }
+        result
+    }
+
+    #[inline]
+    pub fn is_prefix_of(&self, other: &Self) -> bool {
+        let self_bit = self.mark_bit_position();
+        let other_bit = other.mark_bit_position();
+        if self_bit > other_bit {
+            return false;
+        }
+
+        (other.0 >> (other_bit - self_bit)) == self.0
+    }
+
+    /// Projects the given octree path along the up axis, giving the path of
+    /// the column containing it
+    pub fn from_cell_path(path: &CellPath, up: OctreeAxis) -> Self {
+        let [a, b] = up.other_bits();
+        let mut result = Self::new();
+        for comp in path {
+            let v = comp.value();
+            result.push(u2::new(((v >> a) & 1) | (((v >> b) & 1) << 1)));
+        }
+        result
+    }
+
+    /// Returns the paths of all octree cells of the same depth in the column
+    /// of this path, ordered from the lowest to the highest along the up axis
+    pub fn column(&self, up: OctreeAxis) -> impl DoubleEndedIterator<Item = CellPath> + '_ {
+        let [a, b] = up.other_bits();
+        let depth = self.depth();
+        (0..(1u64 << depth)).map(move |height| {
+            let mut path = CellPath::new();
+            for (level, comp) in self.iter().enumerate() {
+                let v = comp.value();
+                let h = ((height >> (depth - level as u32 - 1)) & 1) as u8;
+                path.push(u3::new(
+                    ((v & 1) << a) | (((v >> 1) & 1) << b) | (h << up.bit())
+                ));
+            }
+            path
+        })
+    }
+}
+
+impl Default for QuadPath {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for QuadPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("QuadPath(1")?;
+        for comp in self.iter() {
+            write!(f, "_{:02b}", comp.value())?;
+        }
+        f.write_str(")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_pop() {
+        let mut path = QuadPath::new();
+        path.push(u2::new(0b01));
+        path.push(u2::new(0b10));
+        assert_eq!(path, QuadPath(0b1_01_10));
+        assert_eq!(path.len(), 2);
+        assert_eq!(path.get(0), Some(u2::new(0b01)));
+        assert_eq!(path.pop(), Some(u2::new(0b10)));
+        assert_eq!(path.pop(), Some(u2::new(0b01)));
+        assert_eq!(path.pop(), None);
+    }
+
+    #[test]
+    fn test_get_pos() {
+        assert_eq!(QuadPath::new().get_pos(), UVec2::ZERO);
+        assert_eq!(QuadPath(0b1_11).get_pos(), UVec2::new(1, 1));
+        assert_eq!(QuadPath(0b1_10_01).get_pos(), UVec2::new(1, 2));
+    }
+
+    #[test]
+    fn test_from_cell_path() {
+        let path = CellPath::new()
+            .with_push(u3::new(0b110))
+            .with_push(u3::new(0b011));
+        assert_eq!(QuadPath::from_cell_path(&path, OctreeAxis::Y), QuadPath(0b1_10_01));
+        assert_eq!(QuadPath::from_cell_path(&path, OctreeAxis::Z), QuadPath(0b1_10_11));
+        assert_eq!(QuadPath::from_cell_path(&path, OctreeAxis::X), QuadPath(0b1_11_01));
+    }
+
+    #[test]
+    fn test_column() {
+        let quad = QuadPath(0b1_10_01);
+        let column = quad.column(OctreeAxis::Y).collect::<Vec<_>>();
+        assert_eq!(column.len(), 4);
+        for (i, path) in column.iter().enumerate() {
+            assert_eq!(QuadPath::from_cell_path(path, OctreeAxis::Y), quad);
+            assert_eq!(path.get_pos().y, i as u32);
+        }
+    }
+}
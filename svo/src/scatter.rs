@@ -0,0 +1,240 @@
+//! Deterministic scattering of instances (vegetation, rocks...) on the
+//! surface of terrain svos
+//!
+//! Candidates come from a world space grid of [ScatterConfig::spacing]
+//! instead of the leaves, with a seed derived from the grid cell, so the same
+//! instances are found whatever the depth of the svo (up to the precision of
+//! its surface) and whatever chunk they are computed from.
+
+use bevy_math::DVec3;
+use utils::DAabb;
+
+use crate::{interpolation::{leaf_at, sample_trilinear}, Cell, CellPath, SvoPtr, TerrainCellData, TerrainCellKind};
+
+/// Newton iterations used to move candidates onto the surface
+const PROJECTION_ITERATIONS: u32 = 3;
+/// Candidates further than this fraction of the spacing from the surface
+/// after projection are discarded
+const SURFACE_TOLERANCE: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScatterConfig {
+    /// Size of the cells of the grid candidates are taken from, each cell
+    /// crossed by the surface gives about one candidate
+    pub spacing: f64,
+    pub seed: u64,
+}
+
+/// A point on the surface given to the density function of [scatter]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScatterCandidate {
+    pub position: DVec3,
+    pub normal: DVec3,
+    /// Kind of the solid terrain under the point
+    pub kind: TerrainCellKind,
+    /// Random seed of the candidate, kept by its [ScatterInstance]
+    pub seed: u64,
+}
+
+impl ScatterCandidate {
+    /// Angle (in radians) between the surface and the plane orthogonal to
+    /// the given up direction
+    pub fn slope(&self, up: DVec3) -> f64 {
+        self.normal.angle_between(up)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScatterInstance {
+    pub position: DVec3,
+    pub normal: DVec3,
+    /// Deterministic seed to randomize the instance (model, rotation...)
+    pub seed: u64,
+}
+
+/// Splitmix64 based generator seeded by a grid cell
+struct CellRng(u64);
+
+impl CellRng {
+    fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn new(seed: u64, cell: [i64; 3]) -> Self {
+        Self(cell.into_iter().fold(Self::mix(seed), |state, c| Self::mix(state ^ c as u64)))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        Self::mix(self.0)
+    }
+
+    /// In [0; 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn contains_half_open(aabb: DAabb, pos: DVec3) -> bool {
+    pos.cmpge(aabb.min()).all() && pos.cmplt(aabb.max()).all()
+}
+
+fn intersection(a: DAabb, b: DAabb) -> Option<DAabb> {
+    let min = a.min().max(b.min());
+    let max = a.max().min(b.max());
+    min.cmplt(max).all().then(|| DAabb::from_minmax(min, max))
+}
+
+/// Moves the point onto the surface, returning it with the surface normal
+fn project_on_surface<Ptr: SvoPtr<TerrainCellData>>(
+    root: &Cell<TerrainCellData, Ptr>,
+    root_aabb: DAabb,
+    point: DVec3,
+    spacing: f64,
+) -> Option<(DVec3, DVec3)> {
+    let mut pos = point;
+    for _ in 0..PROJECTION_ITERATIONS {
+        let sample = sample_trilinear(root, root_aabb, pos)?;
+        let gradient_length_2 = sample.gradient.length_squared();
+        if gradient_length_2 == 0. {
+            return None;
+        }
+        pos -= sample.gradient * sample.distance / gradient_length_2;
+        if pos.distance_squared(point) > spacing * spacing {
+            return None;
+        }
+    }
+
+    let sample = sample_trilinear(root, root_aabb, pos)?;
+    if sample.distance.abs() > spacing * SURFACE_TOLERANCE {
+        return None;
+    }
+    Some((pos, sample.gradient.try_normalize()?))
+}
+
+/// Scatters instances on the surface (where solid terrain is under air) in
+/// the given region of the svo, each candidate being kept with the
+/// probability returned by `density` (so from 0 to 1). Only the leaves of
+/// the region are visited, so calling this for each chunk gives the same
+/// instances as calling it once for the whole svo.
+///
+/// The number of candidates evaluated per leaf grows with the cube of
+/// leaf size / spacing, so regions whose leaves are much bigger than the
+/// spacing should be avoided.
+pub fn scatter<Ptr: SvoPtr<TerrainCellData>>(
+    root: &Cell<TerrainCellData, Ptr>,
+    root_aabb: DAabb,
+    region: &CellPath,
+    config: &ScatterConfig,
+    density: impl Fn(&ScatterCandidate) -> f64,
+) -> Vec<ScatterInstance> {
+    let spacing = config.spacing;
+    let region_aabb = region.get_aabb(root_aabb);
+    let (reached, cell) = root.follow_path(region);
+    let cell_aabb = reached.get_aabb(root_aabb);
+
+    let mut instances = vec![];
+    for item in cell.iter() {
+        let leaf_aabb = item.path.get_aabb(cell_aabb);
+        let Some(aabb) = intersection(leaf_aabb, region_aabb)
+        else { continue; };
+        // Candidates move at most by the spacing to reach the surface
+        if item.data.distance.to_f64().abs() > leaf_aabb.size.length() + spacing {
+            continue;
+        }
+
+        let min = (aabb.min() / spacing).floor().as_i64vec3();
+        let max = (aabb.max() / spacing).ceil().as_i64vec3();
+        for x in min.x..max.x {
+            for y in min.y..max.y {
+                for z in min.z..max.z {
+                    let grid_cell = [x, y, z];
+                    let mut rng = CellRng::new(config.seed, grid_cell);
+                    let jitter = DVec3::new(rng.next_f64(), rng.next_f64(), rng.next_f64());
+                    let grid_pos = DVec3::new(x as f64, y as f64, z as f64);
+                    let point = (grid_pos + jitter) * spacing;
+                    // Each grid cell is handled by the leaf its point is in
+                    if !contains_half_open(aabb, point) {
+                        continue;
+                    }
+
+                    let Some((position, normal)) = project_on_surface(
+                        root, root_aabb, point, spacing,
+                    )
+                    else { continue; };
+                    // Surfaces are only crossed once by the cell's point
+                    // when it stays in it, avoiding duplicates from cells
+                    // around the surface
+                    if (position / spacing).floor() != grid_pos {
+                        continue;
+                    }
+                    let Some((_, below)) = leaf_at(
+                        root, root_aabb, position - normal * spacing * SURFACE_TOLERANCE,
+                    )
+                    else { continue; };
+                    if below.kind.empty() {
+                        continue;
+                    }
+
+                    let candidate = ScatterCandidate {
+                        position,
+                        normal,
+                        kind: below.kind,
+                        seed: rng.next_u64(),
+                    };
+                    if rng.next_f64() < density(&candidate) {
+                        instances.push(ScatterInstance {
+                            position,
+                            normal,
+                            seed: candidate.seed,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    instances
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_math::UVec3;
+    use utils::{ApproxEq, Tolerance};
+
+    use super::*;
+    use crate::tests::{solid_inside, terrain};
+
+    #[test]
+    pub fn test_scatter() {
+        let root_aabb = DAabb::new_center_size(DVec3::splat(4.), DVec3::splat(8.));
+        // Ground plane at y = 3.3
+        let distance = |pos: UVec3| pos.y as f32 - 3.3;
+        let root = terrain(3, distance, |pos| solid_inside(distance(pos)));
+        let config = ScatterConfig { spacing: 1., seed: 42 };
+
+        let sorted = |mut instances: Vec<ScatterInstance>| {
+            instances.sort_by_key(|instance| instance.seed);
+            instances
+        };
+        let all = sorted(scatter(&root, root_aabb, &CellPath::new(), &config, |_| 1.));
+        // One instance per column of grid cells
+        assert_eq!(all.len(), 64);
+        for instance in &all {
+            assert!(instance.position.y.approx_eq(&3.3, Tolerance::Absolute(1e-2)), "{instance:?}");
+            assert!(instance.normal.abs_diff_eq(DVec3::Y, 1e-3));
+        }
+
+        // Same instances when scattering each chunk
+        let by_chunks = sorted(CellPath::new().children().into_iter()
+            .flat_map(|chunk| scatter(&root, root_aabb, &chunk, &config, |_| 1.))
+            .collect());
+        assert_eq!(all, by_chunks);
+
+        assert!(scatter(&root, root_aabb, &CellPath::new(), &config, |_| 0.).is_empty());
+        let other_seed = ScatterConfig { seed: 7, ..config };
+        assert_ne!(all, sorted(scatter(&root, root_aabb, &CellPath::new(), &other_seed, |_| 1.)));
+    }
+}
@@ -4,6 +4,8 @@ use utils::DAabb;
 
 use crate::{self as svo, CellPath, PackedCell};
 
+pub mod primitives;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct SdfSample {
     pub dist: f64,
@@ -16,6 +18,7 @@ impl SdfSample {
             kind: self.material,
             distance: f16::from_f64(self.dist),
             empty: self.material.empty(),
+            error: f16::ZERO,
         }
     }
 }
@@ -72,14 +75,17 @@ fn svo_inner<F, HG>(
     });
 
     if children.iter().all(|c| matches!(c, svo::Cell::Packed(..))) {
-        let packed_children = children.each_ref().map(|cell| match cell {
+        let packed_children = children.map(|cell| match cell {
             svo::Cell::Packed(p) => p,
             _ => unreachable!("checked before"),
         });
         let root = *packed_children[0].get(&CellPath::new()).into_inner();
-        if let Some(repacked) = PackedCell::new_repack(packed_children, root) {
-            return repacked.into();
-        }
+        return match PackedCell::from_children(packed_children, root) {
+            Ok(repacked) => repacked.into(),
+            Err(packed_children) => svo::InternalCell::from_children(
+                packed_children.map(svo::TerrainCell::from)
+            ).into(),
+        };
     }
 
     svo::InternalCell::from_children(children).into()
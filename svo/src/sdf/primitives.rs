@@ -0,0 +1,82 @@
+//! Signed distance functions generic over the float precision, so the same
+//! code can be used for f64 generation and f32 meshing
+
+use utils::{GlamFloat, Vec3Ext};
+
+pub fn sphere<T: GlamFloat>(p: T::Vec3, radius: T) -> T {
+    p.length() - radius
+}
+
+pub fn cuboid<T: GlamFloat>(p: T::Vec3, half_size: T::Vec3) -> T {
+    let q = p.abs() - half_size;
+    q.max(T::Vec3::zero()).length() + q.max_element().min(T::zero())
+}
+
+/// Plane going through the origin offsetted along its normal by `offset`
+pub fn plane<T: GlamFloat>(p: T::Vec3, normal: T::Vec3, offset: T) -> T {
+    p.dot(normal.normalize()) - offset
+}
+
+pub fn union<T: GlamFloat>(a: T, b: T) -> T {
+    a.min(b)
+}
+
+pub fn intersection<T: GlamFloat>(a: T, b: T) -> T {
+    a.max(b)
+}
+
+/// Removes b from a
+pub fn subtraction<T: GlamFloat>(a: T, b: T) -> T {
+    a.max(-b)
+}
+
+/// Like [union] but blends the two shapes over a distance of `k`
+pub fn smooth_union<T: GlamFloat>(a: T, b: T, k: T) -> T {
+    let half = T::new(0.5);
+    let h = num_traits::clamp(half + half * (b - a) / k, T::zero(), T::one());
+    b * (T::one() - h) + a * h - k * h * (T::one() - h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_math::{DVec3, Vec3};
+    use utils::{ApproxEq, Tolerance};
+
+    #[test]
+    fn test_precisions_agree() {
+        let points = [
+            DVec3::new(0., 0., 0.),
+            DVec3::new(1.5, -0.25, 3.),
+            DVec3::new(-2., 0.5, 0.75),
+        ];
+        for p in points {
+            let pf = p.as_vec3();
+
+            let d64 = sphere::<f64>(p, 1.);
+            let d32 = sphere(pf, 1f32);
+            assert!(d64.approx_eq(&(d32 as f64), Tolerance::Absolute(1e-5)));
+
+            let d64 = cuboid::<f64>(p, DVec3::new(1., 0.5, 2.));
+            let d32 = cuboid::<f32>(pf, Vec3::new(1., 0.5, 2.));
+            assert!(d64.approx_eq(&(d32 as f64), Tolerance::Absolute(1e-5)));
+        }
+    }
+
+    #[test]
+    fn test_cuboid() {
+        let half_size = DVec3::new(1., 2., 3.);
+        assert_eq!(cuboid::<f64>(DVec3::ZERO, half_size), -1.);
+        assert_eq!(cuboid::<f64>(DVec3::new(2., 0., 0.), half_size), 1.);
+        assert_eq!(cuboid::<f64>(DVec3::new(0., 2., 0.), half_size), 0.);
+    }
+
+    #[test]
+    fn test_combinators() {
+        assert_eq!(union(1., -2.), -2.);
+        assert_eq!(intersection(1., -2.), 1.);
+        assert_eq!(subtraction(-1., -2.), 2.);
+        assert!(smooth_union(1., 1., 0.5) < 1.);
+        assert_eq!(smooth_union(-10., 10., 0.5), -10.);
+    }
+}
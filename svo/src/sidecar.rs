@@ -0,0 +1,280 @@
+//! Payloads attached to the leaves of an svo but stored out of it, see
+//! [SidecarMap]
+
+use std::collections::HashMap;
+
+use super::*;
+
+/// Sparse map of payloads (e.g. entity references or damage values) attached
+/// to leaves of an svo by their path, so that gameplay data doesn't have to
+/// be stored in the data of every cell
+///
+/// Like cells behind [ArcPtr]s the map is copy-on-write: clones are cheap
+/// and only copy the payloads when modified.
+///
+/// The map does not own the svo so it must be kept in sync with its splits
+/// and merges, either with [Self::split] and [Self::merge_with] for each of
+/// them or with [Self::sync_with] after any number of them, so that the
+/// payloads follow the leaf they were attached to.
+pub struct SidecarMap<T> {
+    payloads: Arc<HashMap<CellPath, T>>,
+}
+
+impl<T> SidecarMap<T> {
+    pub fn new() -> Self {
+        Self {
+            payloads: Arc::new(HashMap::new()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.payloads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.payloads.is_empty()
+    }
+
+    pub fn get(&self, path: &CellPath) -> Option<&T> {
+        self.payloads.get(path)
+    }
+
+    pub fn contains(&self, path: &CellPath) -> bool {
+        self.payloads.contains_key(path)
+    }
+
+    /// All payloads in an arbitrary order
+    pub fn iter(&self) -> impl Iterator<Item = (&CellPath, &T)> {
+        self.payloads.iter()
+    }
+
+    /// Payloads of the cell at the given path and of all its descendants
+    pub fn iter_descendants<'a>(
+        &'a self, path: &'a CellPath,
+    ) -> impl Iterator<Item = (&'a CellPath, &'a T)> {
+        self.payloads.iter()
+            .filter(|(other, _)| path.is_prefix_of(other))
+    }
+
+    /// Whether the payloads are shared with a clone, and so will be copied
+    /// by the next modification
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.payloads) > 1
+    }
+
+    pub fn clear(&mut self) {
+        self.payloads = Arc::new(HashMap::new());
+    }
+}
+
+impl<T: Clone> SidecarMap<T> {
+    fn payloads_mut(&mut self) -> &mut HashMap<CellPath, T> {
+        Arc::make_mut(&mut self.payloads)
+    }
+
+    pub fn get_mut(&mut self, path: &CellPath) -> Option<&mut T> {
+        // Don't copy shared payloads for nothing
+        if !self.contains(path) {
+            return None;
+        }
+        self.payloads_mut().get_mut(path)
+    }
+
+    /// Returns the previous payload of the path if any
+    pub fn insert(&mut self, path: CellPath, payload: T) -> Option<T> {
+        self.payloads_mut().insert(path, payload)
+    }
+
+    pub fn remove(&mut self, path: &CellPath) -> Option<T> {
+        if !self.contains(path) {
+            return None;
+        }
+        self.payloads_mut().remove(path)
+    }
+
+    /// To be called when the leaf at the given path is split (e.g. with
+    /// [Cell::split]), its payload is copied to all its children
+    pub fn split(&mut self, path: &CellPath) {
+        let Some(payload) = self.remove(path)
+        else { return };
+        let payloads = self.payloads_mut();
+        for child in path.children() {
+            payloads.insert(child, payload.clone());
+        }
+    }
+
+    /// To be called when the cell at the given path is merged into a leaf
+    /// (e.g. with [Cell::try_merge]), `merge` is given the payloads of it
+    /// and all its descendants with their paths, which are removed, and
+    /// gives the payload of the new leaf
+    ///
+    /// `merge` is not called if there are no such payloads.
+    pub fn merge_with<F>(&mut self, path: &CellPath, merge: F)
+        where F: FnOnce(&CellPath, Vec<(CellPath, T)>) -> Option<T>
+    {
+        if self.iter_descendants(path).next().is_none() {
+            return;
+        }
+        let payloads = self.payloads_mut();
+        let paths = payloads.keys()
+            .filter(|other| path.is_prefix_of(other))
+            .cloned()
+            .collect::<Vec<_>>();
+        let merged = paths.into_iter()
+            .map(|other| {
+                let payload = payloads.remove(&other).expect("just found");
+                (other, payload)
+            })
+            .collect::<Vec<_>>();
+        if let Some(payload) = merge(path, merged) {
+            payloads.insert(path.clone(), payload);
+        }
+    }
+
+    /// Moves every payload that is no longer attached to a leaf of the given
+    /// svo (because of any number of splits and merges) to the leaves it now
+    /// belongs to, like [Self::split] and [Self::merge_with] would have
+    ///
+    /// `merge` is given the leaf and every payload that ends up in it with
+    /// its previous path, only when there is more than one or one from a
+    /// merged descendant, and gives the payload of the leaf.
+    pub fn sync_with<D, Ptr, F>(&mut self, root: &Cell<D, Ptr>, mut merge: F)
+        where D: Data,
+              Ptr: SvoPtr<D>,
+              F: FnMut(&CellPath, Vec<(CellPath, T)>) -> Option<T>,
+    {
+        let moved = self.payloads.keys()
+            .filter_map(|path| {
+                let leaves = leaves_of(root, path);
+                (leaves.len() != 1 || leaves[0] != *path).then(|| (path.clone(), leaves))
+            })
+            .collect::<Vec<_>>();
+        if moved.is_empty() {
+            return;
+        }
+
+        let payloads = self.payloads_mut();
+        let mut leaves = HashMap::<CellPath, Vec<(CellPath, T)>>::new();
+        for (path, targets) in moved {
+            let payload = payloads.remove(&path).expect("just found");
+            for leaf in targets {
+                leaves.entry(leaf).or_default().push((path.clone(), payload.clone()));
+            }
+        }
+
+        for (leaf, mut group) in leaves {
+            let existing = payloads.remove(&leaf);
+            // Only split from a parent, just like Self::split
+            if existing.is_none() && group.len() == 1 && group[0].0.is_prefix_of(&leaf) {
+                let (_, payload) = group.pop().expect("just checked");
+                payloads.insert(leaf, payload);
+                continue;
+            }
+            group.extend(existing.map(|payload| (leaf.clone(), payload)));
+            if let Some(payload) = merge(&leaf, group) {
+                payloads.insert(leaf, payload);
+            }
+        }
+    }
+}
+
+/// Paths of the leaves of the svo the cell at the given path is, is part of
+/// (if it was merged) or is split into
+fn leaves_of<D: Data, Ptr: SvoPtr<D>>(root: &Cell<D, Ptr>, path: &CellPath) -> Vec<CellPath> {
+    let (followed, cell) = root.follow_path(path);
+    let leaf_depth = followed.depth() + match cell {
+        Cell::Internal(_) => {
+            return path.children().iter()
+                .flat_map(|child| leaves_of(root, child))
+                .collect();
+        },
+        Cell::Leaf(_) => 0,
+        Cell::Packed(p) => p.depth(),
+    };
+
+    if leaf_depth <= path.depth() {
+        return vec![path.take(leaf_depth)];
+    }
+    let mut leaves = vec![path.clone()];
+    for _ in path.depth()..leaf_depth {
+        leaves = leaves.iter()
+            .flat_map(CellPath::children)
+            .collect();
+    }
+    leaves
+}
+
+impl<T> Default for SidecarMap<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Custom impl to not require T: Clone
+impl<T> Clone for SidecarMap<T> {
+    fn clone(&self) -> Self {
+        Self {
+            payloads: Arc::clone(&self.payloads),
+        }
+    }
+}
+
+impl<T: Debug> Debug for SidecarMap<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.payloads.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::mc;
+
+    #[test]
+    pub fn test_sidecar_map() {
+        let p = |comps: &[u8]| comps.iter()
+            .fold(CellPath::new(), |path, &comp| path.with_push(u3::new(comp)));
+
+        let mut cell = mc(0);
+        let mut map = SidecarMap::<i32>::new();
+        map.insert(CellPath::new(), 1);
+
+        // Splitting copies the payload to the children
+        cell.split();
+        map.split(&CellPath::new());
+        assert_eq!(map.len(), 8);
+        assert_eq!(map.get(&p(&[3])), Some(&1));
+
+        // Clones share the payloads until modified
+        let snapshot = map.clone();
+        assert!(map.is_shared());
+        *map.get_mut(&p(&[3])).unwrap() = 5;
+        assert!(!map.is_shared());
+        assert_eq!(snapshot.get(&p(&[3])), Some(&1));
+        assert_eq!(map.get(&p(&[3])), Some(&5));
+
+        // Splits of the svo alone are caught up with
+        cell.follow_path_mut(&p(&[3])).1.split();
+        map.sync_with(&cell, |_, _| panic!("nothing to merge"));
+        assert_eq!(map.len(), 15);
+        assert!(!map.contains(&p(&[3])));
+        assert_eq!(map.get(&p(&[3, 7])), Some(&5));
+        assert_eq!(map.iter_descendants(&p(&[3])).count(), 8);
+
+        map.merge_with(&p(&[3]), |path, payloads| {
+            assert_eq!(path, &p(&[3]));
+            Some(payloads.into_iter().map(|(_, payload)| payload).sum())
+        });
+        assert_eq!(map.len(), 8);
+        assert_eq!(map.get(&p(&[3])), Some(&40));
+
+        // And so are merges
+        map.sync_with(&mc(0), |path, payloads| {
+            assert!(path.is_empty());
+            Some(payloads.len() as i32)
+        });
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&CellPath::new()), Some(&8));
+        assert_eq!(snapshot.len(), 8);
+    }
+}
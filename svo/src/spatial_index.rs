@@ -0,0 +1,377 @@
+//! Octree of points (e.g. the bodies of an nbody simulation) whose internal
+//! cells hold an aggregate of their points (e.g. their total mass), see
+//! [SpatialIndex]
+//!
+//! The leaves are buckets of points that are split when they hold too many
+//! of them, the user only provides the point type and the aggregate with a
+//! [PointAggregate], so it works with any [SvoPtr] (arena allocated or not).
+
+use std::{borrow::Cow, cell::RefCell};
+
+use arbitrary_int::u3;
+use bevy_math::DVec3;
+use utils::DAabb;
+
+use super::*;
+
+/// A point stored in a [SpatialIndex]
+pub trait IndexedPoint: Debug + Clone {
+    fn position(&self) -> DVec3;
+}
+
+/// Summary of the points of a cell, e.g. their total mass and center of mass
+pub trait PointAggregate: Debug + Default + Clone {
+    type Point: IndexedPoint;
+
+    /// Leaves with more points are split by [SplittableData::should_auto_split]
+    const LEAF_MAX_POINTS: usize;
+    /// Cells whose children are all but one leaves with less points are
+    /// merged by [BorrowedMergeableData::should_auto_merge]
+    const LEAF_MIN_POINTS: usize;
+
+    /// Aggregate of the points of a leaf, never called with no points
+    fn from_points(points: &[Self::Point]) -> Self;
+    /// Aggregate of all the points of the given aggregates, the ones of
+    /// empty cells are not given
+    fn combine<'a>(parts: impl Iterator<Item = &'a Self>) -> Self
+        where Self: 'a;
+}
+
+/// Leaf data of a [SpatialIndex], a bucket of points
+#[derive(Debug, Default, Clone)]
+pub struct PointLeafData<A: PointAggregate> {
+    pub aabb: DAabb,
+    pub points: Vec<A::Point>,
+    /// How many more times the leaf can be split
+    pub remaining_allowed_depth: u8,
+}
+
+impl<A: PointAggregate> PointLeafData<A> {
+    /// Leaf of a new index, use [Cell::auto_split] (or
+    /// [Cell::par_auto_replace_with] for other pointers) to insert all the
+    /// points at once
+    pub fn new(aabb: DAabb, points: Vec<A::Point>, max_depth: u8) -> Self {
+        Self {
+            aabb,
+            points,
+            remaining_allowed_depth: max_depth,
+        }
+    }
+}
+
+/// Internal data of a [SpatialIndex]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PointInternalData<A: PointAggregate> {
+    pub aabb: DAabb,
+    /// Number of points in the cell
+    pub count: u32,
+    pub aggregate: A,
+}
+
+/// Octant of the aabb containing the given position, points on the middle
+/// planes go to the lower octants
+fn octant_of(aabb: DAabb, position: DVec3) -> u3 {
+    let middle = aabb.position + aabb.size / 2.;
+    let mut comp = 0b000u8;
+    if position.x > middle.x {
+        comp |= 0b001;
+    }
+    if position.y > middle.y {
+        comp |= 0b010;
+    }
+    if position.z > middle.z {
+        comp |= 0b100;
+    }
+    u3::new(comp)
+}
+
+fn overlaps(a: DAabb, b: DAabb) -> bool {
+    a.min().cmple(b.max()).all() && b.min().cmple(a.max()).all()
+}
+
+fn contains(aabb: DAabb, point: DVec3) -> bool {
+    aabb.min().cmple(point).all() && point.cmple(aabb.max()).all()
+}
+
+impl<A: PointAggregate> Data for PointLeafData<A> {
+    type Internal = PointInternalData<A>;
+}
+
+impl<A: PointAggregate> InternalData for PointInternalData<A> {  }
+
+impl<A: PointAggregate> AggregateData for PointLeafData<A> {
+    fn aggregate(children: [EitherDataRef<Self>; 8]) -> Self::Internal {
+        let mut count = 0u32;
+        let mut parts = Vec::<Cow<A>>::with_capacity(8);
+        for child in children.iter() {
+            match child {
+                Either::Left(internal) => {
+                    count += internal.count;
+                    if internal.count > 0 {
+                        parts.push(Cow::Borrowed(&internal.aggregate));
+                    }
+                },
+                Either::Right(leaf) => {
+                    count += u32::try_from(leaf.points.len()).expect("too much points!!");
+                    if !leaf.points.is_empty() {
+                        parts.push(Cow::Owned(A::from_points(&leaf.points)));
+                    }
+                },
+            }
+        }
+
+        let aabb = children.iter()
+            .map(|c| match c {
+                Either::Left(l) => l.aabb,
+                Either::Right(r) => r.aabb,
+            })
+            .reduce(|mut a, b| {a.expand_to_contain_aabb(b); a})
+            .expect("non empty");
+
+        PointInternalData {
+            aabb,
+            count,
+            aggregate: A::combine(parts.iter().map(|part| &**part)),
+        }
+    }
+}
+
+impl<A: PointAggregate> SplittableData for PointLeafData<A> {
+    fn should_auto_split(&self) -> bool {
+        self.remaining_allowed_depth > 0 &&
+        self.points.len() > A::LEAF_MAX_POINTS
+    }
+
+    fn split(self) -> (Self::Internal, [Self; 8]) {
+        std::thread_local! {
+            static TARGET_VEC: RefCell<Vec<u3>> = const { RefCell::new(Vec::new()) };
+        }
+
+        let mut children = CellPath::components().map(|comp| PointLeafData {
+            aabb: self.aabb.octant(comp),
+            remaining_allowed_depth: self.remaining_allowed_depth.saturating_sub(1),
+            points: vec![],
+        });
+
+        TARGET_VEC.with(|targets| {
+            let mut targets = targets.borrow_mut();
+            targets.clear();
+            targets.reserve(self.points.len());
+
+            let mut counts = [0usize; 8];
+            targets.extend(self.points.iter().map(|point| {
+                let comp = octant_of(self.aabb, point.position());
+                counts[comp.value() as usize] += 1;
+                comp
+            }));
+
+            for i in 0..8usize {
+                children[i].points.reserve_exact(counts[i]);
+            }
+
+            for (point, comp) in self.points.into_iter().zip(targets.iter()) {
+                children[comp.value() as usize].points.push(point);
+            }
+        });
+
+        let internal = Self::aggregate(
+            children.each_ref().map(Either::Right)
+        );
+
+        (internal, children)
+    }
+}
+
+impl<A: PointAggregate> BorrowedMergeableData for PointLeafData<A> {
+    fn should_auto_merge(
+        _this: &Self::Internal,
+        children: [&Self; 8]
+    ) -> bool {
+        let small_count = children.iter()
+            .filter(|data| data.points.len() < A::LEAF_MIN_POINTS)
+            .count();
+
+        // Should merge if all but one leaf is under the minimum
+        // so that if there is 100 in one leaf but only 5 in the other its not worth splitting
+        small_count >= 7
+    }
+
+    fn merge(
+        this: &Self::Internal,
+        children: [&Self; 8]
+    ) -> Self {
+        Self {
+            aabb: this.aabb,
+            remaining_allowed_depth: children.iter()
+                .map(|p| p.remaining_allowed_depth)
+                .max().unwrap_or_default() + 1,
+            points: children.into_iter()
+                .flat_map(|data| data.points.iter().cloned())
+                .collect(),
+        }
+    }
+}
+
+/// Minimal interface of a spatial index of points, implemented by svos of
+/// [PointLeafData] with any pointer type
+///
+/// Packed cells are not supported, they cannot hold the points.
+pub trait SpatialIndex {
+    type Point: IndexedPoint;
+    type Aggregate: PointAggregate<Point = Self::Point>;
+
+    /// Calls `f` with every point inside of the given region
+    fn query_region<'a>(&'a self, region: DAabb, f: &mut impl FnMut(&'a Self::Point));
+
+    /// Aggregate of all the points of the index
+    fn aggregate(&self) -> Self::Aggregate;
+
+    /// Number of points in the index
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A [SpatialIndex] that can grow one point at a time, only possible with
+/// pointers that can allocate their cells (see [OwnedSvoPtr])
+pub trait SpatialIndexInsert: SpatialIndex {
+    /// Adds a point, which should be inside the root aabb, splitting the
+    /// leaf it ends up in if it has too many points
+    fn insert(&mut self, point: Self::Point);
+}
+
+impl<A, Ptr> SpatialIndex for Cell<PointLeafData<A>, Ptr>
+    where A: PointAggregate,
+          Ptr: SvoPtr<PointLeafData<A>>,
+{
+    type Point = A::Point;
+    type Aggregate = A;
+
+    fn query_region<'a>(&'a self, region: DAabb, f: &mut impl FnMut(&'a A::Point)) {
+        match self {
+            Cell::Internal(internal) => {
+                if internal.data.count == 0 || !overlaps(internal.data.aabb, region) {
+                    return;
+                }
+                for child in internal.iter_children() {
+                    child.query_region(region, &mut *f);
+                }
+            },
+            Cell::Leaf(leaf) => {
+                leaf.data.points.iter()
+                    .filter(|point| contains(region, point.position()))
+                    .for_each(f);
+            },
+            Cell::Packed(_) => panic!("Packed cells cannot hold points"),
+        }
+    }
+
+    fn aggregate(&self) -> A {
+        match self {
+            Cell::Internal(internal) => internal.data.aggregate.clone(),
+            Cell::Leaf(leaf) if leaf.data.points.is_empty() => A::default(),
+            Cell::Leaf(leaf) => A::from_points(&leaf.data.points),
+            Cell::Packed(_) => panic!("Packed cells cannot hold points"),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Cell::Internal(internal) => internal.data.count as usize,
+            Cell::Leaf(leaf) => leaf.data.points.len(),
+            Cell::Packed(_) => panic!("Packed cells cannot hold points"),
+        }
+    }
+}
+
+impl<A, Ptr> SpatialIndexInsert for Cell<PointLeafData<A>, Ptr>
+    where A: PointAggregate,
+          Ptr: MutableSvoPtr<PointLeafData<A>> + OwnedSvoPtr<PointLeafData<A>>,
+{
+    fn insert(&mut self, point: A::Point) {
+        match self {
+            Cell::Internal(internal) => {
+                let comp = octant_of(internal.data.aabb, point.position());
+                internal.get_child_mut(comp).insert(point);
+                internal.shallow_update();
+            },
+            Cell::Leaf(leaf) => {
+                leaf.data.points.push(point);
+                self.auto_split(u32::MAX);
+            },
+            Cell::Packed(_) => panic!("Packed cells cannot hold points"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct MassPoint(DVec3, f64);
+
+    impl IndexedPoint for MassPoint {
+        fn position(&self) -> DVec3 {
+            self.0
+        }
+    }
+
+    #[derive(Debug, Default, Clone, Copy, PartialEq)]
+    struct TotalMass(f64);
+
+    impl PointAggregate for TotalMass {
+        type Point = MassPoint;
+
+        const LEAF_MAX_POINTS: usize = 4;
+        const LEAF_MIN_POINTS: usize = 2;
+
+        fn from_points(points: &[MassPoint]) -> Self {
+            TotalMass(points.iter().map(|point| point.1).sum())
+        }
+
+        fn combine<'a>(parts: impl Iterator<Item = &'a Self>) -> Self {
+            TotalMass(parts.map(|part| part.0).sum())
+        }
+    }
+
+    #[test]
+    pub fn test_spatial_index() {
+        let root_aabb = DAabb::from_minmax(DVec3::ZERO, DVec3::splat(8.));
+        let points = (0..64).map(|i| MassPoint(
+            DVec3::new((i % 4) as f64 * 2. + 0.5, ((i / 4) % 4) as f64 * 2. + 0.5, (i / 16) as f64 * 2. + 0.5),
+            1.,
+        )).collect::<Vec<_>>();
+
+        type Index = Cell<PointLeafData<TotalMass>, BoxPtr<PointLeafData<TotalMass>>>;
+
+        let mut bulk = Index::from(PointLeafData::new(root_aabb, points.clone(), 3));
+        bulk.auto_split(u32::MAX);
+        let mut inserted = Index::from(PointLeafData::new(root_aabb, vec![], 3));
+        for &point in &points {
+            inserted.insert(point);
+        }
+
+        for index in [&bulk, &inserted] {
+            assert_eq!(index.len(), 64);
+            assert_eq!(index.aggregate(), TotalMass(64.));
+            assert!(index.depth() > 0);
+
+            let region = DAabb::from_minmax(DVec3::ZERO, DVec3::splat(4.));
+            let mut found = Vec::new();
+            index.query_region(region, &mut |point| found.push(*point));
+            assert_eq!(found.len(), 8);
+            assert!(found.iter().all(|point| point.0.max_element() < 4.));
+        }
+
+        // Leaves never have more points than allowed if they can be split
+        for item in bulk.iter() {
+            assert!(item.data.points.len() <= TotalMass::LEAF_MAX_POINTS);
+        }
+
+        inserted.auto_merge_borrow();
+        assert_eq!(inserted.aggregate(), TotalMass(64.));
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::*;
+
+/// Whether the counters of [stats] are counted, which is only the case with
+/// the `stats` feature so they cost nothing otherwise
+pub const STATS_ENABLED: bool = cfg!(feature = "stats");
+
+/// Global counters of the hot paths of all svos since the start of the
+/// program (or the last [reset_stats]), e.g. to find out if slow traversals
+/// come from copy on write storms, see [STATS_ENABLED]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SvoStats {
+    /// Children accessed with [InternalCell::get_child] or
+    /// [InternalCell::get_child_mut]
+    pub node_visits: u64,
+    /// Clones of [ArcPtr]s
+    pub arc_clones: u64,
+    /// Calls to [MutableSvoPtr::make_mut] on shared [ArcPtr]s, which copy
+    /// the cell
+    pub make_mut_copies: u64,
+    /// Leaves looked up with [leaf_at], 8 per [sample_trilinear]
+    pub sampler_lookups: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum StatCounter {
+    NodeVisits,
+    ArcClones,
+    MakeMutCopies,
+    SamplerLookups,
+}
+
+static COUNTERS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+/// Increments the counter if [STATS_ENABLED], does nothing otherwise
+#[inline(always)]
+pub(crate) fn count_stat(counter: StatCounter) {
+    if STATS_ENABLED {
+        COUNTERS[counter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Current value of the counters, all zero without the `stats` feature
+pub fn stats() -> SvoStats {
+    let load = |counter: StatCounter| COUNTERS[counter as usize].load(Ordering::Relaxed);
+    SvoStats {
+        node_visits: load(StatCounter::NodeVisits),
+        arc_clones: load(StatCounter::ArcClones),
+        make_mut_copies: load(StatCounter::MakeMutCopies),
+        sampler_lookups: load(StatCounter::SamplerLookups),
+    }
+}
+
+/// Sets all counters back to zero
+pub fn reset_stats() {
+    for counter in &COUNTERS {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{mc, SumData};
+
+    #[test]
+    pub fn test_stats() {
+        let before = stats();
+        let root: Cell<SumData> = InternalCell::new_full(SumData(0), mc(1)).into();
+        let mut ptr = ArcPtr::new(root);
+        let shared = ptr.clone();
+        // Copies the shared root
+        ptr.make_mut();
+        assert_eq!(ptr.get_path(CellPath::new().with_push(u3::new(0))).into_inner().0, 1);
+        drop(shared);
+
+        // Other tests may run at the same time
+        let after = stats();
+        if STATS_ENABLED {
+            assert!(after.arc_clones > before.arc_clones);
+            assert!(after.make_mut_copies > before.make_mut_copies);
+            assert!(after.node_visits > before.node_visits);
+        }
+        else {
+            assert_eq!(after, SvoStats::default());
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Iteration over the cells of an svo along with the data of their
+//! neighbors, for cellular automata style updates (water flow, erosion...)
+
+use super::*;
+
+/// Offsets of all neighbors, the ones sharing a face first, then the ones
+/// sharing an edge and then the corners
+const NEIGHBOR_OFFSETS: [(i8, i8, i8); 26] = [
+    (-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1),
+
+    (-1, -1, 0), (-1, 1, 0), (1, -1, 0), (1, 1, 0),
+    (-1, 0, -1), (-1, 0, 1), (1, 0, -1), (1, 0, 1),
+    (0, -1, -1), (0, -1, 1), (0, 1, -1), (0, 1, 1),
+
+    (-1, -1, -1), (-1, -1, 1), (-1, 1, -1), (-1, 1, 1),
+    (1, -1, -1), (1, -1, 1), (1, 1, -1), (1, 1, 1),
+];
+
+/// Which cells are the neighbors of a [Stencil]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StencilShape {
+    /// The 6 cells sharing a face
+    Faces,
+    /// The 26 cells sharing a face, an edge or a corner
+    Full,
+}
+
+impl StencilShape {
+    pub fn neighbor_count(self) -> usize {
+        match self {
+            Self::Faces => 6,
+            Self::Full => 26,
+        }
+    }
+
+    /// Offsets of the neighbors, in the order of [Stencil::neighbors]
+    pub fn offsets(self) -> &'static [(i8, i8, i8)] {
+        &NEIGHBOR_OFFSETS[..self.neighbor_count()]
+    }
+}
+
+/// A cell along with the data of its neighbors of the same depth, see
+/// [Cell::stencils]
+///
+/// Neighbors that are part of a bigger leaf give the data of that leaf, and
+/// the ones that are split further give their internal (aggregated) data.
+#[derive(Debug, Clone)]
+pub struct Stencil<'a, D: Data> {
+    pub path: CellPath,
+    pub data: Either<&'a D::Internal, &'a D>,
+    shape: StencilShape,
+    neighbors: [Option<Either<&'a D::Internal, &'a D>>; 26],
+}
+
+impl<'a, D: Data> Stencil<'a, D> {
+    pub fn shape(&self) -> StencilShape {
+        self.shape
+    }
+
+    /// Data of the neighbors along with their offsets, None for the ones
+    /// outside of the root cell
+    pub fn neighbors(
+        &self,
+    ) -> impl ExactSizeIterator<Item = ((i8, i8, i8), Option<EitherDataRef<'a, D>>)> + '_ {
+        self.shape.offsets().iter()
+            .copied()
+            .zip(self.neighbors.iter().copied())
+    }
+
+    /// Data of the neighbor with the given offset (in -1..=1), None if it is
+    /// outside of the root cell or not part of the stencil's shape
+    pub fn neighbor(&self, dx: i8, dy: i8, dz: i8) -> Option<EitherDataRef<'a, D>> {
+        let index = self.shape.offsets().iter()
+            .position(|&offset| offset == (dx, dy, dz))?;
+        self.neighbors[index]
+    }
+}
+
+impl<D: Data, Ptr: SvoPtr<D>> Cell<D, Ptr> {
+    /// Calls `f` with the path and data of every cell at the given depth, and
+    /// of the leaves that are not as deep, in depth first order
+    ///
+    /// Cells at the given depth that are split further give their internal
+    /// data.
+    pub fn for_each_at_depth<'a>(
+        &'a self, depth: u32, f: &mut impl FnMut(CellPath, EitherDataRef<'a, D>),
+    ) {
+        fn visit<'a, D: Data, Ptr: SvoPtr<D>>(
+            cell: &'a Cell<D, Ptr>,
+            path: CellPath,
+            depth: u32,
+            f: &mut impl FnMut(CellPath, EitherDataRef<'a, D>),
+        ) {
+            match cell {
+                _ if path.depth() >= depth => f(path, cell.data()),
+                Cell::Leaf(leaf) => f(path, Either::Right(&leaf.data)),
+                Cell::Internal(internal) => {
+                    for comp in CellPath::components() {
+                        visit(internal.get_child(comp), path.clone().with_push(comp), depth, f);
+                    }
+                },
+                Cell::Packed(packed) => {
+                    let packed_depth = (depth - path.depth()).min(packed.depth());
+                    for (_, sub_path) in PackedIndexIterator::new(packed_depth) {
+                        f(path.clone().extended(&sub_path), packed.get(&sub_path));
+                    }
+                },
+            }
+        }
+
+        visit(self, CellPath::new(), depth, f);
+    }
+
+    /// All cells given by [Self::for_each_at_depth] along with the data of
+    /// their neighbors of the same depth
+    ///
+    /// Meant for simulations that compute the next state of each cell from
+    /// its neighbors, e.g. into a new svo built with
+    /// [Self::build_with].
+    pub fn stencils(&self, depth: u32, shape: StencilShape) -> impl Iterator<Item = Stencil<'_, D>> {
+        let mut cells = Vec::new();
+        self.for_each_at_depth(depth, &mut |path, _| cells.push(path));
+
+        cells.into_iter().map(move |path| self.stencil(path, shape))
+    }
+
+    /// The stencil of a single cell, its data and the one of its neighbors
+    /// are the ones given by [Self::get_path]
+    ///
+    /// Unlike [Self::stencils] the path can be deeper than the svo, e.g. to
+    /// build a deeper svo with [Self::build_with].
+    pub fn stencil(&self, path: CellPath, shape: StencilShape) -> Stencil<'_, D> {
+        let mut neighbors = [None; 26];
+        for (neighbor, &(dx, dy, dz)) in neighbors.iter_mut().zip(shape.offsets()) {
+            *neighbor = path.neighbor(dx, dy, dz)
+                .map(|neighbor_path| self.get_path(neighbor_path));
+        }
+        let data = self.get_path(path.clone());
+        Stencil { path, data, shape, neighbors }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{mc, SumData};
+
+    #[test]
+    pub fn test_stencils() {
+        // Leaves hold their x position, the last octant is split once more
+        let leaf = |path: CellPath| SumData(path.get_pos().x as i32);
+        let mut cell = Cell::<SumData>::build_with(2, leaf);
+        let deep = CellPath::from_pos(2, UVec3::new(3, 3, 3)).unwrap();
+        *cell.follow_internal_path(&deep) = InternalCell::from_children(
+            CellPath::components().map(|_| mc(1))
+        ).into();
+        cell.update_all();
+
+        let stencils = cell.stencils(2, StencilShape::Faces).collect_vec();
+        assert_eq!(stencils.len(), 64);
+        for stencil in &stencils {
+            let pos = stencil.path.get_pos();
+            assert_eq!(stencil.neighbors().len(), 6);
+            assert_eq!(stencil.neighbor(-1, 0, 0).is_none(), pos.x == 0);
+            assert_eq!(stencil.neighbor(0, 0, 1).is_none(), pos.z == 3);
+            assert!(stencil.neighbor(1, 1, 0).is_none(), "Not a face neighbor");
+        }
+
+        // The split cell gives its aggregated data
+        let next_to_deep = CellPath::from_pos(2, UVec3::new(2, 3, 3)).unwrap();
+        let stencil = stencils.iter()
+            .find(|stencil| stencil.path == next_to_deep)
+            .unwrap();
+        assert_eq!(stencil.data.into_inner().0, 2);
+        assert_eq!(stencil.neighbor(1, 0, 0).unwrap().into_inner().0, 8);
+        assert!(stencil.neighbor(1, 0, 0).unwrap().is_left());
+
+        // Shallower leaves are given once, with neighbors of their depth
+        let stencils = cell.stencils(3, StencilShape::Full).collect_vec();
+        assert_eq!(stencils.len(), 63 + 8);
+        let stencil = stencils.iter()
+            .find(|stencil| stencil.path == next_to_deep)
+            .unwrap();
+        assert_eq!(stencil.neighbors().len(), 26);
+        assert_eq!(stencil.neighbors().filter(|(_, data)| data.is_some()).count(), 11);
+    }
+}
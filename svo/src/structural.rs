@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use num_traits::PrimInt;
+
+use super::*;
+
+/// Data that can be hashed by [StructuralHasher], separate from [Hash] as
+/// it is also implemented for float based data (like [TerrainCellData]'s
+/// distances) by hashing their bits
+pub trait StructuralHash {
+    fn structural_hash<H: Hasher>(&self, state: &mut H);
+}
+
+macro_rules! impl_structural_hash_with_hash {
+    ($($t:ty),*$(,)?) => {
+        $(impl StructuralHash for $t {
+            fn structural_hash<H: Hasher>(&self, state: &mut H) {
+                self.hash(state);
+            }
+        })*
+    };
+}
+
+impl_structural_hash_with_hash!(
+    (), bool, u8, u16, u32, u64, i8, i16, i32, i64, TerrainCellKind,
+);
+
+impl StructuralHash for StatBool {
+    fn structural_hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl StructuralHash for InnerStatBool {
+    fn structural_hash<H: Hasher>(&self, state: &mut H) {
+        self.any.hash(state);
+        self.all.hash(state);
+    }
+}
+
+impl<T: Default + Debug + PrimInt + Hash> StructuralHash for StatInt<T> {
+    fn structural_hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl<T: Hash> StructuralHash for InnerStatInt<T> {
+    fn structural_hash<H: Hasher>(&self, state: &mut H) {
+        self.min.hash(state);
+        self.max.hash(state);
+        self.average.hash(state);
+    }
+}
+
+impl StructuralHash for TerrainCellData {
+    fn structural_hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+        self.distance.to_bits().hash(state);
+        self.empty.hash(state);
+    }
+}
+
+/// Two cells are equal if they have the same structure and data, subtrees
+/// shared by both (like cloned [ArcPtr]s) are not visited
+///
+/// A [Cell::Packed] is never equal to an equivalent [Cell::Internal] tree.
+impl<D, Ptr> PartialEq for Cell<D, Ptr>
+    where D: Data + PartialEq,
+          D::Internal: PartialEq,
+          Ptr: SvoPtr<D>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        if std::ptr::eq(self, other) {
+            return true;
+        }
+
+        match (self, other) {
+            (Cell::Internal(a), Cell::Internal(b)) => {
+                a.data == b.data && a.children.iter().zip(&b.children)
+                    .all(|(a, b)| **a == **b)
+            },
+            (Cell::Leaf(a), Cell::Leaf(b)) => a.data == b.data,
+            (Cell::Packed(a), Cell::Packed(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Computes hashes of svos from their structure and data, see
+/// [Cell::structural_hash]
+///
+/// Hashes are cached by cell address like [MemoryUsageCounter] does, so
+/// cells shared between svos are only hashed once. This means the hasher
+/// must not outlive the svos it hashed, or be used after they are modified.
+#[derive(Debug, Default)]
+pub struct StructuralHasher {
+    cache: HashMap<usize, u64>,
+}
+
+impl StructuralHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn hash<D, Ptr>(&mut self, cell: &Cell<D, Ptr>) -> u64
+        where D: Data + StructuralHash,
+              D::Internal: StructuralHash,
+              Ptr: SvoPtr<D>,
+    {
+        let address = cell as *const Cell<D, Ptr> as usize;
+        if let Some(&hash) = self.cache.get(&address) {
+            return hash;
+        }
+
+        // The default hasher's keys are fixed so hashes are deterministic
+        let mut state = DefaultHasher::new();
+        match cell {
+            Cell::Internal(internal) => {
+                0u8.hash(&mut state);
+                internal.data.structural_hash(&mut state);
+                for child in &internal.children {
+                    self.hash(&**child).hash(&mut state);
+                }
+            },
+            Cell::Leaf(leaf) => {
+                1u8.hash(&mut state);
+                leaf.data.structural_hash(&mut state);
+            },
+            Cell::Packed(packed) => {
+                2u8.hash(&mut state);
+                packed.structural_hash(&mut state);
+            },
+        }
+        let hash = state.finish();
+
+        self.cache.insert(address, hash);
+        hash
+    }
+}
+
+impl<D: Data, Ptr: SvoPtr<D>> Cell<D, Ptr> {
+    /// Hash of the structure and data of this cell and its descendants, equal
+    /// cells (see [Cell]'s [PartialEq] impl) always have the same hash
+    pub fn structural_hash(&self) -> u64
+        where D: StructuralHash,
+              D::Internal: StructuralHash,
+    {
+        StructuralHasher::new().hash(self)
+    }
+}
+
+impl<D, Ptr> Cell<D, Ptr>
+    where D: SplittableData + MergeableData,
+          Ptr: MutableSvoPtr<D> + OwnedSvoPtr<D>,
+{
+    /// Rewrites the tree into a canonical form that only depends on its
+    /// leaves, so that trees built with splits and merges in different
+    /// orders are equal and have the same [structural hash](Self::structural_hash)
+    ///
+    /// Everything that can be merged (see [Self::try_merge]) is, then the
+    /// biggest subtrees whose leaves are all at the same depth, at least
+    /// `min_packed_depth` (and 1) levels below them, are packed and every
+    /// other packed cell is unpacked.
+    ///
+    /// Internal data is kept as is, [update](Self::update_all) it before if
+    /// it may be out of date.
+    pub fn normalize(&mut self, min_packed_depth: u32) {
+        let min_packed_depth = min_packed_depth.max(1);
+
+        utils::replace_with(self, |this| match this {
+            Cell::Packed(packed) => match packed.try_into_leaf() {
+                Ok(leaf) => leaf.into(),
+                Err(packed) => {
+                    let (data, children) = packed.split();
+                    InternalCell::<D, Ptr> {
+                        children: children.map(|child| Ptr::new(child.into())),
+                        data,
+                    }.into()
+                },
+            },
+            this => this,
+        });
+
+        self.iter_children_mut()
+            .for_each(|child| child.normalize(min_packed_depth));
+        self.try_merge();
+
+        let should_pack = matches!(self, Cell::Internal(_)) &&
+            PackedCell::packed_depth(self).is_some_and(|depth| depth >= min_packed_depth);
+        if should_pack {
+            utils::replace_with(self, |this| {
+                PackedCell::from_cell(this)
+                    .map_or_else(|this| this, Cell::Packed)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_structural_eq() {
+        let leaf = |value: bool| -> Cell<StatBool> { LeafCell::new(StatBool(value)).into() };
+        let tree = |values: [bool; 8]| -> Cell<StatBool> {
+            InternalCell::from_children(values.map(leaf)).into()
+        };
+        let values = [true, false, false, true, true, true, false, false];
+
+        let a = tree(values);
+        let b = tree(values);
+        assert!(a == b);
+        assert_eq!(a.structural_hash(), b.structural_hash());
+
+        let mut other_values = values;
+        other_values[3] = false;
+        let c = tree(other_values);
+        assert!(a != c);
+        assert_ne!(a.structural_hash(), c.structural_hash());
+        assert!(a != leaf(true));
+
+        // Shared subtrees are hashed once
+        let shared = ArcPtr::from(a.clone());
+        let parent: Cell<StatBool> = InternalCell::new_full(
+            InnerStatBool::default(), shared,
+        ).into();
+        let mut hasher = StructuralHasher::new();
+        let parent_hash = hasher.hash(&parent);
+        assert_eq!(hasher.hash(&a), a.structural_hash());
+        assert_eq!(parent_hash, parent.structural_hash());
+
+        let packed = PackedCell::new_filled(1, InnerStatBool::default(), StatBool(true));
+        let mut packed2 = packed.clone();
+        assert!(Cell::<StatBool>::from(packed.clone()) == Cell::from(packed2.clone()));
+        *packed2.leaf_level_mut().raw_array_mut().last_mut().unwrap() = StatBool(false);
+        assert!(packed != packed2);
+    }
+}
@@ -0,0 +1,48 @@
+//! Typed subdivision counts, so that the ones relative to the root of an svo
+//! (its depth) and the ones relative to one of its cells (e.g. a chunk) are
+//! not mixed up
+
+use bevy_math::DVec3;
+
+use super::*;
+
+/// Subdivisions relative to the root of an svo, so the depth of the cells
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AbsoluteSubdivs(pub u32);
+
+/// Subdivisions relative to a cell, so the depth of its descendants minus
+/// its own
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RelativeSubdivs(pub u32);
+
+impl AbsoluteSubdivs {
+    /// Subdivs relative to the cell at the given path, 0 if it is deeper
+    pub fn relative_to(self, path: &CellPath) -> RelativeSubdivs {
+        RelativeSubdivs(self.0.saturating_sub(path.depth()))
+    }
+
+    /// Like [Self::relative_to] but None if the cell is deeper
+    pub fn checked_relative_to(self, path: &CellPath) -> Option<RelativeSubdivs> {
+        self.0.checked_sub(path.depth()).map(RelativeSubdivs)
+    }
+
+    pub fn saturating_sub(self, subdivs: u32) -> Self {
+        Self(self.0.saturating_sub(subdivs))
+    }
+}
+
+impl RelativeSubdivs {
+    /// Subdivs relative to the root of the svo of the cell at the given path
+    pub fn absolute_from(self, path: &CellPath) -> AbsoluteSubdivs {
+        AbsoluteSubdivs(path.depth() + self.0)
+    }
+
+    pub fn saturating_sub(self, subdivs: u32) -> Self {
+        Self(self.0.saturating_sub(subdivs))
+    }
+
+    /// Size of the descendants of a cell of the given size
+    pub fn cell_size(self, size: DVec3) -> DVec3 {
+        size / 2f64.powi(self.0 as i32)
+    }
+}
@@ -1,9 +1,11 @@
+use bevy_math::{DMat3, DVec3};
 use bevy_render::color::Color;
 use half::f16;
+use utils::{DAabb, IsZeroApprox};
 
 use super::*;
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u8)]
 pub enum TerrainCellKind {
     #[default]
@@ -30,6 +32,18 @@ impl TerrainCellKind {
     pub fn empty(&self) -> bool {
         matches!(self, TerrainCellKind::Invalid | TerrainCellKind::Air)
     }
+
+    /// Density relative to water, 0 for empty kinds
+    pub fn density(&self) -> f64 {
+        match self {
+            TerrainCellKind::Invalid => 0.,
+            TerrainCellKind::Air => 0.,
+            TerrainCellKind::StoneDarker => 3.,
+            TerrainCellKind::Stone => 2.7,
+            TerrainCellKind::Pink => 1.5,
+            TerrainCellKind::Blue => 1.,
+        }
+    }
 }
 
 impl From<TerrainCellKind> for TerrainLeafCell {
@@ -38,6 +52,7 @@ impl From<TerrainCellKind> for TerrainLeafCell {
             kind: val,
             distance: f16::ZERO,
             empty: val.empty(),
+            error: f16::ZERO,
         })
     }
 }
@@ -56,6 +71,10 @@ pub struct TerrainCellData {
     pub distance: f16,
     /// Wether any children or self has any non-air terrain
     pub empty: bool,
+    /// Biggest change of the distances when the cell's descendants are
+    /// replaced by it, see [Self::simplification_error], 0 for leaves that
+    /// are not the result of a merge
+    pub error: f16,
 }
 
 impl TerrainCellData {
@@ -77,6 +96,32 @@ impl TerrainCellData {
 
         (sum / count).sqrt()
     }
+
+    /// Error of replacing the given children by their parent: the biggest
+    /// distance between their distances and the plane fitted to them (as
+    /// marching cubes interpolates linearly between samples) plus their own
+    /// error
+    ///
+    /// So smooth and flat terrain has a small error, even on slopes, unlike
+    /// rough terrain.
+    pub fn simplification_error(d: [&Self; 8]) -> f32 {
+        let distances = d.map(|c| c.distance.to_f32());
+        let mean = distances.iter().sum::<f32>() / 8.;
+
+        // Samples are the corners of a cube, for which the least squares fit
+        // has a closed form
+        let offset = |i: usize, axis: usize| if i & (1 << axis) != 0 { 0.5 } else { -0.5 };
+        let gradient: [f32; 3] = std::array::from_fn(|axis| (0..8)
+            .map(|i| offset(i, axis) * (distances[i] - mean))
+            .sum::<f32>() / 2.);
+
+        (0..8).map(|i| {
+            let plane = mean + (0..3)
+                .map(|axis| offset(i, axis) * gradient[axis])
+                .sum::<f32>();
+            (distances[i] - plane).abs() + d[i].error.to_f32()
+        }).fold(0., f32::max)
+    }
 }
 
 impl Data for TerrainCellData {
@@ -93,9 +138,11 @@ impl SplittableData for TerrainCellData {
 
 impl AggregateData for TerrainCellData {
     fn aggregate<'a>(d: [EitherDataRef<Self>; 8]) -> Self {
+        let d = d.map(Either::into_inner);
         Self {
             empty: d.iter().all(|d| d.empty),
-            ..*d[0].into_inner()   
+            error: f16::from_f32(Self::simplification_error(d)),
+            ..*d[0]
         }
     }
 }
@@ -121,3 +168,358 @@ pub type TerrainCell = Cell<TerrainCellData>;
 pub type TerrainInternalCell = InternalCell<TerrainCellData>;
 pub type TerrainLeafCell = LeafCell<TerrainCellData>;
 pub type TerrainPackedCell = PackedCell<TerrainCellData>;
+
+/// Volume and mass of the terrain in a region, see [Cell::integrate]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TerrainIntegral {
+    /// Volume of the non-empty cells
+    pub solid_volume: f64,
+    /// Sum of the volumes weighted by [TerrainCellKind::density]
+    pub mass: f64,
+    /// Sum of the positions weighted by mass, see [Self::center_of_mass]
+    pub weighted_position: DVec3,
+}
+
+impl TerrainIntegral {
+    pub fn center_of_mass(&self) -> Option<DVec3> {
+        (self.mass != 0.).then(|| self.weighted_position / self.mass)
+    }
+
+    fn add(&mut self, data: &TerrainCellData, aabb: DAabb) {
+        if data.kind.empty() {
+            return;
+        }
+        let volume = aabb.size.x * aabb.size.y * aabb.size.z;
+        let mass = volume * data.kind.density();
+        self.solid_volume += volume;
+        self.mass += mass;
+        self.weighted_position += (aabb.position + aabb.size / 2.) * mass;
+    }
+}
+
+/// Part of the aabb inside the region, None if they do not intersect
+fn aabb_intersection(aabb: DAabb, region: DAabb) -> Option<DAabb> {
+    let min = aabb.min().max(region.min());
+    let max = aabb.max().min(region.max());
+    (min.cmplt(max).all()).then(|| DAabb::from_minmax(min, max))
+}
+
+impl<Ptr: SvoPtr<TerrainCellData>> Cell<TerrainCellData, Ptr> {
+    /// Integrates the terrain over the given region (e.g. the submerged part
+    /// of a body for buoyancy, or the whole svo for the planet's mass).
+    ///
+    /// Leaves are considered uniformly filled, so leaves partially inside the
+    /// region only count for their part inside it.
+    pub fn integrate(&self, root_aabb: DAabb, region: DAabb) -> TerrainIntegral {
+        fn rec<Ptr: SvoPtr<TerrainCellData>>(
+            cell: &Cell<TerrainCellData, Ptr>,
+            aabb: DAabb,
+            region: DAabb,
+            out: &mut TerrainIntegral,
+        ) {
+            if aabb_intersection(aabb, region).is_none() {
+                return;
+            }
+            match cell {
+                Cell::Internal(internal) => {
+                    // Nothing to find in empty cells
+                    if internal.data.empty {
+                        return;
+                    }
+                    for comp in CellPath::components() {
+                        rec(&**internal.get_child(comp), aabb.octant(comp), region, out);
+                    }
+                },
+                Cell::Leaf(leaf) => {
+                    if let Some(inter) = aabb_intersection(aabb, region) {
+                        out.add(&leaf.data, inter);
+                    }
+                },
+                Cell::Packed(_) => {
+                    for item in cell.iter() {
+                        let item_aabb = item.path.get_aabb(aabb);
+                        if let Some(inter) = aabb_intersection(item_aabb, region) {
+                            out.add(item.data, inter);
+                        }
+                    }
+                },
+            }
+        }
+
+        let mut out = TerrainIntegral::default();
+        rec(self, root_aabb, region, &mut out);
+        out
+    }
+
+    /// Errors of meshing the cell at the given path with 0 to `subdivs`
+    /// subdivs: the i-th one is the biggest [TerrainCellData::error] of its
+    /// descendants i levels below it, so the most the distances used by
+    /// marching cubes can be off compared to the finest data of the svo
+    ///
+    /// Never increases with the subdivs. If the path goes into a packed cell
+    /// the errors of the whole packed cell are used, which is conservative.
+    pub fn simplification_errors(&self, path: &CellPath, subdivs: u32) -> Vec<f32> {
+        fn rec<Ptr: SvoPtr<TerrainCellData>>(
+            cell: &Cell<TerrainCellData, Ptr>,
+            errors: &mut [f32],
+        ) {
+            let Some((first, rest)) = errors.split_first_mut()
+            else { return; };
+            match cell {
+                Cell::Internal(internal) => {
+                    *first = first.max(internal.data.error.to_f32());
+                    for child in internal.iter_children() {
+                        rec(&**child, &mut *rest);
+                    }
+                },
+                // Leaves are used as is at all depths
+                Cell::Leaf(leaf) => {
+                    let error = leaf.data.error.to_f32();
+                    errors.iter_mut().for_each(|e| *e = e.max(error));
+                },
+                Cell::Packed(packed) => {
+                    let max_error = |level: &[TerrainCellData]| level.iter()
+                        .map(|data| data.error.to_f32())
+                        .fold(0f32, f32::max);
+                    for (depth, e) in errors.iter_mut().enumerate() {
+                        let depth = depth as u32;
+                        let level = if depth < packed.depth() {
+                            packed.internal_level(depth).raw_array()
+                        } else {
+                            packed.leaf_level().raw_array()
+                        };
+                        *e = e.max(max_error(level));
+                    }
+                },
+            }
+        }
+
+        let mut errors = vec![0f32; subdivs as usize + 1];
+        let (_, cell) = self.follow_path(path);
+        rec(cell, &mut errors);
+        errors
+    }
+}
+
+/// Configures how [Cell::merge_terrain] replaces subtrees by a single leaf
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TerrainMergePolicy {
+    /// Cells are merged if all leaves have the same kind and their distances
+    /// are at most this far from the least squares plane fitted to them
+    pub tolerance: f64,
+}
+
+impl Default for TerrainMergePolicy {
+    fn default() -> Self {
+        Self { tolerance: 0.5 }
+    }
+}
+
+impl TerrainMergePolicy {
+    /// Returns the data of the leaf replacing the given leaves, or None if
+    /// they cannot be merged
+    ///
+    /// Positions are those of the samples (the min corner of the leaves), the
+    /// merged distance is the value of the plane at `merged_position`
+    pub fn try_merge<'a>(
+        &self,
+        leaves: impl IntoIterator<Item = (DVec3, &'a TerrainCellData)>,
+        merged_position: DVec3,
+    ) -> Option<TerrainCellData> {
+        let mut leaves = leaves.into_iter().peekable();
+        let kind = leaves.peek()?.1.kind;
+
+        let mut samples = Vec::new();
+        let mut leaves_error = 0f64;
+        for (position, data) in leaves {
+            if data.kind != kind {
+                return None;
+            }
+            samples.push((position, data.distance.to_f64()));
+            leaves_error = leaves_error.max(data.error.to_f64());
+        }
+
+        let (distance, error) = plane_fit(&samples, merged_position)?;
+        (error <= self.tolerance).then(|| TerrainCellData {
+            kind,
+            distance: f16::from_f64(distance),
+            empty: kind.empty(),
+            error: f16::from_f64(error + leaves_error),
+        })
+    }
+}
+
+/// Least squares fitting of a plane to the (position, value) samples, returns
+/// the value of the plane at `at` and the biggest distance between the plane
+/// and a sample
+///
+/// None if the samples do not span the three axes
+fn plane_fit(samples: &[(DVec3, f64)], at: DVec3) -> Option<(f64, f64)> {
+    let count = samples.len() as f64;
+    let mean_position = samples.iter().map(|(p, _)| *p).sum::<DVec3>() / count;
+    let mean_value = samples.iter().map(|(_, v)| *v).sum::<f64>() / count;
+
+    let mut covariance = DMat3::ZERO;
+    let mut cross = DVec3::ZERO;
+    for &(position, value) in samples {
+        let x = position - mean_position;
+        covariance += DMat3::from_cols(x * x.x, x * x.y, x * x.z);
+        cross += x * (value - mean_value);
+    }
+    if covariance.determinant().is_zero_approx() {
+        return None;
+    }
+    let gradient = covariance.inverse() * cross;
+
+    let plane = |position: DVec3| mean_value + gradient.dot(position - mean_position);
+    let error = samples.iter()
+        .map(|&(position, value)| (value - plane(position)).abs())
+        .fold(0f64, f64::max);
+
+    Some((plane(at), error))
+}
+
+impl<Ptr: MutableSvoPtr<TerrainCellData>> Cell<TerrainCellData, Ptr> {
+    /// Replaces, from the bottom up, internal and packed cells by a single
+    /// leaf when the policy allows it, and returns the number of merged cells
+    ///
+    /// Unlike [auto_merge](Self::auto_merge) which only merges far from the
+    /// surface, smooth parts of the terrain (where distances are close to a
+    /// plane) are merged too
+    pub fn merge_terrain(&mut self, aabb: DAabb, policy: &TerrainMergePolicy) -> usize {
+        let (merged, total) = match self {
+            Cell::Leaf(_) => return 0,
+            Cell::Internal(internal) => {
+                let total: usize = CellPath::components()
+                    .map(|comp| internal.get_child_mut(comp)
+                        .merge_terrain(aabb.octant(comp), policy))
+                    .sum();
+                internal.shallow_update();
+
+                let leaves = CellPath::components().map(|comp| {
+                    match &**internal.get_child(comp) {
+                        Cell::Leaf(leaf) => Some((aabb.octant(comp).min(), &leaf.data)),
+                        _ => None,
+                    }
+                });
+                let merged = leaves.into_iter().collect::<Option<Vec<_>>>()
+                    .and_then(|leaves| policy.try_merge(leaves, aabb.min()));
+                (merged, total)
+            },
+            Cell::Packed(_) => {
+                let merged = policy.try_merge(
+                    self.iter().map(|item| (item.path.get_aabb(aabb).min(), item.data)),
+                    aabb.min(),
+                );
+                (merged, 0)
+            },
+        };
+
+        match merged {
+            Some(data) => {
+                *self = LeafCell::new(data).into();
+                total + 1
+            },
+            None => total,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::{ApproxEq, Tolerance};
+
+    use super::*;
+    use crate::tests::terrain;
+
+    #[test]
+    pub fn test_terrain_integrate() {
+        // Bottom half is stone
+        let root = terrain(2, |_| 0., |pos| {
+            if pos.y < 2 { TerrainCellKind::Stone } else { TerrainCellKind::Air }
+        });
+        let root_aabb = DAabb::new_center_size(DVec3::splat(2.), DVec3::splat(4.));
+        let density = TerrainCellKind::Stone.density();
+
+        let all = root.integrate(root_aabb, root_aabb);
+        assert_eq!(all.solid_volume, 32.);
+        assert!(all.mass.approx_eq(&(32. * density), Tolerance::Absolute(1e-9)));
+        assert!(all.center_of_mass().unwrap().abs_diff_eq(DVec3::new(2., 1., 2.), 1e-9));
+
+        let air = root.integrate(root_aabb, DAabb::from_minmax(
+            DVec3::new(0., 2., 0.), DVec3::splat(4.)
+        ));
+        assert_eq!(air, TerrainIntegral::default());
+        assert_eq!(air.center_of_mass(), None);
+
+        // Cuts through leaves
+        let slice = root.integrate(root_aabb, DAabb::from_minmax(
+            DVec3::new(-1., -1., -1.), DVec3::new(0.5, 1.5, 5.),
+        ));
+        assert_eq!(slice.solid_volume, 0.5 * 1.5 * 4.);
+        assert!(slice.mass.approx_eq(&(slice.solid_volume * density), Tolerance::Absolute(1e-9)));
+    }
+
+    #[test]
+    pub fn test_terrain_merge() {
+        let root_aabb = DAabb::new_center_size(DVec3::splat(4.), DVec3::splat(8.));
+        let build = |distance: fn(UVec3) -> f32, kind: fn(UVec3) -> TerrainCellKind| {
+            terrain(3, distance, kind)
+        };
+        let policy = TerrainMergePolicy { tolerance: 0.1 };
+
+        // A plane merges into a single leaf
+        let mut plane = build(|p| p.y as f32 - 3.5, |_| TerrainCellKind::Air);
+        assert_eq!(plane.merge_terrain(root_aabb, &policy), 64 + 8 + 1);
+        let Cell::Leaf(leaf) = &plane
+        else { panic!("should be a leaf") };
+        assert_eq!(leaf.data.distance.to_f32(), -3.5);
+
+        // Different kinds never merge
+        let mut kinds = build(|p| p.y as f32 - 3.5, |p| if p.y < 4 {
+            TerrainCellKind::Stone
+        } else {
+            TerrainCellKind::Air
+        });
+        assert_eq!(kinds.merge_terrain(root_aabb, &policy), 64 + 8);
+        assert!(kinds.has_children());
+
+        // Curved surfaces only merge within the tolerance, the 2x2x2 blocks
+        // of x*y are 0.25 away from the plane
+        let mut curved = build(|p| (p.x * p.y) as f32, |_| TerrainCellKind::Air);
+        assert_eq!(curved.merge_terrain(root_aabb, &policy), 0);
+        let mut curved = build(|p| (p.x * p.y) as f32, |_| TerrainCellKind::Air);
+        assert_eq!(curved.merge_terrain(root_aabb, &TerrainMergePolicy { tolerance: 0.5 }), 64);
+    }
+
+    #[test]
+    pub fn test_terrain_simplification_error() {
+        let build = |distance: fn(UVec3) -> f32| {
+            terrain(3, distance, |_| TerrainCellKind::Air)
+        };
+
+        // Planes, even sloped, are simplified without any error
+        let plane = build(|p| p.x as f32 * 0.5 + p.y as f32 - 3.5);
+        assert_eq!(plane.simplification_errors(&CellPath::new(), 3), vec![0.; 4]);
+
+        // The 2x2x2 blocks of x*y are 0.25 away from the plane, and the
+        // errors add up when going up
+        let curved = build(|p| (p.x * p.y) as f32);
+        let errors = curved.simplification_errors(&CellPath::new(), 3);
+        assert_eq!(errors[3], 0.);
+        assert_eq!(errors[2], 0.25);
+        assert!(errors[1] > errors[2] && errors[0] >= errors[1]);
+
+        let child_errors = curved.simplification_errors(
+            &CellPath::new().with_push(u3::new(0)), 2,
+        );
+        assert_eq!(child_errors.len(), 3);
+        assert_eq!(&child_errors[1..], &[0.25, 0.]);
+
+        // Merged leaves keep the error of the merge at all depths
+        let mut merged = curved.clone();
+        let root_aabb = DAabb::new_center_size(DVec3::splat(4.), DVec3::splat(8.));
+        merged.merge_terrain(root_aabb, &TerrainMergePolicy { tolerance: 0.5 });
+        assert_eq!(&merged.simplification_errors(&CellPath::new(), 3)[2..], &[0.25, 0.25]);
+    }
+}
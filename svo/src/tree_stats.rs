@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::mem::size_of;
+
+use super::*;
+
+/// Shape of an svo, see [Cell::tree_stats]
+///
+/// Counts are of the cells of the tree as if nothing was shared, cells
+/// inside packed cells included, while [TreeStats::memory] counts shared
+/// cells only once like [Cell::memory_usage].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TreeStats {
+    /// Number of cells at each depth, the root being at depth 0
+    pub cells_per_depth: Vec<usize>,
+    pub internal_count: usize,
+    pub leaf_count: usize,
+    /// Number of [PackedCell]s
+    pub packed_count: usize,
+    /// Part of the internal and leaf cells that are inside packed cells
+    pub packed_cell_count: usize,
+    pub memory: MemoryUsage,
+}
+
+impl TreeStats {
+    pub fn cell_count(&self) -> usize {
+        self.internal_count + self.leaf_count
+    }
+
+    /// Depth of the deepest cell, see [Cell::depth]
+    pub fn depth(&self) -> u32 {
+        self.cells_per_depth.len().saturating_sub(1) as u32
+    }
+
+    /// Part of the cells that are inside packed cells, between 0 and 1
+    pub fn packed_ratio(&self) -> f64 {
+        if self.cell_count() == 0 {
+            return 0.;
+        }
+        self.packed_cell_count as f64 / self.cell_count() as f64
+    }
+
+    /// Number of children per level that would give the same number of
+    /// leaves at the same depth, 8 when every cell is split down to the
+    /// deepest level and lower the more the tree is sparse
+    pub fn average_branching(&self) -> f64 {
+        if self.depth() == 0 {
+            return 0.;
+        }
+        (self.leaf_count as f64).powf(1. / self.depth() as f64)
+    }
+
+    fn add_cells(&mut self, depth: u32, count: usize, leaves: bool, packed: bool) {
+        let depth = depth as usize;
+        if self.cells_per_depth.len() <= depth {
+            self.cells_per_depth.resize(depth + 1, 0);
+        }
+        self.cells_per_depth[depth] += count;
+        if leaves {
+            self.leaf_count += count;
+        }
+        else {
+            self.internal_count += count;
+        }
+        if packed {
+            self.packed_cell_count += count;
+        }
+    }
+}
+
+impl fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIB: f64 = 1024. * 1024.;
+
+        writeln!(
+            f, "cells: {} ({} internal, {} leaves)",
+            self.cell_count(), self.internal_count, self.leaf_count,
+        )?;
+        writeln!(
+            f, "packed: {:.1}% of cells in {} packed cells",
+            self.packed_ratio() * 100., self.packed_count,
+        )?;
+        writeln!(f, "average branching: {:.2}", self.average_branching())?;
+        writeln!(
+            f, "memory: {:.2} MiB (nodes {:.2}, leaves {:.2}, packed {:.2}, pointers {:.2})",
+            self.memory.total() as f64 / MIB,
+            self.memory.nodes as f64 / MIB,
+            self.memory.leaf_data as f64 / MIB,
+            self.memory.packed_levels as f64 / MIB,
+            self.memory.ptr_overhead as f64 / MIB,
+        )?;
+        for (depth, count) in self.cells_per_depth.iter().enumerate() {
+            write!(f, "\ndepth {depth}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+struct TreeStatsCounter {
+    seen: HashSet<usize>,
+    stats: TreeStats,
+}
+
+impl TreeStatsCounter {
+    fn add<D: Data, Ptr: SvoPtr<D>>(&mut self, cell: &Cell<D, Ptr>, depth: u32) {
+        let new = self.seen.insert(cell as *const Cell<D, Ptr> as usize);
+
+        let cell_size = size_of::<Cell<D, Ptr>>();
+        match cell {
+            Cell::Internal(internal) => {
+                self.stats.add_cells(depth, 1, false, false);
+                if new {
+                    self.stats.memory.nodes += cell_size;
+                    self.stats.memory.internal_count += 1;
+                }
+                for child in &internal.children {
+                    let child_new = !self.seen.contains(&(&**child as *const Cell<D, Ptr> as usize));
+                    if new && child_new {
+                        self.stats.memory.ptr_overhead += Ptr::allocation_overhead();
+                    }
+                    self.add(&**child, depth + 1);
+                }
+            },
+            Cell::Leaf(_) => {
+                self.stats.add_cells(depth, 1, true, false);
+                if new {
+                    self.stats.memory.leaf_data += cell_size;
+                    self.stats.memory.leaf_count += 1;
+                }
+            },
+            Cell::Packed(packed) => {
+                self.stats.packed_count += 1;
+                let packed_depth = packed.depth();
+                for level in 0..=packed_depth {
+                    self.stats.add_cells(
+                        depth + level, 8usize.pow(level), level == packed_depth, true,
+                    );
+                }
+                if new {
+                    self.stats.memory += packed.memory_usage();
+                    self.stats.memory.nodes += cell_size;
+                }
+            },
+        }
+    }
+}
+
+impl<D: Data, Ptr: SvoPtr<D>> Cell<D, Ptr> {
+    /// Computes the [TreeStats] of this cell and all its descendants in a
+    /// single traversal
+    pub fn tree_stats(&self) -> TreeStats {
+        let mut counter = TreeStatsCounter {
+            seen: HashSet::new(),
+            stats: TreeStats::default(),
+        };
+        counter.add(self, 0);
+        counter.stats
+    }
+}
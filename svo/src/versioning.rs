@@ -0,0 +1,318 @@
+//! Versioning of serialized svos, so saves written with older versions of
+//! their data can still be loaded
+//!
+//! [Versioned] values are serialized along with the version of their data
+//! and are upgraded with [Migrate::migrate] when it is older than the
+//! current one.
+
+use std::marker::PhantomData;
+
+use serde::{de::{DeserializeOwned, DeserializeSeed, Error as _, MapAccess, SeqAccess, Visitor}, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::*;
+
+/// Types whose serialized representation may change
+pub trait Migrate: Serialize + DeserializeOwned {
+    /// Version of the current representation, to be incremented every time
+    /// it changes
+    const VERSION: u32;
+
+    /// Deserializes a value saved with the given older version, usually by
+    /// deserializing the old type and converting it
+    ///
+    /// By default no older version is supported.
+    fn migrate<'de, De: Deserializer<'de>>(
+        version: u32,
+        deserializer: De,
+    ) -> Result<Self, De::Error> {
+        let _ = deserializer;
+        Err(De::Error::custom(format_args!(
+            "cannot migrate from version {version} to version {}", Self::VERSION,
+        )))
+    }
+}
+
+/// Svo data whose serialized representation may change, which makes
+/// [PackedCell]s of it [Migrate]
+pub trait VersionedData: Data {
+    /// Version of the current representation of the data and of its
+    /// internal data
+    const VERSION: u32;
+
+    /// Deserializes a packed cell saved with the given older version of the
+    /// data, usually by deserializing a packed cell of the old data type and
+    /// converting it with [PackedCell::map_data]
+    ///
+    /// By default no older version is supported.
+    fn migrate_packed<'de, De: Deserializer<'de>>(
+        version: u32,
+        deserializer: De,
+    ) -> Result<PackedCell<Self>, De::Error> {
+        let _ = deserializer;
+        Err(De::Error::custom(format_args!(
+            "cannot migrate from version {version} to version {}", Self::VERSION,
+        )))
+    }
+}
+
+impl<D> Migrate for PackedCell<D>
+    where D: VersionedData + Serialize + DeserializeOwned,
+          D::Internal: Serialize + DeserializeOwned,
+{
+    const VERSION: u32 = D::VERSION;
+
+    fn migrate<'de, De: Deserializer<'de>>(
+        version: u32,
+        deserializer: De,
+    ) -> Result<Self, De::Error> {
+        D::migrate_packed(version, deserializer)
+    }
+}
+
+/// Serializes the value along with its [Migrate::VERSION], migrating it when
+/// deserializing an older one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Versioned<T> {
+    /// Version the value was deserialized from
+    loaded_version: u32,
+    data: T,
+}
+
+impl<T: Migrate> Versioned<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            loaded_version: T::VERSION,
+            data,
+        }
+    }
+
+    /// Version the value was saved with, the current one for new values
+    pub fn loaded_version(&self) -> u32 {
+        self.loaded_version
+    }
+
+    /// Whether the value was saved with an older version and migrated
+    pub fn was_migrated(&self) -> bool {
+        self.loaded_version != T::VERSION
+    }
+
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+}
+
+impl<T: Migrate> From<T> for Versioned<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: Migrate> Serialize for Versioned<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        // The data is always in its current version
+        let mut state = serializer.serialize_struct("Versioned", 2)?;
+        state.serialize_field("version", &T::VERSION)?;
+        state.serialize_field("data", &self.data)?;
+        state.end()
+    }
+}
+
+impl<'de, T: Migrate> Deserialize<'de> for Versioned<T> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        deserializer.deserialize_struct(
+            "Versioned", &["version", "data"], VersionedVisitor(PhantomData),
+        )
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum VersionedField {
+    Version,
+    Data,
+}
+
+struct VersionedVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Migrate> Visitor<'de> for VersionedVisitor<T> {
+    type Value = Versioned<T>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a version followed by the versioned data")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let version = seq.next_element::<u32>()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        let data = seq.next_element_seed(VersionSeed::<T>(version, PhantomData))?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+        Ok(Versioned { loaded_version: version, data })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        // The version must be known before reading the data
+        let Some(VersionedField::Version) = map.next_key()?
+        else { return Err(A::Error::custom("the version must be the first field")); };
+        let version = map.next_value::<u32>()?;
+
+        let Some(VersionedField::Data) = map.next_key()?
+        else { return Err(A::Error::missing_field("data")); };
+        let data = map.next_value_seed(VersionSeed::<T>(version, PhantomData))?;
+
+        Ok(Versioned { loaded_version: version, data })
+    }
+}
+
+/// Deserializes a T of the given version
+struct VersionSeed<T>(u32, PhantomData<T>);
+
+impl<'de, T: Migrate> DeserializeSeed<'de> for VersionSeed<T> {
+    type Value = T;
+
+    fn deserialize<De: Deserializer<'de>>(self, deserializer: De) -> Result<T, De::Error> {
+        use std::cmp::Ordering;
+
+        let VersionSeed(version, _) = self;
+        match version.cmp(&T::VERSION) {
+            Ordering::Equal => T::deserialize(deserializer),
+            Ordering::Less => T::migrate(version, deserializer),
+            Ordering::Greater => Err(De::Error::custom(format_args!(
+                "version {version} is newer than the supported version {}", T::VERSION,
+            ))),
+        }
+    }
+}
+
+/// [TerrainCellData] before it had its [error](TerrainCellData::error)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct TerrainCellDataV1 {
+    kind: TerrainCellKind,
+    distance: half::f16,
+    empty: bool,
+}
+
+impl Data for TerrainCellDataV1 {
+    type Internal = Self;
+}
+
+impl InternalData for TerrainCellDataV1 {  }
+
+impl VersionedData for TerrainCellData {
+    const VERSION: u32 = 2;
+
+    fn migrate_packed<'de, De: Deserializer<'de>>(
+        version: u32,
+        deserializer: De,
+    ) -> Result<PackedCell<Self>, De::Error> {
+        if version != 1 {
+            return Err(De::Error::custom(format_args!(
+                "cannot migrate from version {version} to version {}", Self::VERSION,
+            )));
+        }
+        let upgrade = |TerrainCellDataV1 { kind, distance, empty }| TerrainCellData {
+            kind, distance, empty, error: half::f16::ZERO,
+        };
+        let mut packed = PackedCell::<TerrainCellDataV1>::deserialize(deserializer)?
+            .map_data(upgrade, upgrade);
+        // Errors of the internal cells are computed from their children
+        packed.update_all();
+        Ok(packed)
+    }
+}
+
+impl<D: Data> PackedCell<D> {
+    /// Converts all the data of the cell, e.g. to migrate it (see
+    /// [VersionedData::migrate_packed])
+    pub fn map_data<D2: Data>(
+        self,
+        mut internal: impl FnMut(D::Internal) -> D2::Internal,
+        leaf: impl FnMut(D) -> D2,
+    ) -> PackedCell<D2> {
+        PackedCell {
+            levels: self.levels.into_iter()
+                .map(|level| PackedCellLevel {
+                    data: level.data.into_vec().into_iter().map(&mut internal).collect(),
+                })
+                .collect(),
+            leaf_level: PackedCellLevel {
+                data: self.leaf_level.data.into_vec().into_iter().map(leaf).collect(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct OldData(u8);
+
+    impl Data for OldData {
+        type Internal = OldData;
+    }
+    impl InternalData for OldData {  }
+    impl VersionedData for OldData {
+        const VERSION: u32 = 1;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct NewData {
+        value: u16,
+        solid: bool,
+    }
+
+    impl Data for NewData {
+        type Internal = NewData;
+    }
+    impl InternalData for NewData {  }
+    impl VersionedData for NewData {
+        const VERSION: u32 = 2;
+
+        fn migrate_packed<'de, De: serde::Deserializer<'de>>(
+            version: u32,
+            deserializer: De,
+        ) -> Result<PackedCell<Self>, De::Error> {
+            assert_eq!(version, 1);
+            let upgrade = |OldData(value)| NewData { value: value.into(), solid: value > 0 };
+            let old = <PackedCell<OldData> as serde::Deserialize>::deserialize(deserializer)?;
+            Ok(old.map_data(upgrade, upgrade))
+        }
+    }
+
+    #[test]
+    pub fn test_versioned_migration() {
+        let old = PackedCell::from_children(
+            CellPath::components().map(|a| PackedCell::new_leaf(OldData(a.value()))),
+            OldData(0),
+        ).unwrap();
+        let saved = ron::to_string(&Versioned::new(old)).unwrap();
+
+        let loaded: Versioned<PackedCell<NewData>> = ron::from_str(&saved).unwrap();
+        assert_eq!(loaded.loaded_version(), 1);
+        assert!(loaded.was_migrated());
+        let packed = loaded.into_inner();
+        assert_eq!(
+            packed.get(&CellPath::new()).unwrap_left(),
+            &NewData { value: 0, solid: false },
+        );
+        assert_eq!(
+            packed.get(&CellPath::new().with_push(u3::new(6))).unwrap_right(),
+            &NewData { value: 6, solid: true },
+        );
+
+        // Saving again writes the current version
+        let resaved = ron::to_string(&Versioned::new(packed)).unwrap();
+        let reloaded: Versioned<PackedCell<NewData>> = ron::from_str(&resaved).unwrap();
+        assert!(!reloaded.was_migrated());
+
+        // Newer saves cannot be read
+        assert!(ron::from_str::<Versioned<PackedCell<OldData>>>(&resaved).is_err());
+    }
+}
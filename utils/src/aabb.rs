@@ -1,5 +1,5 @@
 use arbitrary_int::u3;
-use bevy_math::{bounding::Aabb3d, DVec3};
+use bevy_math::{bounding::Aabb3d, DAffine3, DVec3};
 use bevy_render::primitives::Aabb;
 
 use crate::{AabbExt, AsVecExt};
@@ -27,6 +27,37 @@ impl DAabb {
         }
     }
 
+    /// Smallest aabb containing all the given points, None if there are none
+    ///
+    /// # Example
+    /// ```
+    /// use bevy_math::DVec3;
+    /// use utils::DAabb;
+    ///
+    /// let points = [DVec3::new(1., -2., 0.), DVec3::new(-1., 3., 0.5)];
+    /// assert_eq!(
+    ///     DAabb::from_points(points),
+    ///     Some(DAabb::from_minmax(DVec3::new(-1., -2., 0.), DVec3::new(1., 3., 0.5))),
+    /// );
+    /// assert_eq!(DAabb::from_points([]), None);
+    /// ```
+    pub fn from_points(points: impl IntoIterator<Item = DVec3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        let mut aabb = Self::from_minmax(first, first);
+        points.for_each(|point| aabb.expand_to_contain_point(point));
+        Some(aabb)
+    }
+
+    /// Smallest aabb containing the given sphere
+    pub fn from_sphere(sphere_origin: DVec3, sphere_radius: f64) -> Self {
+        Self::new_center_size(sphere_origin, DVec3::splat(sphere_radius * 2.))
+    }
+
+    pub fn center(&self) -> DVec3 {
+        self.position + self.size / 2.
+    }
+
     pub fn min(&self) -> DVec3 {
         self.position
     }
@@ -43,10 +74,23 @@ impl DAabb {
         self.size = val.into() - self.position;
     }
 
+    /// The corner of index `i` has its x, y and z at the max of the aabb
+    /// when the bits 0, 1 and 2 of `i` are set, like [Self::octant]
+    ///
+    /// # Example
+    /// ```
+    /// use bevy_math::DVec3;
+    /// use utils::DAabb;
+    ///
+    /// let corners = DAabb::from_minmax(DVec3::ZERO, DVec3::new(1., 2., 3.)).corners();
+    /// assert_eq!(corners[0], DVec3::ZERO);
+    /// assert_eq!(corners[0b101], DVec3::new(1., 0., 3.));
+    /// assert_eq!(corners[7], DVec3::new(1., 2., 3.));
+    /// ```
     pub fn corners(&self) -> [DVec3; 8] {
         let mut out = [DVec3::ZERO; 8];
 
-        for (i, comp) in (0..0b111u8).enumerate() {
+        for (i, comp) in (0..=0b111u8).enumerate() {
             let dx = if comp & 0b001 == 0 { 0. } else { 1. };
             let dy = if comp & 0b010 == 0 { 0. } else { 1. };
             let dz = if comp & 0b100 == 0 { 0. } else { 1. };
@@ -67,6 +111,40 @@ impl DAabb {
         }
     }
 
+    /// Center and radius of the smallest sphere containing the aabb
+    ///
+    /// # Example
+    /// ```
+    /// use bevy_math::DVec3;
+    /// use utils::DAabb;
+    ///
+    /// let aabb = DAabb::from_minmax(DVec3::ZERO, DVec3::new(6., 0., 8.));
+    /// assert_eq!(aabb.bounding_sphere(), (DVec3::new(3., 0., 4.), 5.));
+    /// ```
+    pub fn bounding_sphere(&self) -> (DVec3, f64) {
+        (self.center(), self.size.length() / 2.)
+    }
+
+    /// Smallest aabb containing this one once transformed (e.g. rotated)
+    ///
+    /// # Example
+    /// ```
+    /// use bevy_math::{DAffine3, DQuat, DVec3};
+    /// use utils::DAabb;
+    ///
+    /// let aabb = DAabb::from_minmax(DVec3::ZERO, DVec3::new(2., 1., 1.));
+    /// let transform = DAffine3::from_rotation_translation(
+    ///     DQuat::from_rotation_z(std::f64::consts::FRAC_PI_2), DVec3::X,
+    /// );
+    /// let transformed = aabb.transformed(&transform);
+    /// assert!(transformed.min().distance(DVec3::new(0., 0., 0.)) < 1e-9);
+    /// assert!(transformed.max().distance(DVec3::new(1., 2., 1.)) < 1e-9);
+    /// ```
+    pub fn transformed(&self, transform: &DAffine3) -> Self {
+        Self::from_points(self.corners().map(|corner| transform.transform_point3(corner)))
+            .expect("Eight corners")
+    }
+
     pub fn fully_contained_in_sphere(self, sphere_origin: DVec3, sphere_radius: f64) -> bool {
         let r2 = sphere_radius.powi(2);
         self.translated(-sphere_origin).corners()
@@ -96,15 +174,92 @@ impl DAabb {
         self.set_max(DVec3::max(self.max(), point));
     }
 
+    /// Squared distance from the point to the closest point of the aabb, 0
+    /// if it is inside
+    ///
+    /// # Example
+    /// ```
+    /// use bevy_math::DVec3;
+    /// use utils::DAabb;
+    ///
+    /// let aabb = DAabb::from_minmax(DVec3::ZERO, DVec3::ONE);
+    /// assert_eq!(aabb.distance_squared_to(DVec3::splat(0.5)), 0.);
+    /// assert_eq!(aabb.distance_squared_to(DVec3::new(3., 0.5, -1.)), 5.);
+    /// ```
+    pub fn distance_squared_to(&self, point: DVec3) -> f64 {
+        self.closest_point(point).distance_squared(point)
+    }
+
+    /// Distance from the point to the furthest corner of the aabb
+    ///
+    /// # Example
+    /// ```
+    /// use bevy_math::DVec3;
+    /// use utils::DAabb;
+    ///
+    /// let aabb = DAabb::from_minmax(DVec3::ZERO, DVec3::new(3., 4., 0.));
+    /// assert_eq!(aabb.max_distance_to(DVec3::ZERO), 5.);
+    /// assert_eq!(aabb.max_distance_to(DVec3::new(3., 0., 0.)), 5.);
+    /// ```
+    pub fn max_distance_to(&self, point: DVec3) -> f64 {
+        self.furthest_point(point).distance(point)
+    }
+
+    /// One eighth of the aabb, at the max of the aabb on the x, y and z
+    /// axes when the bits 0, 1 and 2 of `comp` are set, which is the order of
+    /// svo children
+    ///
+    /// # Example
+    /// ```
+    /// use arbitrary_int::u3;
+    /// use bevy_math::DVec3;
+    /// use utils::DAabb;
+    ///
+    /// let aabb = DAabb::from_minmax(DVec3::ZERO, DVec3::splat(2.));
+    /// assert_eq!(
+    ///     aabb.octant(u3::new(0b011)),
+    ///     DAabb::from_minmax(DVec3::new(1., 1., 0.), DVec3::new(2., 2., 1.)),
+    /// );
+    /// ```
+    pub fn octant(self, comp: u3) -> Self {
+        let size = self.size / 2.;
+        Self {
+            position: self.position + comp.as_uvec().as_dvec3() * size,
+            size,
+        }
+    }
+
+    /// Replaces the aabb with its given [octant](Self::octant)
     pub fn octdivide(&mut self, comp: u3) {
-        self.size /= 2.;
-        self.position += comp.as_uvec().as_dvec3() * self.size;
+        *self = self.octant(comp);
+    }
+
+    #[deprecated = "renamed to octant"]
+    pub fn octdivided(self, comp: u3) -> Self {
+        self.octant(comp)
     }
 
-    pub fn octdivided(mut self, comp: u3) -> Self {
-        self.size /= 2.;
-        self.position += comp.as_uvec().as_dvec3() * self.size;
-        self
+    /// All [octants](Self::octant) of the aabb, the one of `comp` at index
+    /// `comp`
+    ///
+    /// # Example
+    /// ```
+    /// use arbitrary_int::u3;
+    /// use bevy_math::DVec3;
+    /// use utils::DAabb;
+    ///
+    /// let aabb = DAabb::new_center_size(DVec3::ZERO, DVec3::splat(4.));
+    /// let octants = aabb.subdivide();
+    /// for (i, octant) in octants.iter().enumerate() {
+    ///     assert_eq!(*octant, aabb.octant(u3::new(i as u8)));
+    ///     assert_eq!(octant.size, DVec3::splat(2.));
+    ///     // Octants touch the center and the corner of the same index
+    ///     assert_eq!(octant.distance_squared_to(DVec3::ZERO), 0.);
+    ///     assert_eq!(octant.distance_squared_to(aabb.corners()[i]), 0.);
+    /// }
+    /// ```
+    pub fn subdivide(self) -> [Self; 8] {
+        std::array::from_fn(|i| self.octant(u3::new(i as u8)))
     }
 }
 
@@ -0,0 +1,130 @@
+use bevy_math::{DVec2, DVec3, Vec2, Vec3};
+
+/// How close two floats must be to be considered equal by [ApproxEq]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tolerance {
+    /// The difference is at most this epsilon
+    Absolute(f64),
+    /// The difference is at most this fraction of the biggest magnitude,
+    /// which is never true close to zero (except for equal values)
+    Relative(f64),
+    /// Either of [Tolerance::Absolute] or [Tolerance::Relative], so values
+    /// close to zero are compared with the absolute one
+    AbsoluteOrRelative {
+        absolute: f64,
+        relative: f64,
+    },
+    /// At most this many representable floats between the two, which scales
+    /// with the magnitude like [Tolerance::Relative] but with the precision
+    /// of the type
+    Ulps(u32),
+}
+
+/// Approximate equality of floats and float vectors (component wise)
+///
+/// Equal values (including infinities) are always approximately equal, NaNs
+/// never are.
+///
+/// # Example
+/// ```
+/// use utils::{ApproxEq, Tolerance};
+///
+/// assert!(0.1f64.approx_eq(&(0.3 - 0.2), Tolerance::Ulps(4)));
+/// assert!(!0.1f64.approx_eq(&(0.3 - 0.2), Tolerance::Ulps(0)));
+/// assert!(1000f64.approx_eq(&1001., Tolerance::Relative(1e-2)));
+/// assert!(!1f64.approx_eq(&2., Tolerance::Relative(1e-2)));
+/// assert!(1e-12f64.approx_eq(&0., Tolerance::Absolute(1e-9)));
+/// assert!(!1e-12f64.approx_eq(&0., Tolerance::Relative(1e-2)));
+/// assert!(1e-12f64.approx_eq(&0., Tolerance::AbsoluteOrRelative {
+///     absolute: 1e-9,
+///     relative: 1e-2,
+/// }));
+/// assert!(!f64::NAN.approx_eq(&f64::NAN, Tolerance::Absolute(1.)));
+/// assert!(f32::INFINITY.approx_eq(&f32::INFINITY, Tolerance::Ulps(0)));
+/// assert!((-0f32).approx_eq(&f32::from_bits(1), Tolerance::Ulps(1)));
+/// ```
+pub trait ApproxEq {
+    fn approx_eq(&self, other: &Self, tolerance: Tolerance) -> bool;
+}
+
+/// Maps the bits of floats to integers in the same order as the floats, so
+/// their difference is the number of floats between them
+fn ordered_bits_f64(value: f64) -> i64 {
+    let bits = value.to_bits() as i64;
+    if bits < 0 { i64::MIN - bits } else { bits }
+}
+
+fn ordered_bits_f32(value: f32) -> i32 {
+    let bits = value.to_bits() as i32;
+    if bits < 0 { i32::MIN - bits } else { bits }
+}
+
+fn floats_approx_eq(a: f64, b: f64, ulps: impl FnOnce() -> u64, tolerance: Tolerance) -> bool {
+    if a == b {
+        return true;
+    }
+    if !a.is_finite() || !b.is_finite() {
+        return false;
+    }
+
+    let difference = (a - b).abs();
+    let magnitude = a.abs().max(b.abs());
+    match tolerance {
+        Tolerance::Absolute(epsilon) => difference <= epsilon,
+        Tolerance::Relative(epsilon) => difference <= epsilon * magnitude,
+        Tolerance::AbsoluteOrRelative { absolute, relative } =>
+            difference <= absolute || difference <= relative * magnitude,
+        Tolerance::Ulps(max_ulps) => ulps() <= u64::from(max_ulps),
+    }
+}
+
+impl ApproxEq for f64 {
+    #[inline]
+    fn approx_eq(&self, other: &Self, tolerance: Tolerance) -> bool {
+        floats_approx_eq(*self, *other, || {
+            ordered_bits_f64(*self).abs_diff(ordered_bits_f64(*other))
+        }, tolerance)
+    }
+}
+
+impl ApproxEq for f32 {
+    #[inline]
+    fn approx_eq(&self, other: &Self, tolerance: Tolerance) -> bool {
+        // Differences of f32s are exact in f64
+        floats_approx_eq(f64::from(*self), f64::from(*other), || {
+            u64::from(ordered_bits_f32(*self).abs_diff(ordered_bits_f32(*other)))
+        }, tolerance)
+    }
+}
+
+impl ApproxEq for DVec3 {
+    #[inline]
+    fn approx_eq(&self, other: &Self, tolerance: Tolerance) -> bool {
+        self.to_array().iter().zip(other.to_array())
+            .all(|(a, b)| a.approx_eq(&b, tolerance))
+    }
+}
+
+impl ApproxEq for Vec3 {
+    #[inline]
+    fn approx_eq(&self, other: &Self, tolerance: Tolerance) -> bool {
+        self.to_array().iter().zip(other.to_array())
+            .all(|(a, b)| a.approx_eq(&b, tolerance))
+    }
+}
+
+impl ApproxEq for DVec2 {
+    #[inline]
+    fn approx_eq(&self, other: &Self, tolerance: Tolerance) -> bool {
+        self.to_array().iter().zip(other.to_array())
+            .all(|(a, b)| a.approx_eq(&b, tolerance))
+    }
+}
+
+impl ApproxEq for Vec2 {
+    #[inline]
+    fn approx_eq(&self, other: &Self, tolerance: Tolerance) -> bool {
+        self.to_array().iter().zip(other.to_array())
+            .all(|(a, b)| a.approx_eq(&b, tolerance))
+    }
+}
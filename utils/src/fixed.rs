@@ -0,0 +1,251 @@
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+use bevy_math::DVec3;
+
+/// Fixed point number stored in an i128, with [Fixed::FRACTION_BITS] bits
+/// after the point.
+///
+/// All operations are done on integers so results are the same on every
+/// platform, unlike floats.
+///
+/// # Example
+/// ```
+/// use utils::Fixed;
+///
+/// let a = Fixed::from_f64(1.5);
+/// let b = Fixed::from_int(-4);
+/// assert_eq!((a * b).to_f64(), -6.);
+/// assert_eq!((b / a * a).to_f64().round(), -4.);
+/// assert_eq!(Fixed::from_f64(0.1) + Fixed::from_f64(0.2), Fixed::from_f64(0.3));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i128);
+
+impl Fixed {
+    pub const FRACTION_BITS: u32 = 32;
+    const FRACTION_MASK: i128 = (1 << Self::FRACTION_BITS) - 1;
+
+    pub const ZERO: Self = Self(0);
+    pub const ONE: Self = Self(1 << Self::FRACTION_BITS);
+    /// Smallest representable step
+    pub const EPSILON: Self = Self(1);
+
+    pub const fn from_raw(raw: i128) -> Self {
+        Self(raw)
+    }
+
+    pub const fn raw(self) -> i128 {
+        self.0
+    }
+
+    pub const fn from_int(value: i64) -> Self {
+        Self((value as i128) << Self::FRACTION_BITS)
+    }
+
+    /// Rounds to the closest representable value, saturating for values
+    /// that are too big (and giving 0 for NaN)
+    pub fn from_f64(value: f64) -> Self {
+        Self((value * (1u64 << Self::FRACTION_BITS) as f64).round() as i128)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / (1u64 << Self::FRACTION_BITS) as f64
+    }
+
+    /// Rounds toward negative infinity
+    pub const fn floor(self) -> i128 {
+        self.0 >> Self::FRACTION_BITS
+    }
+
+    pub const fn abs(self) -> Self {
+        Self(self.0.abs())
+    }
+}
+
+impl Add for Fixed {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0 - rhs.0)
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(-self.0)
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Self;
+
+    /// Rounds toward negative infinity
+    fn mul(self, rhs: Self) -> Self {
+        // Split self in its integer and fractional parts so the
+        // intermediate products do not overflow
+        let int = self.0 >> Self::FRACTION_BITS;
+        let frac = self.0 & Self::FRACTION_MASK;
+        Self(int * rhs.0 + ((frac * rhs.0) >> Self::FRACTION_BITS))
+    }
+}
+
+impl Div for Fixed {
+    type Output = Self;
+
+    /// Rounds toward zero, panics if rhs is zero
+    fn div(self, rhs: Self) -> Self {
+        let quotient = self.0 / rhs.0;
+        let remainder = self.0 % rhs.0;
+        Self(
+            (quotient << Self::FRACTION_BITS)
+            + ((remainder << Self::FRACTION_BITS) / rhs.0)
+        )
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl From<i64> for Fixed {
+    fn from(value: i64) -> Self {
+        Self::from_int(value)
+    }
+}
+
+/// Vector of [Fixed], meant for deterministic world positions while
+/// rendering and physics keep using floats relative to some origin
+/// (see [FixedVec3::relative_to])
+///
+/// # Example
+/// ```
+/// use bevy_math::DVec3;
+/// use utils::FixedVec3;
+///
+/// let far = FixedVec3::from_dvec3(DVec3::new(1e12, 0., -1e12));
+/// let near = far + FixedVec3::from_dvec3(DVec3::new(0.25, 0.5, 0.));
+/// assert_eq!(near.relative_to(far), DVec3::new(0.25, 0.5, 0.));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedVec3 {
+    pub x: Fixed,
+    pub y: Fixed,
+    pub z: Fixed,
+}
+
+impl FixedVec3 {
+    pub const ZERO: Self = Self::splat(Fixed::ZERO);
+    pub const ONE: Self = Self::splat(Fixed::ONE);
+
+    pub const fn new(x: Fixed, y: Fixed, z: Fixed) -> Self {
+        Self { x, y, z }
+    }
+
+    pub const fn splat(v: Fixed) -> Self {
+        Self::new(v, v, v)
+    }
+
+    pub fn from_dvec3(v: DVec3) -> Self {
+        Self::new(Fixed::from_f64(v.x), Fixed::from_f64(v.y), Fixed::from_f64(v.z))
+    }
+
+    pub fn to_dvec3(self) -> DVec3 {
+        DVec3::new(self.x.to_f64(), self.y.to_f64(), self.z.to_f64())
+    }
+
+    /// Position relative to the given origin as floats, precise as long as
+    /// both are close even if they are far from zero
+    pub fn relative_to(self, origin: Self) -> DVec3 {
+        (self - origin).to_dvec3()
+    }
+
+    pub fn dot(self, rhs: Self) -> Fixed {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub fn length_squared(self) -> Fixed {
+        self.dot(self)
+    }
+}
+
+impl Add for FixedVec3 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl Sub for FixedVec3 {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl Neg for FixedVec3 {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl Mul<Fixed> for FixedVec3 {
+    type Output = Self;
+
+    fn mul(self, rhs: Fixed) -> Self {
+        Self::new(self.x * rhs, self.y * rhs, self.z * rhs)
+    }
+}
+
+impl Div<Fixed> for FixedVec3 {
+    type Output = Self;
+
+    fn div(self, rhs: Fixed) -> Self {
+        Self::new(self.x / rhs, self.y / rhs, self.z / rhs)
+    }
+}
+
+impl AddAssign for FixedVec3 {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for FixedVec3 {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl From<DVec3> for FixedVec3 {
+    fn from(value: DVec3) -> Self {
+        Self::from_dvec3(value)
+    }
+}
+
+impl From<FixedVec3> for DVec3 {
+    fn from(value: FixedVec3) -> Self {
+        value.to_dvec3()
+    }
+}
@@ -1,6 +1,6 @@
 use bevy_math::{bounding::Aabb3d, DVec3, Vec3};
 use num_traits::{Float, Num, NumAssign};
-use std::fmt::Debug;
+use std::{fmt::Debug, ops::{Add, Div, Mul, Neg, Sub}};
 
 use crate::DAabb;
 
@@ -90,17 +90,22 @@ impl AabbExt<f64> for DAabb {
 }
 
 pub trait Vec3Ext<T: Num + Copy>
-    where Self: Debug + Copy
+    where Self: Debug + Copy + PartialEq
+        + Add<Output = Self> + Sub<Output = Self> + Neg<Output = Self>
+        + Mul<T, Output = Self> + Div<T, Output = Self>
 {
     fn new(x: T, y: T, z: T) -> Self;
     fn from_array(a: [T; 3]) -> Self {
         Self::new(a[0], a[1], a[2])
     }
+    fn splat(v: T) -> Self {
+        Self::new(v, v, v)
+    }
     fn zero() -> Self {
-        Self::new(T::zero(), T::zero(), T::zero())
+        Self::splat(T::zero())
     }
     fn one() -> Self {
-        Self::new(T::one(), T::one(), T::one())
+        Self::splat(T::one())
     }
 
     fn clamped(&self, min: Self, max: Self) -> Self
@@ -122,6 +127,32 @@ pub trait Vec3Ext<T: Num + Copy>
 
     fn array(&self) -> [T; 3];
     fn array_mut(&mut self) -> [&mut T; 3];
+
+    /// Component-wise absolute value
+    fn abs(&self) -> Self;
+    /// Component-wise minimum
+    fn min(&self, other: Self) -> Self;
+    /// Component-wise maximum
+    fn max(&self, other: Self) -> Self;
+    fn min_element(&self) -> T;
+    fn max_element(&self) -> T;
+
+    fn dot(&self, other: Self) -> T;
+    fn length_squared(&self) -> T {
+        self.dot(*self)
+    }
+    fn length(&self) -> T;
+    fn normalize(&self) -> Self;
+
+    /// For each component takes the one of `if_true` if the mask is true
+    /// or the one of `if_false` otherwise
+    fn select(mask: [bool; 3], if_true: Self, if_false: Self) -> Self {
+        let if_true = if_true.array();
+        let if_false = if_false.array();
+        Self::from_array([0,1,2].map(|i|
+            if mask[i] { if_true[i] } else { if_false[i] }
+        ))
+    }
 }
 
 impl Vec3Ext<f32> for Vec3 {
@@ -160,6 +191,42 @@ impl Vec3Ext<f32> for Vec3 {
     fn array_mut(&mut self) -> [&mut f32; 3] {
         [&mut self.x, &mut self.y, &mut self.z]
     }
+
+    fn abs(&self) -> Self {
+        Vec3::abs(*self)
+    }
+
+    fn min(&self, other: Self) -> Self {
+        Vec3::min(*self, other)
+    }
+
+    fn max(&self, other: Self) -> Self {
+        Vec3::max(*self, other)
+    }
+
+    fn min_element(&self) -> f32 {
+        Vec3::min_element(*self)
+    }
+
+    fn max_element(&self) -> f32 {
+        Vec3::max_element(*self)
+    }
+
+    fn dot(&self, other: Self) -> f32 {
+        Vec3::dot(*self, other)
+    }
+
+    fn length_squared(&self) -> f32 {
+        Vec3::length_squared(*self)
+    }
+
+    fn length(&self) -> f32 {
+        Vec3::length(*self)
+    }
+
+    fn normalize(&self) -> Self {
+        Vec3::normalize(*self)
+    }
 }
 
 impl Vec3Ext<f64> for DVec3 {
@@ -198,4 +265,40 @@ impl Vec3Ext<f64> for DVec3 {
     fn array_mut(&mut self) -> [&mut f64; 3] {
         [&mut self.x, &mut self.y, &mut self.z]
     }
+
+    fn abs(&self) -> Self {
+        DVec3::abs(*self)
+    }
+
+    fn min(&self, other: Self) -> Self {
+        DVec3::min(*self, other)
+    }
+
+    fn max(&self, other: Self) -> Self {
+        DVec3::max(*self, other)
+    }
+
+    fn min_element(&self) -> f64 {
+        DVec3::min_element(*self)
+    }
+
+    fn max_element(&self) -> f64 {
+        DVec3::max_element(*self)
+    }
+
+    fn dot(&self, other: Self) -> f64 {
+        DVec3::dot(*self, other)
+    }
+
+    fn length_squared(&self) -> f64 {
+        DVec3::length_squared(*self)
+    }
+
+    fn length(&self) -> f64 {
+        DVec3::length(*self)
+    }
+
+    fn normalize(&self) -> Self {
+        DVec3::normalize(*self)
+    }
 }
@@ -1,9 +1,18 @@
 use bevy_math::{DVec3, Vec3};
 
-use crate::Vec3Ext;
+use crate::{ApproxEq, Tolerance, Vec3Ext};
 
 pub trait IsZeroApprox {
+    /// Whether all components are at most the epsilon of their type from zero
     fn is_zero_approx(&self) -> bool;
+
+    /// Like [Self::is_zero_approx] with the given epsilon, see [ApproxEq] for
+    /// other kinds of tolerances
+    fn is_zero_within(&self, epsilon: f64) -> bool
+        where Self: ApproxEq + Default
+    {
+        self.approx_eq(&Self::default(), Tolerance::Absolute(epsilon))
+    }
 }
 
 impl IsZeroApprox for f32 {
@@ -12,8 +12,16 @@ mod generic_glam;
 pub use generic_glam::*;
 #[cfg(feature = "logging")]
 pub mod logging;
+#[cfg(feature = "ron_file")]
+mod ron_file;
+#[cfg(feature = "ron_file")]
+pub use ron_file::*;
 mod is_zero_approx;
 pub use is_zero_approx::*;
+mod approx_eq;
+pub use approx_eq::*;
+mod fixed;
+pub use fixed::*;
 
 pub use replace_with::replace_with_or_abort as replace_with;
 pub use bimap::BiHashMap;
@@ -163,6 +171,14 @@ impl<T> RangeExt<T> for Range<T> {
     }
 }
 
+/// Unit direction of the given latitude and longitude (in radians), with the
+/// poles on the Y axis and the zero longitude towards +Z
+pub fn direction_from_lat_lon(latitude: f64, longitude: f64) -> DVec3 {
+    let (lat_sin, lat_cos) = latitude.sin_cos();
+    let (lon_sin, lon_cos) = longitude.sin_cos();
+    DVec3::new(lat_cos * lon_sin, lat_sin, lat_cos * lon_cos)
+}
+
 pub trait DQuatExt {
     fn looking_at(direction: DVec3, up: DVec3) -> DQuat;
 }
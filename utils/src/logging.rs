@@ -1,7 +1,188 @@
+use std::{fs::{File, OpenOptions}, io::Write, path::{Path, PathBuf}, time::SystemTime};
+
+use fern::colors::{ColoredLevelConfig, Color};
+pub use log::LevelFilter;
+
+/// Logging setup used by all binaries, see [LoggingConfig] for more control
 pub fn setup_basic_logging() -> Result<(), Box<dyn std::error::Error>> {
-    use fern::colors::{ ColoredLevelConfig, Color };
-    use log::LevelFilter as LF;
+    LoggingConfig::new().apply()
+}
+
+/// Where and how [LoggingConfig] writes logs to a file
+#[derive(Debug, Clone)]
+pub struct FileOutput {
+    pub path: PathBuf,
+    /// When the file gets bigger than this it is renamed with a `.1` suffix
+    /// (older files being shifted) and a new one is started, None to never
+    /// roll
+    pub max_size: Option<u64>,
+    /// Number of rolled files kept besides the current one
+    pub max_rolled_files: u32,
+    /// Write one json object per line instead of plain text lines
+    pub json: bool,
+}
+
+impl FileOutput {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_size: None,
+            max_rolled_files: 5,
+            json: false,
+        }
+    }
+
+    pub fn rolling(mut self, max_size: u64, max_rolled_files: u32) -> Self {
+        self.max_size = Some(max_size);
+        self.max_rolled_files = max_rolled_files;
+        self
+    }
+
+    pub fn json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+}
+
+/// Builder for the global logger
+///
+/// # Example
+/// ```no_run
+/// use utils::logging::{LoggingConfig, FileOutput, LevelFilter};
+///
+/// LoggingConfig::new()
+///     .level_for("erionite::generator", LevelFilter::Warn)
+///     .file(FileOutput::new("nsim.log").rolling(10 << 20, 3))
+///     .apply()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct LoggingConfig {
+    level: LevelFilter,
+    /// Later entries take precedence, targets are matched by prefix by fern
+    levels: Vec<(String, LevelFilter)>,
+    stdout: bool,
+    /// Write json lines to stdout instead of colored text
+    stdout_json: bool,
+    file: Option<FileOutput>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoggingConfig {
+    /// Same config as [setup_basic_logging]
+    pub fn new() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            levels: vec![
+                ("wgpu".to_string(), LevelFilter::Error),
+                ("wgpu_hal".to_string(), LevelFilter::Error),
+                ("mio".to_string(), LevelFilter::Off),
+                ("erionite".to_string(), LevelFilter::Trace),
+            ],
+            stdout: true,
+            stdout_json: false,
+            file: None,
+        }
+    }
+
+    /// Level of targets without a specific one
+    pub fn level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Level of the given target (a crate or module path like
+    /// `erionite::generator`) and its submodules
+    pub fn level_for(mut self, target: impl Into<String>, level: LevelFilter) -> Self {
+        let target = target.into();
+        self.levels.retain(|(t, _)| *t != target);
+        self.levels.push((target, level));
+        self
+    }
 
+    /// Applies filters written like `info,erionite::generator=warn,nbody=debug`
+    /// (a bare level sets the default one), as found in `RUST_LOG`
+    pub fn filters(mut self, spec: &str) -> Result<Self, FilterParseError> {
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parse_level = |level: &str| level.parse::<LevelFilter>()
+                .map_err(|_| FilterParseError(directive.to_string()));
+            self = match directive.split_once('=') {
+                Some((target, level)) => self.level_for(target.trim(), parse_level(level.trim())?),
+                None => self.level(parse_level(directive)?),
+            };
+        }
+        Ok(self)
+    }
+
+    /// Applies the filters of the given environment variable if it is set
+    pub fn env_filters(self, var: &str) -> Result<Self, FilterParseError> {
+        match std::env::var(var) {
+            Ok(spec) => self.filters(&spec),
+            Err(_) => Ok(self),
+        }
+    }
+
+    pub fn stdout(mut self, enabled: bool) -> Self {
+        self.stdout = enabled;
+        self
+    }
+
+    pub fn stdout_json(mut self, json: bool) -> Self {
+        self.stdout_json = json;
+        self
+    }
+
+    pub fn file(mut self, file: FileOutput) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Sets up the global logger, fails if one was already set
+    pub fn apply(self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut dispatch = fern::Dispatch::new()
+            .level(self.level);
+        for (target, level) in self.levels {
+            dispatch = dispatch.level_for(target, level);
+        }
+
+        if self.stdout {
+            let stdout = if self.stdout_json {
+                json_dispatch()
+            }
+            else {
+                colored_dispatch()
+            };
+            dispatch = dispatch.chain(stdout.chain(std::io::stdout()));
+        }
+
+        if let Some(file) = self.file {
+            let base = if file.json { json_dispatch() } else { plain_dispatch() };
+            let writer: Box<dyn Write + Send> = Box::new(RollingFile::open(file)?);
+            dispatch = dispatch.chain(base.chain(writer));
+        }
+
+        dispatch.apply()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FilterParseError(String);
+
+impl std::fmt::Display for FilterParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid log filter directive '{}'", self.0)
+    }
+}
+
+impl std::error::Error for FilterParseError {}
+
+fn colored_dispatch() -> fern::Dispatch {
     let colors = ColoredLevelConfig::new()
         .error(Color::Red)
         .warn(Color::Yellow)
@@ -10,9 +191,6 @@ pub fn setup_basic_logging() -> Result<(), Box<dyn std::error::Error>> {
         .trace(Color::BrightBlack);
 
     fern::Dispatch::new()
-        .filter(|m|
-            !m.target().starts_with("mio")
-        )
         .format(move |out, message, record| {
             out.finish(format_args!(
                 "{color_line}[{}][{}] {}\x1B[39m",
@@ -26,11 +204,122 @@ pub fn setup_basic_logging() -> Result<(), Box<dyn std::error::Error>> {
                 ),
             ))
         })
-        .level(LF::Info)
-        .level_for("wgpu", LF::Error)
-        .level_for("wgpu_hal", LF::Error)
-        .level_for("erionite", LF::Trace)
-        .chain(std::io::stdout())
-        .apply()?;
-    Ok(())
+}
+
+fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0., |d| d.as_secs_f64())
+}
+
+/// Like [colored_dispatch] with a timestamp, as files are read later
+fn plain_dispatch() -> fern::Dispatch {
+    fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "[{:.3}][{}][{}] {}",
+                unix_time(),
+                record.target(),
+                record.level(),
+                message,
+            ))
+        })
+}
+
+fn json_dispatch() -> fern::Dispatch {
+    fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                r#"{{"time":{:.3},"level":"{}","target":{},"message":{}}}"#,
+                unix_time(),
+                record.level(),
+                JsonString(record.target()),
+                JsonString(&message.to_string()),
+            ))
+        })
+}
+
+/// Displays the string as a quoted and escaped json string
+struct JsonString<'a>(&'a str);
+
+impl std::fmt::Display for JsonString<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use std::fmt::Write as _;
+
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// File appending writer rolling over to a new file when too big, see
+/// [FileOutput]
+struct RollingFile {
+    output: FileOutput,
+    file: File,
+    size: u64,
+}
+
+impl RollingFile {
+    fn open(output: FileOutput) -> std::io::Result<Self> {
+        if let Some(folder) = output.path.parent() {
+            std::fs::create_dir_all(folder)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&output.path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { output, file, size })
+    }
+
+    fn rolled_path(path: &Path, index: u32) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+
+    fn roll(&mut self) -> std::io::Result<()> {
+        let path = &self.output.path;
+        let max = self.output.max_rolled_files;
+        if max == 0 {
+            self.file.set_len(0)?;
+        }
+        else {
+            for i in (1..max).rev() {
+                let from = Self::rolled_path(path, i);
+                if from.exists() {
+                    std::fs::rename(from, Self::rolled_path(path, i + 1))?;
+                }
+            }
+            std::fs::rename(path, Self::rolled_path(path, 1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    /// fern flushes after each record, so rolling here never splits one
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.output.max_size.is_some_and(|max| self.size >= max) {
+            self.roll()?;
+        }
+        Ok(())
+    }
 }
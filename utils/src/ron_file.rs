@@ -0,0 +1,71 @@
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Error of [load_ron] and [save_ron]
+#[derive(Debug)]
+pub enum RonFileError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Deserialize(ron::error::SpannedError),
+}
+
+impl RonFileError {
+    /// Whether the file to load does not exist, which callers usually treat
+    /// as having nothing saved yet
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Self::Io(e) if e.kind() == std::io::ErrorKind::NotFound)
+    }
+}
+
+impl std::fmt::Display for RonFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "could not access file: {e}"),
+            Self::Serialize(e) => write!(f, "could not serialize: {e}"),
+            Self::Deserialize(e) => write!(f, "could not deserialize: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RonFileError {}
+
+impl From<std::io::Error> for RonFileError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<ron::Error> for RonFileError {
+    fn from(value: ron::Error) -> Self {
+        Self::Serialize(value)
+    }
+}
+
+impl From<ron::error::SpannedError> for RonFileError {
+    fn from(value: ron::error::SpannedError) -> Self {
+        Self::Deserialize(value)
+    }
+}
+
+pub fn load_ron<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T, RonFileError> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(ron::from_str(&content)?)
+}
+
+/// Pretty printing is best left out for big files that are not meant to be
+/// read by people
+pub fn save_ron<T: Serialize>(
+    path: impl AsRef<Path>,
+    value: &T,
+    pretty: bool,
+) -> Result<(), RonFileError> {
+    let content = if pretty {
+        ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())?
+    }
+    else {
+        ron::ser::to_string(value)?
+    };
+    std::fs::write(path, content)?;
+    Ok(())
+}